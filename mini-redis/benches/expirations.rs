@@ -0,0 +1,135 @@
+//! Compares the timing wheel tracking key expirations with the sorted set it replaced.
+//!
+//! Run with `cargo bench --bench expirations`. Keys are session-like: a million keys with
+//! TTLs of a few minutes, most of them refreshed before they expire.
+
+use mini_redis::wheel::TimingWheel;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant as StdInstant};
+use tokio::time::Instant;
+
+/// Number of keys with a TTL
+const KEYS: usize = 1_000_000;
+
+/// Number of TTLs refreshed, as `SET key value EX ttl` would
+const REFRESHES: usize = 2_000_000;
+
+fn main() {
+    let origin = Instant::now();
+    let keys: Vec<String> = (0..KEYS).map(|i| format!("session:{:016x}", i)).collect();
+
+    fastrand::seed(7);
+    let ttls: Vec<Duration> = (0..KEYS + REFRESHES)
+        .map(|_| Duration::from_millis(fastrand::u64(60_000..600_000)))
+        .collect();
+    let refreshed: Vec<usize> = (0..REFRESHES).map(|_| fastrand::usize(..KEYS)).collect();
+
+    sorted_set(origin, &keys, &ttls, &refreshed);
+    timing_wheel(origin, &keys, &ttls, &refreshed);
+}
+
+/// Expirations as a `BTreeSet<(Instant, String)>`, each key cloned in the set.
+fn sorted_set(origin: Instant, keys: &[String], ttls: &[Duration], refreshed: &[usize]) {
+    let mut expirations = BTreeSet::new();
+    let mut entries: HashMap<String, Instant> = HashMap::new();
+
+    let insert = measure(|| {
+        for (key, ttl) in keys.iter().zip(ttls) {
+            let when = origin + *ttl;
+            entries.insert(key.clone(), when);
+            expirations.insert((when, key.clone()));
+        }
+    });
+
+    let refresh = measure(|| {
+        for (&i, ttl) in refreshed.iter().zip(&ttls[keys.len()..]) {
+            let key = &keys[i];
+            let when = origin + *ttl;
+
+            if let Some(previous) = entries.insert(key.clone(), when) {
+                expirations.remove(&(previous, key.clone()));
+            }
+            expirations.insert((when, key.clone()));
+        }
+    });
+
+    let expire = measure(|| {
+        // Polled every 100 ms, as the purge task would with keys expiring continuously
+        let mut now = origin;
+
+        while !expirations.is_empty() {
+            now += Duration::from_millis(100);
+
+            while let Some((when, key)) = expirations.first() {
+                if *when > now {
+                    break;
+                }
+
+                entries.remove(key);
+                expirations.pop_first();
+            }
+        }
+    });
+
+    assert!(entries.is_empty());
+    report("sorted set", insert, refresh, expire);
+}
+
+/// Expirations as a `TimingWheel`, sharing the keys with the entries.
+fn timing_wheel(origin: Instant, keys: &[String], ttls: &[Duration], refreshed: &[usize]) {
+    let mut expirations = TimingWheel::new();
+    let mut entries = HashMap::new();
+
+    let insert = measure(|| {
+        for (key, ttl) in keys.iter().zip(ttls) {
+            let key: Arc<str> = Arc::from(key.as_str());
+            let timer = expirations.insert(origin + *ttl, key.clone());
+            entries.insert(key, timer);
+        }
+    });
+
+    let refresh = measure(|| {
+        for (&i, ttl) in refreshed.iter().zip(&ttls[keys.len()..]) {
+            if let Some(timer) = entries.get_mut(keys[i].as_str()) {
+                if let Some(key) = expirations.remove(*timer) {
+                    *timer = expirations.insert(origin + *ttl, key);
+                }
+            }
+        }
+    });
+
+    let expire = measure(|| {
+        // Polled every 100 ms, as the purge task would with keys expiring continuously
+        let mut now = origin;
+
+        while !expirations.is_empty() {
+            now += Duration::from_millis(100);
+
+            for key in expirations.poll(now) {
+                entries.remove(&key);
+            }
+        }
+    });
+
+    assert!(entries.is_empty());
+    report("timing wheel", insert, refresh, expire);
+}
+
+fn measure(f: impl FnOnce()) -> Duration {
+    let start = StdInstant::now();
+    f();
+    start.elapsed()
+}
+
+fn report(name: &str, insert: Duration, refresh: Duration, expire: Duration) {
+    println!(
+        "{:<14} insert {:>6} keys: {:>10.2?}   refresh {:>6} TTLs: {:>10.2?}   expire all: {:>10.2?}",
+        name,
+        KEYS,
+        insert,
+        REFRESHES,
+        refresh,
+        expire
+    );
+}
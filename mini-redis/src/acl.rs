@@ -0,0 +1,903 @@
+//! Access control lists: the users connections authenticate as with `AUTH`, and the
+//! commands, keys and pub/sub channels each of them may access, as Redis's ACLs.
+
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    fmt, fs,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    commands::Command,
+    config::{self, Config},
+    connection::Connection,
+    frame::Frame,
+    glob,
+};
+
+/// User `AUTH` authenticates as when no username is given, and which connections are
+/// authenticated as right away if it has no password.
+pub(crate) const DEFAULT_USER: &str = "default";
+
+/// Reply to the commands of a connection which must authenticate first.
+const NOAUTH_ERROR: &str = "NOAUTH Authentication required.";
+
+/// Categories of commands listed by `ACL CAT`, including the ones of the data types this
+/// server doesn't implement, as Redis lists them.
+pub(crate) const CATEGORIES: [&str; 21] = [
+    "keyspace",
+    "read",
+    "write",
+    "set",
+    "sortedset",
+    "list",
+    "hash",
+    "string",
+    "bitmap",
+    "hyperloglog",
+    "geo",
+    "stream",
+    "pubsub",
+    "admin",
+    "fast",
+    "slow",
+    "blocking",
+    "dangerous",
+    "connection",
+    "transaction",
+    "scripting",
+];
+
+/// Commands the users are allowed or denied, and their categories.
+const COMMANDS: [(&str, &[&str]); 47] = [
+    ("get", &["read", "string", "fast"]),
+    ("set", &["write", "string", "slow"]),
+    ("del", &["keyspace", "write", "slow"]),
+    ("select", &["connection", "fast"]),
+    ("swapdb", &["keyspace", "write", "fast", "dangerous"]),
+    ("move", &["keyspace", "write", "fast"]),
+    ("flushdb", &["keyspace", "write", "slow", "dangerous"]),
+    ("flushall", &["keyspace", "write", "slow", "dangerous"]),
+    ("dbsize", &["keyspace", "read", "fast"]),
+    ("publish", &["pubsub", "fast"]),
+    ("subscribe", &["pubsub", "slow"]),
+    ("unsubscribe", &["pubsub", "slow"]),
+    ("psubscribe", &["pubsub", "slow"]),
+    ("punsubscribe", &["pubsub", "slow"]),
+    ("pubsub", &["pubsub", "slow"]),
+    ("ping", &["fast", "connection"]),
+    ("reset", &["fast", "connection"]),
+    ("quit", &["fast", "connection"]),
+    ("multi", &["fast", "transaction"]),
+    ("exec", &["slow", "transaction"]),
+    ("discard", &["fast", "transaction"]),
+    ("watch", &["fast", "transaction"]),
+    ("unwatch", &["fast", "transaction"]),
+    ("eval", &["slow", "scripting"]),
+    ("evalsha", &["slow", "scripting"]),
+    ("script", &["slow", "scripting"]),
+    ("save", &["admin", "slow", "dangerous"]),
+    ("bgsave", &["admin", "slow", "dangerous"]),
+    ("lastsave", &["admin", "fast", "dangerous"]),
+    ("shutdown", &["admin", "slow", "dangerous"]),
+    ("bgrewriteaof", &["admin", "slow", "dangerous"]),
+    ("dump", &["keyspace", "read", "slow"]),
+    ("restore", &["keyspace", "write", "slow", "dangerous"]),
+    ("object", &["keyspace", "read", "slow"]),
+    ("replicaof", &["admin", "slow", "dangerous"]),
+    ("failover", &["admin", "slow", "dangerous"]),
+    ("cluster", &["slow"]),
+    ("asking", &["fast", "connection"]),
+    ("migrate", &["keyspace", "write", "slow", "dangerous"]),
+    ("replconf", &["admin", "slow", "dangerous"]),
+    ("psync", &["admin", "slow", "dangerous"]),
+    ("role", &["admin", "fast", "dangerous"]),
+    ("info", &["slow", "dangerous"]),
+    ("config", &["admin", "slow", "dangerous"]),
+    ("wait", &["slow", "connection"]),
+    ("auth", &["fast", "connection"]),
+    ("acl", &["admin", "slow", "dangerous"]),
+];
+
+/// Denials of a user on the same object are counted on a single log entry within this period.
+const LOG_GROUPING_PERIOD: Duration = Duration::from_secs(60);
+
+/// Users of the server and the log of the denials.
+///
+/// Kept out of the `Db` state, users being looked up for every command.
+#[derive(Debug)]
+pub(crate) struct Acl {
+    /// Users by name, listed in alphabetical order
+    users: Mutex<BTreeMap<String, User>>,
+
+    /// Latest denied commands and failed authentications, the most recent first
+    log: Mutex<Log>,
+
+    /// Identifies the next user created
+    next_user_id: AtomicU64,
+}
+
+/// A user connections authenticate as.
+#[derive(Clone, Debug)]
+pub(crate) struct User {
+    /// Unique to the user, so that connections authenticated as a deleted user don't get
+    /// the permissions of a new one of the same name
+    id: u64,
+
+    /// Disabled users can't authenticate, connections already authenticated as them go on
+    enabled: bool,
+
+    /// Any password authenticates as the user
+    nopass: bool,
+
+    /// SHA-256 digests of the passwords, in hex
+    passwords: Vec<String>,
+
+    /// Commands the user may run
+    commands: HashSet<&'static str>,
+
+    /// Rules `commands` results from, e.g. `-@all +@read +set`
+    command_rules: Vec<String>,
+
+    /// Glob-style patterns of the keys the user may access, `*` for all
+    keys: Vec<String>,
+
+    /// Glob-style patterns of the channels the user may publish and subscribe to, `*` for all
+    channels: Vec<String>,
+}
+
+/// Authentication state of a connection.
+#[derive(Clone, Debug)]
+pub(crate) struct Session {
+    /// Name and id of the user the connection is authenticated as, `None` until it does
+    user: Option<(String, u64)>,
+
+    /// Address of the client, reported by `ACL LOG`
+    client_info: String,
+}
+
+/// Why a command or an authentication was denied, as reported by `ACL LOG`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Reason {
+    Command,
+    Key,
+    Channel,
+    Auth,
+}
+
+#[derive(Debug, Default)]
+struct Log {
+    entries: VecDeque<LogEntry>,
+    next_entry_id: u64,
+}
+
+/// Entry of the ACL log, counting the similar denials.
+#[derive(Clone, Debug)]
+pub(crate) struct LogEntry {
+    /// Number of denials
+    pub(crate) count: u64,
+    pub(crate) reason: Reason,
+
+    /// `toplevel`, or `multi` for a command queued in a transaction
+    pub(crate) context: &'static str,
+
+    /// Denied command, key or channel
+    pub(crate) object: String,
+    pub(crate) username: String,
+    pub(crate) client_info: String,
+    pub(crate) entry_id: u64,
+    pub(crate) created: SystemTime,
+    pub(crate) updated: SystemTime,
+}
+
+impl Acl {
+    /// Create the ACLs with the `default` user, which may do anything and has `requirepass`
+    /// as its password if set.
+    pub(crate) fn new(config: &Config) -> Acl {
+        let acl = Acl {
+            users: Mutex::new(BTreeMap::new()),
+            log: Mutex::new(Log::default()),
+            next_user_id: AtomicU64::new(1),
+        };
+
+        let mut default = acl.new_user();
+        for rule in ["on", "nopass", "~*", "&*", "+@all"] {
+            default.apply_rule(rule).expect("valid rule");
+        }
+
+        acl.users
+            .lock()
+            .unwrap()
+            .insert(DEFAULT_USER.to_string(), default);
+        acl.set_default_password(config.requirepass.as_deref());
+
+        acl
+    }
+
+    /// Replace the passwords of the `default` user by `password`, as `CONFIG SET requirepass`
+    /// does, `None` letting anyone authenticate as it.
+    pub(crate) fn set_default_password(&self, password: Option<&str>) {
+        let mut users = self.users.lock().unwrap();
+
+        if let Some(user) = users.get_mut(DEFAULT_USER) {
+            let rule = match password {
+                Some(password) => format!(">{}", password),
+                None => "nopass".to_string(),
+            };
+
+            user.apply_rule("resetpass").expect("valid rule");
+            user.apply_rule(&rule).expect("valid rule");
+        }
+    }
+
+    /// Replace the users by the ones of the ACL file at `path`, declared one per line as
+    /// `user <name> [rule ...]`, the format of `ACL LIST`. Lines starting with `#` are
+    /// comments.
+    ///
+    /// The `default` user is kept as is unless the file declares it.
+    pub(crate) fn load_file(&self, path: &Path) -> crate::FnResult<()> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| format!("failed to read the ACL file `{}`: {}", path.display(), err))?;
+
+        let mut loaded = BTreeMap::new();
+
+        for (number, line) in contents.lines().enumerate() {
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+
+            let invalid = |err: String| {
+                format!(
+                    "invalid ACL file `{}`: line {}: {}",
+                    path.display(),
+                    number + 1,
+                    err
+                )
+            };
+            let args = config::split_args(line).map_err(|err| invalid(err.to_string()))?;
+
+            let (name, rules) = match &args[..] {
+                [] => continue,
+                [keyword, name, rules @ ..] if keyword == "user" => (name, rules),
+                _ => return Err(invalid("expected `user <name> [rule ...]`".to_string()).into()),
+            };
+
+            if loaded.contains_key(name) {
+                return Err(invalid(format!("duplicate user '{}'", name)).into());
+            }
+
+            let mut user = self.new_user();
+            for rule in rules {
+                user.apply_rule(rule).map_err(|err| {
+                    invalid(format!("Error in user declaration '{}': {}", rule, err))
+                })?;
+            }
+
+            loaded.insert(name.clone(), user);
+        }
+
+        let mut users = self.users.lock().unwrap();
+
+        if let Some(default) = users.remove(DEFAULT_USER) {
+            loaded.entry(DEFAULT_USER.to_string()).or_insert(default);
+        }
+
+        *users = loaded;
+
+        Ok(())
+    }
+
+    /// Returns the id of the user `name` if it's enabled and `password` is one of its
+    /// passwords, or it has none with `nopass`.
+    pub(crate) fn authenticate(&self, name: &str, password: &str) -> Option<u64> {
+        let users = self.users.lock().unwrap();
+        let user = users.get(name)?;
+
+        let valid = user.nopass || user.passwords.contains(&hash(password));
+        (user.enabled && valid).then_some(user.id)
+    }
+
+    /// Returns `true` if the user `name` exists and has no password, with `nopass`.
+    pub(crate) fn is_nopass(&self, name: &str) -> bool {
+        self.users
+            .lock()
+            .unwrap()
+            .get(name)
+            .is_some_and(|user| user.nopass)
+    }
+
+    /// Check `cmd` against the permissions of the user `session` is authenticated as, the
+    /// commands it may run, and the keys and channels it may access. The denials are logged
+    /// in `context`: `toplevel`, `multi` for the commands queued in a transaction, or `lua`
+    /// for the ones called by scripts.
+    ///
+    /// Returns the error to reply when denied. The connections of a deleted user must
+    /// authenticate again.
+    pub(crate) fn check(
+        &self,
+        session: &Session,
+        cmd: &Command,
+        context: &'static str,
+        log_max_len: usize,
+    ) -> Result<(), Frame> {
+        if cmd.is_no_auth() {
+            return Ok(());
+        }
+
+        let (name, id) = match &session.user {
+            Some(user) => user,
+            None => return Err(Frame::Error(NOAUTH_ERROR.to_string())),
+        };
+
+        let users = self.users.lock().unwrap();
+
+        let user = match users.get(name) {
+            Some(user) if user.id == *id => user,
+            _ => return Err(Frame::Error(NOAUTH_ERROR.to_string())),
+        };
+
+        // Patterns subscribed to must be allowed as is, not only match an allowed pattern
+        let literal = matches!(cmd, Command::PSubscribe(_));
+
+        let (reason, object) = if !user.commands.contains(cmd.get_name()) {
+            (Reason::Command, cmd.get_name().to_string())
+        } else if let Some(key) = cmd.keys().into_iter().find(|key| !user.may_access_key(key)) {
+            (Reason::Key, key.to_string())
+        } else if let Some(channel) = cmd
+            .channels()
+            .into_iter()
+            .find(|channel| !user.may_access_channel(channel, literal))
+        {
+            (Reason::Channel, channel.to_string())
+        } else {
+            return Ok(());
+        };
+
+        drop(users);
+
+        let response = match reason {
+            Reason::Command => format!(
+                "NOPERM User {} has no permissions to run the '{}' command",
+                name, object
+            ),
+            Reason::Key => "NOPERM No permissions to access a key".to_string(),
+            _ => "NOPERM No permissions to access a channel".to_string(),
+        };
+
+        self.log(reason, context, object, name, session, log_max_len);
+
+        Err(Frame::Error(response))
+    }
+
+    /// Log a failed authentication as the user `name`.
+    pub(crate) fn log_auth_failure(&self, session: &Session, name: &str, log_max_len: usize) {
+        self.log(
+            Reason::Auth,
+            "toplevel",
+            "AUTH".to_string(),
+            name,
+            session,
+            log_max_len,
+        );
+    }
+
+    /// Create the user `name`, or update it, applying the rules in order: either all of
+    /// them, or none if one is invalid.
+    ///
+    /// Returns the error to reply otherwise.
+    pub(crate) fn set_user(&self, name: &str, rules: &[String]) -> Result<(), String> {
+        let mut users = self.users.lock().unwrap();
+
+        let mut user = match users.get(name) {
+            Some(user) => user.clone(),
+            None => self.new_user(),
+        };
+
+        for rule in rules {
+            user.apply_rule(rule)
+                .map_err(|err| format!("ERR Error in ACL SETUSER modifier '{}': {}", rule, err))?;
+        }
+
+        users.insert(name.to_string(), user);
+
+        Ok(())
+    }
+
+    /// Returns the user `name`, if it exists.
+    pub(crate) fn user(&self, name: &str) -> Option<User> {
+        self.users.lock().unwrap().get(name).cloned()
+    }
+
+    /// Delete the users `names`, returning how many existed.
+    ///
+    /// Returns the error to reply if one of them is the `default` user, which can't be
+    /// deleted.
+    pub(crate) fn del_users(&self, names: &[String]) -> Result<usize, String> {
+        if names.iter().any(|name| name == DEFAULT_USER) {
+            return Err("ERR The 'default' user cannot be removed".to_string());
+        }
+
+        let mut users = self.users.lock().unwrap();
+
+        Ok(names
+            .iter()
+            .filter(|name| users.remove(*name).is_some())
+            .count())
+    }
+
+    /// Returns every user, described as `user <name> [rule ...]`.
+    pub(crate) fn list(&self) -> Vec<String> {
+        self.users
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, user)| format!("user {} {}", name, user.describe()))
+            .collect()
+    }
+
+    /// Returns the `count` latest entries of the log, the most recent first.
+    pub(crate) fn log_entries(&self, count: usize) -> Vec<LogEntry> {
+        self.log
+            .lock()
+            .unwrap()
+            .entries
+            .iter()
+            .take(count)
+            .cloned()
+            .collect()
+    }
+
+    /// Clear the log.
+    pub(crate) fn reset_log(&self) {
+        self.log.lock().unwrap().entries.clear();
+    }
+
+    fn new_user(&self) -> User {
+        User {
+            id: self.next_user_id.fetch_add(1, Ordering::Relaxed),
+            enabled: false,
+            nopass: false,
+            passwords: vec![],
+            commands: HashSet::new(),
+            command_rules: vec!["-@all".to_string()],
+            keys: vec![],
+            channels: vec![],
+        }
+    }
+
+    /// Log a denial, counting it on the entry of a similar one if logged recently.
+    fn log(
+        &self,
+        reason: Reason,
+        context: &'static str,
+        object: String,
+        name: &str,
+        session: &Session,
+        max_len: usize,
+    ) {
+        let mut log = self.log.lock().unwrap();
+        let now = SystemTime::now();
+
+        let similar = log.entries.iter().position(|entry| {
+            entry.reason == reason
+                && entry.context == context
+                && entry.object == object
+                && entry.username == name
+                && now.duration_since(entry.updated).unwrap_or_default() < LOG_GROUPING_PERIOD
+        });
+
+        let entry = match similar.and_then(|position| log.entries.remove(position)) {
+            Some(entry) => LogEntry {
+                count: entry.count + 1,
+                client_info: session.client_info.clone(),
+                updated: now,
+                ..entry
+            },
+            None => {
+                log.next_entry_id += 1;
+
+                LogEntry {
+                    count: 1,
+                    reason,
+                    context,
+                    object,
+                    username: name.to_string(),
+                    client_info: session.client_info.clone(),
+                    entry_id: log.next_entry_id - 1,
+                    created: now,
+                    updated: now,
+                }
+            }
+        };
+
+        log.entries.push_front(entry);
+        log.entries.truncate(max_len);
+    }
+
+    /// Returns the user new connections are authenticated as: `default`, unless it has
+    /// a password or is disabled.
+    fn initial_user(&self) -> Option<(String, u64)> {
+        let users = self.users.lock().unwrap();
+        let user = users.get(DEFAULT_USER)?;
+
+        (user.enabled && user.nopass).then(|| (DEFAULT_USER.to_string(), user.id))
+    }
+}
+
+impl User {
+    /// Returns `on` or `off`, and `nopass` if the user has no password.
+    pub(crate) fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+
+        if self.nopass {
+            flags.push("nopass");
+        }
+
+        flags
+    }
+
+    /// Returns the SHA-256 digests of the passwords, in hex.
+    pub(crate) fn passwords(&self) -> &[String] {
+        &self.passwords
+    }
+
+    /// Returns the rules the allowed commands result from, e.g. `-@all +@read +set`.
+    pub(crate) fn describe_commands(&self) -> String {
+        self.command_rules.join(" ")
+    }
+
+    /// Returns the key patterns, e.g. `~cache:* ~session:*`.
+    pub(crate) fn describe_keys(&self) -> String {
+        describe_patterns('~', &self.keys)
+    }
+
+    /// Returns the channel patterns, e.g. `&news.*`.
+    pub(crate) fn describe_channels(&self) -> String {
+        describe_patterns('&', &self.channels)
+    }
+
+    /// Describe the user as the rules recreating it, as `ACL LIST` and the ACL file.
+    fn describe(&self) -> String {
+        let mut rules: Vec<String> = self.flags().into_iter().map(String::from).collect();
+        rules.extend(
+            self.passwords
+                .iter()
+                .map(|password| format!("#{}", password)),
+        );
+        rules.push(self.describe_keys());
+
+        // New users may access no channel, which must be reset when the user is recreated
+        if self.channels != ["*"] {
+            rules.push("resetchannels".to_string());
+        }
+
+        rules.push(self.describe_channels());
+        rules.push(self.describe_commands());
+
+        rules.retain(|rule| !rule.is_empty());
+        rules.join(" ")
+    }
+
+    fn may_access_key(&self, key: &str) -> bool {
+        self.keys
+            .iter()
+            .any(|pattern| glob::matches(pattern.as_bytes(), key.as_bytes()))
+    }
+
+    /// Returns `true` if the user may access `channel`, or the pattern `channel` if `literal`.
+    fn may_access_channel(&self, channel: &str, literal: bool) -> bool {
+        self.channels.iter().any(|pattern| {
+            pattern == "*"
+                || if literal {
+                    pattern == channel
+                } else {
+                    glob::matches(pattern.as_bytes(), channel.as_bytes())
+                }
+        })
+    }
+
+    /// Apply a rule of `ACL SETUSER`, returning why it's invalid otherwise.
+    fn apply_rule(&mut self, rule: &str) -> Result<(), &'static str> {
+        match &rule.to_lowercase()[..] {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => self.keys = vec!["*".to_string()],
+            "resetkeys" => self.keys.clear(),
+            "allchannels" => self.channels = vec!["*".to_string()],
+            "resetchannels" => self.channels.clear(),
+            "allcommands" => self.set_all_commands(true),
+            "nocommands" => self.set_all_commands(false),
+            "reset" => {
+                for rule in ["resetpass", "resetkeys", "resetchannels", "off", "-@all"] {
+                    self.apply_rule(rule)?;
+                }
+            }
+            _ => {
+                let mut chars = rule.chars();
+                let (prefix, value) = (chars.next(), chars.as_str());
+
+                match prefix {
+                    Some('>') => self.add_password(hash(value)),
+                    Some('<') => self.remove_password(&hash(value))?,
+                    Some('#') => self.add_password(parse_hash(value)?.to_string()),
+                    Some('!') => self.remove_password(parse_hash(value)?)?,
+                    Some('~') => add_pattern(&mut self.keys, value).map_err(|_| {
+                        "Adding a pattern after the * pattern (or the 'allkeys' flag) is not valid and does not have any effect. Try 'resetkeys' to start with an empty list of patterns"
+                    })?,
+                    Some('&') => add_pattern(&mut self.channels, value).map_err(|_| {
+                        "Adding a pattern after the * pattern (or the 'allchannels' flag) is not valid and does not have any effect. Try 'resetchannels' to start with an empty list of channels"
+                    })?,
+                    Some('+') => self.apply_command_rule(true, &value.to_lowercase())?,
+                    Some('-') => self.apply_command_rule(false, &value.to_lowercase())?,
+                    _ => return Err("Syntax error"),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_password(&mut self, digest: String) {
+        self.nopass = false;
+
+        if !self.passwords.contains(&digest) {
+            self.passwords.push(digest);
+        }
+    }
+
+    fn remove_password(&mut self, digest: &str) -> Result<(), &'static str> {
+        match self
+            .passwords
+            .iter()
+            .position(|password| password == digest)
+        {
+            Some(position) => {
+                self.passwords.remove(position);
+                Ok(())
+            }
+            None => Err("The password you are trying to remove from the user does not exist"),
+        }
+    }
+
+    /// Allow or deny a command, or a category of commands with `@category`.
+    fn apply_command_rule(&mut self, allow: bool, name: &str) -> Result<(), &'static str> {
+        let commands = match name.strip_prefix('@') {
+            Some("all") => {
+                self.set_all_commands(allow);
+                return Ok(());
+            }
+            Some(category) => commands_in(category),
+            None => COMMANDS
+                .iter()
+                .find(|(command, _)| *command == name)
+                .map(|(command, _)| vec![*command]),
+        };
+
+        let commands = commands.ok_or("Unknown command or category name in ACL")?;
+
+        for command in commands {
+            if allow {
+                self.commands.insert(command);
+            } else {
+                self.commands.remove(command);
+            }
+        }
+
+        self.command_rules
+            .push(format!("{}{}", if allow { '+' } else { '-' }, name));
+
+        Ok(())
+    }
+
+    fn set_all_commands(&mut self, allow: bool) {
+        self.commands = match allow {
+            true => COMMANDS.iter().map(|(command, _)| *command).collect(),
+            false => HashSet::new(),
+        };
+
+        self.command_rules = vec![if allow { "+@all" } else { "-@all" }.to_string()];
+    }
+}
+
+impl Session {
+    /// Start the session of a new connection, authenticated as the `default` user right
+    /// away if it has no password.
+    pub(crate) fn new(acl: &Acl, connection: &Connection) -> Session {
+        let client_info = match connection.peer_addr() {
+            Ok(addr) => format!("addr={}", addr),
+            Err(_) => "addr=unix".to_string(),
+        };
+
+        Session {
+            user: acl.initial_user(),
+            client_info,
+        }
+    }
+
+    /// Name of the user the connection is authenticated as, `None` until it does.
+    pub(crate) fn username(&self) -> Option<&str> {
+        self.user.as_ref().map(|(name, _)| &name[..])
+    }
+
+    /// Authenticate as the user `name`, as returned by `Acl::authenticate`.
+    pub(crate) fn authenticate(&mut self, name: &str, id: u64) {
+        self.user = Some((name.to_string(), id));
+    }
+
+    /// Authenticate as a new connection would, as `RESET` does.
+    pub(crate) fn reset(&mut self, acl: &Acl) {
+        self.user = acl.initial_user();
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::Command => "command",
+            Reason::Key => "key",
+            Reason::Channel => "channel",
+            Reason::Auth => "auth",
+        }
+        .fmt(fmt)
+    }
+}
+
+/// Returns the commands of `category`, or `None` if there's no such category.
+pub(crate) fn commands_in(category: &str) -> Option<Vec<&'static str>> {
+    if !CATEGORIES.contains(&category) {
+        return None;
+    }
+
+    let commands = COMMANDS
+        .iter()
+        .filter(|(_, categories)| categories.contains(&category))
+        .map(|(command, _)| *command);
+
+    Some(commands.collect())
+}
+
+/// SHA-256 digest of a password, in hex.
+fn hash(password: &str) -> String {
+    format!("{:x}", Sha256::digest(password.as_bytes()))
+}
+
+/// Validate the digest of a password given with `#` or `!`.
+fn parse_hash(digest: &str) -> Result<&str, &'static str> {
+    match digest.len() == 64 && digest.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        true => Ok(digest),
+        false => Err("The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters"),
+    }
+}
+
+/// Add a key or channel pattern, `*` replacing all the others.
+///
+/// Fails if all are already allowed, the pattern would have no effect.
+fn add_pattern(patterns: &mut Vec<String>, pattern: &str) -> Result<(), ()> {
+    if pattern == "*" {
+        *patterns = vec!["*".to_string()];
+    } else if patterns.iter().any(|allowed| allowed == "*") {
+        return Err(());
+    } else if !patterns.iter().any(|allowed| allowed == pattern) {
+        patterns.push(pattern.to_string());
+    }
+
+    Ok(())
+}
+
+fn describe_patterns(prefix: char, patterns: &[String]) -> String {
+    patterns
+        .iter()
+        .map(|pattern| format!("{}{}", prefix, pattern))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(rules: &[&str]) -> User {
+        let acl = Acl::new(&Config::default());
+        let rules: Vec<String> = rules.iter().map(|rule| rule.to_string()).collect();
+
+        acl.set_user("alice", &rules).unwrap();
+        acl.user("alice").unwrap()
+    }
+
+    #[test]
+    fn should_apply_command_rules_in_order() {
+        let alice = user(&["+@read", "-get", "+set"]);
+
+        assert!(alice.commands.contains("dbsize"));
+        assert!(alice.commands.contains("set"));
+        assert!(!alice.commands.contains("get"));
+        assert_eq!(alice.describe_commands(), "-@all +@read -get +set");
+
+        let alice = user(&["+get", "allcommands", "-@dangerous"]);
+
+        assert!(alice.commands.contains("get"));
+        assert!(!alice.commands.contains("flushall"));
+        assert_eq!(alice.describe_commands(), "+@all -@dangerous");
+    }
+
+    #[test]
+    fn should_match_keys_and_channels() {
+        let alice = user(&["~cache:*", "&news.*"]);
+
+        assert!(alice.may_access_key("cache:1"));
+        assert!(!alice.may_access_key("session:1"));
+        assert!(alice.may_access_channel("news.tech", false));
+        assert!(!alice.may_access_channel("news.*.tech", true));
+        assert!(alice.may_access_channel("news.*", true));
+
+        let alice = user(&["allchannels"]);
+        assert!(alice.may_access_channel("*", true));
+    }
+
+    #[test]
+    fn should_reject_invalid_rules() {
+        let acl = Acl::new(&Config::default());
+
+        for rule in ["+unknown", "+@unknown", "#abc", "<missing", "bogus"] {
+            assert!(
+                acl.set_user("alice", &[rule.to_string()]).is_err(),
+                "{}",
+                rule
+            );
+        }
+
+        assert!(acl
+            .set_user("alice", &["allkeys".to_string(), "~cache:*".to_string()])
+            .is_err());
+
+        // Nothing was applied
+        assert!(acl.user("alice").is_none());
+    }
+
+    #[test]
+    fn should_describe_users_as_their_rules() {
+        let acl = Acl::new(&Config {
+            requirepass: Some("secret".to_string()),
+            ..Default::default()
+        });
+
+        let rules = ["on", ">pass", "~cache:*", "&news.*", "+@read", "-dbsize"];
+        acl.set_user("alice", &rules.map(String::from)).unwrap();
+
+        assert_eq!(
+            acl.list(),
+            vec![
+                format!(
+                    "user alice on #{} ~cache:* resetchannels &news.* -@all +@read -dbsize",
+                    hash("pass")
+                ),
+                format!("user default on #{} ~* &* +@all", hash("secret")),
+            ]
+        );
+
+        // The description recreates the user
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.acl");
+        fs::write(&path, acl.list().join("\n")).unwrap();
+
+        let loaded = Acl::new(&Config::default());
+        loaded.load_file(&path).unwrap();
+        assert_eq!(loaded.list(), acl.list());
+        assert!(loaded.authenticate("alice", "pass").is_some());
+        assert!(loaded.authenticate("default", "").is_none());
+    }
+}
@@ -0,0 +1,750 @@
+use bytes::Bytes;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Cursor, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
+
+use crate::{
+    commands::{Exec, Multi, Select},
+    config::{AofConfig, AppendFsync, RecoveryPoint},
+    frame::{self, Frame},
+    rdb::Snapshot,
+};
+
+/// Append-only file, logging the writes applied to the databases as RESP commands.
+///
+/// Commands are buffered by `feed` while the state is locked, and written by `flush`
+/// before the lock is released, so that the log order matches the order the writes
+/// were applied in.
+#[derive(Debug)]
+pub(crate) struct Aof {
+    path: PathBuf,
+
+    /// Handle appending to the file, shared with the task syncing it every second
+    file: Arc<File>,
+
+    fsync: AppendFsync,
+
+    /// Commands fed since the last flush
+    feed: Feed,
+
+    /// Commands logged while a rewrite runs, appended to the rewritten file once written
+    rewrite_buf: Option<Vec<u8>>,
+
+    /// `true` if data was written since the file was last synced
+    unsynced: bool,
+
+    /// Annotate the commands with the Unix time they were logged at
+    timestamps: bool,
+
+    /// Unix time of the last annotation
+    last_timestamp: u64,
+}
+
+/// Buffer of commands to propagate, to the append-only file or to the replicas.
+///
+/// `SELECT` is inserted whenever the database written to changes, and commands fed
+/// together are wrapped in `MULTI`/`EXEC` when taken.
+#[derive(Debug, Default)]
+pub(crate) struct Feed {
+    buf: Vec<u8>,
+
+    /// Number of commands in `buf`
+    num_commands: usize,
+
+    /// Database selected by the last `SELECT`, if any
+    selected: Option<usize>,
+}
+
+/// An entry of the append-only file.
+#[derive(Debug)]
+enum Entry {
+    Command(Frame),
+
+    /// `#TS:<unix time>` annotation, preceding the commands logged during that second
+    Timestamp(u64),
+}
+
+/// Summary of the commands left out by a point-in-time recovery.
+#[derive(Debug)]
+pub(crate) struct Skipped {
+    /// Number of skipped writes, `MULTI`, `EXEC` and `SELECT` aside
+    pub(crate) commands: usize,
+
+    /// Size of the skipped part of the file
+    pub(crate) bytes: u64,
+
+    /// Unix times at which the first and last skipped writes were logged, if annotated
+    pub(crate) first_timestamp: Option<u64>,
+    pub(crate) last_timestamp: Option<u64>,
+
+    /// Number of skipped writes, by command name
+    pub(crate) by_name: BTreeMap<String, usize>,
+
+    /// File the skipped part of the log was moved to
+    pub(crate) saved_to: PathBuf,
+}
+
+impl Aof {
+    /// Open the append-only file at `path`, creating it if needed.
+    pub(crate) fn open(path: &Path, config: &AofConfig) -> io::Result<Aof> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Aof {
+            path: path.to_path_buf(),
+            file: Arc::new(file),
+            fsync: config.fsync,
+            feed: Feed::default(),
+            rewrite_buf: None,
+            unsynced: false,
+            timestamps: config.timestamps,
+            last_timestamp: 0,
+        })
+    }
+
+    /// Path of the append-only file.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Log a command applied to the database at `index`, or to the whole server when `None`.
+    pub(crate) fn feed(&mut self, index: Option<usize>, command: &Frame) {
+        self.feed.feed(index, command);
+    }
+
+    /// Write the commands fed since the last call, syncing them to disk with
+    /// `AppendFsync::Always`.
+    ///
+    /// Commands applied under the same lock, e.g. by `EXEC` or a script, are wrapped in
+    /// `MULTI`/`EXEC`, so that they're replayed all together or not at all.
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        let mut data = match self.feed.take() {
+            Some(data) => data,
+            None => return Ok(()),
+        };
+
+        if self.timestamps {
+            let now = unix_time();
+
+            if now != self.last_timestamp {
+                let mut annotated = timestamp_annotation(now);
+                annotated.append(&mut data);
+                data = annotated;
+                self.last_timestamp = now;
+            }
+        }
+
+        if let Some(rewrite_buf) = &mut self.rewrite_buf {
+            rewrite_buf.extend_from_slice(&data);
+        }
+
+        (&*self.file).write_all(&data)?;
+
+        match self.fsync {
+            AppendFsync::Always => self.file.sync_data()?,
+            AppendFsync::EverySec => self.unsynced = true,
+            AppendFsync::No => {}
+        }
+
+        Ok(())
+    }
+
+    /// Returns the file to sync to disk, if data was written since the last call.
+    pub(crate) fn take_unsynced(&mut self) -> Option<Arc<File>> {
+        if !self.unsynced {
+            return None;
+        }
+
+        self.unsynced = false;
+        Some(self.file.clone())
+    }
+
+    /// Returns `true` while a rewrite runs.
+    pub(crate) fn is_rewriting(&self) -> bool {
+        self.rewrite_buf.is_some()
+    }
+
+    /// Start keeping the commands logged from now on, to append them to the rewritten file.
+    pub(crate) fn start_rewrite(&mut self) {
+        self.rewrite_buf = Some(vec![]);
+
+        // The rewritten file doesn't select any database
+        self.feed.reset_selected();
+    }
+
+    /// Complete a rewrite started with `start_rewrite`: the commands logged meanwhile are
+    /// appended to the rewritten file at `temp_path`, which then replaces the current one.
+    pub(crate) fn finish_rewrite(&mut self, temp_path: &Path) -> io::Result<()> {
+        let rewrite_buf = self.rewrite_buf.take().unwrap_or_default();
+
+        let result = (|| {
+            let mut file = OpenOptions::new().append(true).open(temp_path)?;
+            file.write_all(&rewrite_buf)?;
+            file.sync_all()?;
+            fs::rename(temp_path, &self.path)?;
+            Ok(file)
+        })();
+
+        match result {
+            Ok(file) => {
+                self.file = Arc::new(file);
+                self.unsynced = false;
+                Ok(())
+            }
+            Err(err) => {
+                let _ = fs::remove_file(temp_path);
+                Err(err)
+            }
+        }
+    }
+
+    /// Give up on a rewrite started with `start_rewrite`.
+    pub(crate) fn abort_rewrite(&mut self) {
+        self.rewrite_buf = None;
+    }
+}
+
+impl Feed {
+    /// Add a command applied to the database at `index`, or to the whole server when `None`.
+    pub(crate) fn feed(&mut self, index: Option<usize>, command: &Frame) {
+        if let Some(index) = index {
+            if self.selected != Some(index) {
+                write_command(&mut self.buf, &Select::new(index as u64).into_frame());
+                self.selected = Some(index);
+            }
+        }
+
+        write_command(&mut self.buf, command);
+        self.num_commands += 1;
+    }
+
+    /// Take the encoded commands fed since the last call, if any.
+    pub(crate) fn take(&mut self) -> Option<Vec<u8>> {
+        if self.buf.is_empty() {
+            return None;
+        }
+
+        let mut data = std::mem::take(&mut self.buf);
+
+        if self.num_commands > 1 {
+            let mut wrapped = vec![];
+            write_command(&mut wrapped, &Multi::new().into_frame());
+            wrapped.append(&mut data);
+            write_command(&mut wrapped, &Exec::new().into_frame());
+            data = wrapped;
+        }
+
+        self.num_commands = 0;
+
+        Some(data)
+    }
+
+    /// Database selected by the commands fed so far, if any.
+    pub(crate) fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    /// Select the database again before the next command, for a reader starting from here.
+    pub(crate) fn reset_selected(&mut self) {
+        self.selected = None;
+    }
+}
+
+/// Returns the `SET` command recreating a key, its expiration given as a Unix time.
+pub(crate) fn set_command(key: &str, value: Bytes, expires_at: Option<SystemTime>) -> Frame {
+    let mut frame = Frame::array();
+    frame.push_bulk(Bytes::from("set".as_bytes()));
+    frame.push_bulk(Bytes::from(key.to_string().into_bytes()));
+    frame.push_bulk(value);
+
+    if let Some(expires_at) = expires_at {
+        let ms = expires_at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        frame.push_bulk(Bytes::from("pxat".as_bytes()));
+        frame.push_int(ms.as_millis() as u64);
+    }
+
+    frame
+}
+
+/// Write the commands recreating the snapshot to a temporary file next to `path`, synced
+/// to disk, annotated with the current Unix time if `timestamps`. Returns the path of the
+/// temporary file.
+pub(crate) fn rewrite(path: &Path, snapshot: &Snapshot, timestamps: bool) -> io::Result<PathBuf> {
+    let temp_path = path.with_file_name(format!("temp-rewriteaof-{}.aof", std::process::id()));
+
+    let result = (|| {
+        let mut file = File::create(&temp_path)?;
+        let mut buf = vec![];
+
+        if timestamps {
+            buf = timestamp_annotation(unix_time());
+        }
+
+        for (index, records) in snapshot.databases.iter().enumerate() {
+            if records.is_empty() {
+                continue;
+            }
+
+            write_command(&mut buf, &Select::new(index as u64).into_frame());
+
+            for record in records {
+                let command = set_command(&record.key, record.value.clone(), record.expires_at);
+                write_command(&mut buf, &command);
+            }
+
+            file.write_all(&buf)?;
+            buf.clear();
+        }
+
+        // Holds the annotation alone if all databases are empty
+        file.write_all(&buf)?;
+
+        file.sync_all()
+    })();
+
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err);
+    }
+
+    Ok(temp_path)
+}
+
+/// Write the commands recreating the snapshot to `path` atomically.
+pub(crate) fn create(path: &Path, snapshot: &Snapshot, timestamps: bool) -> io::Result<()> {
+    let temp_path = rewrite(path, snapshot, timestamps)?;
+
+    fs::rename(&temp_path, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp_path);
+    })
+}
+
+/// Read the commands logged in the append-only file at `path`. Returns `None` if there
+/// is no such file.
+///
+/// A crash while writing leaves an incomplete command at the end of the file, or a
+/// `MULTI` without its `EXEC`: they're left out and the file is truncated before them,
+/// so that the next commands are appended after complete ones. The truncated bytes are
+/// copied to a file next to `path` first.
+///
+/// With a `recover_to` point, the commands logged after it are left out as well: they're
+/// moved to a file next to `path`, and summarized in the returned `Skipped`.
+pub(crate) fn load(
+    path: &Path,
+    recover_to: Option<RecoveryPoint>,
+) -> crate::FnResult<Option<(Vec<Frame>, Option<Skipped>)>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let (entries, complete_len) = parse(&data)?;
+
+    // Offset following entry `i`
+    let end = |i: usize| entries.get(i + 1).map_or(complete_len, |(start, _)| *start);
+
+    // Index of the first entry left out
+    let mut cut = match recover_to {
+        Some(RecoveryPoint::Timestamp(timestamp)) => entries
+            .iter()
+            .position(|(_, entry)| matches!(entry, Entry::Timestamp(ts) if *ts > timestamp)),
+        Some(RecoveryPoint::Offset(offset)) => (0..entries.len()).find(|&i| end(i) > offset),
+        None => None,
+    }
+    .unwrap_or(entries.len());
+
+    // A transaction is kept whole or left out whole
+    let mut multi = None;
+    for (i, (_, entry)) in entries[..cut].iter().enumerate() {
+        if let Entry::Command(command) = entry {
+            match command_name(command).as_deref() {
+                Some("multi") => multi = Some(i),
+                Some("exec") => multi = None,
+                _ => {}
+            }
+        }
+    }
+    if let Some(i) = multi {
+        cut = i;
+    }
+
+    let cut_offset = entries.get(cut).map_or(complete_len, |(start, _)| *start);
+
+    let skipped = match recover_to {
+        Some(_) if cut_offset < complete_len => {
+            let saved_to = save_aside(
+                path,
+                "skipped",
+                &data[cut_offset as usize..complete_len as usize],
+            )?;
+            Some(Skipped::new(
+                &entries,
+                cut,
+                complete_len - cut_offset,
+                saved_to,
+            ))
+        }
+        _ => None,
+    };
+
+    let valid_len = if skipped.is_some() {
+        complete_len
+    } else {
+        cut_offset
+    };
+
+    if valid_len as usize != data.len() {
+        let saved_to = save_aside(path, "truncated", &data[valid_len as usize..])?;
+
+        warn!(
+            path = %path.display(),
+            discarded = data.len() as u64 - valid_len,
+            saved_to = %saved_to.display(),
+            "append-only file ends with an incomplete command, truncating it"
+        );
+    }
+
+    if cut_offset as usize != data.len() {
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(cut_offset)?;
+    }
+
+    let commands = entries
+        .into_iter()
+        .take(cut)
+        .filter_map(|(_, entry)| match entry {
+            Entry::Command(command) => Some(command),
+            Entry::Timestamp(_) => None,
+        })
+        .collect();
+
+    Ok(Some((commands, skipped)))
+}
+
+/// Parse the entries of an append-only file, along with their offset. Also returns the
+/// offset following the last complete entry.
+fn parse(data: &[u8]) -> crate::FnResult<(Vec<(u64, Entry)>, u64)> {
+    let mut entries = vec![];
+    let mut cursor = Cursor::new(data);
+
+    loop {
+        let start = cursor.position();
+        let rest = &data[start as usize..];
+
+        if rest.is_empty() {
+            return Ok((entries, start));
+        }
+
+        // Annotations are lines starting with `#`, ignored by the Redis protocol parser
+        if rest[0] == b'#' {
+            let len = match rest.windows(2).position(|window| window == b"\r\n") {
+                Some(len) => len,
+                None => return Ok((entries, start)),
+            };
+
+            let annotation = String::from_utf8_lossy(&rest[1..len]);
+            if let Some(timestamp) = annotation.strip_prefix("TS:") {
+                let timestamp = timestamp.parse().map_err(|_| {
+                    format!(
+                        "invalid timestamp annotation at offset {}: {}",
+                        start, annotation
+                    )
+                })?;
+                entries.push((start, Entry::Timestamp(timestamp)));
+            }
+
+            cursor.set_position(start + len as u64 + 2);
+            continue;
+        }
+
+        match Frame::check(&mut cursor) {
+            Ok(()) => {}
+            Err(frame::Error::Incomplete) => return Ok((entries, start)),
+            Err(err) => {
+                return Err(format!("invalid append-only file at offset {}: {}", start, err).into())
+            }
+        }
+
+        cursor.set_position(start);
+        entries.push((start, Entry::Command(Frame::parse(&mut cursor)?)));
+    }
+}
+
+impl Skipped {
+    /// Summarize the entries from `cut` on, `bytes` long, moved to `saved_to`.
+    fn new(entries: &[(u64, Entry)], cut: usize, bytes: u64, saved_to: PathBuf) -> Skipped {
+        let mut skipped = Skipped {
+            commands: 0,
+            bytes,
+            first_timestamp: None,
+            last_timestamp: None,
+            by_name: BTreeMap::new(),
+            saved_to,
+        };
+
+        let mut timestamp = None;
+
+        for (i, (_, entry)) in entries.iter().enumerate() {
+            let command = match entry {
+                Entry::Timestamp(ts) => {
+                    timestamp = Some(*ts);
+                    continue;
+                }
+                Entry::Command(command) => command,
+            };
+
+            if i < cut {
+                continue;
+            }
+
+            let name = command_name(command).unwrap_or_default();
+            if matches!(&name[..], "multi" | "exec" | "select") {
+                continue;
+            }
+
+            skipped.commands += 1;
+            *skipped.by_name.entry(name).or_default() += 1;
+            skipped.first_timestamp = skipped.first_timestamp.or(timestamp);
+            skipped.last_timestamp = timestamp;
+        }
+
+        skipped
+    }
+}
+
+impl fmt::Display for Skipped {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "skipped {} writes in {} bytes",
+            self.commands, self.bytes
+        )?;
+
+        if !self.by_name.is_empty() {
+            let by_name: Vec<_> = self
+                .by_name
+                .iter()
+                .map(|(name, count)| format!("{} {}", count, name))
+                .collect();
+            write!(fmt, " ({})", by_name.join(", "))?;
+        }
+
+        if let (Some(first), Some(last)) = (self.first_timestamp, self.last_timestamp) {
+            write!(fmt, " logged from Unix time {} to {}", first, last)?;
+        }
+
+        write!(fmt, ", moved to {}", self.saved_to.display())
+    }
+}
+
+/// Write the part of the append-only file at `path` skipped by a recovery, or truncated, to
+/// a new file next to it named after `reason`, never overwriting a previous one. Returns the
+/// path of the new file.
+fn save_aside(path: &Path, reason: &str, data: &[u8]) -> io::Result<PathBuf> {
+    let base = format!(
+        "{}.{}-{}",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        reason,
+        unix_time()
+    );
+
+    for n in 0.. {
+        let saved_to = match n {
+            0 => path.with_file_name(&base),
+            n => path.with_file_name(format!("{}-{}", base, n)),
+        };
+
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&saved_to)
+        {
+            Ok(mut file) => {
+                file.write_all(data)?;
+                file.sync_all()?;
+                return Ok(saved_to);
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    unreachable!()
+}
+
+/// Returns the annotation of the commands logged at the Unix time `timestamp`.
+fn timestamp_annotation(timestamp: u64) -> Vec<u8> {
+    format!("#TS:{}\r\n", timestamp).into_bytes()
+}
+
+/// Current Unix time in seconds.
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Lowercase name of a logged command.
+fn command_name(command: &Frame) -> Option<String> {
+    match command {
+        Frame::Array(args) => match args.first() {
+            Some(Frame::Bulk(name)) => Some(String::from_utf8_lossy(name).to_lowercase()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Encode a command as a RESP array of bulk strings, as clients send them.
+fn write_command(buf: &mut Vec<u8>, command: &Frame) {
+    let args = match command {
+        Frame::Array(args) => args,
+        _ => unreachable!("commands are array frames"),
+    };
+
+    buf.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+
+    for arg in args {
+        let arg = match arg {
+            Frame::Bulk(data) => data.clone(),
+            Frame::Simple(s) => Bytes::from(s.clone().into_bytes()),
+            Frame::Integer(i) => Bytes::from(i.to_string().into_bytes()),
+            _ => unreachable!("command arguments are bulk or integer frames"),
+        };
+
+        buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        buf.extend_from_slice(&arg);
+        buf.extend_from_slice(b"\r\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Del;
+
+    #[test]
+    fn should_encode_commands_as_bulk_strings() {
+        let mut buf = vec![];
+        write_command(&mut buf, &Select::new(3).into_frame());
+
+        assert_eq!(buf, b"*2\r\n$6\r\nselect\r\n$1\r\n3\r\n");
+    }
+
+    #[test]
+    fn should_truncate_incomplete_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("appendonly.aof");
+
+        let mut data = vec![];
+        write_command(&mut data, &set_command("a", Bytes::from("1"), None));
+        let valid_len = data.len();
+        write_command(&mut data, &Multi::new().into_frame());
+        write_command(&mut data, &set_command("b", Bytes::from("2"), None));
+        data.extend_from_slice(b"*3\r\n$3\r\nset\r\n$1\r\nc");
+        fs::write(&path, &data).unwrap();
+
+        let (commands, skipped) = load(&path, None).unwrap().unwrap();
+
+        assert_eq!(commands.len(), 1);
+        assert!(skipped.is_none());
+        assert_eq!(fs::metadata(&path).unwrap().len(), valid_len as u64);
+
+        // The truncated tail is kept aside
+        let saved: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|saved| saved != &path)
+            .collect();
+        assert_eq!(saved.len(), 1);
+        assert!(saved[0]
+            .to_string_lossy()
+            .contains("appendonly.aof.truncated-"));
+        assert_eq!(fs::read(&saved[0]).unwrap(), &data[valid_len..]);
+    }
+
+    #[test]
+    fn should_recover_to_timestamp() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("appendonly.aof");
+
+        let mut data = timestamp_annotation(100);
+        write_command(&mut data, &set_command("a", Bytes::from("1"), None));
+        data.extend(timestamp_annotation(200));
+        write_command(&mut data, &set_command("b", Bytes::from("2"), None));
+        let kept = data.len();
+        data.extend(timestamp_annotation(300));
+        write_command(&mut data, &Multi::new().into_frame());
+        write_command(&mut data, &set_command("c", Bytes::from("3"), None));
+        write_command(&mut data, &Del::new(&["a".to_string()]).into_frame());
+        write_command(&mut data, &Exec::new().into_frame());
+        data.extend(timestamp_annotation(400));
+        write_command(&mut data, &set_command("d", Bytes::from("4"), None));
+        fs::write(&path, &data).unwrap();
+
+        let (commands, skipped) = load(&path, Some(RecoveryPoint::Timestamp(299)))
+            .unwrap()
+            .unwrap();
+        let skipped = skipped.unwrap();
+
+        assert_eq!(commands.len(), 2);
+        assert_eq!(skipped.commands, 3);
+        assert_eq!(skipped.bytes, (data.len() - kept) as u64);
+        assert_eq!(skipped.first_timestamp, Some(300));
+        assert_eq!(skipped.last_timestamp, Some(400));
+        assert_eq!(skipped.by_name.get("set"), Some(&2));
+        assert_eq!(skipped.by_name.get("del"), Some(&1));
+        assert_eq!(fs::read(&path).unwrap(), &data[..kept]);
+        assert_eq!(fs::read(&skipped.saved_to).unwrap(), &data[kept..]);
+        assert_eq!(
+            skipped.to_string(),
+            format!(
+                "skipped 3 writes in {} bytes (1 del, 2 set) logged from Unix time 300 to 400, moved to {}",
+                data.len() - kept,
+                skipped.saved_to.display()
+            )
+        );
+    }
+
+    #[test]
+    fn should_recover_to_offset_without_splitting_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("appendonly.aof");
+
+        let mut data = vec![];
+        write_command(&mut data, &set_command("a", Bytes::from("1"), None));
+        let kept = data.len();
+        write_command(&mut data, &Multi::new().into_frame());
+        write_command(&mut data, &set_command("b", Bytes::from("2"), None));
+        let offset = data.len();
+        write_command(&mut data, &set_command("c", Bytes::from("3"), None));
+        write_command(&mut data, &Exec::new().into_frame());
+        fs::write(&path, &data).unwrap();
+
+        let (commands, skipped) = load(&path, Some(RecoveryPoint::Offset(offset as u64)))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(commands.len(), 1);
+        assert_eq!(skipped.unwrap().commands, 2);
+        assert_eq!(fs::metadata(&path).unwrap().len(), kept as u64);
+
+        // Nothing to skip past the end of the file
+        let (commands, skipped) = load(&path, Some(RecoveryPoint::Offset(1000)))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(commands.len(), 1);
+        assert!(skipped.is_none());
+    }
+}
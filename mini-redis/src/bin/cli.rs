@@ -2,9 +2,11 @@ use bytes::Bytes;
 use clap::{Parser, Subcommand};
 use core::str;
 use std::num::ParseIntError;
+use std::path::PathBuf;
 use std::time::Duration;
 
 use mini_redis::clients::client::Client;
+use mini_redis::config::TlsClientConfig;
 use mini_redis::constants::DEFAULT_PORT;
 use mini_redis::FnResult;
 
@@ -24,6 +26,38 @@ struct Cli {
 
     #[arg(long, default_value_t = DEFAULT_PORT)]
     port: u16,
+
+    /// Unix socket to connect through, instead of the host and port
+    #[arg(short = 's', long)]
+    socket: Option<PathBuf>,
+
+    /// Connect over TLS, verifying the server certificate against `--cacert`
+    #[arg(long, requires = "cacert")]
+    tls: bool,
+
+    /// CA certificates the server certificate is verified against
+    #[arg(long)]
+    cacert: Option<PathBuf>,
+
+    /// Client certificate to authenticate with, when the server requires one
+    #[arg(long, requires = "key")]
+    cert: Option<PathBuf>,
+
+    /// Private key of the client certificate
+    #[arg(long, requires = "cert")]
+    key: Option<PathBuf>,
+
+    /// Server name to verify the certificate against, instead of the hostname
+    #[arg(long)]
+    sni: Option<String>,
+
+    /// Password to authenticate with, as the `--user` or the `default` user
+    #[arg(short = 'a', long = "pass")]
+    password: Option<String>,
+
+    /// User to authenticate as, with `--pass`
+    #[arg(long, requires = "password")]
+    user: Option<String>,
 }
 
 #[derive(Subcommand, Debug)]
@@ -55,9 +89,25 @@ enum Command {
 async fn main() -> FnResult<()> {
     let cli = Cli::parse();
 
-    let addr = format!("{}:{}", cli.host, cli.port);
+    let mut client = match &cli.socket {
+        #[cfg(unix)]
+        Some(path) => Client::connect_unix(path).await?,
+        _ if cli.tls => {
+            let tls = TlsClientConfig {
+                ca_cert_file: cli.cacert.clone().unwrap_or_default(),
+                cert_file: cli.cert.clone(),
+                key_file: cli.key.clone(),
+                sni: cli.sni.clone(),
+            };
 
-    let mut client = Client::connect(&addr).await?;
+            Client::connect_tls(&cli.host, cli.port, &tls).await?
+        }
+        _ => Client::connect(format!("{}:{}", cli.host, cli.port)).await?,
+    };
+
+    if let Some(password) = &cli.password {
+        client.auth(cli.user.as_deref(), password).await?;
+    }
 
     match cli.command {
         Command::Ping { msg } => {
@@ -1,8 +1,22 @@
 use clap::Parser;
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use tokio::net::TcpListener;
 use tokio::signal;
 
-use mini_redis::{constants::DEFAULT_PORT, server, FnResult};
+use mini_redis::{
+    config::{
+        AppendFsync, ClusterConfig, ClusterNodeConfig, Config, KeyspaceEvents, LagPolicy, LogLevel,
+        MaxMemoryConfig, MaxMemoryPolicy, RecoveryPoint, SaveRule, SlotRange, TlsAuthClients,
+    },
+    constants::DEFAULT_SAVE_RULES,
+    logging,
+    server::{self, ListenSocket},
+    FnResult,
+};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -12,25 +26,449 @@ use mini_redis::{constants::DEFAULT_PORT, server, FnResult};
     about = "Mini redis server"
 )]
 struct Cli {
+    /// Configuration file, in the format of Redis's `redis.conf`, overridden by the flags.
+    /// `CONFIG REWRITE` writes the configuration back to it.
+    config: Option<PathBuf>,
+
+    /// Addresses to listen on, IPv4 or IPv6 (e.g. `--bind 127.0.0.1 ::1`)
+    #[arg(long, num_args = 1..)]
+    bind: Vec<IpAddr>,
+
+    /// Port to listen on, 0 to only listen on the Unix socket
     #[arg(long)]
     port: Option<u16>,
+
+    /// Unix domain socket to also listen on
+    #[arg(long)]
+    unixsocket: Option<PathBuf>,
+
+    /// Permissions of the Unix socket file, as an octal mode (e.g. `770`)
+    #[arg(long)]
+    unixsocketperm: Option<String>,
+
+    /// Port to listen on for TLS connections, 0 for none
+    #[arg(long)]
+    tls_port: Option<u16>,
+
+    /// Certificate chain of the server, in the PEM format
+    #[arg(long)]
+    tls_cert_file: Option<PathBuf>,
+
+    /// Private key of the server certificate, in the PEM format
+    #[arg(long)]
+    tls_key_file: Option<PathBuf>,
+
+    /// CA certificates the client certificates are verified against, in the PEM format
+    #[arg(long)]
+    tls_ca_cert_file: Option<PathBuf>,
+
+    /// Whether TLS clients must present a certificate: yes, no or optional
+    #[arg(long)]
+    tls_auth_clients: Option<TlsAuthClients>,
+
+    /// Maximum number of connected clients
+    #[arg(long)]
+    maxclients: Option<usize>,
+
+    /// Duration in seconds after which an idle client connection is closed, 0 for never
+    #[arg(long)]
+    timeout: Option<u64>,
+
+    /// Password of the `default` user, which connections must authenticate with
+    #[arg(long)]
+    requirepass: Option<String>,
+
+    /// File the users are loaded from, one `user <name> [rule ...]` line each
+    #[arg(long)]
+    aclfile: Option<PathBuf>,
+
+    /// Maximum number of entries of the ACL log
+    #[arg(long)]
+    acllog_max_len: Option<usize>,
+
+    /// User to authenticate as with the leader, when replicating one
+    #[arg(long)]
+    masteruser: Option<String>,
+
+    /// Password to authenticate with to the leader, when replicating one
+    #[arg(long)]
+    masterauth: Option<String>,
+
+    /// Minimum level of the logged messages: debug, verbose, notice or warning
+    #[arg(long)]
+    loglevel: Option<LogLevel>,
+
+    /// File the logs are appended to, the standard output by default
+    #[arg(long)]
+    logfile: Option<PathBuf>,
+
+    /// Number of logical databases, selected with `SELECT`
+    #[arg(long)]
+    databases: Option<usize>,
+
+    /// Number of shards the keys are partitioned into, each locked on its own
+    #[arg(long)]
+    shards: Option<usize>,
+
+    /// Duration in milliseconds after which a running Lua script is aborted
+    #[arg(long)]
+    lua_time_limit: Option<u64>,
+
+    /// Number of messages a pub/sub channel retains for its slowest subscriber
+    #[arg(long)]
+    pubsub_capacity: Option<usize>,
+
+    /// Policy applied to slow subscribers: skip, disconnect or backpressure. Backpressure
+    /// only holds PUBLISH sent outside of MULTI/EXEC and scripts, the other messages are
+    /// skipped
+    #[arg(long)]
+    pubsub_lag_policy: Option<LagPolicy>,
+
+    /// Classes of keyspace events to publish, using Redis's flags (e.g. `KEA`)
+    #[arg(long)]
+    notify_keyspace_events: Option<KeyspaceEvents>,
+
+    /// Directory holding the snapshot and append-only files
+    #[arg(long)]
+    dir: Option<PathBuf>,
+
+    /// Name of the snapshot file
+    #[arg(long)]
+    dbfilename: Option<String>,
+
+    /// Snapshot save rules, as pairs of `<seconds> <changes>`, an empty string disables them
+    #[arg(long)]
+    save: Option<String>,
+
+    /// Log writes to the append-only file, and restore the databases from it on startup
+    #[arg(long)]
+    appendonly: bool,
+
+    /// Name of the append-only file
+    #[arg(long)]
+    appendfilename: Option<String>,
+
+    /// When the append-only file is synced to disk: always, everysec or no
+    #[arg(long)]
+    appendfsync: Option<AppendFsync>,
+
+    /// Annotate the writes logged to the append-only file with the time they were applied at
+    #[arg(long)]
+    aof_timestamp_enabled: bool,
+
+    /// Only replay the append-only file up to the given Unix time, in seconds, moving
+    /// the later writes aside
+    #[arg(long, conflicts_with = "recover_until_offset")]
+    recover_until_timestamp: Option<u64>,
+
+    /// Only replay the append-only file up to the given offset, in bytes, moving the later
+    /// writes aside
+    #[arg(long)]
+    recover_until_offset: Option<u64>,
+
+    /// Size of the replication backlog in bytes, letting replicas resync from where they
+    /// left off after losing their connection
+    #[arg(long)]
+    repl_backlog_size: Option<usize>,
+
+    /// Memory limit of the databases, in bytes or with a unit (e.g. `100mb`), 0 for none
+    #[arg(long)]
+    maxmemory: Option<String>,
+
+    /// Keys evicted once over the memory limit: noeviction, allkeys-lru, allkeys-lfu,
+    /// allkeys-random, volatile-lru, volatile-lfu, volatile-random or volatile-ttl
+    #[arg(long)]
+    maxmemory_policy: Option<MaxMemoryPolicy>,
+
+    /// Number of keys sampled per database to pick the one to evict
+    #[arg(long)]
+    maxmemory_samples: Option<usize>,
+
+    /// Run in cluster mode, sharding the keys across the nodes by hash slot
+    #[arg(long)]
+    cluster_enabled: bool,
+
+    /// Slots served by this node in cluster mode, as comma separated ranges (e.g. `0-5460`)
+    #[arg(long, requires = "cluster_enabled")]
+    cluster_slots: Option<String>,
+
+    /// Another node of the cluster and the slots it serves, as `host:port[@bus_port]=slots`
+    /// (e.g. `127.0.0.1:7001=5461-10922`), repeated for each node
+    #[arg(long = "cluster-node", requires = "cluster_enabled")]
+    cluster_nodes: Vec<ClusterNodeConfig>,
+
+    /// Host announced to clients in cluster mode
+    #[arg(long, requires = "cluster_enabled")]
+    cluster_announce_host: Option<String>,
+
+    /// Port of the cluster bus, on which the nodes exchange their view of the cluster,
+    /// the port plus 10000 by default
+    #[arg(long, requires = "cluster_enabled")]
+    cluster_bus_port: Option<u16>,
+
+    /// Duration in milliseconds after which a node not answering pings is flagged as failing
+    #[arg(long, requires = "cluster_enabled")]
+    cluster_node_timeout: Option<u64>,
 }
 
 #[tokio::main]
 async fn main() -> FnResult<()> {
-    set_up_logging()?;
-
     let cli = Cli::parse();
-    let port = cli.port.unwrap_or(DEFAULT_PORT);
 
-    // Bind a TCP listener
-    let listener = TcpListener::bind(&format!("127.0.0.1:{}", port)).await?;
+    let mut config = Config::default();
+    config.rdb.save = SaveRule::parse_rules(DEFAULT_SAVE_RULES)?;
+
+    if let Some(path) = &cli.config {
+        config.load_file(path)?;
+    }
+
+    if !cli.bind.is_empty() {
+        config.bind = cli.bind;
+    }
+
+    if let Some(port) = cli.port {
+        config.port = port;
+    }
+
+    if let Some(unixsocket) = cli.unixsocket {
+        config.unixsocket = Some(unixsocket);
+    }
+
+    if let Some(unixsocketperm) = cli.unixsocketperm {
+        config.set("unixsocketperm", &unixsocketperm)?;
+    }
+
+    if let Some(tls_port) = cli.tls_port {
+        config.tls.port = tls_port;
+    }
+
+    if let Some(cert_file) = cli.tls_cert_file {
+        config.tls.cert_file = Some(cert_file);
+    }
+
+    if let Some(key_file) = cli.tls_key_file {
+        config.tls.key_file = Some(key_file);
+    }
+
+    if let Some(ca_cert_file) = cli.tls_ca_cert_file {
+        config.tls.ca_cert_file = Some(ca_cert_file);
+    }
+
+    if let Some(auth_clients) = cli.tls_auth_clients {
+        config.tls.auth_clients = auth_clients;
+    }
+
+    if let Some(maxclients) = cli.maxclients {
+        if maxclients == 0 {
+            return Err("--maxclients must be greater than 0".into());
+        }
+
+        config.maxclients = maxclients;
+    }
+
+    if let Some(timeout) = cli.timeout {
+        config.timeout = Duration::from_secs(timeout);
+    }
+
+    if let Some(requirepass) = cli.requirepass {
+        config.set("requirepass", &requirepass)?;
+    }
+
+    if let Some(aclfile) = cli.aclfile {
+        config.aclfile = Some(aclfile);
+    }
+
+    if let Some(acllog_max_len) = cli.acllog_max_len {
+        config.acllog_max_len = acllog_max_len;
+    }
+
+    if let Some(masteruser) = cli.masteruser {
+        config.set("masteruser", &masteruser)?;
+    }
+
+    if let Some(masterauth) = cli.masterauth {
+        config.set("masterauth", &masterauth)?;
+    }
+
+    if let Some(loglevel) = cli.loglevel {
+        config.loglevel = loglevel;
+    }
+
+    if let Some(logfile) = cli.logfile {
+        config.logfile = Some(logfile);
+    }
+
+    if let Some(databases) = cli.databases {
+        if databases == 0 {
+            return Err("--databases must be greater than 0".into());
+        }
+
+        config.databases = databases;
+    }
+
+    if let Some(shards) = cli.shards {
+        if shards == 0 {
+            return Err("--shards must be greater than 0".into());
+        }
+
+        config.shards = shards;
+    }
+
+    if let Some(lua_time_limit) = cli.lua_time_limit {
+        config.lua_time_limit = Duration::from_millis(lua_time_limit);
+    }
+
+    if let Some(capacity) = cli.pubsub_capacity {
+        if capacity == 0 {
+            return Err("--pubsub-capacity must be greater than 0".into());
+        }
 
-    server::run(listener, signal::ctrl_c()).await;
+        config.pub_sub.capacity = capacity;
+    }
+
+    if let Some(lag_policy) = cli.pubsub_lag_policy {
+        config.pub_sub.lag_policy = lag_policy;
+    }
+
+    if let Some(notify_keyspace_events) = cli.notify_keyspace_events {
+        config.notify_keyspace_events = notify_keyspace_events;
+    }
+
+    if let Some(dir) = cli.dir {
+        config.dir = dir;
+    }
+
+    if let Some(dbfilename) = cli.dbfilename {
+        config.rdb.dbfilename = dbfilename;
+    }
+
+    if let Some(save) = cli.save {
+        config.rdb.save = SaveRule::parse_rules(&save)?;
+    }
+
+    if cli.appendonly {
+        config.aof.enabled = true;
+    }
+
+    if let Some(appendfilename) = cli.appendfilename {
+        config.aof.filename = appendfilename;
+    }
+
+    if let Some(appendfsync) = cli.appendfsync {
+        config.aof.fsync = appendfsync;
+    }
+
+    if cli.aof_timestamp_enabled {
+        config.aof.timestamps = true;
+    }
+
+    config.aof.recover_to = match (cli.recover_until_timestamp, cli.recover_until_offset) {
+        (Some(timestamp), _) => Some(RecoveryPoint::Timestamp(timestamp)),
+        (_, Some(offset)) => Some(RecoveryPoint::Offset(offset)),
+        (None, None) => None,
+    };
+
+    if config.aof.recover_to.is_some() && !config.aof.enabled {
+        return Err("point-in-time recovery requires appendonly".into());
+    }
+
+    if let Some(repl_backlog_size) = cli.repl_backlog_size {
+        config.repl_backlog_size = repl_backlog_size;
+    }
+
+    if let Some(maxmemory) = cli.maxmemory {
+        config.maxmemory.limit = MaxMemoryConfig::parse_size(&maxmemory)?;
+    }
+
+    if let Some(policy) = cli.maxmemory_policy {
+        config.maxmemory.policy = policy;
+    }
+
+    if let Some(samples) = cli.maxmemory_samples {
+        if samples == 0 {
+            return Err("--maxmemory-samples must be greater than 0".into());
+        }
+
+        config.maxmemory.samples = samples;
+    }
+
+    if cli.cluster_enabled {
+        let mut cluster = ClusterConfig {
+            slots: SlotRange::parse_ranges(cli.cluster_slots.as_deref().unwrap_or_default())?,
+            nodes: cli.cluster_nodes,
+            bus_port: cli.cluster_bus_port,
+            ..Default::default()
+        };
+
+        if let Some(node_timeout) = cli.cluster_node_timeout {
+            if node_timeout == 0 {
+                return Err("--cluster-node-timeout must be greater than 0".into());
+            }
+
+            cluster.node_timeout = Duration::from_millis(node_timeout);
+        }
+
+        if let Some(announce_host) = cli.cluster_announce_host {
+            cluster.announce_host = announce_host;
+        }
+
+        config.cluster = Some(cluster);
+    }
+
+    logging::init(&config)?;
+
+    // Bind a TCP listener per address, unless the port is 0, the same for the TLS port,
+    // and the Unix socket
+    let mut listeners: Vec<ListenSocket> = vec![];
+
+    if config.port != 0 {
+        for addr in &config.bind {
+            let listener = TcpListener::bind((*addr, config.port))
+                .await
+                .map_err(|err| format!("failed to bind {}:{}: {}", addr, config.port, err))?;
+
+            listeners.push(listener.into());
+        }
+    }
+
+    if config.tls.port != 0 {
+        for addr in &config.bind {
+            let listener = TcpListener::bind((*addr, config.tls.port))
+                .await
+                .map_err(|err| format!("failed to bind {}:{}: {}", addr, config.tls.port, err))?;
+
+            listeners.push(ListenSocket::Tls(listener));
+        }
+    }
+
+    let unixsocket = config.unixsocket.clone();
+
+    if let Some(path) = &unixsocket {
+        listeners.push(bind_unix_socket(path, config.unixsocketperm)?);
+    }
+
+    if listeners.is_empty() {
+        return Err("nothing to listen on, set a port, a tls-port or a unixsocket".into());
+    }
+
+    server::run_with_listeners(listeners, config, signal::ctrl_c()).await;
+
+    if let Some(path) = &unixsocket {
+        let _ = std::fs::remove_file(path);
+    }
 
     Ok(())
 }
 
-fn set_up_logging() -> FnResult<()> {
-    tracing_subscriber::fmt::try_init()
+#[cfg(unix)]
+fn bind_unix_socket(path: &Path, mode: u32) -> FnResult<ListenSocket> {
+    match server::bind_unix_socket(path, mode) {
+        Ok(listener) => Ok(listener.into()),
+        Err(err) => Err(format!("failed to bind {}: {}", path.display(), err).into()),
+    }
+}
+
+#[cfg(not(unix))]
+fn bind_unix_socket(_path: &Path, _mode: u32) -> FnResult<ListenSocket> {
+    Err("Unix sockets are not supported on this platform".into())
 }
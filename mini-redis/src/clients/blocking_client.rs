@@ -1,8 +1,15 @@
 use bytes::Bytes;
+#[cfg(unix)]
+use std::path::Path;
 use std::time::Duration;
 use tokio::{net::ToSocketAddrs, runtime::Runtime};
 
-pub use crate::clients::client::Message;
+pub use crate::clients::client::{
+    Message, MigrateOptions, ReplicaRole, Reply, ServerRole, SlotRange,
+};
+
+use crate::commands::SetSlot;
+use crate::config::TlsClientConfig;
 
 pub struct BlockingClient {
     // The asynchronous `Client`
@@ -42,6 +49,35 @@ impl BlockingClient {
         Ok(BlockingClient { inner, runtime })
     }
 
+    /// Establish a TLS connection with a Redis server listening on the TLS port of `host`.
+    pub fn connect_tls(
+        host: &str,
+        port: u16,
+        config: &TlsClientConfig,
+    ) -> crate::FnResult<BlockingClient> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let inner = runtime.block_on(crate::clients::client::Client::connect_tls(
+            host, port, config,
+        ))?;
+
+        Ok(BlockingClient { inner, runtime })
+    }
+
+    /// Establish connection with a Redis server listening on the Unix socket at `path`.
+    #[cfg(unix)]
+    pub fn connect_unix(path: impl AsRef<Path>) -> crate::FnResult<BlockingClient> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let inner = runtime.block_on(crate::clients::client::Client::connect_unix(path))?;
+
+        Ok(BlockingClient { inner, runtime })
+    }
+
     /// Get the value of a key.
     pub fn get(&mut self, key: &str) -> crate::FnResult<Option<Bytes>> {
         self.runtime.block_on(self.inner.get(key))
@@ -63,6 +99,260 @@ impl BlockingClient {
             .block_on(self.inner.set_expires(key, value, expires))
     }
 
+    /// Remove the given keys. Returns the number of keys that were removed.
+    pub fn del(&mut self, keys: &[String]) -> crate::FnResult<u64> {
+        self.runtime.block_on(self.inner.del(keys))
+    }
+
+    /// Serialize the value of a key, to recreate it with `restore`. Returns `None` if the
+    /// key doesn't exist.
+    pub fn dump(&mut self, key: &str) -> crate::FnResult<Option<Bytes>> {
+        self.runtime.block_on(self.inner.dump(key))
+    }
+
+    /// Create a key from a payload returned by `dump`, expiring after `ttl` if set.
+    ///
+    /// Fails if the key already exists, unless `replace` is set.
+    pub fn restore(
+        &mut self,
+        key: &str,
+        ttl: Option<Duration>,
+        payload: Bytes,
+        replace: bool,
+    ) -> crate::FnResult<()> {
+        self.runtime
+            .block_on(self.inner.restore(key, ttl, payload, replace))
+    }
+
+    /// Atomically move `keys` to the database `db` of the server at `host:port`, waiting
+    /// up to `timeout` for it. Returns `false` if none of the keys exists.
+    pub fn migrate(
+        &mut self,
+        host: &str,
+        port: u16,
+        keys: &[String],
+        db: u64,
+        timeout: Duration,
+        options: MigrateOptions,
+    ) -> crate::FnResult<bool> {
+        self.runtime
+            .block_on(self.inner.migrate(host, port, keys, db, timeout, options))
+    }
+
+    /// Select the database subsequent commands operate on.
+    pub fn select(&mut self, index: u64) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.select(index))
+    }
+
+    /// Swap the content of two databases.
+    pub fn swapdb(&mut self, index1: u64, index2: u64) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.swapdb(index1, index2))
+    }
+
+    /// Move `key` from the selected database to the database at `index`.
+    pub fn move_key(&mut self, key: &str, index: u64) -> crate::FnResult<bool> {
+        self.runtime.block_on(self.inner.move_key(key, index))
+    }
+
+    /// Remove all keys from the selected database.
+    pub fn flushdb(&mut self) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.flushdb())
+    }
+
+    /// Remove all keys from all databases.
+    pub fn flushall(&mut self) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.flushall())
+    }
+
+    /// Returns the number of keys in the selected database.
+    pub fn dbsize(&mut self) -> crate::FnResult<u64> {
+        self.runtime.block_on(self.inner.dbsize())
+    }
+
+    /// Watch the given keys: the next transaction is not applied if any of them is modified before.
+    pub fn watch(&mut self, keys: &[String]) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.watch(keys))
+    }
+
+    /// Forget about all the watched keys.
+    pub fn unwatch(&mut self) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.unwatch())
+    }
+
+    /// Run a Lua script on the server, with the given keys and arguments.
+    pub fn eval(
+        &mut self,
+        script: &str,
+        keys: &[String],
+        args: &[Bytes],
+    ) -> crate::FnResult<Reply> {
+        self.runtime.block_on(self.inner.eval(script, keys, args))
+    }
+
+    /// Run a Lua script cached by the server, given its SHA1 digest.
+    pub fn evalsha(
+        &mut self,
+        sha1: &str,
+        keys: &[String],
+        args: &[Bytes],
+    ) -> crate::FnResult<Reply> {
+        self.runtime.block_on(self.inner.evalsha(sha1, keys, args))
+    }
+
+    /// Cache a Lua script on the server without running it, returning its SHA1 digest.
+    pub fn script_load(&mut self, script: &str) -> crate::FnResult<String> {
+        self.runtime.block_on(self.inner.script_load(script))
+    }
+
+    /// Returns whether each of the scripts, given by their SHA1 digest, is cached by the server.
+    pub fn script_exists(&mut self, sha1s: &[String]) -> crate::FnResult<Vec<bool>> {
+        self.runtime.block_on(self.inner.script_exists(sha1s))
+    }
+
+    /// Remove all the scripts cached by the server.
+    pub fn script_flush(&mut self) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.script_flush())
+    }
+
+    /// Abort the script being run by the server, as long as it didn't write yet.
+    pub fn script_kill(&mut self) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.script_kill())
+    }
+
+    /// Save a snapshot of all databases on the server, returning once it's written.
+    pub fn save(&mut self) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.save())
+    }
+
+    /// Start saving a snapshot of all databases in the background.
+    pub fn bgsave(&mut self) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.bgsave())
+    }
+
+    /// Returns the Unix time, in seconds, of the last successful snapshot.
+    pub fn lastsave(&mut self) -> crate::FnResult<u64> {
+        self.runtime.block_on(self.inner.lastsave())
+    }
+
+    /// Start rewriting the append-only file in the background.
+    pub fn bgrewriteaof(&mut self) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.bgrewriteaof())
+    }
+
+    /// Make the server a replica of the leader at `host:port`.
+    pub fn replicaof(&mut self, host: &str, port: u16) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.replicaof(host, port))
+    }
+
+    /// Stop the replication, making the server a leader again.
+    pub fn replicaof_no_one(&mut self) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.replicaof_no_one())
+    }
+
+    /// Hand the leadership over to the replica at `target`, or to the most up to date
+    /// replica if `None`, making the server its replica. The failover is aborted if the
+    /// replica doesn't catch up within `timeout`, if set.
+    pub fn failover(
+        &mut self,
+        target: Option<(&str, u16)>,
+        timeout: Option<Duration>,
+    ) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.failover(target, timeout))
+    }
+
+    /// Returns the replication role of the server.
+    pub fn role(&mut self) -> crate::FnResult<ServerRole> {
+        self.runtime.block_on(self.inner.role())
+    }
+
+    /// Wait until `num_replicas` replicas acknowledged all the writes applied so far, or
+    /// `timeout` elapsed if set. Returns the number of replicas which acknowledged them.
+    pub fn wait(&mut self, num_replicas: u64, timeout: Option<Duration>) -> crate::FnResult<u64> {
+        self.runtime
+            .block_on(self.inner.wait(num_replicas, timeout))
+    }
+
+    /// Returns information about the server, restricted to `section` if set.
+    pub fn info(&mut self, section: Option<&str>) -> crate::FnResult<String> {
+        self.runtime.block_on(self.inner.info(section))
+    }
+
+    /// Authenticate the connection as `username`, or as the `default` user if `None`.
+    pub fn auth(&mut self, username: Option<&str>, password: &str) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.auth(username, password))
+    }
+
+    /// Returns the ranges of hash slots served by each node of the cluster.
+    pub fn cluster_slots(&mut self) -> crate::FnResult<Vec<SlotRange>> {
+        self.runtime.block_on(self.inner.cluster_slots())
+    }
+
+    /// Returns the hash slot of `key`.
+    pub fn cluster_keyslot(&mut self, key: &str) -> crate::FnResult<u16> {
+        self.runtime.block_on(self.inner.cluster_keyslot(key))
+    }
+
+    /// Returns the number of keys of the node in `slot`.
+    pub fn cluster_countkeysinslot(&mut self, slot: u16) -> crate::FnResult<u64> {
+        self.runtime
+            .block_on(self.inner.cluster_countkeysinslot(slot))
+    }
+
+    /// Returns up to `count` keys of the node in `slot`.
+    pub fn cluster_getkeysinslot(&mut self, slot: u16, count: u64) -> crate::FnResult<Vec<String>> {
+        self.runtime
+            .block_on(self.inner.cluster_getkeysinslot(slot, count))
+    }
+
+    /// Returns the id of the node.
+    pub fn cluster_myid(&mut self) -> crate::FnResult<String> {
+        self.runtime.block_on(self.inner.cluster_myid())
+    }
+
+    /// Returns the nodes known by the node, a line each, as `CLUSTER NODES`.
+    pub fn cluster_nodes(&mut self) -> crate::FnResult<String> {
+        self.runtime.block_on(self.inner.cluster_nodes())
+    }
+
+    /// Returns the state of the cluster as seen by the node, as `CLUSTER INFO`.
+    pub fn cluster_info(&mut self) -> crate::FnResult<String> {
+        self.runtime.block_on(self.inner.cluster_info())
+    }
+
+    /// Introduce the node to the node listening on `host:port`, whose cluster bus listens
+    /// on `bus_port`, or the port plus 10000 if `None`.
+    pub fn cluster_meet(
+        &mut self,
+        host: &str,
+        port: u16,
+        bus_port: Option<u16>,
+    ) -> crate::FnResult<()> {
+        self.runtime
+            .block_on(self.inner.cluster_meet(host, port, bus_port))
+    }
+
+    /// Assign unassigned `slots` to the node.
+    pub fn cluster_addslots(&mut self, slots: &[u16]) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.cluster_addslots(slots))
+    }
+
+    /// Remove the node `id` from the nodes known by the node.
+    pub fn cluster_forget(&mut self, id: &str) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.cluster_forget(id))
+    }
+
+    /// Set the migration state of `slot`, or assign it to a node.
+    pub fn cluster_setslot(&mut self, slot: u16, state: SetSlot) -> crate::FnResult<()> {
+        self.runtime
+            .block_on(self.inner.cluster_setslot(slot, state))
+    }
+
+    /// Let the next command access a slot being imported by the node, after an `ASK`
+    /// redirect.
+    pub fn asking(&mut self) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.asking())
+    }
+
     /// Post `message` to the given `channel`.
     pub fn publish(&mut self, channel: &str, message: Bytes) -> crate::FnResult<u64> {
         self.runtime.block_on(self.inner.publish(channel, message))
@@ -77,6 +367,16 @@ impl BlockingClient {
             runtime: self.runtime,
         })
     }
+
+    /// Subscribe to the channels matching the specified glob-style patterns.
+    pub fn psubscribe(self, patterns: Vec<String>) -> crate::FnResult<BlockingSubscriber> {
+        let subscriber = self.runtime.block_on(self.inner.psubscribe(patterns))?;
+
+        Ok(BlockingSubscriber {
+            inner: subscriber,
+            runtime: self.runtime,
+        })
+    }
 }
 
 impl BlockingSubscriber {
@@ -85,6 +385,11 @@ impl BlockingSubscriber {
         self.inner.get_subscribed()
     }
 
+    /// Get the number of messages the server dropped because this subscriber lagged behind.
+    pub fn get_dropped(&self) -> u64 {
+        self.inner.get_dropped()
+    }
+
     /// Receive next message published on a subscribed channel, waiting if necessary.
     ///
     /// `None` indicates that the subscription has been terminated.
@@ -94,6 +399,7 @@ impl BlockingSubscriber {
 
     /// Convert the subscriber into an `Iterator` yielding new messages published
     /// on subscribed channels.
+    #[allow(clippy::should_implement_trait)]
     pub fn into_iter(self) -> impl Iterator<Item = crate::FnResult<Message>> {
         SubscriberIterator {
             inner: self.inner,
@@ -110,6 +416,31 @@ impl BlockingSubscriber {
     pub fn unsubscribe(&mut self, channels: &[String]) -> crate::FnResult<()> {
         self.runtime.block_on(self.inner.unsubscribe(channels))
     }
+
+    /// Subscribe to a list of new patterns
+    pub fn psubscribe(&mut self, patterns: &[String]) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.psubscribe(patterns))
+    }
+
+    /// Unsubscribe to a list of patterns
+    pub fn punsubscribe(&mut self, patterns: &[String]) -> crate::FnResult<()> {
+        self.runtime.block_on(self.inner.punsubscribe(patterns))
+    }
+
+    /// Ping the server while in subscribed mode.
+    pub fn ping(&mut self, msg: Option<Bytes>) -> crate::FnResult<Bytes> {
+        self.runtime.block_on(self.inner.ping(msg))
+    }
+
+    /// Leave subscribed mode, returning the client.
+    pub fn reset(self) -> crate::FnResult<BlockingClient> {
+        let inner = self.runtime.block_on(self.inner.reset())?;
+
+        Ok(BlockingClient {
+            inner,
+            runtime: self.runtime,
+        })
+    }
 }
 
 impl Iterator for SubscriberIterator {
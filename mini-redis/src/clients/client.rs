@@ -1,17 +1,30 @@
 use async_stream::try_stream;
 use bytes::Bytes;
+#[cfg(unix)]
+use std::path::Path;
 use std::{
+    collections::VecDeque,
     io::{Error, ErrorKind},
     time::Duration,
 };
+#[cfg(unix)]
+use tokio::net::UnixStream;
 use tokio::net::{TcpStream, ToSocketAddrs};
 use tokio_stream::Stream;
 use tracing::debug;
 
 use crate::{
-    commands::{Get, Ping, Publish, Set, Subscribe, Unsubscribe},
+    commands::{
+        Acl, Asking, Auth, BgRewriteAof, BgSave, Cluster, Config, DbSize, Del, Dump, Eval, EvalSha,
+        Exec, Failover, FlushAll, FlushDb, Get, Info, LastSave, Migrate, Move, Multi, Object,
+        PSubscribe, PUnsubscribe, Ping, PubSub, Publish, Quit, ReplicaOf, Reset, Restore, Role,
+        Save, Script, Select, Set, SetSlot, Shutdown, Subscribe, SwapDb, Unsubscribe, Unwatch,
+        Wait, Watch,
+    },
+    config::TlsClientConfig,
     connection::Connection,
     frame::Frame,
+    scripting, tls,
 };
 
 /// Establish connection with a Redis server.
@@ -24,12 +37,94 @@ pub struct Subscriber {
     // Subscribed client
     client: Client,
     subscribed_channels: Vec<String>,
+    subscribed_patterns: Vec<String>,
+
+    // Messages received while waiting for a command reply, delivered by `next_message`
+    pending: VecDeque<Message>,
+
+    // Number of messages the server reported as missed because we lagged behind
+    dropped: u64,
 }
 
 #[derive(Clone, Debug)]
 pub struct Message {
     pub channel: String,
     pub content: Bytes,
+
+    // Pattern the channel matched, for messages received thru `psubscribe`
+    pub pattern: Option<String>,
+}
+
+/// Commands buffered by `Client::transaction`, sent at once between `MULTI` and `EXEC`.
+pub struct Transaction<'a> {
+    client: &'a mut Client,
+    commands: Vec<Frame>,
+}
+
+/// Reply to a command applied by a transaction, or returned by a script.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reply {
+    Null,
+    Simple(String),
+    Integer(i64),
+    Bulk(Bytes),
+    Array(Vec<Reply>),
+    Error(String),
+}
+
+/// Replication role of a server, returned by `Client::role`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServerRole {
+    /// A leader, streaming its writes to the replicas
+    Leader {
+        /// Offset of the write stream
+        offset: u64,
+        replicas: Vec<ReplicaRole>,
+    },
+
+    /// A replica of the leader at `host:port`
+    Replica {
+        host: String,
+        port: u16,
+
+        /// State of the link to the leader: `connect`, `connecting`, `sync` or `connected`
+        state: String,
+
+        /// Offset of the write stream applied by the replica
+        offset: u64,
+    },
+}
+
+/// A replica connected to a leader, as listed by `Client::role`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplicaRole {
+    pub ip: String,
+    pub port: u16,
+
+    /// Offset acknowledged by the replica
+    pub offset: u64,
+}
+
+/// Range of hash slots served by a cluster node, as listed by `Client::cluster_slots`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlotRange {
+    pub start: u16,
+    pub end: u16,
+    pub host: String,
+    pub port: u16,
+
+    /// Id of the node
+    pub id: String,
+}
+
+/// Options of `Client::migrate`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MigrateOptions {
+    /// Keep the keys on the server they're migrated from
+    pub copy: bool,
+
+    /// Overwrite the keys existing on the server they're migrated to
+    pub replace: bool,
 }
 
 impl Client {
@@ -38,63 +133,779 @@ impl Client {
         let socket = TcpStream::connect(addr).await?;
         let connection = Connection::new(socket);
 
-        Ok(Client { connection })
+        Ok(Client { connection })
+    }
+
+    /// Establish a TLS connection with a Redis server listening on the TLS port of `host`.
+    pub async fn connect_tls(
+        host: &str,
+        port: u16,
+        config: &TlsClientConfig,
+    ) -> crate::FnResult<Client> {
+        let socket =
+            TcpStream::connect((host.trim_start_matches('[').trim_end_matches(']'), port)).await?;
+        let connection = Connection::new(tls::connect(socket, host, config).await?);
+
+        Ok(Client { connection })
+    }
+
+    /// Establish connection with a Redis server listening on the Unix socket at `path`.
+    #[cfg(unix)]
+    pub async fn connect_unix(path: impl AsRef<Path>) -> crate::FnResult<Client> {
+        let socket = UnixStream::connect(path).await?;
+        let connection = Connection::new(socket);
+
+        Ok(Client { connection })
+    }
+
+    /// Ping to the server.
+    pub async fn ping(&mut self, msg: Option<Bytes>) -> crate::FnResult<Bytes> {
+        let frame = Ping::new(msg).into_frame();
+        debug!(request = ?frame);
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(value) => Ok(value.into()),
+            Frame::Bulk(value) => Ok(value),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Get value of a key
+    pub async fn get(&mut self, key: &str) -> crate::FnResult<Option<Bytes>> {
+        let frame = Get::new(key).into_frame();
+        debug!(request = ?frame);
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(value) => Ok(Some(value.into())),
+            Frame::Bulk(value) => Ok(Some(value)),
+            Frame::Null => Ok(None),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Set value of a key.
+    pub async fn set(&mut self, key: &str, value: Bytes) -> crate::FnResult<()> {
+        self.set_cmd(Set::new(key, value, None)).await
+    }
+
+    /// Set value of a key with an expiration time.
+    pub async fn set_expires(
+        &mut self,
+        key: &str,
+        value: Bytes,
+        expires: Duration,
+    ) -> crate::FnResult<()> {
+        self.set_cmd(Set::new(key, value, Some(expires))).await
+    }
+
+    async fn set_cmd(&mut self, cmd: Set) -> crate::FnResult<()> {
+        let frame = cmd.into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Remove the given keys. Returns the number of keys that were removed.
+    pub async fn del(&mut self, keys: &[String]) -> crate::FnResult<u64> {
+        let frame = Del::new(keys).into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(removed) => Ok(removed as u64),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Serialize the value of a key, to recreate it with `restore`. Returns `None` if the
+    /// key doesn't exist.
+    pub async fn dump(&mut self, key: &str) -> crate::FnResult<Option<Bytes>> {
+        let frame = Dump::new(key).into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Bulk(payload) => Ok(Some(payload)),
+            Frame::Null => Ok(None),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the seconds elapsed since `key` was last accessed, or `None` if it doesn't
+    /// exist.
+    pub async fn object_idletime(&mut self, key: &str) -> crate::FnResult<Option<u64>> {
+        let frame = Object::IdleTime(key.to_string()).into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(idle) => Ok(Some(idle as u64)),
+            Frame::Null => Ok(None),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Create a key from a payload returned by `dump`, expiring after `ttl` if set.
+    ///
+    /// Fails if the key already exists, unless `replace` is set.
+    pub async fn restore(
+        &mut self,
+        key: &str,
+        ttl: Option<Duration>,
+        payload: Bytes,
+        replace: bool,
+    ) -> crate::FnResult<()> {
+        self.ok_cmd(Restore::new(key, ttl, payload, replace).into_frame())
+            .await
+    }
+
+    /// Atomically move `keys` to the database `db` of the server at `host:port`, waiting
+    /// up to `timeout` for it. Returns `false` if none of the keys exists.
+    pub async fn migrate(
+        &mut self,
+        host: &str,
+        port: u16,
+        keys: &[String],
+        db: u64,
+        timeout: Duration,
+        options: MigrateOptions,
+    ) -> crate::FnResult<bool> {
+        let frame = Migrate::new(host, port, keys, db, timeout)
+            .copy(options.copy)
+            .replace(options.replace)
+            .into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(true),
+            Frame::Simple(response) if response == "NOKEY" => Ok(false),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Select the database subsequent commands operate on.
+    pub async fn select(&mut self, index: u64) -> crate::FnResult<()> {
+        self.ok_cmd(Select::new(index).into_frame()).await
+    }
+
+    /// Swap the content of two databases.
+    pub async fn swapdb(&mut self, index1: u64, index2: u64) -> crate::FnResult<()> {
+        self.ok_cmd(SwapDb::new(index1, index2).into_frame()).await
+    }
+
+    /// Move `key` from the selected database to the database at `index`.
+    ///
+    /// Returns `false` if the key wasn't moved, because it doesn't exist or already exists in the target database.
+    pub async fn move_key(&mut self, key: &str, index: u64) -> crate::FnResult<bool> {
+        let frame = Move::new(key, index).into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(moved) => Ok(moved == 1),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Remove all keys from the selected database.
+    pub async fn flushdb(&mut self) -> crate::FnResult<()> {
+        self.ok_cmd(FlushDb::new().into_frame()).await
+    }
+
+    /// Remove all keys from all databases.
+    pub async fn flushall(&mut self) -> crate::FnResult<()> {
+        self.ok_cmd(FlushAll::new().into_frame()).await
+    }
+
+    /// Returns the number of keys in the selected database.
+    pub async fn dbsize(&mut self) -> crate::FnResult<u64> {
+        let frame = DbSize::new().into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(size) => Ok(size as u64),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Send a command expecting a simple `OK` reply.
+    async fn ok_cmd(&mut self, frame: Frame) -> crate::FnResult<()> {
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Send a command, returning its reply frame.
+    pub(crate) async fn request(&mut self, frame: &Frame) -> crate::FnResult<Frame> {
+        debug!(request = ?frame);
+
+        self.connection.write_frame(frame).await?;
+        self.read_response().await
+    }
+
+    /// Watch the given keys: the next transaction is not applied if any of them is modified before.
+    pub async fn watch(&mut self, keys: &[String]) -> crate::FnResult<()> {
+        self.ok_cmd(Watch::new(keys).into_frame()).await
+    }
+
+    /// Forget about all the watched keys.
+    pub async fn unwatch(&mut self) -> crate::FnResult<()> {
+        self.ok_cmd(Unwatch::new().into_frame()).await
+    }
+
+    /// Start building a transaction. Its commands are applied atomically by `Transaction::exec`.
+    pub fn transaction(&mut self) -> Transaction<'_> {
+        Transaction {
+            client: self,
+            commands: vec![],
+        }
+    }
+
+    /// Run a Lua script on the server, with the given keys and arguments.
+    ///
+    /// The script is first run by its SHA1 digest, so that it's only sent when it isn't
+    /// cached by the server yet.
+    pub async fn eval(
+        &mut self,
+        script: &str,
+        keys: &[String],
+        args: &[Bytes],
+    ) -> crate::FnResult<Reply> {
+        match self.evalsha(&scripting::sha1_hex(script), keys, args).await {
+            Err(err) if err.to_string().starts_with("NOSCRIPT") => {
+                let frame = Eval::new(script, keys.to_vec(), args.to_vec()).into_frame();
+                self.script_cmd(frame).await
+            }
+            reply => reply,
+        }
+    }
+
+    /// Run a Lua script cached by the server, given its SHA1 digest.
+    ///
+    /// Fails with a `NOSCRIPT` error if the server doesn't know the script.
+    pub async fn evalsha(
+        &mut self,
+        sha1: &str,
+        keys: &[String],
+        args: &[Bytes],
+    ) -> crate::FnResult<Reply> {
+        let frame = EvalSha::new(sha1, keys.to_vec(), args.to_vec()).into_frame();
+        self.script_cmd(frame).await
+    }
+
+    /// Send a script command, returning the script's reply.
+    async fn script_cmd(&mut self, frame: Frame) -> crate::FnResult<Reply> {
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        Ok(Reply::from(self.read_response().await?))
+    }
+
+    /// Cache a Lua script on the server without running it, returning its SHA1 digest.
+    pub async fn script_load(&mut self, script: &str) -> crate::FnResult<String> {
+        let frame = Script::Load(script.to_string()).into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Bulk(sha1) => Ok(String::from_utf8(sha1.to_vec())?),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns whether each of the scripts, given by their SHA1 digest, is cached by the server.
+    pub async fn script_exists(&mut self, sha1s: &[String]) -> crate::FnResult<Vec<bool>> {
+        let frame = Script::Exists(sha1s.to_vec()).into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Array(frames) => frames
+                .into_iter()
+                .map(|frame| match frame {
+                    Frame::Integer(exists) => Ok(exists == 1),
+                    frame => Err(frame.to_error()),
+                })
+                .collect(),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Remove all the scripts cached by the server.
+    pub async fn script_flush(&mut self) -> crate::FnResult<()> {
+        self.ok_cmd(Script::Flush.into_frame()).await
+    }
+
+    /// Abort the script being run by the server, as long as it didn't write yet.
+    pub async fn script_kill(&mut self) -> crate::FnResult<()> {
+        self.ok_cmd(Script::Kill.into_frame()).await
+    }
+
+    /// Save a snapshot of all databases on the server, returning once it's written.
+    pub async fn save(&mut self) -> crate::FnResult<()> {
+        self.ok_cmd(Save::new().into_frame()).await
+    }
+
+    /// Start saving a snapshot of all databases in the background.
+    pub async fn bgsave(&mut self) -> crate::FnResult<()> {
+        let frame = BgSave::new().into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "Background saving started" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the Unix time, in seconds, of the last successful snapshot.
+    pub async fn lastsave(&mut self) -> crate::FnResult<u64> {
+        let frame = LastSave::new().into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(response) => Ok(response as u64),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Stop the server. `save` overrides whether it saves the databases on the way out:
+    /// `Some(true)` for `SHUTDOWN SAVE`, `Some(false)` for `SHUTDOWN NOSAVE`.
+    ///
+    /// The server closes the connection rather than replying.
+    pub async fn shutdown(mut self, save: Option<bool>) -> crate::FnResult<()> {
+        let frame = Shutdown::new(save).into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.connection.read_frame().await? {
+            None => Ok(()),
+            Some(Frame::Error(msg)) => Err(msg.into()),
+            Some(frame) => Err(frame.to_error()),
+        }
+    }
+
+    /// Start rewriting the append-only file in the background.
+    pub async fn bgrewriteaof(&mut self) -> crate::FnResult<()> {
+        let frame = BgRewriteAof::new().into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response)
+                if response == "Background append only file rewriting started" =>
+            {
+                Ok(())
+            }
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Make the server a replica of the leader at `host:port`.
+    pub async fn replicaof(&mut self, host: &str, port: u16) -> crate::FnResult<()> {
+        self.replicaof_cmd(ReplicaOf::new(host, port).into_frame())
+            .await
+    }
+
+    /// Stop the replication, making the server a leader again.
+    pub async fn replicaof_no_one(&mut self) -> crate::FnResult<()> {
+        self.replicaof_cmd(ReplicaOf::no_one().into_frame()).await
+    }
+
+    async fn replicaof_cmd(&mut self, frame: Frame) -> crate::FnResult<()> {
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            // Also "OK Already connected to specified master"
+            Frame::Simple(response) if response.starts_with("OK") => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Hand the leadership over to the replica at `target`, or to the most up to date
+    /// replica if `None`, making the server its replica. The failover is aborted if the
+    /// replica doesn't catch up within `timeout`, if set.
+    ///
+    /// Returns once the failover started, it's performed in the background.
+    pub async fn failover(
+        &mut self,
+        target: Option<(&str, u16)>,
+        timeout: Option<Duration>,
+    ) -> crate::FnResult<()> {
+        let frame = Failover::new(target, timeout).into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the replication role of the server.
+    pub async fn role(&mut self) -> crate::FnResult<ServerRole> {
+        let frame = Role::new().into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        let frame = self.read_response().await?;
+
+        let role = match &frame {
+            Frame::Array(frames) => match &frames[..] {
+                [role, Frame::Integer(offset), Frame::Array(replicas)] if *role == "master" => {
+                    replicas
+                        .iter()
+                        .map(|replica| match replica {
+                            Frame::Array(fields) => match &fields[..] {
+                                [ip, port, offset] => Some(ReplicaRole {
+                                    ip: ip.to_string(),
+                                    port: port.to_string().parse().ok()?,
+                                    offset: offset.to_string().parse().ok()?,
+                                }),
+                                _ => None,
+                            },
+                            _ => None,
+                        })
+                        .collect::<Option<_>>()
+                        .map(|replicas| ServerRole::Leader {
+                            offset: *offset as u64,
+                            replicas,
+                        })
+                }
+                [role, host, Frame::Integer(port), state, Frame::Integer(offset)]
+                    if *role == "slave" =>
+                {
+                    Some(ServerRole::Replica {
+                        host: host.to_string(),
+                        port: *port as u16,
+                        state: state.to_string(),
+                        offset: *offset as u64,
+                    })
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+
+        role.ok_or_else(|| frame.to_error())
+    }
+
+    /// Wait until `num_replicas` replicas acknowledged all the writes applied so far, or
+    /// `timeout` elapsed if set. Returns the number of replicas which acknowledged them.
+    pub async fn wait(
+        &mut self,
+        num_replicas: u64,
+        timeout: Option<Duration>,
+    ) -> crate::FnResult<u64> {
+        let frame = Wait::new(num_replicas, timeout).into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(response) => Ok(response as u64),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns information about the server, restricted to `section` if set.
+    pub async fn info(&mut self, section: Option<&str>) -> crate::FnResult<String> {
+        let frame = Info::new(section).into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            frame @ Frame::Bulk(_) => Ok(frame.to_string()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the configuration parameters matching the glob-style `pattern`, and their values.
+    pub async fn config_get(&mut self, pattern: &str) -> crate::FnResult<Vec<(String, String)>> {
+        let frame = Config::Get(vec![pattern.to_string()]).into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Array(ref frames) => frames
+                .chunks(2)
+                .map(|pair| match pair {
+                    [name, value] => Ok((name.to_string(), value.to_string())),
+                    _ => Err(Frame::Array(pair.to_vec()).to_error()),
+                })
+                .collect(),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Change a configuration parameter of the server at runtime.
+    pub async fn config_set(&mut self, name: &str, value: &str) -> crate::FnResult<()> {
+        self.ok_cmd(Config::Set(vec![(name.to_string(), value.to_string())]).into_frame())
+            .await
+    }
+
+    /// Write the configuration back to the file the server was started with.
+    pub async fn config_rewrite(&mut self) -> crate::FnResult<()> {
+        self.ok_cmd(Config::Rewrite.into_frame()).await
+    }
+
+    /// Authenticate the connection as `username`, or as the `default` user if `None`.
+    pub async fn auth(&mut self, username: Option<&str>, password: &str) -> crate::FnResult<()> {
+        self.ok_cmd(Auth::new(username, password).into_frame())
+            .await
+    }
+
+    /// Create the user `name`, or apply the `rules` to it, e.g. `on >secret ~cache:* +get`.
+    pub async fn acl_setuser(&mut self, name: &str, rules: &[&str]) -> crate::FnResult<()> {
+        let rules = rules.iter().map(|rule| rule.to_string()).collect();
+        self.ok_cmd(Acl::SetUser(name.to_string(), rules).into_frame())
+            .await
+    }
+
+    /// Returns the flags, passwords, commands, keys and channels of the user `name`, as
+    /// field and value pairs, or `Reply::Null` if there's no such user.
+    pub async fn acl_getuser(&mut self, name: &str) -> crate::FnResult<Reply> {
+        match self.acl_cmd(Acl::GetUser(name.to_string())).await? {
+            frame @ (Frame::Array(_) | Frame::Null) => Ok(Reply::from(frame)),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Delete the users, returning how many of them existed.
+    pub async fn acl_deluser(&mut self, names: &[String]) -> crate::FnResult<u64> {
+        match self.acl_cmd(Acl::DelUser(names.to_vec())).await? {
+            Frame::Integer(deleted) => Ok(deleted as u64),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the users and their rules, a line each, in the format of the ACL file.
+    pub async fn acl_list(&mut self) -> crate::FnResult<Vec<String>> {
+        match self.acl_cmd(Acl::List).await? {
+            Frame::Array(users) => Ok(users.iter().map(|user| user.to_string()).collect()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the name of the user the connection is authenticated as.
+    pub async fn acl_whoami(&mut self) -> crate::FnResult<String> {
+        match self.acl_cmd(Acl::WhoAmI).await? {
+            frame @ Frame::Bulk(_) => Ok(frame.to_string()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the command categories, or the commands of `category`.
+    pub async fn acl_cat(&mut self, category: Option<&str>) -> crate::FnResult<Vec<String>> {
+        match self.acl_cmd(Acl::Cat(category.map(str::to_string))).await? {
+            Frame::Array(names) => Ok(names.iter().map(|name| name.to_string()).collect()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the latest `count` denied commands and failed authentications, or 10 if
+    /// `None`, the most recent first.
+    pub async fn acl_log(&mut self, count: Option<u64>) -> crate::FnResult<Reply> {
+        match self
+            .acl_cmd(Acl::Log(count.map(|count| count as usize)))
+            .await?
+        {
+            frame @ Frame::Array(_) => Ok(Reply::from(frame)),
+            frame => Err(frame.to_error()),
+        }
     }
 
-    /// Ping to the server.
-    pub async fn ping(&mut self, msg: Option<Bytes>) -> crate::FnResult<Bytes> {
-        let frame = Ping::new(msg).into_frame();
+    /// Clear the ACL log.
+    pub async fn acl_log_reset(&mut self) -> crate::FnResult<()> {
+        self.ok_cmd(Acl::LogReset.into_frame()).await
+    }
+
+    async fn acl_cmd(&mut self, cmd: Acl) -> crate::FnResult<Frame> {
+        let frame = cmd.into_frame();
         debug!(request = ?frame);
+
         self.connection.write_frame(&frame).await?;
 
-        match self.read_response().await? {
-            Frame::Simple(value) => Ok(value.into()),
-            Frame::Bulk(value) => Ok(value),
+        self.read_response().await
+    }
+
+    /// Returns the ranges of hash slots served by each node of the cluster.
+    pub async fn cluster_slots(&mut self) -> crate::FnResult<Vec<SlotRange>> {
+        let frame = self.cluster_cmd(Cluster::Slots).await?;
+
+        let ranges = match &frame {
+            Frame::Array(ranges) => ranges
+                .iter()
+                .map(|range| match range {
+                    Frame::Array(fields) => match &fields[..] {
+                        [Frame::Integer(start), Frame::Integer(end), Frame::Array(node), ..] => {
+                            match &node[..] {
+                                [host, Frame::Integer(port), id, ..] => Some(SlotRange {
+                                    start: *start as u16,
+                                    end: *end as u16,
+                                    host: host.to_string(),
+                                    port: *port as u16,
+                                    id: id.to_string(),
+                                }),
+                                _ => None,
+                            }
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        };
+
+        ranges.ok_or_else(|| frame.to_error())
+    }
+
+    /// Returns the hash slot of `key`.
+    pub async fn cluster_keyslot(&mut self, key: &str) -> crate::FnResult<u16> {
+        match self.cluster_cmd(Cluster::KeySlot(key.to_string())).await? {
+            Frame::Integer(slot) => Ok(slot as u16),
             frame => Err(frame.to_error()),
         }
     }
 
-    /// Get value of a key
-    pub async fn get(&mut self, key: &str) -> crate::FnResult<Option<Bytes>> {
-        let frame = Get::new(key).into_frame();
-        debug!(request = ?frame);
-        self.connection.write_frame(&frame).await?;
+    /// Returns the number of keys of the node in `slot`.
+    pub async fn cluster_countkeysinslot(&mut self, slot: u16) -> crate::FnResult<u64> {
+        match self.cluster_cmd(Cluster::CountKeysInSlot(slot)).await? {
+            Frame::Integer(count) => Ok(count as u64),
+            frame => Err(frame.to_error()),
+        }
+    }
 
-        match self.read_response().await? {
-            Frame::Simple(value) => Ok(Some(value.into())),
-            Frame::Bulk(value) => Ok(Some(value)),
-            Frame::Null => Ok(None),
+    /// Returns up to `count` keys of the node in `slot`.
+    pub async fn cluster_getkeysinslot(
+        &mut self,
+        slot: u16,
+        count: u64,
+    ) -> crate::FnResult<Vec<String>> {
+        match self
+            .cluster_cmd(Cluster::GetKeysInSlot(slot, count))
+            .await?
+        {
+            Frame::Array(keys) => Ok(keys.iter().map(|key| key.to_string()).collect()),
             frame => Err(frame.to_error()),
         }
     }
 
-    /// Set value of a key.
-    pub async fn set(&mut self, key: &str, value: Bytes) -> crate::FnResult<()> {
-        self.set_cmd(Set::new(key, value, None)).await
+    /// Returns the id of the node.
+    pub async fn cluster_myid(&mut self) -> crate::FnResult<String> {
+        match self.cluster_cmd(Cluster::MyId).await? {
+            frame @ Frame::Bulk(_) => Ok(frame.to_string()),
+            frame => Err(frame.to_error()),
+        }
     }
 
-    /// Set value of a key with an expiration time.
-    pub async fn set_expires(
+    /// Returns the nodes known by the node, a line each, as `CLUSTER NODES`.
+    pub async fn cluster_nodes(&mut self) -> crate::FnResult<String> {
+        match self.cluster_cmd(Cluster::Nodes).await? {
+            frame @ Frame::Bulk(_) => Ok(frame.to_string()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns the state of the cluster as seen by the node, as `CLUSTER INFO`.
+    pub async fn cluster_info(&mut self) -> crate::FnResult<String> {
+        match self.cluster_cmd(Cluster::Info).await? {
+            frame @ Frame::Bulk(_) => Ok(frame.to_string()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Introduce the node to the node listening on `host:port`, whose cluster bus listens
+    /// on `bus_port`, or the port plus 10000 if `None`.
+    pub async fn cluster_meet(
         &mut self,
-        key: &str,
-        value: Bytes,
-        expires: Duration,
+        host: &str,
+        port: u16,
+        bus_port: Option<u16>,
     ) -> crate::FnResult<()> {
-        self.set_cmd(Set::new(key, value, Some(expires))).await
+        match self
+            .cluster_cmd(Cluster::Meet(host.to_string(), port, bus_port))
+            .await?
+        {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
     }
 
-    async fn set_cmd(&mut self, cmd: Set) -> crate::FnResult<()> {
-        let frame = cmd.into_frame();
-        debug!(request = ?frame);
+    /// Assign unassigned `slots` to the node.
+    pub async fn cluster_addslots(&mut self, slots: &[u16]) -> crate::FnResult<()> {
+        match self.cluster_cmd(Cluster::AddSlots(slots.to_vec())).await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
 
-        self.connection.write_frame(&frame).await?;
+    /// Remove the node `id` from the nodes known by the node.
+    pub async fn cluster_forget(&mut self, id: &str) -> crate::FnResult<()> {
+        match self.cluster_cmd(Cluster::Forget(id.to_string())).await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
 
-        match self.read_response().await? {
+    /// Set the migration state of `slot`, or assign it to a node.
+    pub async fn cluster_setslot(&mut self, slot: u16, state: SetSlot) -> crate::FnResult<()> {
+        match self.cluster_cmd(Cluster::SetSlot(slot, state)).await? {
             Frame::Simple(response) if response == "OK" => Ok(()),
             frame => Err(frame.to_error()),
         }
     }
 
+    /// Let the next command access a slot being imported by the node, after an `ASK`
+    /// redirect.
+    pub async fn asking(&mut self) -> crate::FnResult<()> {
+        self.ok_cmd(Asking::new().into_frame()).await
+    }
+
+    async fn cluster_cmd(&mut self, cmd: Cluster) -> crate::FnResult<Frame> {
+        let frame = cmd.into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        self.read_response().await
+    }
+
     /// Post `message` to the given `channel`.
     pub async fn publish(&mut self, channel: &str, message: Bytes) -> crate::FnResult<u64> {
         let frame = Publish::new(channel, message).into_frame();
@@ -103,45 +914,87 @@ impl Client {
         self.connection.write_frame(&frame).await?;
 
         match self.read_response().await? {
-            Frame::Integer(response) => Ok(response),
+            Frame::Integer(response) => Ok(response as u64),
             frame => Err(frame.to_error()),
         }
     }
 
     /// Subscribe to the specified channels.
-    pub async fn subscribe(mut self, channels: Vec<String>) -> crate::FnResult<Subscriber> {
-        self.subscribe_cmd(&channels).await?;
+    pub async fn subscribe(self, channels: Vec<String>) -> crate::FnResult<Subscriber> {
+        let mut subscriber = Subscriber::new(self);
+        subscriber.subscribe(&channels).await?;
 
-        Ok(Subscriber {
-            client: self,
-            subscribed_channels: channels,
-        })
+        Ok(subscriber)
     }
 
-    async fn subscribe_cmd(&mut self, channels: &[String]) -> crate::FnResult<()> {
-        let frame = Subscribe::new(channels.to_vec()).into_frame();
+    /// Subscribe to the channels matching the specified glob-style patterns.
+    pub async fn psubscribe(self, patterns: Vec<String>) -> crate::FnResult<Subscriber> {
+        let mut subscriber = Subscriber::new(self);
+        subscriber.psubscribe(&patterns).await?;
+
+        Ok(subscriber)
+    }
+
+    /// List the channels having at least one subscriber, optionally restricted
+    /// to the ones matching the glob-style `pattern`.
+    pub async fn pubsub_channels(&mut self, pattern: Option<&str>) -> crate::FnResult<Vec<String>> {
+        let frame = PubSub::Channels(pattern.map(|pattern| pattern.to_string())).into_frame();
         debug!(request = ?frame);
 
         self.connection.write_frame(&frame).await?;
 
-        // For each channel subscribed to, server responds with a message confirming subscripton
-        for channel in channels {
-            let response = self.read_response().await?;
+        match self.read_response().await? {
+            Frame::Array(frames) => Ok(frames.iter().map(|frame| frame.to_string()).collect()),
+            frame => Err(frame.to_error()),
+        }
+    }
 
-            // Verify it is confirmation of the subscription
-            match response {
-                Frame::Array(ref frame) => match frame.as_slice() {
-                    // Server responds with an array frame of this shape:
-                    // ["subscribe", channel, num_subscribed]
-                    [subscribe, schannel, ..]
-                        if *subscribe == "subscribe" && *schannel == channel => {}
-                    _ => return Err(response.to_error()),
-                },
-                frame => return Err(frame.to_error()),
-            };
+    /// Get the number of subscribers of each of the given `channels`.
+    pub async fn pubsub_numsub(
+        &mut self,
+        channels: &[String],
+    ) -> crate::FnResult<Vec<(String, u64)>> {
+        let frame = PubSub::NumSub(channels.to_vec()).into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Array(ref frames) => frames
+                .chunks(2)
+                .map(|pair| match pair {
+                    [channel, Frame::Integer(num)] => Ok((channel.to_string(), *num as u64)),
+                    _ => Err(Frame::Array(pair.to_vec()).to_error()),
+                })
+                .collect(),
+            frame => Err(frame.to_error()),
         }
+    }
 
-        Ok(())
+    /// Get the lag statistics of every subscriber, one line per subscriber.
+    pub async fn pubsub_lag(&mut self) -> crate::FnResult<String> {
+        let frame = PubSub::Lag.into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            frame @ Frame::Bulk(_) => Ok(frame.to_string()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Close the connection, once the server has acknowledged it.
+    pub async fn quit(mut self) -> crate::FnResult<()> {
+        let frame = Quit::new().into_frame();
+        debug!(request = ?frame);
+
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
     }
 
     async fn read_response(&mut self) -> crate::FnResult<Frame> {
@@ -161,31 +1014,52 @@ impl Client {
 }
 
 impl Subscriber {
+    fn new(client: Client) -> Subscriber {
+        Subscriber {
+            client,
+            subscribed_channels: vec![],
+            subscribed_patterns: vec![],
+            pending: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
     /// Get list of subscribed channels.
     pub fn get_subscribed(&self) -> &[String] {
         &self.subscribed_channels
     }
 
+    /// Get list of subscribed patterns.
+    pub fn get_subscribed_patterns(&self) -> &[String] {
+        &self.subscribed_patterns
+    }
+
+    /// Get the number of messages the server dropped because this subscriber lagged behind.
+    pub fn get_dropped(&self) -> u64 {
+        self.dropped
+    }
+
     /// Receive next message published on a subscribed channel, waiting if necessary.
     ///
     /// `None` indicates that the subscription has been terminated.
     pub async fn next_message(&mut self) -> crate::FnResult<Option<Message>> {
-        match self.client.connection.read_frame().await? {
-            Some(frame) => {
-                debug!(?frame);
-
-                match frame {
-                    Frame::Array(ref frames) => match frames.as_slice() {
-                        [message, channel, content] if *message == "message" => Ok(Some(Message {
-                            channel: channel.to_string(),
-                            content: Bytes::from(content.to_string()),
-                        })),
-                        _ => Err(frame.to_error()),
-                    },
-                    frame => Err(frame.to_error()),
-                }
+        if let Some(message) = self.pending.pop_front() {
+            return Ok(Some(message));
+        }
+
+        loop {
+            let frame = match self.client.connection.read_frame().await? {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+
+            debug!(?frame);
+
+            match self.handle_push(frame) {
+                Ok(Some(message)) => return Ok(Some(message)),
+                Ok(None) => {}
+                Err(frame) => return Err(frame.to_error()),
             }
-            None => Ok(None),
         }
     }
 
@@ -202,7 +1076,8 @@ impl Subscriber {
 
     /// Subscribe to a list of new channels
     pub async fn subscribe(&mut self, channels: &[String]) -> crate::FnResult<()> {
-        self.client.subscribe_cmd(channels).await?;
+        let frame = Subscribe::new(channels.to_vec()).into_frame();
+        self.subscribe_cmd("subscribe", frame, channels).await?;
 
         self.subscribed_channels
             .extend(channels.iter().map(Clone::clone));
@@ -210,41 +1085,129 @@ impl Subscriber {
         Ok(())
     }
 
+    /// Subscribe to a list of new patterns
+    pub async fn psubscribe(&mut self, patterns: &[String]) -> crate::FnResult<()> {
+        let frame = PSubscribe::new(patterns.to_vec()).into_frame();
+        self.subscribe_cmd("psubscribe", frame, patterns).await?;
+
+        self.subscribed_patterns
+            .extend(patterns.iter().map(Clone::clone));
+
+        Ok(())
+    }
+
     /// Unsubscribe to a list of new channels
     pub async fn unsubscribe(&mut self, channels: &[String]) -> crate::FnResult<()> {
         let frame = Unsubscribe::new(channels).into_frame();
+        let num = num_unsubscribe_replies(channels, &self.subscribed_channels);
+
+        for channel in self.unsubscribe_cmd("unsubscribe", frame, num).await? {
+            self.subscribed_channels.retain(|c| *c != channel);
+        }
+
+        Ok(())
+    }
+
+    /// Unsubscribe to a list of patterns
+    pub async fn punsubscribe(&mut self, patterns: &[String]) -> crate::FnResult<()> {
+        let frame = PUnsubscribe::new(patterns).into_frame();
+        let num = num_unsubscribe_replies(patterns, &self.subscribed_patterns);
+
+        for pattern in self.unsubscribe_cmd("punsubscribe", frame, num).await? {
+            self.subscribed_patterns.retain(|p| *p != pattern);
+        }
+
+        Ok(())
+    }
+
+    /// Ping the server while in subscribed mode.
+    pub async fn ping(&mut self, msg: Option<Bytes>) -> crate::FnResult<Bytes> {
+        let frame = Ping::new(msg).into_frame();
         debug!(request = ?frame);
 
         self.client.connection.write_frame(&frame).await?;
 
-        // If input channel list is empty, we'll assume the unsubscribed list received
-        // matches the client subscribed channels
-        let num = if channels.is_empty() {
-            self.subscribed_channels.len()
-        } else {
-            channels.len()
-        };
+        let response = self.read_reply().await?;
 
-        for _ in 0..num {
-            let response = self.client.read_response().await?;
+        match response {
+            // Server responds with an array frame of this shape: ["pong", msg]
+            Frame::Array(ref frames) => match frames.as_slice() {
+                [pong, Frame::Bulk(msg)] if *pong == "pong" => Ok(msg.clone()),
+                _ => Err(response.to_error()),
+            },
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Leave subscribed mode, dropping all subscriptions and pending messages.
+    ///
+    /// Returns the client, ready to issue regular commands.
+    pub async fn reset(mut self) -> crate::FnResult<Client> {
+        let frame = Reset::new().into_frame();
+        debug!(request = ?frame);
+
+        self.client.connection.write_frame(&frame).await?;
+
+        match self.read_reply().await? {
+            Frame::Simple(response) if response == "RESET" => Ok(self.client),
+            frame => Err(frame.to_error()),
+        }
+    }
 
+    async fn subscribe_cmd(
+        &mut self,
+        kind: &str,
+        frame: Frame,
+        names: &[String],
+    ) -> crate::FnResult<()> {
+        debug!(request = ?frame);
+
+        self.client.connection.write_frame(&frame).await?;
+
+        // For each channel or pattern subscribed to, server responds with a message confirming subscripton
+        for name in names {
+            let response = self.read_reply().await?;
+
+            // Verify it is confirmation of the subscription
             match response {
                 Frame::Array(ref frame) => match frame.as_slice() {
-                    [unsubscribe, channel, ..] if *unsubscribe == "unsubscribe" => {
-                        let len = self.subscribed_channels.len();
+                    // Server responds with an array frame of this shape:
+                    // ["subscribe", channel, num_subscribed]
+                    [subscribe, sname, ..] if *subscribe == kind && *sname == name => {}
+                    _ => return Err(response.to_error()),
+                },
+                frame => return Err(frame.to_error()),
+            };
+        }
 
-                        if len == 0 {
-                            // There must be at least one channel
-                            return Err(response.to_error());
-                        }
+        Ok(())
+    }
+
+    /// Send an unsubscribe `frame` and read `num` confirmations.
+    ///
+    /// Returns the names the server confirmed being unsubscribed from.
+    async fn unsubscribe_cmd(
+        &mut self,
+        kind: &str,
+        frame: Frame,
+        num: usize,
+    ) -> crate::FnResult<Vec<String>> {
+        debug!(request = ?frame);
 
-                        // Unsubscribed channel should exist in the subscribed list at this points
-                        self.subscribed_channels.retain(|c| *channel != &c[..]);
+        self.client.connection.write_frame(&frame).await?;
 
-                        // Only one channel should be removed from the list of subscribed channels
-                        if self.subscribed_channels.len() != len - 1 {
-                            return Err(response.to_error());
-                        }
+        let mut unsubscribed = vec![];
+
+        for _ in 0..num {
+            let response = self.read_reply().await?;
+
+            match response {
+                Frame::Array(ref frame) => match frame.as_slice() {
+                    // Server responds with an array frame of this shape:
+                    // ["unsubscribe", channel, num_subscribed], channel being null if there was none to unsubscribe from
+                    [unsubscribe, Frame::Null, _] if *unsubscribe == kind => {}
+                    [unsubscribe, name, _] if *unsubscribe == kind => {
+                        unsubscribed.push(name.to_string())
                     }
                     _ => return Err(response.to_error()),
                 },
@@ -252,6 +1215,152 @@ impl Subscriber {
             }
         }
 
-        Ok(())
+        Ok(unsubscribed)
+    }
+
+    /// Read the reply to a command, queuing the messages received in the meantime.
+    async fn read_reply(&mut self) -> crate::FnResult<Frame> {
+        loop {
+            let frame = self.client.read_response().await?;
+
+            match self.handle_push(frame) {
+                Ok(Some(message)) => self.pending.push_back(message),
+                Ok(None) => {}
+                Err(frame) => return Ok(frame),
+            }
+        }
+    }
+
+    /// Handle a frame pushed by the server without being requested.
+    ///
+    /// Returns the message it holds, if any, or the frame itself if it isn't a pushed frame.
+    fn handle_push(&mut self, frame: Frame) -> Result<Option<Message>, Frame> {
+        match frame {
+            Frame::Array(ref frames) => match frames.as_slice() {
+                [message, channel, content] if *message == "message" => Ok(Some(Message {
+                    channel: channel.to_string(),
+                    content: Bytes::from(content.to_string()),
+                    pattern: None,
+                })),
+                [pmessage, pattern, channel, content] if *pmessage == "pmessage" => {
+                    Ok(Some(Message {
+                        channel: channel.to_string(),
+                        content: Bytes::from(content.to_string()),
+                        pattern: Some(pattern.to_string()),
+                    }))
+                }
+                // Server notifies that we missed messages, keep count
+                [lagged, _, Frame::Integer(dropped)] if *lagged == "lagged" => {
+                    self.dropped += *dropped as u64;
+                    Ok(None)
+                }
+                _ => Err(frame),
+            },
+            frame => Err(frame),
+        }
+    }
+}
+
+/// Number of replies expected when unsubscribing from `names`.
+///
+/// Unsubscribing from all gets a reply per subscription, or a single one if there is none.
+fn num_unsubscribe_replies(names: &[String], subscribed: &[String]) -> usize {
+    if names.is_empty() {
+        subscribed.len().max(1)
+    } else {
+        names.len()
+    }
+}
+
+impl Transaction<'_> {
+    /// Queue a `GET` of `key`.
+    pub fn get(mut self, key: &str) -> Self {
+        self.commands.push(Get::new(key).into_frame());
+        self
+    }
+
+    /// Queue a `SET` of `key` to `value`.
+    pub fn set(mut self, key: &str, value: Bytes) -> Self {
+        self.commands.push(Set::new(key, value, None).into_frame());
+        self
+    }
+
+    /// Queue a `SET` of `key` to `value`, expiring after `expiration`.
+    pub fn set_expires(mut self, key: &str, value: Bytes, expiration: Duration) -> Self {
+        self.commands
+            .push(Set::new(key, value, Some(expiration)).into_frame());
+        self
+    }
+
+    /// Queue a `DEL` of the given keys.
+    pub fn del(mut self, keys: &[String]) -> Self {
+        self.commands.push(Del::new(keys).into_frame());
+        self
+    }
+
+    /// Queue a `SELECT` of the database at `index`, for the next commands of the transaction.
+    pub fn select(mut self, index: u64) -> Self {
+        self.commands.push(Select::new(index).into_frame());
+        self
+    }
+
+    /// Queue a `PUBLISH` of `message` to `channel`.
+    pub fn publish(mut self, channel: &str, message: Bytes) -> Self {
+        self.commands
+            .push(Publish::new(channel, message).into_frame());
+        self
+    }
+
+    /// Send the queued commands and apply them atomically, returning their replies in order.
+    ///
+    /// Commands failing at runtime are answered with a `Reply::Error`, while a command rejected
+    /// by the server when queued discards the whole transaction with an `EXECABORT` error.
+    ///
+    /// Returns `None` if the transaction wasn't applied because a watched key was modified.
+    pub async fn exec(self) -> crate::FnResult<Option<Vec<Reply>>> {
+        let client = self.client;
+
+        client.ok_cmd(Multi::new().into_frame()).await?;
+
+        for frame in self.commands {
+            debug!(request = ?frame);
+
+            client.connection.write_frame(&frame).await?;
+
+            // A command rejected when queued is reported by `EXEC`
+            match client.connection.read_frame().await? {
+                Some(Frame::Simple(response)) if response == "QUEUED" => {}
+                Some(Frame::Error(_)) => {}
+                Some(frame) => return Err(frame.to_error()),
+                None => {
+                    let err = Error::new(ErrorKind::ConnectionReset, "connection reset by server");
+                    return Err(err.into());
+                }
+            }
+        }
+
+        let frame = Exec::new().into_frame();
+        debug!(request = ?frame);
+
+        client.connection.write_frame(&frame).await?;
+
+        match client.read_response().await? {
+            Frame::Array(replies) => Ok(Some(replies.into_iter().map(Reply::from).collect())),
+            Frame::Null => Ok(None),
+            frame => Err(frame.to_error()),
+        }
+    }
+}
+
+impl From<Frame> for Reply {
+    fn from(frame: Frame) -> Reply {
+        match frame {
+            Frame::Null => Reply::Null,
+            Frame::Simple(s) => Reply::Simple(s),
+            Frame::Integer(n) => Reply::Integer(n),
+            Frame::Bulk(data) => Reply::Bulk(data),
+            Frame::Array(frames) => Reply::Array(frames.into_iter().map(Reply::from).collect()),
+            Frame::Error(msg) => Reply::Error(msg),
+        }
     }
 }
@@ -0,0 +1,326 @@
+use bytes::Bytes;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    time::Duration,
+};
+use tokio::time;
+
+use crate::{
+    clients::client::{Client, Reply},
+    cluster::key_slot,
+    commands::{Del, Dump, Eval, EvalSha, Get, Restore, Set},
+    constants::CLUSTER_SLOTS,
+    frame::Frame,
+    scripting,
+};
+
+/// Number of redirections followed for a single command before giving up
+const MAX_REDIRECTIONS: usize = 16;
+
+/// Delay before sending again a command rejected with `TRYAGAIN`
+const TRYAGAIN_DELAY: Duration = Duration::from_millis(50);
+
+/// Establish connections with the nodes of a Redis cluster.
+///
+/// Each command is sent to the node serving the hash slot of its keys, as listed by
+/// `CLUSTER SLOTS`, over a connection of its own per node. `MOVED` redirections refresh
+/// the slots, `ASK` ones send the command once to the other node after `ASKING`.
+pub struct ClusterClient {
+    /// Addresses the client was bootstrapped from
+    seeds: Vec<String>,
+
+    /// Address of the node serving each slot
+    slots: Vec<Option<String>>,
+
+    /// Connection to each node, by address
+    connections: HashMap<String, Client>,
+}
+
+/// Redirection of a command to another node.
+enum Redirect {
+    /// The slot is served by the node at this address
+    Moved(u16, String),
+
+    /// The slot is being migrated, the command must be sent once to the node at this address
+    Ask(String),
+
+    /// Some of the keys are being migrated, the command must be sent again later
+    TryAgain,
+}
+
+impl ClusterClient {
+    /// Establish connection with the cluster, fetching its slots from the first node of
+    /// `seeds` to reply.
+    pub async fn connect<T: ToString>(seeds: &[T]) -> crate::FnResult<ClusterClient> {
+        let mut client = ClusterClient {
+            seeds: seeds.iter().map(ToString::to_string).collect(),
+            slots: vec![None; CLUSTER_SLOTS as usize],
+            connections: HashMap::new(),
+        };
+
+        client.refresh_slots().await?;
+
+        Ok(client)
+    }
+
+    /// Fetch again the slots served by each node, from the first node known to reply.
+    pub async fn refresh_slots(&mut self) -> crate::FnResult<()> {
+        let mut addrs: Vec<_> = self.connections.keys().cloned().collect();
+        addrs.extend(
+            self.seeds
+                .iter()
+                .filter(|seed| !self.connections.contains_key(*seed))
+                .cloned(),
+        );
+
+        let mut last_err = None;
+
+        for addr in addrs {
+            let ranges = match self.connection(&addr).await {
+                Ok(client) => client.cluster_slots().await,
+                Err(err) => Err(err),
+            };
+
+            match ranges {
+                Ok(ranges) => {
+                    self.slots = vec![None; CLUSTER_SLOTS as usize];
+
+                    for range in ranges {
+                        let addr = format!("{}:{}", range.host, range.port);
+                        for slot in range.start..=range.end {
+                            self.slots[slot as usize] = Some(addr.clone());
+                        }
+                    }
+
+                    return Ok(());
+                }
+                Err(err) => {
+                    self.connections.remove(&addr);
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| "ERR no cluster node to connect to".into()))
+    }
+
+    /// Get value of a key
+    pub async fn get(&mut self, key: &str) -> crate::FnResult<Option<Bytes>> {
+        match self
+            .send(key_slot(key.as_bytes()), &Get::new(key).into_frame())
+            .await?
+        {
+            Frame::Simple(value) => Ok(Some(value.into())),
+            Frame::Bulk(value) => Ok(Some(value)),
+            Frame::Null => Ok(None),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Set value of a key.
+    pub async fn set(&mut self, key: &str, value: Bytes) -> crate::FnResult<()> {
+        self.set_cmd(Set::new(key, value, None)).await
+    }
+
+    /// Set value of a key with an expiration time.
+    pub async fn set_expires(
+        &mut self,
+        key: &str,
+        value: Bytes,
+        expires: Duration,
+    ) -> crate::FnResult<()> {
+        self.set_cmd(Set::new(key, value, Some(expires))).await
+    }
+
+    async fn set_cmd(&mut self, cmd: Set) -> crate::FnResult<()> {
+        let slot = key_slot(cmd.key().as_bytes());
+
+        match self.send(slot, &cmd.into_frame()).await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Remove the given keys. Returns the number of keys that were removed.
+    ///
+    /// The keys are removed by one `DEL` per hash slot, so that they may be served by
+    /// different nodes: the removal isn't atomic across slots.
+    pub async fn del(&mut self, keys: &[String]) -> crate::FnResult<u64> {
+        let mut by_slot: BTreeMap<u16, Vec<String>> = BTreeMap::new();
+        for key in keys {
+            by_slot
+                .entry(key_slot(key.as_bytes()))
+                .or_default()
+                .push(key.clone());
+        }
+
+        let mut removed = 0;
+
+        for (slot, keys) in by_slot {
+            match self.send(slot, &Del::new(&keys).into_frame()).await? {
+                Frame::Integer(count) => removed += count as u64,
+                frame => return Err(frame.to_error()),
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Serialize the value of a key, to recreate it with `restore`. Returns `None` if the
+    /// key doesn't exist.
+    pub async fn dump(&mut self, key: &str) -> crate::FnResult<Option<Bytes>> {
+        match self
+            .send(key_slot(key.as_bytes()), &Dump::new(key).into_frame())
+            .await?
+        {
+            Frame::Bulk(payload) => Ok(Some(payload)),
+            Frame::Null => Ok(None),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Create a key from a payload returned by `dump`, expiring after `ttl` if set.
+    ///
+    /// Fails if the key already exists, unless `replace` is set.
+    pub async fn restore(
+        &mut self,
+        key: &str,
+        ttl: Option<Duration>,
+        payload: Bytes,
+        replace: bool,
+    ) -> crate::FnResult<()> {
+        let frame = Restore::new(key, ttl, payload, replace).into_frame();
+
+        match self.send(key_slot(key.as_bytes()), &frame).await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Run a Lua script on the node serving the given keys, which must all hash to the
+    /// same slot.
+    ///
+    /// The script is first run by its SHA1 digest, so that it's only sent when it isn't
+    /// cached by the node yet.
+    pub async fn eval(
+        &mut self,
+        script: &str,
+        keys: &[String],
+        args: &[Bytes],
+    ) -> crate::FnResult<Reply> {
+        match self.evalsha(&scripting::sha1_hex(script), keys, args).await {
+            Err(err) if err.to_string().starts_with("NOSCRIPT") => {
+                let frame = Eval::new(script, keys.to_vec(), args.to_vec()).into_frame();
+                Ok(Reply::from(self.send(Self::keys_slot(keys), &frame).await?))
+            }
+            reply => reply,
+        }
+    }
+
+    /// Run a Lua script cached by the node serving the given keys, given its SHA1 digest.
+    pub async fn evalsha(
+        &mut self,
+        sha1: &str,
+        keys: &[String],
+        args: &[Bytes],
+    ) -> crate::FnResult<Reply> {
+        let frame = EvalSha::new(sha1, keys.to_vec(), args.to_vec()).into_frame();
+        Ok(Reply::from(self.send(Self::keys_slot(keys), &frame).await?))
+    }
+
+    /// Returns the slot commands on `keys` are routed by: the slot of the first key, the
+    /// node checking that the others hash to it too.
+    fn keys_slot(keys: &[String]) -> u16 {
+        keys.first().map_or(0, |key| key_slot(key.as_bytes()))
+    }
+
+    /// Send a command on keys of `slot` to the node serving it, following its redirections.
+    async fn send(&mut self, slot: u16, frame: &Frame) -> crate::FnResult<Frame> {
+        let mut addr = self.node(slot).await?;
+        let mut asking = false;
+
+        for _ in 0..MAX_REDIRECTIONS {
+            let client = self.connection(&addr).await?;
+
+            let reply = if asking {
+                match client.asking().await {
+                    Ok(()) => client.request(frame).await,
+                    Err(err) => Err(err),
+                }
+            } else {
+                client.request(frame).await
+            };
+
+            let err = match reply {
+                Ok(reply) => return Ok(reply),
+                Err(err) => err,
+            };
+
+            if err.is::<io::Error>() {
+                // The node is likely down: the command isn't sent again, it may have been
+                // applied, but the next ones are routed to the node now serving the slot
+                self.connections.remove(&addr);
+                let _ = self.refresh_slots().await;
+                return Err(err);
+            }
+
+            match Redirect::parse(&err.to_string()) {
+                Some(Redirect::Moved(moved, to)) => {
+                    // The other slots were likely reassigned too
+                    let _ = self.refresh_slots().await;
+                    self.slots[moved as usize] = Some(to.clone());
+                    addr = to;
+                    asking = false;
+                }
+                Some(Redirect::Ask(to)) => {
+                    addr = to;
+                    asking = true;
+                }
+                Some(Redirect::TryAgain) => time::sleep(TRYAGAIN_DELAY).await,
+                None => return Err(err),
+            }
+        }
+
+        Err("ERR too many cluster redirections".into())
+    }
+
+    /// Returns the address of the node serving `slot`, refreshing the slots if none is known.
+    async fn node(&mut self, slot: u16) -> crate::FnResult<String> {
+        if self.slots[slot as usize].is_none() {
+            self.refresh_slots().await?;
+        }
+
+        match &self.slots[slot as usize] {
+            Some(addr) => Ok(addr.clone()),
+            None => Err("CLUSTERDOWN Hash slot not served".into()),
+        }
+    }
+
+    /// Returns the connection to the node at `addr`, establishing it if needed.
+    async fn connection(&mut self, addr: &str) -> crate::FnResult<&mut Client> {
+        if !self.connections.contains_key(addr) {
+            let client = Client::connect(addr).await?;
+            self.connections.insert(addr.to_string(), client);
+        }
+
+        Ok(self.connections.get_mut(addr).unwrap())
+    }
+}
+
+impl Redirect {
+    /// Parse a redirection from an error reply, e.g. `MOVED 3999 127.0.0.1:6381`.
+    fn parse(err: &str) -> Option<Redirect> {
+        let mut parts = err.split(' ');
+
+        match parts.next()? {
+            "MOVED" => {
+                let slot = parts.next()?.parse().ok()?;
+                Some(Redirect::Moved(slot, parts.next()?.to_string()))
+            }
+            "ASK" => Some(Redirect::Ask(parts.nth(1)?.to_string())),
+            "TRYAGAIN" => Some(Redirect::TryAgain),
+            _ => None,
+        }
+    }
+}
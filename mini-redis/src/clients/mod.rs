@@ -1,3 +1,4 @@
 pub mod blocking_client;
 pub mod buffered_client;
-pub mod client;
\ No newline at end of file
+pub mod client;
+pub mod cluster_client;
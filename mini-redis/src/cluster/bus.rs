@@ -0,0 +1,697 @@
+//! Cluster bus: the nodes of the cluster exchange their view of it on a secondary port.
+//!
+//! Every node keeps a link to each of the other nodes, on which it regularly sends a `PING`
+//! answered with a `PONG`. Both carry the sender's id, address, epochs and slots, along with
+//! gossip about the other nodes it knows. A node met with `CLUSTER MEET` is sent a `MEET`
+//! instead, so that it adds the sender to its nodes, and the other nodes learn about each
+//! other through the gossip.
+//!
+//! A node not answering a ping within the node timeout is flagged as `PFAIL` by the node
+//! that pinged it, then as `FAIL` once a majority of the nodes serving slots report it as
+//! failing in their gossip, which is broadcast to the other nodes with a `FAIL` message.
+
+use bytes::Bytes;
+use std::time::{Duration, Instant};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::broadcast,
+    time,
+};
+use tracing::{debug, error, info, warn};
+
+use super::{bulk, ClusterState, SlotSet};
+use crate::{
+    connection::Connection, constants::CLUSTER_SLOTS, db::Db, frame::Frame, shutdown::Shutdown,
+};
+
+/// Interval between two checks of the state of the other nodes, and of the links to them
+const CRON_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Maximum interval between two pings to a node
+const MAX_PING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Minimum duration after which a handshake with a node not answering is abandoned
+const MIN_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Message exchanged on the cluster bus.
+#[derive(Debug)]
+enum Message {
+    /// `PING`, `PONG` or `MEET`, with the sender's view of the cluster
+    Ping(Kind, Header),
+
+    /// The node with the given id is failing
+    Fail(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Ping,
+    Pong,
+    Meet,
+}
+
+/// The sender of a `PING`, `PONG` or `MEET`, and its gossip about the other nodes.
+#[derive(Debug)]
+struct Header {
+    id: String,
+    host: String,
+    port: u16,
+    bus_port: u16,
+    current_epoch: u64,
+    config_epoch: u64,
+    slots: SlotSet,
+    gossip: Vec<Gossip>,
+}
+
+/// Another node, as seen by the sender of a message.
+#[derive(Debug)]
+struct Gossip {
+    id: String,
+    host: String,
+    port: u16,
+    bus_port: u16,
+
+    /// Whether the node is flagged as `PFAIL` or `FAIL` by the sender
+    failing: bool,
+}
+
+/// Run the cluster bus: accept the connections of the other nodes on `listener`, and
+/// maintain the links to them, until the server shuts down.
+pub(crate) async fn run(db: Db, listener: TcpListener, notify_shutdown: broadcast::Sender<()>) {
+    let mut shutdown = Shutdown::new(notify_shutdown.subscribe());
+    let mut cron = time::interval(CRON_INTERVAL);
+
+    loop {
+        tokio::select! {
+            res = listener.accept() => match res {
+                Ok((socket, _)) => {
+                    let db = db.clone();
+                    let shutdown = Shutdown::new(notify_shutdown.subscribe());
+
+                    tokio::spawn(async move {
+                        if let Err(err) = serve_node(db, socket, shutdown).await {
+                            debug!(cause = %err, "cluster bus connection closed");
+                        }
+                    });
+                }
+                Err(err) => error!(cause = %err, "failed to accept a cluster bus connection"),
+            },
+            _ = cron.tick() => {
+                if let Some(cluster) = db.lock().cluster() {
+                    cluster.cron();
+
+                    for link in cluster.unlinked() {
+                        let task = tokio::spawn(run_link(db.clone(), link));
+                        cluster.set_link_task(link, task.abort_handle());
+                    }
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+
+    if let Some(cluster) = db.lock().cluster() {
+        cluster.stop_links();
+    }
+}
+
+/// Answer the messages of another node, connected to the cluster bus.
+async fn serve_node(db: Db, socket: TcpStream, mut shutdown: Shutdown) -> crate::FnResult<()> {
+    let mut connection = Connection::new(socket);
+
+    loop {
+        let frame = tokio::select! {
+            res = connection.read_frame() => res?,
+            _ = shutdown.recv() => return Ok(()),
+        };
+
+        let frame = match frame {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+
+        match Message::from_frame(frame) {
+            Some(Message::Ping(kind @ (Kind::Ping | Kind::Meet), header)) => {
+                let pong = match db.lock().cluster() {
+                    Some(cluster) => {
+                        cluster.receive(kind, header, None);
+                        cluster.message(Kind::Pong)
+                    }
+                    None => return Ok(()),
+                };
+
+                connection.write_frame(&pong).await?;
+            }
+            Some(Message::Fail(id)) => {
+                if let Some(cluster) = db.lock().cluster() {
+                    cluster.receive_fail(&id);
+                }
+            }
+            _ => return Err("invalid cluster bus message".into()),
+        }
+    }
+}
+
+/// Maintain the link to the node with the given link id, pinging it and sending it the
+/// queued messages, until the node is removed.
+async fn run_link(db: Db, link: u64) {
+    loop {
+        // Trying to connect counts as an unanswered ping, flagging unreachable nodes
+        let target = db.lock().cluster().and_then(|cluster| {
+            let node_timeout = cluster.node_timeout;
+            let node = cluster.nodes.values_mut().find(|node| node.link == link)?;
+            node.ping_sent.get_or_insert_with(Instant::now);
+
+            Some((node.host.clone(), node.bus_port, node_timeout))
+        });
+
+        let (host, bus_port, node_timeout) = match target {
+            Some(target) => target,
+            None => return,
+        };
+
+        match time::timeout(node_timeout, TcpStream::connect((host.as_str(), bus_port))).await {
+            Ok(Ok(socket)) => {
+                set_connected(&db, link, true);
+
+                match exchange(&db, link, Connection::new(socket), node_timeout).await {
+                    Ok(()) => return,
+                    Err(err) => debug!(%host, bus_port, cause = %err, "cluster bus link lost"),
+                }
+
+                set_connected(&db, link, false);
+            }
+            Ok(Err(err)) => {
+                debug!(%host, bus_port, cause = %err, "failed to connect to a cluster node")
+            }
+            Err(_) => debug!(%host, bus_port, "timed out connecting to a cluster node"),
+        }
+
+        time::sleep(CRON_INTERVAL).await;
+    }
+}
+
+/// Ping the node on the connected link, until the link fails, returning an error, or the
+/// node is removed.
+async fn exchange(
+    db: &Db,
+    link: u64,
+    mut connection: Connection,
+    node_timeout: Duration,
+) -> crate::FnResult<()> {
+    let ping_interval = (node_timeout / 2).min(MAX_PING_INTERVAL);
+    let mut last_ping: Option<Instant> = None;
+    let mut cron = time::interval(CRON_INTERVAL);
+
+    loop {
+        cron.tick().await;
+
+        let ping_due = last_ping.is_none_or(|sent| sent.elapsed() >= ping_interval);
+
+        let messages = db.lock().cluster().and_then(|cluster| {
+            let ping = ping_due.then(|| {
+                let node = cluster.nodes.values().find(|node| node.link == link)?;
+                let kind = if node.meet { Kind::Meet } else { Kind::Ping };
+                Some(cluster.message(kind))
+            });
+
+            let node = cluster.nodes.values_mut().find(|node| node.link == link)?;
+            let mut messages = std::mem::take(&mut node.outbox);
+
+            if let Some(ping) = ping.flatten() {
+                node.ping_sent.get_or_insert_with(Instant::now);
+                messages.push(ping);
+            }
+
+            Some(messages)
+        });
+
+        let messages = match messages {
+            Some(messages) => messages,
+            None => return Ok(()),
+        };
+
+        for message in &messages {
+            connection.write_frame(message).await?;
+        }
+
+        if !ping_due {
+            continue;
+        }
+
+        last_ping = Some(Instant::now());
+
+        let frame = match time::timeout(node_timeout, connection.read_frame()).await {
+            Ok(frame) => frame?.ok_or("connection closed by the node")?,
+            Err(_) => return Err("timed out waiting for a pong".into()),
+        };
+
+        match Message::from_frame(frame) {
+            Some(Message::Ping(Kind::Pong, header)) => {
+                if let Some(cluster) = db.lock().cluster() {
+                    cluster.receive(Kind::Pong, header, Some(link));
+                }
+            }
+            _ => return Err("invalid cluster bus message".into()),
+        }
+    }
+}
+
+fn set_connected(db: &Db, link: u64, connected: bool) {
+    if let Some(cluster) = db.lock().cluster() {
+        if let Some(node) = cluster.nodes.values_mut().find(|node| node.link == link) {
+            node.connected = connected;
+        }
+    }
+}
+
+impl ClusterState {
+    /// Returns a `PING`, `PONG` or `MEET` message from this node.
+    fn message(&self, kind: Kind) -> Frame {
+        let myself = &self.nodes[&self.myself];
+
+        let gossip = self
+            .nodes
+            .values()
+            .filter(|node| node.id != self.myself && node.handshake.is_none())
+            .map(|node| Gossip {
+                id: node.id.clone(),
+                host: node.host.clone(),
+                port: node.port,
+                bus_port: node.bus_port,
+                failing: node.pfail || node.fail,
+            })
+            .collect();
+
+        let header = Header {
+            id: myself.id.clone(),
+            host: myself.host.clone(),
+            port: myself.port,
+            bus_port: myself.bus_port,
+            current_epoch: self.current_epoch,
+            config_epoch: myself.config_epoch,
+            slots: myself.slots.clone(),
+            gossip,
+        };
+
+        Message::Ping(kind, header).into_frame()
+    }
+
+    /// Process a `PING`, `PONG` or `MEET` message. `link` is the link the message was
+    /// received on for a `PONG`, answering a ping of this node.
+    fn receive(&mut self, kind: Kind, header: Header, link: Option<u64>) {
+        if header.id == self.myself || self.is_forgotten(&header.id) {
+            // Stop the handshake with a forgotten node
+            if let Some(link) = link {
+                if let Some(id) = self
+                    .linked(link)
+                    .filter(|id| self.nodes[id].handshake.is_some())
+                {
+                    self.remove_node(&id);
+                }
+            }
+            return;
+        }
+
+        if let Some(link) = link {
+            let id = match self.linked(link) {
+                Some(id) => id,
+                None => return,
+            };
+
+            if self.nodes[&id].handshake.is_some() {
+                // The node answered the handshake: its id is now known
+                if self.nodes.contains_key(&header.id) {
+                    self.remove_node(&id);
+                    return;
+                }
+
+                let mut node = self.nodes.remove(&id).expect("unknown node");
+                info!(id = %header.id, host = %node.host, port = node.port, "handshake with a cluster node completed");
+
+                node.id = header.id.clone();
+                node.handshake = None;
+                self.nodes.insert(header.id.clone(), node);
+            } else if id != header.id {
+                // Another node now listens at this address
+                return;
+            }
+
+            let node = self.node_mut(&header.id);
+            node.meet = false;
+            node.ping_sent = None;
+            node.pong_received = Some(Instant::now());
+
+            if node.pfail || node.fail {
+                info!(id = %header.id, "cluster node is reachable again");
+                node.pfail = false;
+                node.fail = false;
+            }
+        } else if !self.nodes.contains_key(&header.id) {
+            // A new node introducing itself, superseding any handshake in progress with it
+            let handshake = self
+                .find_by_address(&header.host, header.bus_port)
+                .filter(|node| node.handshake.is_some())
+                .map(|node| node.id.clone());
+
+            if let Some(id) = handshake {
+                self.remove_node(&id);
+            }
+
+            info!(id = %header.id, host = %header.host, port = header.port, ?kind, "cluster node added");
+
+            let node = self.new_node(
+                header.id.clone(),
+                &header.host,
+                header.port,
+                header.bus_port,
+            );
+            self.nodes.insert(header.id.clone(), node);
+        }
+
+        self.current_epoch = self.current_epoch.max(header.current_epoch);
+
+        let node = self.node_mut(&header.id);
+        node.host = header.host;
+        node.port = header.port;
+        node.bus_port = header.bus_port;
+        node.config_epoch = header.config_epoch;
+
+        self.update_slots(&header.id, &header.slots, header.config_epoch);
+        self.receive_gossip(&header.id, header.gossip);
+    }
+
+    /// Process a `FAIL` message, flagging the node `id` as failing.
+    fn receive_fail(&mut self, id: &str) {
+        if id == self.myself {
+            return;
+        }
+
+        if let Some(node) = self.nodes.get_mut(id) {
+            if !node.fail {
+                warn!(id, "cluster node flagged as failing by another node");
+                node.fail = true;
+                node.pfail = false;
+            }
+        }
+    }
+
+    /// Update the owners of the slots from the claim of the node `id` on `slots`: a slot
+    /// goes to the claiming node unless served by a node with a higher configuration epoch.
+    fn update_slots(&mut self, id: &str, slots: &SlotSet, config_epoch: u64) {
+        for slot in 0..CLUSTER_SLOTS {
+            let (claimed, owner) = (slots.contains(slot), self.owner(slot));
+
+            match owner {
+                Some(owner) if owner.id == id && !claimed => self.node_mut(id).slots.remove(slot),
+                Some(owner) if owner.id == id || !claimed || owner.config_epoch >= config_epoch => {
+                }
+                _ if claimed => self.assign(slot, id),
+                _ => {}
+            }
+        }
+    }
+
+    /// Process the gossip of the node `sender` about the other nodes: failure reports, and
+    /// nodes unknown to this one, to start a handshake with.
+    fn receive_gossip(&mut self, sender: &str, gossip: Vec<Gossip>) {
+        // Only the nodes serving slots vote on the failure of the others
+        let voter = !self.nodes[sender].slots.is_empty();
+
+        for entry in gossip {
+            if entry.id == self.myself || self.is_forgotten(&entry.id) {
+                continue;
+            }
+
+            match self.nodes.get_mut(&entry.id) {
+                Some(node) => {
+                    if voter && entry.failing {
+                        node.fail_reports.insert(sender.to_string(), Instant::now());
+                    } else {
+                        node.fail_reports.remove(sender);
+                    }
+
+                    self.check_failure(&entry.id);
+                }
+                None => {
+                    if !entry.failing && self.find_by_address(&entry.host, entry.bus_port).is_none()
+                    {
+                        self.start_handshake(&entry.host, entry.port, entry.bus_port, false);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Flag the node `id` as failing if it doesn't answer this node, and a majority of the
+    /// nodes serving slots, counting this one, report it as failing. The other nodes are
+    /// told with a `FAIL` message.
+    fn check_failure(&mut self, id: &str) {
+        let needed = self.size() / 2 + 1;
+        let validity = self.node_timeout * 2;
+
+        let node = self.node_mut(id);
+        if !node.pfail || node.fail {
+            return;
+        }
+
+        let reports = node
+            .fail_reports
+            .values()
+            .filter(|time| time.elapsed() <= validity)
+            .count();
+        if reports + 1 < needed {
+            return;
+        }
+
+        warn!(id, reports, "cluster node flagged as failing");
+        node.fail = true;
+        node.pfail = false;
+
+        let fail = Message::Fail(id.to_string()).into_frame();
+
+        for node in self.nodes.values_mut() {
+            if node.id != id && node.id != self.myself && node.handshake.is_none() {
+                node.outbox.push(fail.clone());
+            }
+        }
+    }
+
+    /// Check the other nodes, run every `CRON_INTERVAL`: nodes not answering in time are
+    /// flagged as `PFAIL`, or `FAIL` on agreement, and stalled handshakes abandoned.
+    fn cron(&mut self) {
+        let now = Instant::now();
+        self.forgotten.retain(|_, until| *until > now);
+
+        let handshake_timeout = self.node_timeout.max(MIN_HANDSHAKE_TIMEOUT);
+        let stalled: Vec<_> = self
+            .nodes
+            .values()
+            .filter(|node| {
+                node.handshake
+                    .is_some_and(|started| now - started > handshake_timeout)
+            })
+            .map(|node| node.id.clone())
+            .collect();
+
+        for id in stalled {
+            debug!(%id, "handshake with a cluster node timed out");
+            self.remove_node(&id);
+        }
+
+        let validity = self.node_timeout * 2;
+        let mut failing = vec![];
+
+        for node in self.nodes.values_mut() {
+            node.fail_reports.retain(|_, time| now - *time <= validity);
+
+            if node.id == self.myself || node.handshake.is_some() || node.fail {
+                continue;
+            }
+
+            let timed_out = node
+                .ping_sent
+                .is_some_and(|sent| now - sent > self.node_timeout);
+            if timed_out && !node.pfail {
+                info!(id = %node.id, "cluster node not answering, flagged as possibly failing");
+                node.pfail = true;
+            }
+
+            if node.pfail {
+                failing.push(node.id.clone());
+            }
+        }
+
+        for id in failing {
+            self.check_failure(&id);
+        }
+    }
+
+    /// Returns the links to the other nodes whose task isn't started.
+    fn unlinked(&self) -> Vec<u64> {
+        self.nodes
+            .values()
+            .filter(|node| node.id != self.myself && node.link_task.is_none())
+            .map(|node| node.link)
+            .collect()
+    }
+
+    fn set_link_task(&mut self, link: u64, task: tokio::task::AbortHandle) {
+        if let Some(node) = self.nodes.values_mut().find(|node| node.link == link) {
+            node.link_task = Some(task);
+        }
+    }
+
+    /// Stop the links to the other nodes, when the server shuts down.
+    fn stop_links(&mut self) {
+        for node in self.nodes.values_mut() {
+            if let Some(task) = node.link_task.take() {
+                task.abort();
+            }
+        }
+    }
+
+    /// Returns the id of the node with the given link, if any.
+    fn linked(&self, link: u64) -> Option<String> {
+        self.nodes
+            .values()
+            .find(|node| node.link == link)
+            .map(|node| node.id.clone())
+    }
+
+    fn is_forgotten(&self, id: &str) -> bool {
+        self.forgotten.contains_key(id)
+    }
+}
+
+impl Message {
+    /// Converts the message into a frame: `[kind, id, host, port, bus_port, current_epoch,
+    /// config_epoch, slots, [[id, host, port, bus_port, failing], ...]]` for a `PING`,
+    /// `PONG` or `MEET`, `[fail, id]` for a `FAIL`.
+    fn into_frame(self) -> Frame {
+        match self {
+            Message::Ping(kind, header) => {
+                let kind = match kind {
+                    Kind::Ping => "ping",
+                    Kind::Pong => "pong",
+                    Kind::Meet => "meet",
+                };
+
+                let gossip = header
+                    .gossip
+                    .into_iter()
+                    .map(|node| {
+                        Frame::Array(vec![
+                            bulk(&node.id),
+                            bulk(&node.host),
+                            Frame::Integer(node.port as i64),
+                            Frame::Integer(node.bus_port as i64),
+                            Frame::Integer(node.failing as i64),
+                        ])
+                    })
+                    .collect();
+
+                let slots = header
+                    .slots
+                    .0
+                    .iter()
+                    .flat_map(|word| word.to_le_bytes())
+                    .collect::<Vec<_>>();
+
+                Frame::Array(vec![
+                    bulk(kind),
+                    bulk(&header.id),
+                    bulk(&header.host),
+                    Frame::Integer(header.port as i64),
+                    Frame::Integer(header.bus_port as i64),
+                    Frame::Integer(header.current_epoch as i64),
+                    Frame::Integer(header.config_epoch as i64),
+                    Frame::Bulk(Bytes::from(slots)),
+                    Frame::Array(gossip),
+                ])
+            }
+            Message::Fail(id) => Frame::Array(vec![bulk("fail"), bulk(&id)]),
+        }
+    }
+
+    /// Parse a message from a frame, returning `None` if malformed.
+    fn from_frame(frame: Frame) -> Option<Message> {
+        let mut parts = match frame {
+            Frame::Array(parts) => parts.into_iter(),
+            _ => return None,
+        };
+
+        let kind = match &string(parts.next()?)?[..] {
+            "ping" => Kind::Ping,
+            "pong" => Kind::Pong,
+            "meet" => Kind::Meet,
+            "fail" => return Some(Message::Fail(string(parts.next()?)?)),
+            _ => return None,
+        };
+
+        let id = string(parts.next()?)?;
+        let host = string(parts.next()?)?;
+        let port = integer(parts.next()?)?;
+        let bus_port = integer(parts.next()?)?;
+        let current_epoch = integer(parts.next()?)?;
+        let config_epoch = integer(parts.next()?)?;
+
+        let slots = match parts.next()? {
+            Frame::Bulk(bytes) if bytes.len() == CLUSTER_SLOTS as usize / 8 => SlotSet(
+                bytes
+                    .chunks(8)
+                    .map(|word| u64::from_le_bytes(word.try_into().expect("8 bytes chunk")))
+                    .collect(),
+            ),
+            _ => return None,
+        };
+
+        let gossip = match parts.next()? {
+            Frame::Array(nodes) => nodes
+                .into_iter()
+                .map(|node| {
+                    let mut parts = match node {
+                        Frame::Array(parts) => parts.into_iter(),
+                        _ => return None,
+                    };
+
+                    Some(Gossip {
+                        id: string(parts.next()?)?,
+                        host: string(parts.next()?)?,
+                        port: integer(parts.next()?)?,
+                        bus_port: integer(parts.next()?)?,
+                        failing: integer::<u8>(parts.next()?)? != 0,
+                    })
+                })
+                .collect::<Option<_>>()?,
+            _ => return None,
+        };
+
+        let header = Header {
+            id,
+            host,
+            port,
+            bus_port,
+            current_epoch,
+            config_epoch,
+            slots,
+            gossip,
+        };
+
+        Some(Message::Ping(kind, header))
+    }
+}
+
+fn string(frame: Frame) -> Option<String> {
+    match frame {
+        Frame::Bulk(bytes) => String::from_utf8(bytes.to_vec()).ok(),
+        _ => None,
+    }
+}
+
+fn integer<T: TryFrom<i64>>(frame: Frame) -> Option<T> {
+    match frame {
+        Frame::Integer(value) => value.try_into().ok(),
+        _ => None,
+    }
+}
@@ -0,0 +1,800 @@
+use bytes::Bytes;
+use crc::{Crc, CRC_16_XMODEM};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::task::AbortHandle;
+
+use crate::{
+    config::{ClusterConfig, SlotRange},
+    constants::{CLUSTER_BUS_PORT_OFFSET, CLUSTER_FORGET_TTL, CLUSTER_SLOTS},
+    frame::Frame,
+    scripting,
+};
+
+pub(crate) mod bus;
+
+/// CRC16 used to hash the keys into slots, as Redis's (XMODEM)
+const CRC16: Crc<u16> = Crc::<u16>::new(&CRC_16_XMODEM);
+
+/// Reply to a command whose keys hash to different slots.
+pub(crate) const CROSSSLOT_ERROR: &str = "CROSSSLOT Keys in request don't hash to the same slot";
+
+/// Reply to a command on a key whose slot isn't served by any node.
+const CLUSTERDOWN_ERROR: &str = "CLUSTERDOWN Hash slot not served";
+
+/// Reply to a command on a key whose slot is served by a failing node.
+const CLUSTER_FAIL_ERROR: &str = "CLUSTERDOWN The cluster is down";
+
+/// Reply to a command on keys of a migrating slot, some of which were already migrated.
+const TRYAGAIN_ERROR: &str = "TRYAGAIN Multiple keys request during rehashing of slot";
+
+/// Returns the hash slot of `key`.
+///
+/// When the key holds a non-empty hash tag, `{...}`, only the tag is hashed, so that
+/// related keys can be stored on the same node, e.g. `{user:1}:name` and `{user:1}:age`.
+pub(crate) fn key_slot(key: &[u8]) -> u16 {
+    let key = match key.iter().position(|&b| b == b'{') {
+        Some(open) => match key[open + 1..].iter().position(|&b| b == b'}') {
+            Some(len) if len > 0 => &key[open + 1..open + 1 + len],
+            _ => key,
+        },
+        None => key,
+    };
+
+    CRC16.checksum(key) % CLUSTER_SLOTS
+}
+
+/// State of the cluster, as known by this node.
+///
+/// The nodes keep it up to date by gossiping on the cluster bus, see `bus`.
+#[derive(Debug)]
+pub(crate) struct ClusterState {
+    /// Id of this node
+    myself: String,
+
+    /// Nodes of the cluster by id, including this one
+    nodes: BTreeMap<String, Node>,
+
+    /// Highest configuration epoch known in the cluster
+    current_epoch: u64,
+
+    /// Duration after which a node not answering pings is flagged as failing
+    node_timeout: Duration,
+
+    /// Nodes removed by `CLUSTER FORGET`, ignored by the gossip until the given time
+    forgotten: HashMap<String, Instant>,
+
+    /// Id of the next link to another node
+    next_link: u64,
+
+    /// Slots of this node being migrated, with the id of the node they're migrated to
+    migrating: BTreeMap<u16, String>,
+
+    /// Slots being imported by this node, with the id of the node they're imported from
+    importing: BTreeMap<u16, String>,
+}
+
+/// Node of the cluster.
+#[derive(Debug)]
+struct Node {
+    id: String,
+    host: String,
+    port: u16,
+    bus_port: u16,
+
+    /// Slots served by the node
+    slots: SlotSet,
+
+    /// Epoch of the node's claim on its slots, the highest wins
+    config_epoch: u64,
+
+    /// When the handshake with the node started, while its id isn't known yet: the node
+    /// has a temporary random id until it answers
+    handshake: Option<Instant>,
+
+    /// Whether to introduce this node to the node with a `MEET`, rather than a `PING`
+    meet: bool,
+
+    /// Whether the node didn't answer in time, as seen by this node (`PFAIL`)
+    pfail: bool,
+
+    /// Whether a majority of the nodes serving slots agree the node is failing (`FAIL`)
+    fail: bool,
+
+    /// When the unanswered ping to the node was sent, if any
+    ping_sent: Option<Instant>,
+
+    /// When the node last answered a ping
+    pong_received: Option<Instant>,
+
+    /// When the other nodes reported the node as failing, by id
+    fail_reports: HashMap<String, Instant>,
+
+    /// Id of the link to the node, identifying the node across a change of id
+    link: u64,
+
+    /// Task maintaining the link to the node, if started
+    link_task: Option<AbortHandle>,
+
+    /// Whether the link to the node is connected
+    connected: bool,
+
+    /// Messages to send to the node on its link, without expecting a reply
+    outbox: Vec<Frame>,
+}
+
+/// Set of hash slots, as a bitmap.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct SlotSet(Vec<u64>);
+
+impl ClusterState {
+    /// Create the cluster state from its configuration. The ports this node listens on are
+    /// set once known, with `set_ports`.
+    ///
+    /// The other nodes of the configuration are met on the cluster bus, serving their
+    /// configured slots until they tell otherwise.
+    pub(crate) fn new(config: &ClusterConfig) -> ClusterState {
+        let mut state = ClusterState {
+            myself: new_node_id(),
+            nodes: BTreeMap::new(),
+            current_epoch: 0,
+            node_timeout: config.node_timeout,
+            forgotten: HashMap::new(),
+            next_link: 0,
+            migrating: BTreeMap::new(),
+            importing: BTreeMap::new(),
+        };
+
+        let mut myself = state.new_node(state.myself.clone(), &config.announce_host, 0, 0);
+        myself.slots = SlotSet::from_ranges(&config.slots);
+        state.nodes.insert(myself.id.clone(), myself);
+
+        for node in &config.nodes {
+            let bus_port = node
+                .bus_port
+                .unwrap_or_else(|| node.port.saturating_add(CLUSTER_BUS_PORT_OFFSET));
+            let id = state.start_handshake(&node.host, node.port, bus_port, true);
+
+            for range in &node.slots {
+                for slot in range.start..=range.end {
+                    state.assign(slot, &id);
+                }
+            }
+        }
+
+        state
+    }
+
+    /// Set the ports this node listens on, for the clients and the cluster bus.
+    pub(crate) fn set_ports(&mut self, port: u16, bus_port: u16) {
+        if let Some(myself) = self.nodes.get_mut(&self.myself) {
+            myself.port = port;
+            myself.bus_port = bus_port;
+        }
+    }
+
+    /// Id of this node.
+    pub(crate) fn myself(&self) -> &str {
+        &self.myself
+    }
+
+    /// Start a handshake with the node at the given address, unless already known, as
+    /// `CLUSTER MEET`.
+    pub(crate) fn meet(&mut self, host: &str, port: u16, bus_port: u16) {
+        if self.find_by_address(host, bus_port).is_none() {
+            self.start_handshake(host, port, bus_port, true);
+        }
+    }
+
+    /// Assign `slots` to this node, as `CLUSTER ADDSLOTS`, failing if any is already served.
+    pub(crate) fn add_slots(&mut self, slots: &[u16]) -> Result<(), String> {
+        for (i, &slot) in slots.iter().enumerate() {
+            if slots[..i].contains(&slot) {
+                return Err(format!("ERR Slot {} specified multiple times", slot));
+            }
+
+            if self.owner(slot).is_some() {
+                return Err(format!("ERR Slot {} is already busy", slot));
+            }
+        }
+
+        let myself = self.myself.clone();
+
+        for &slot in slots {
+            self.assign(slot, &myself);
+        }
+
+        // Claim the slots with a configuration epoch of our own, that other nodes can't
+        // dispute
+        if self.nodes[&myself].config_epoch == 0 {
+            self.bump_config_epoch();
+        }
+
+        Ok(())
+    }
+
+    /// Remove the node `id` from the known nodes, as `CLUSTER FORGET`. The node is ignored
+    /// by the gossip for `CLUSTER_FORGET_TTL`, so that the other nodes can forget it too.
+    pub(crate) fn forget(&mut self, id: &str) -> Result<(), String> {
+        if id == self.myself {
+            return Err("ERR I tried hard but I can't forget myself...".to_string());
+        }
+
+        if !self.nodes.contains_key(id) {
+            return Err(format!("ERR Unknown node {}", id));
+        }
+
+        self.remove_node(id);
+        self.forgotten
+            .insert(id.to_string(), Instant::now() + CLUSTER_FORGET_TTL);
+
+        Ok(())
+    }
+
+    /// Import `slot` from the node `id`, as `CLUSTER SETSLOT slot IMPORTING id`.
+    pub(crate) fn import_slot(&mut self, slot: u16, id: &str) -> Result<(), String> {
+        if self.serves(slot) {
+            return Err(format!("ERR I'm already the owner of hash slot {}", slot));
+        }
+
+        self.check_other_node(id)?;
+        self.importing.insert(slot, id.to_string());
+
+        Ok(())
+    }
+
+    /// Migrate `slot` to the node `id`, as `CLUSTER SETSLOT slot MIGRATING id`.
+    pub(crate) fn migrate_slot(&mut self, slot: u16, id: &str) -> Result<(), String> {
+        if !self.serves(slot) {
+            return Err(format!("ERR I'm not the owner of hash slot {}", slot));
+        }
+
+        self.check_other_node(id)?;
+        self.migrating.insert(slot, id.to_string());
+
+        Ok(())
+    }
+
+    /// Cancel the migration of `slot`, as `CLUSTER SETSLOT slot STABLE`.
+    pub(crate) fn stabilize_slot(&mut self, slot: u16) {
+        self.migrating.remove(&slot);
+        self.importing.remove(&slot);
+    }
+
+    /// Assign `slot` to the node `id`, as `CLUSTER SETSLOT slot NODE id`, ending its
+    /// migration. `keys` is the number of keys this node holds in the slot, which must
+    /// have been migrated before the slot is given away.
+    ///
+    /// The node importing the slot claims it with a new configuration epoch, so that the
+    /// other nodes learn the slot moved from the gossip.
+    pub(crate) fn set_slot_node(&mut self, slot: u16, id: &str, keys: usize) -> Result<(), String> {
+        if !self.nodes.contains_key(id) {
+            return Err(format!("ERR Unknown node {}", id));
+        }
+
+        let owned = self.serves(slot);
+
+        if owned && id != self.myself && keys > 0 {
+            return Err(format!(
+                "ERR Can't assign hashslot {} to a different node while I still hold keys for this hash slot.",
+                slot
+            ));
+        }
+
+        let imported = id == self.myself && self.importing.contains_key(&slot);
+        self.assign(slot, id);
+
+        if imported {
+            self.bump_config_epoch();
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if this node serves `slot`.
+    fn serves(&self, slot: u16) -> bool {
+        self.owner(slot).is_some_and(|node| node.id == self.myself)
+    }
+
+    fn check_other_node(&self, id: &str) -> Result<(), String> {
+        if !self.nodes.contains_key(id) {
+            return Err(format!("ERR I don't know about node {}", id));
+        }
+
+        if id == self.myself {
+            return Err("ERR Target node is myself".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the node serving `slot`, if any.
+    fn owner(&self, slot: u16) -> Option<&Node> {
+        self.nodes.values().find(|node| node.slots.contains(slot))
+    }
+
+    /// Check that `keys` hash to the same slot, and that this node serves it, returning
+    /// the slot if there is any key. `pinned` is the slot of the keys of the previous
+    /// commands of a transaction, if any.
+    ///
+    /// Otherwise, returns the error to reply: `CROSSSLOT`, or `MOVED slot host:port` to
+    /// redirect the client to the node serving the slot.
+    ///
+    /// While the slot is migrated, keys which don't `exist` on this node any more are
+    /// redirected to the node importing the slot with `ASK slot host:port`. That node
+    /// serves the slot to the clients `asking` it, with `ASKING`, before it owns it.
+    pub(crate) fn check(
+        &self,
+        keys: &[&str],
+        pinned: Option<u16>,
+        asking: bool,
+        exists: impl Fn(&str) -> bool,
+    ) -> Result<Option<u16>, Frame> {
+        let mut slot = pinned;
+
+        for key in keys {
+            let key_slot = key_slot(key.as_bytes());
+
+            match slot {
+                Some(slot) if slot != key_slot => {
+                    return Err(Frame::Error(CROSSSLOT_ERROR.to_string()))
+                }
+                _ => slot = Some(key_slot),
+            }
+        }
+
+        let slot = match slot {
+            Some(slot) => slot,
+            None => return Ok(None),
+        };
+
+        match self.owner(slot) {
+            Some(node) if node.id == self.myself => match self.migrating.get(&slot) {
+                Some(target) => {
+                    let missing = keys.iter().filter(|key| !exists(key)).count();
+
+                    match (missing, self.nodes.get(target)) {
+                        (0, _) | (_, None) => Ok(Some(slot)),
+                        (missing, Some(target)) if missing == keys.len() => Err(Frame::Error(
+                            format!("ASK {} {}:{}", slot, target.host, target.port),
+                        )),
+                        _ => Err(Frame::Error(TRYAGAIN_ERROR.to_string())),
+                    }
+                }
+                None => Ok(Some(slot)),
+            },
+            _ if asking && self.importing.contains_key(&slot) => Ok(Some(slot)),
+            Some(node) if node.fail => Err(Frame::Error(CLUSTER_FAIL_ERROR.to_string())),
+            Some(node) => Err(Frame::Error(format!(
+                "MOVED {} {}:{}",
+                slot, node.host, node.port
+            ))),
+            None => Err(Frame::Error(CLUSTERDOWN_ERROR.to_string())),
+        }
+    }
+
+    /// Returns the reply to `CLUSTER SLOTS`: `[start, end, [host, port, id]]` for each
+    /// range of slots served by a node.
+    pub(crate) fn slots(&self) -> Frame {
+        let mut ranges: Vec<_> = self
+            .nodes
+            .values()
+            .flat_map(|node| {
+                node.slots
+                    .ranges()
+                    .into_iter()
+                    .map(move |range| (range, node))
+            })
+            .collect();
+
+        ranges.sort_by_key(|(range, _)| range.start);
+
+        Frame::Array(
+            ranges
+                .into_iter()
+                .map(|(range, node)| {
+                    Frame::Array(vec![
+                        Frame::Integer(range.start as i64),
+                        Frame::Integer(range.end as i64),
+                        node.endpoint(),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the reply to `CLUSTER SHARDS`: the slot ranges and nodes of each shard.
+    /// `offset` is the replication offset of this node.
+    pub(crate) fn shards(&self, offset: u64) -> Frame {
+        Frame::Array(
+            self.nodes
+                .values()
+                .filter(|node| node.handshake.is_none())
+                .map(|node| {
+                    let slots = node
+                        .slots
+                        .ranges()
+                        .into_iter()
+                        .flat_map(|range| {
+                            [
+                                Frame::Integer(range.start as i64),
+                                Frame::Integer(range.end as i64),
+                            ]
+                        })
+                        .collect();
+
+                    let offset = if node.id == self.myself { offset } else { 0 };
+                    let health = if node.fail { "fail" } else { "online" };
+
+                    let description = vec![
+                        bulk("id"),
+                        bulk(&node.id),
+                        bulk("port"),
+                        Frame::Integer(node.port as i64),
+                        bulk("ip"),
+                        bulk(&node.host),
+                        bulk("endpoint"),
+                        bulk(&node.host),
+                        bulk("role"),
+                        bulk("master"),
+                        bulk("replication-offset"),
+                        Frame::Integer(offset as i64),
+                        bulk("health"),
+                        bulk(health),
+                    ];
+
+                    Frame::Array(vec![
+                        bulk("slots"),
+                        Frame::Array(slots),
+                        bulk("nodes"),
+                        Frame::Array(vec![Frame::Array(description)]),
+                    ])
+                })
+                .collect(),
+        )
+    }
+}
+
+impl ClusterState {
+    /// Returns the reply to `CLUSTER NODES`: a line per node, with its id, address, flags,
+    /// master, ping and pong times, configuration epoch, link state and slots.
+    pub(crate) fn nodes_description(&self) -> String {
+        let mut description = String::new();
+
+        for node in self.nodes.values() {
+            let myself = node.id == self.myself;
+
+            let mut flags = vec![];
+            if myself {
+                flags.push("myself");
+            }
+            flags.push("master");
+            if node.pfail {
+                flags.push("fail?");
+            }
+            if node.fail {
+                flags.push("fail");
+            }
+            if node.handshake.is_some() {
+                flags.push("handshake");
+            }
+
+            let link = if myself || node.connected {
+                "connected"
+            } else {
+                "disconnected"
+            };
+
+            description.push_str(&format!(
+                "{} {}:{}@{} {} - {} {} {} {}",
+                node.id,
+                node.host,
+                node.port,
+                node.bus_port,
+                flags.join(","),
+                unix_millis(node.ping_sent),
+                unix_millis(node.pong_received),
+                node.config_epoch,
+                link
+            ));
+
+            for range in node.slots.ranges() {
+                if range.start == range.end {
+                    description.push_str(&format!(" {}", range.start));
+                } else {
+                    description.push_str(&format!(" {}-{}", range.start, range.end));
+                }
+            }
+
+            if myself {
+                for (slot, id) in &self.migrating {
+                    description.push_str(&format!(" [{}->-{}]", slot, id));
+                }
+
+                for (slot, id) in &self.importing {
+                    description.push_str(&format!(" [{}-<-{}]", slot, id));
+                }
+            }
+
+            description.push('\n');
+        }
+
+        description
+    }
+
+    /// Returns the reply to `CLUSTER INFO`.
+    pub(crate) fn info(&self) -> String {
+        let mut assigned = 0;
+        let mut pfail = 0;
+        let mut fail = 0;
+
+        for slot in 0..CLUSTER_SLOTS {
+            if let Some(node) = self.owner(slot) {
+                assigned += 1;
+
+                if node.fail {
+                    fail += 1;
+                } else if node.pfail {
+                    pfail += 1;
+                }
+            }
+        }
+
+        let state = if assigned == CLUSTER_SLOTS && fail == 0 {
+            "ok"
+        } else {
+            "fail"
+        };
+
+        format!(
+            "cluster_enabled:1\r\n\
+             cluster_state:{}\r\n\
+             cluster_slots_assigned:{}\r\n\
+             cluster_slots_ok:{}\r\n\
+             cluster_slots_pfail:{}\r\n\
+             cluster_slots_fail:{}\r\n\
+             cluster_known_nodes:{}\r\n\
+             cluster_size:{}\r\n\
+             cluster_current_epoch:{}\r\n\
+             cluster_my_epoch:{}\r\n",
+            state,
+            assigned,
+            assigned - pfail - fail,
+            pfail,
+            fail,
+            self.nodes.len(),
+            self.size(),
+            self.current_epoch,
+            self.nodes[&self.myself].config_epoch
+        )
+    }
+
+    /// Number of nodes serving slots, voting on the failure of the others.
+    fn size(&self) -> usize {
+        self.nodes
+            .values()
+            .filter(|node| !node.slots.is_empty())
+            .count()
+    }
+
+    fn new_node(&mut self, id: String, host: &str, port: u16, bus_port: u16) -> Node {
+        self.next_link += 1;
+
+        Node {
+            id,
+            host: host.to_string(),
+            port,
+            bus_port,
+            slots: SlotSet::new(),
+            config_epoch: 0,
+            handshake: None,
+            meet: false,
+            pfail: false,
+            fail: false,
+            ping_sent: None,
+            pong_received: None,
+            fail_reports: HashMap::new(),
+            link: self.next_link,
+            link_task: None,
+            connected: false,
+            outbox: vec![],
+        }
+    }
+
+    /// Add a node at the given address with a temporary id, returned, until it answers.
+    fn start_handshake(&mut self, host: &str, port: u16, bus_port: u16, meet: bool) -> String {
+        let mut node = self.new_node(new_node_id(), host, port, bus_port);
+        node.handshake = Some(Instant::now());
+        node.meet = meet;
+
+        let id = node.id.clone();
+        self.nodes.insert(id.clone(), node);
+
+        id
+    }
+
+    fn find_by_address(&self, host: &str, bus_port: u16) -> Option<&Node> {
+        self.nodes
+            .values()
+            .find(|node| node.host == host && node.bus_port == bus_port)
+    }
+
+    fn node_mut(&mut self, id: &str) -> &mut Node {
+        self.nodes.get_mut(id).expect("unknown node")
+    }
+
+    /// Assign `slot` to the node `id`, removing it from its previous owner, which ends
+    /// its migration.
+    fn assign(&mut self, slot: u16, id: &str) {
+        for node in self.nodes.values_mut() {
+            if node.id == id {
+                node.slots.insert(slot);
+            } else {
+                node.slots.remove(slot);
+            }
+        }
+
+        if id == self.myself {
+            self.importing.remove(&slot);
+        } else {
+            self.migrating.remove(&slot);
+        }
+    }
+
+    /// Bump the configuration epoch of this node to a new highest epoch, so that its claim
+    /// on its slots wins over the claims of the other nodes.
+    fn bump_config_epoch(&mut self) {
+        self.current_epoch += 1;
+
+        let epoch = self.current_epoch;
+        let myself = self.myself.clone();
+        self.node_mut(&myself).config_epoch = epoch;
+    }
+
+    /// Remove the node `id`, stopping its link.
+    fn remove_node(&mut self, id: &str) {
+        if let Some(node) = self.nodes.remove(id) {
+            if let Some(task) = node.link_task {
+                task.abort();
+            }
+        }
+
+        for node in self.nodes.values_mut() {
+            node.fail_reports.remove(id);
+        }
+
+        self.migrating.retain(|_, target| target != id);
+        self.importing.retain(|_, source| source != id);
+    }
+}
+
+impl Node {
+    /// Returns `[host, port, id]`, as listed by `CLUSTER SLOTS`.
+    fn endpoint(&self) -> Frame {
+        Frame::Array(vec![
+            bulk(&self.host),
+            Frame::Integer(self.port as i64),
+            bulk(&self.id),
+        ])
+    }
+}
+
+impl SlotSet {
+    fn new() -> SlotSet {
+        SlotSet(vec![0; CLUSTER_SLOTS as usize / 64])
+    }
+
+    fn from_ranges(ranges: &[SlotRange]) -> SlotSet {
+        let mut slots = SlotSet::new();
+
+        for range in ranges {
+            for slot in range.start..=range.end {
+                slots.insert(slot);
+            }
+        }
+
+        slots
+    }
+
+    fn insert(&mut self, slot: u16) {
+        self.0[slot as usize / 64] |= 1 << (slot % 64);
+    }
+
+    fn remove(&mut self, slot: u16) {
+        self.0[slot as usize / 64] &= !(1 << (slot % 64));
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.iter().all(|&word| word == 0)
+    }
+
+    fn contains(&self, slot: u16) -> bool {
+        self.0[slot as usize / 64] & (1 << (slot % 64)) != 0
+    }
+
+    /// Returns the ranges of consecutive slots in the set, in order.
+    fn ranges(&self) -> Vec<SlotRange> {
+        let mut ranges: Vec<SlotRange> = vec![];
+
+        for slot in (0..CLUSTER_SLOTS).filter(|&slot| self.contains(slot)) {
+            match ranges.last_mut() {
+                Some(range) if range.end + 1 == slot => range.end = slot,
+                _ => ranges.push(SlotRange {
+                    start: slot,
+                    end: slot,
+                }),
+            }
+        }
+
+        ranges
+    }
+}
+
+fn bulk(s: &str) -> Frame {
+    Frame::Bulk(Bytes::from(s.to_string().into_bytes()))
+}
+
+/// Returns `time` as milliseconds since the Unix epoch, or 0 for `None`.
+fn unix_millis(time: Option<Instant>) -> u128 {
+    let time = match time {
+        Some(time) => SystemTime::now() - time.elapsed(),
+        None => return 0,
+    };
+
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or_default()
+}
+
+/// Returns a new random node id, of 40 hexadecimal characters.
+fn new_node_id() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+
+    scripting::sha1_hex(&format!(
+        "node-{}-{:?}-{}",
+        std::process::id(),
+        SystemTime::now(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_slots_honor_hash_tags() {
+        // Reference values from Redis's `CLUSTER KEYSLOT`
+        assert_eq!(12182, key_slot(b"foo"));
+        assert_eq!(5061, key_slot(b"bar"));
+        assert_eq!(key_slot(b"user:1000"), key_slot(b"{user:1000}:name"));
+        assert_eq!(key_slot(b"{user:1000}:name"), key_slot(b"{user:1000}:age"));
+
+        // Empty or unclosed tags hash the whole key
+        assert_eq!(CRC16.checksum(b"{}foo") % CLUSTER_SLOTS, key_slot(b"{}foo"));
+        assert_eq!(CRC16.checksum(b"{foo") % CLUSTER_SLOTS, key_slot(b"{foo"));
+
+        // Only the first tag counts
+        assert_eq!(key_slot(b"a"), key_slot(b"{a}{b}"));
+    }
+
+    #[test]
+    fn slot_sets_list_ranges() {
+        let ranges = vec![
+            SlotRange { start: 0, end: 10 },
+            SlotRange { start: 12, end: 12 },
+            SlotRange {
+                start: 16383,
+                end: 16383,
+            },
+        ];
+        let slots = SlotSet::from_ranges(&ranges);
+
+        assert!(slots.contains(10));
+        assert!(!slots.contains(11));
+        assert_eq!(ranges, slots.ranges());
+    }
+}
@@ -0,0 +1,265 @@
+use bytes::Bytes;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+use crate::{
+    acl::{self, Session},
+    connection::Connection,
+    db::Db,
+    frame::Frame,
+    parse::{Parse, ParseError},
+};
+
+/// Number of entries `ACL LOG` replies with when no count is given.
+const DEFAULT_LOG_COUNT: usize = 10;
+
+/// Manage the users of the server and their permissions.
+///
+/// Supported subcommands:
+/// - `ACL SETUSER username [rule ...]`: create the user or apply the rules to it
+/// - `ACL GETUSER username`: the rules of the user
+/// - `ACL DELUSER username [username ...]`: delete the users, replying with how many existed
+/// - `ACL LIST`: the users and their rules, in the format of the ACL file
+/// - `ACL WHOAMI`: the user the connection is authenticated as
+/// - `ACL CAT [category]`: the categories, or the commands of a category
+/// - `ACL LOG [count | RESET]`: the latest denials, or clear them
+#[derive(Debug)]
+pub enum Acl {
+    SetUser(String, Vec<String>),
+    GetUser(String),
+    DelUser(Vec<String>),
+    List,
+    WhoAmI,
+    Cat(Option<String>),
+    Log(Option<usize>),
+    LogReset,
+}
+
+impl Acl {
+    /// Parse an `Acl` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<Acl> {
+        // Note: the `ACL` string has already been consumed, next value is the subcommand
+        let subcommand = parse.next_string()?.to_lowercase();
+
+        match &subcommand[..] {
+            "setuser" => {
+                let name = parse.next_string()?;
+                let mut rules = vec![];
+
+                loop {
+                    match parse.next_string() {
+                        Ok(rule) => rules.push(rule),
+                        Err(ParseError::EndOfStream) => break,
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+
+                Ok(Acl::SetUser(name, rules))
+            }
+            "getuser" => Ok(Acl::GetUser(parse.next_string()?)),
+            "deluser" => {
+                let mut names = vec![parse.next_string()?];
+
+                loop {
+                    match parse.next_string() {
+                        Ok(name) => names.push(name),
+                        Err(ParseError::EndOfStream) => break,
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+
+                Ok(Acl::DelUser(names))
+            }
+            "list" => Ok(Acl::List),
+            "whoami" => Ok(Acl::WhoAmI),
+            "cat" => match parse.next_string() {
+                Ok(category) => Ok(Acl::Cat(Some(category))),
+                Err(ParseError::EndOfStream) => Ok(Acl::Cat(None)),
+                Err(err) => Err(err.into()),
+            },
+            "log" => match parse.next_string() {
+                Ok(arg) if arg.eq_ignore_ascii_case("reset") => Ok(Acl::LogReset),
+                Ok(arg) => match arg.parse() {
+                    Ok(count) => Ok(Acl::Log(Some(count))),
+                    Err(_) => Err("ERR value is out of range, must be positive".into()),
+                },
+                Err(ParseError::EndOfStream) => Ok(Acl::Log(None)),
+                Err(err) => Err(err.into()),
+            },
+            _ => Err(format!("ERR unknown subcommand '{}' for 'acl'", subcommand).into()),
+        }
+    }
+
+    /// Apply the `Acl` command to the users of the specified `Db` instance.
+    ///
+    /// The `Db` isn't locked, the users having their own lock.
+    pub(crate) async fn apply(
+        self,
+        db: &Db,
+        dst: &mut Connection,
+        session: &mut Session,
+    ) -> crate::FnResult<()> {
+        let response = self.execute(db.acl(), session);
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `Acl` command on the users of the server, returning the reply.
+    ///
+    /// `session` is the one of the connection running the command, for `ACL WHOAMI`.
+    pub(crate) fn execute(self, acl: &acl::Acl, session: &Session) -> Frame {
+        match self {
+            Acl::SetUser(name, rules) => match acl.set_user(&name, &rules) {
+                Ok(()) => Frame::Simple("OK".to_string()),
+                Err(msg) => Frame::Error(msg),
+            },
+            Acl::GetUser(name) => {
+                let user = match acl.user(&name) {
+                    Some(user) => user,
+                    None => return Frame::Null,
+                };
+
+                Frame::Array(vec![
+                    bulk("flags"),
+                    bulks(user.flags().into_iter().map(str::to_string)),
+                    bulk("passwords"),
+                    bulks(user.passwords().iter().cloned()),
+                    bulk("commands"),
+                    bulk(&user.describe_commands()),
+                    bulk("keys"),
+                    bulk(&user.describe_keys()),
+                    bulk("channels"),
+                    bulk(&user.describe_channels()),
+                    bulk("selectors"),
+                    Frame::array(),
+                ])
+            }
+            Acl::DelUser(names) => match acl.del_users(&names) {
+                Ok(deleted) => Frame::Integer(deleted as i64),
+                Err(msg) => Frame::Error(msg),
+            },
+            Acl::List => bulks(acl.list()),
+            Acl::WhoAmI => match session.username() {
+                Some(name) => Frame::Bulk(Bytes::from(name.to_string().into_bytes())),
+                None => Frame::Null,
+            },
+            Acl::Cat(None) => bulks(acl::CATEGORIES.iter().map(|category| category.to_string())),
+            Acl::Cat(Some(category)) => match acl::commands_in(&category.to_lowercase()) {
+                Some(commands) => bulks(commands.into_iter().map(str::to_string)),
+                None => Frame::Error(format!("ERR Unknown category '{}'", category)),
+            },
+            Acl::Log(count) => {
+                let now = SystemTime::now();
+                let entries = acl
+                    .log_entries(count.unwrap_or(DEFAULT_LOG_COUNT))
+                    .into_iter()
+                    .map(|entry| {
+                        let age = now.duration_since(entry.created).unwrap_or_default();
+
+                        let mut frame = Frame::array();
+                        frame.push_bulk(Bytes::from("count".as_bytes()));
+                        frame.push_int(entry.count);
+                        frame.push_bulk(Bytes::from("reason".as_bytes()));
+                        frame.push_bulk(Bytes::from(entry.reason.to_string().into_bytes()));
+                        frame.push_bulk(Bytes::from("context".as_bytes()));
+                        frame.push_bulk(Bytes::from(entry.context.as_bytes()));
+                        frame.push_bulk(Bytes::from("object".as_bytes()));
+                        frame.push_bulk(Bytes::from(entry.object.into_bytes()));
+                        frame.push_bulk(Bytes::from("username".as_bytes()));
+                        frame.push_bulk(Bytes::from(entry.username.into_bytes()));
+                        frame.push_bulk(Bytes::from("age-seconds".as_bytes()));
+                        frame.push_bulk(Bytes::from(
+                            format!("{:.3}", age.as_secs_f64()).into_bytes(),
+                        ));
+                        frame.push_bulk(Bytes::from("client-info".as_bytes()));
+                        frame.push_bulk(Bytes::from(entry.client_info.into_bytes()));
+                        frame.push_bulk(Bytes::from("entry-id".as_bytes()));
+                        frame.push_int(entry.entry_id);
+                        frame.push_bulk(Bytes::from("timestamp-created".as_bytes()));
+                        frame.push_int(millis(entry.created));
+                        frame.push_bulk(Bytes::from("timestamp-last-updated".as_bytes()));
+                        frame.push_int(millis(entry.updated));
+                        frame
+                    });
+
+                Frame::Array(entries.collect())
+            }
+            Acl::LogReset => {
+                acl.reset_log();
+                Frame::Simple("OK".to_string())
+            }
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("acl".as_bytes()));
+
+        match self {
+            Acl::SetUser(name, rules) => {
+                frame.push_bulk(Bytes::from("setuser".as_bytes()));
+                frame.push_bulk(Bytes::from(name.into_bytes()));
+                for rule in rules {
+                    frame.push_bulk(Bytes::from(rule.into_bytes()));
+                }
+            }
+            Acl::GetUser(name) => {
+                frame.push_bulk(Bytes::from("getuser".as_bytes()));
+                frame.push_bulk(Bytes::from(name.into_bytes()));
+            }
+            Acl::DelUser(names) => {
+                frame.push_bulk(Bytes::from("deluser".as_bytes()));
+                for name in names {
+                    frame.push_bulk(Bytes::from(name.into_bytes()));
+                }
+            }
+            Acl::List => frame.push_bulk(Bytes::from("list".as_bytes())),
+            Acl::WhoAmI => frame.push_bulk(Bytes::from("whoami".as_bytes())),
+            Acl::Cat(category) => {
+                frame.push_bulk(Bytes::from("cat".as_bytes()));
+                if let Some(category) = category {
+                    frame.push_bulk(Bytes::from(category.into_bytes()));
+                }
+            }
+            Acl::Log(count) => {
+                frame.push_bulk(Bytes::from("log".as_bytes()));
+                if let Some(count) = count {
+                    frame.push_bulk(Bytes::from(count.to_string().into_bytes()));
+                }
+            }
+            Acl::LogReset => {
+                frame.push_bulk(Bytes::from("log".as_bytes()));
+                frame.push_bulk(Bytes::from("reset".as_bytes()));
+            }
+        }
+
+        frame
+    }
+}
+
+/// Bulk string.
+fn bulk(value: &str) -> Frame {
+    Frame::Bulk(Bytes::from(value.to_string().into_bytes()))
+}
+
+/// Array of bulk strings.
+fn bulks(values: impl IntoIterator<Item = String>) -> Frame {
+    Frame::Array(
+        values
+            .into_iter()
+            .map(|value| Frame::Bulk(Bytes::from(value.into_bytes())))
+            .collect(),
+    )
+}
+
+/// Milliseconds since the Unix epoch.
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
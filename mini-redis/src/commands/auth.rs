@@ -0,0 +1,109 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    acl::{Session, DEFAULT_USER},
+    connection::Connection,
+    db::Db,
+    frame::Frame,
+    parse::{Parse, ParseError},
+};
+
+/// Authenticate the connection as a user.
+///
+/// `AUTH password` authenticates as the `default` user, `AUTH username password` as any.
+#[derive(Debug)]
+pub struct Auth {
+    username: Option<String>,
+    password: String,
+}
+
+impl Auth {
+    pub fn new(username: Option<&str>, password: &str) -> Auth {
+        Auth {
+            username: username.map(str::to_string),
+            password: password.to_string(),
+        }
+    }
+
+    /// Parse an `Auth` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<Auth> {
+        // Note: the `AUTH` string has already been consumed, next value is the password, or
+        // the username followed by the password
+        let first = parse.next_string()?;
+
+        match parse.next_string() {
+            Ok(password) => Ok(Auth {
+                username: Some(first),
+                password,
+            }),
+            Err(ParseError::EndOfStream) => Ok(Auth {
+                username: None,
+                password: first,
+            }),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Apply the `Auth` command, authenticating the `session` of the connection.
+    pub(crate) async fn apply(
+        self,
+        db: &Db,
+        dst: &mut Connection,
+        session: &mut Session,
+    ) -> crate::FnResult<()> {
+        let response = self.execute(db, session);
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `Auth` command, returning the reply.
+    ///
+    /// Failed authentications are logged, and leave the connection authenticated as before.
+    pub(crate) fn execute(self, db: &Db, session: &mut Session) -> Frame {
+        let acl = db.acl();
+
+        if self.username.is_none() && acl.is_nopass(DEFAULT_USER) {
+            return Frame::Error(
+                "ERR AUTH <password> called without any password configured for the default user. \
+                 Are you sure your configuration is correct?"
+                    .to_string(),
+            );
+        }
+
+        let username = self.username.as_deref().unwrap_or(DEFAULT_USER);
+
+        match acl.authenticate(username, &self.password) {
+            Some(id) => {
+                session.authenticate(username, id);
+                Frame::Simple("OK".to_string())
+            }
+            None => {
+                let log_max_len = db.config().read(|config| config.acllog_max_len);
+                acl.log_auth_failure(session, username, log_max_len);
+
+                Frame::Error(
+                    "WRONGPASS invalid username-password pair or user is disabled.".to_string(),
+                )
+            }
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("auth".as_bytes()));
+
+        if let Some(username) = self.username {
+            frame.push_bulk(Bytes::from(username.into_bytes()));
+        }
+
+        frame.push_bulk(Bytes::from(self.password.into_bytes()));
+
+        frame
+    }
+}
@@ -0,0 +1,44 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{connection::Connection, db::Db, frame::Frame, parse::Parse};
+
+/// Rewrite the append-only file in the background, as the shortest sequence of commands
+/// recreating the databases.
+#[derive(Debug, Default)]
+pub struct BgRewriteAof;
+
+impl BgRewriteAof {
+    pub fn new() -> BgRewriteAof {
+        BgRewriteAof
+    }
+
+    /// Parse a `BgRewriteAof` instance from a received frame.
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::FnResult<BgRewriteAof> {
+        // Note: the `BGREWRITEAOF` string has already been consumed, it has no argument
+        Ok(BgRewriteAof)
+    }
+
+    /// Apply the `BgRewriteAof` command to the specified `Db` instance.
+    ///
+    /// Replies right away, the file is rewritten by a background task.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = match db.bgrewriteaof() {
+            Ok(()) => Frame::Simple("Background append only file rewriting started".to_string()),
+            Err(msg) => Frame::Error(msg.to_string()),
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("bgrewriteaof".as_bytes()));
+        frame
+    }
+}
@@ -0,0 +1,337 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    cluster,
+    connection::Connection,
+    constants::{CLUSTER_BUS_PORT_OFFSET, CLUSTER_SLOTS},
+    db::{Db, DbGuard},
+    frame::Frame,
+    parse::{Parse, ParseError},
+};
+
+/// Reply to the cluster commands, when the cluster mode is disabled.
+const CLUSTER_DISABLED_ERROR: &str = "ERR This instance has cluster support disabled";
+
+/// Inspect the cluster, in cluster mode.
+///
+/// Supported subcommands:
+/// - `CLUSTER SLOTS`: the ranges of slots served by each node, with its address and id
+/// - `CLUSTER SHARDS`: the slots and nodes of each shard
+/// - `CLUSTER KEYSLOT key`: the hash slot of a key
+/// - `CLUSTER COUNTKEYSINSLOT slot`: the number of keys of this node in a slot
+/// - `CLUSTER GETKEYSINSLOT slot count`: up to `count` keys of this node in a slot
+/// - `CLUSTER MYID`: the id of this node
+/// - `CLUSTER NODES`: the nodes known by this node, a line each
+/// - `CLUSTER INFO`: the state of the cluster, as seen by this node
+/// - `CLUSTER MEET host port [bus_port]`: introduce this node to another one, on its cluster bus
+/// - `CLUSTER ADDSLOTS slot [slot ...]`: serve unassigned slots
+/// - `CLUSTER FORGET id`: remove a node from the nodes known by this node
+/// - `CLUSTER SETSLOT slot IMPORTING|MIGRATING id`, `CLUSTER SETSLOT slot STABLE` and
+///   `CLUSTER SETSLOT slot NODE id`: migrate a slot between two nodes, see `SetSlot`
+#[derive(Debug)]
+pub enum Cluster {
+    Slots,
+    Shards,
+    KeySlot(String),
+    CountKeysInSlot(u16),
+    GetKeysInSlot(u16, u64),
+    MyId,
+    Nodes,
+    Info,
+    Meet(String, u16, Option<u16>),
+    AddSlots(Vec<u16>),
+    Forget(String),
+    SetSlot(u16, SetSlot),
+}
+
+/// Migration state of a slot, set with `CLUSTER SETSLOT`.
+///
+/// A slot is migrated from a node to another by flagging it as importing on the target
+/// node, and as migrating on the source node. The keys are then moved with `MIGRATE`,
+/// meanwhile the source node redirects the commands on the keys it doesn't hold any more
+/// to the target node with `ASK`. The slot is finally assigned to the target node.
+#[derive(Debug)]
+pub enum SetSlot {
+    /// The slot is imported from the node with the given id
+    Importing(String),
+
+    /// The slot is migrated to the node with the given id
+    Migrating(String),
+
+    /// The migration of the slot is cancelled
+    Stable,
+
+    /// The slot is served by the node with the given id, ending its migration
+    Node(String),
+}
+
+/// Let the next command access a slot being imported by this node, as redirected with
+/// `ASK` by the node migrating the slot.
+#[derive(Debug, Default)]
+pub struct Asking;
+
+impl Cluster {
+    /// Parse a `Cluster` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<Cluster> {
+        // Note: the `CLUSTER` string has already been consumed, next value is the subcommand
+        let subcommand = parse.next_string()?.to_lowercase();
+
+        match &subcommand[..] {
+            "slots" => Ok(Cluster::Slots),
+            "shards" => Ok(Cluster::Shards),
+            "keyslot" => Ok(Cluster::KeySlot(parse.next_string()?)),
+            "countkeysinslot" => Ok(Cluster::CountKeysInSlot(parse_slot(parse)?)),
+            "getkeysinslot" => {
+                let slot = parse_slot(parse)?;
+
+                match parse.next_string()?.parse() {
+                    Ok(count) => Ok(Cluster::GetKeysInSlot(slot, count)),
+                    Err(_) => Err("ERR Invalid number of keys".into()),
+                }
+            }
+            "myid" => Ok(Cluster::MyId),
+            "nodes" => Ok(Cluster::Nodes),
+            "info" => Ok(Cluster::Info),
+            "meet" => {
+                let host = parse.next_string()?;
+                let port = parse_port(&parse.next_string()?)?;
+
+                let bus_port = match parse.next_string() {
+                    Ok(bus_port) => Some(parse_port(&bus_port)?),
+                    Err(ParseError::EndOfStream) => None,
+                    Err(err) => return Err(err.into()),
+                };
+
+                Ok(Cluster::Meet(host, port, bus_port))
+            }
+            "addslots" => {
+                let mut slots = vec![parse_slot(parse)?];
+
+                loop {
+                    match parse.next_string() {
+                        Ok(slot) => slots.push(slot_from_str(&slot)?),
+                        Err(ParseError::EndOfStream) => return Ok(Cluster::AddSlots(slots)),
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+            }
+            "forget" => Ok(Cluster::Forget(parse.next_string()?)),
+            "setslot" => {
+                let slot = parse_slot(parse)?;
+
+                let state = match &parse.next_string()?.to_lowercase()[..] {
+                    "importing" => SetSlot::Importing(parse.next_string()?),
+                    "migrating" => SetSlot::Migrating(parse.next_string()?),
+                    "stable" => SetSlot::Stable,
+                    "node" => SetSlot::Node(parse.next_string()?),
+                    _ => {
+                        return Err(
+                            "ERR Invalid CLUSTER SETSLOT action or number of arguments.".into()
+                        )
+                    }
+                };
+
+                Ok(Cluster::SetSlot(slot, state))
+            }
+            _ => Err(format!("ERR unknown subcommand '{}' for 'cluster'", subcommand).into()),
+        }
+    }
+
+    /// Apply the `Cluster` command to the specified `Db` instance.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = match db.lock_unless_busy().await {
+            Ok(mut guard) => self.execute(&mut guard),
+            Err(response) => response,
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `Cluster` command on the locked `Db`, returning the reply.
+    pub(crate) fn execute(self, db: &mut DbGuard) -> Frame {
+        let offset = db.replication().offset();
+
+        let keys = match &self {
+            Cluster::SetSlot(slot, SetSlot::Node(_)) => db.count_keys_in_slot(*slot),
+            _ => 0,
+        };
+
+        let cluster = match db.cluster() {
+            Some(cluster) => cluster,
+            None => return Frame::Error(CLUSTER_DISABLED_ERROR.to_string()),
+        };
+
+        match self {
+            Cluster::Slots => cluster.slots(),
+            Cluster::Shards => cluster.shards(offset),
+            Cluster::KeySlot(key) => Frame::Integer(cluster::key_slot(key.as_bytes()) as i64),
+            Cluster::MyId => Frame::Bulk(Bytes::from(cluster.myself().to_string().into_bytes())),
+            Cluster::Nodes => Frame::Bulk(Bytes::from(cluster.nodes_description().into_bytes())),
+            Cluster::Info => Frame::Bulk(Bytes::from(cluster.info().into_bytes())),
+            Cluster::Meet(host, port, bus_port) => {
+                match bus_port.or_else(|| port.checked_add(CLUSTER_BUS_PORT_OFFSET)) {
+                    Some(bus_port) => {
+                        cluster.meet(&host, port, bus_port);
+                        Frame::Simple("OK".to_string())
+                    }
+                    None => Frame::Error(format!(
+                        "ERR Invalid node address specified: {}:{}",
+                        host, port
+                    )),
+                }
+            }
+            Cluster::AddSlots(slots) => match cluster.add_slots(&slots) {
+                Ok(()) => Frame::Simple("OK".to_string()),
+                Err(err) => Frame::Error(err),
+            },
+            Cluster::Forget(id) => match cluster.forget(&id) {
+                Ok(()) => Frame::Simple("OK".to_string()),
+                Err(err) => Frame::Error(err),
+            },
+            Cluster::SetSlot(slot, state) => {
+                let res = match state {
+                    SetSlot::Importing(id) => cluster.import_slot(slot, &id),
+                    SetSlot::Migrating(id) => cluster.migrate_slot(slot, &id),
+                    SetSlot::Stable => {
+                        cluster.stabilize_slot(slot);
+                        Ok(())
+                    }
+                    SetSlot::Node(id) => cluster.set_slot_node(slot, &id, keys),
+                };
+
+                match res {
+                    Ok(()) => Frame::Simple("OK".to_string()),
+                    Err(err) => Frame::Error(err),
+                }
+            }
+            Cluster::CountKeysInSlot(slot) => Frame::Integer(db.count_keys_in_slot(slot) as i64),
+            Cluster::GetKeysInSlot(slot, count) => {
+                let mut frame = Frame::array();
+                for key in db.keys_in_slot(slot, count as usize) {
+                    frame.push_bulk(Bytes::from(key.into_bytes()));
+                }
+                frame
+            }
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("cluster".as_bytes()));
+
+        match self {
+            Cluster::Slots => frame.push_bulk(Bytes::from("slots".as_bytes())),
+            Cluster::Shards => frame.push_bulk(Bytes::from("shards".as_bytes())),
+            Cluster::KeySlot(key) => {
+                frame.push_bulk(Bytes::from("keyslot".as_bytes()));
+                frame.push_bulk(Bytes::from(key.into_bytes()));
+            }
+            Cluster::CountKeysInSlot(slot) => {
+                frame.push_bulk(Bytes::from("countkeysinslot".as_bytes()));
+                frame.push_bulk(Bytes::from(slot.to_string().into_bytes()));
+            }
+            Cluster::GetKeysInSlot(slot, count) => {
+                frame.push_bulk(Bytes::from("getkeysinslot".as_bytes()));
+                frame.push_bulk(Bytes::from(slot.to_string().into_bytes()));
+                frame.push_bulk(Bytes::from(count.to_string().into_bytes()));
+            }
+            Cluster::MyId => frame.push_bulk(Bytes::from("myid".as_bytes())),
+            Cluster::Nodes => frame.push_bulk(Bytes::from("nodes".as_bytes())),
+            Cluster::Info => frame.push_bulk(Bytes::from("info".as_bytes())),
+            Cluster::Meet(host, port, bus_port) => {
+                frame.push_bulk(Bytes::from("meet".as_bytes()));
+                frame.push_bulk(Bytes::from(host.into_bytes()));
+                frame.push_bulk(Bytes::from(port.to_string().into_bytes()));
+                if let Some(bus_port) = bus_port {
+                    frame.push_bulk(Bytes::from(bus_port.to_string().into_bytes()));
+                }
+            }
+            Cluster::AddSlots(slots) => {
+                frame.push_bulk(Bytes::from("addslots".as_bytes()));
+                for slot in slots {
+                    frame.push_bulk(Bytes::from(slot.to_string().into_bytes()));
+                }
+            }
+            Cluster::Forget(id) => {
+                frame.push_bulk(Bytes::from("forget".as_bytes()));
+                frame.push_bulk(Bytes::from(id.into_bytes()));
+            }
+            Cluster::SetSlot(slot, state) => {
+                frame.push_bulk(Bytes::from("setslot".as_bytes()));
+                frame.push_bulk(Bytes::from(slot.to_string().into_bytes()));
+
+                let (state, id) = match state {
+                    SetSlot::Importing(id) => ("importing", Some(id)),
+                    SetSlot::Migrating(id) => ("migrating", Some(id)),
+                    SetSlot::Stable => ("stable", None),
+                    SetSlot::Node(id) => ("node", Some(id)),
+                };
+
+                frame.push_bulk(Bytes::from(state.as_bytes()));
+                if let Some(id) = id {
+                    frame.push_bulk(Bytes::from(id.into_bytes()));
+                }
+            }
+        }
+
+        frame
+    }
+}
+
+impl Asking {
+    pub fn new() -> Asking {
+        Asking
+    }
+
+    /// Parse an `Asking` instance from a received frame.
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::FnResult<Asking> {
+        // Note: the `ASKING` string has already been consumed, there's no argument
+        Ok(Asking)
+    }
+
+    /// Apply the `Asking` command, the connection lets the next command through.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = if db.is_cluster() {
+            Frame::Simple("OK".to_string())
+        } else {
+            Frame::Error(CLUSTER_DISABLED_ERROR.to_string())
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("asking".as_bytes()));
+        frame
+    }
+}
+
+/// Parse a hash slot argument.
+fn parse_slot(parse: &mut Parse) -> crate::FnResult<u16> {
+    slot_from_str(&parse.next_string()?)
+}
+
+fn slot_from_str(s: &str) -> crate::FnResult<u16> {
+    match s.parse() {
+        Ok(slot) if slot < CLUSTER_SLOTS => Ok(slot),
+        _ => Err("ERR Invalid slot".into()),
+    }
+}
+
+/// Parse a port argument of `CLUSTER MEET`.
+fn parse_port(s: &str) -> crate::FnResult<u16> {
+    s.parse()
+        .map_err(|_| format!("ERR Invalid node address specified: {}", s).into())
+}
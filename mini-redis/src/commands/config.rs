@@ -0,0 +1,155 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    acl::Acl,
+    config::{self, LiveConfig},
+    connection::Connection,
+    db::Db,
+    frame::Frame,
+    glob,
+    parse::{Parse, ParseError},
+};
+
+/// Read and change the configuration of the server.
+///
+/// Supported subcommands:
+/// - `CONFIG GET pattern [pattern ...]`: the parameters matching any of the glob-style
+///   patterns, and their values
+/// - `CONFIG SET parameter value [parameter value ...]`: change parameters at runtime,
+///   either all of them or none if one can't be set
+/// - `CONFIG REWRITE`: write the configuration back to the file the server started with
+#[derive(Debug)]
+pub enum Config {
+    Get(Vec<String>),
+    Set(Vec<(String, String)>),
+    Rewrite,
+}
+
+impl Config {
+    /// Parse a `Config` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<Config> {
+        // Note: the `CONFIG` string has already been consumed, next value is the subcommand
+        let subcommand = parse.next_string()?.to_lowercase();
+
+        match &subcommand[..] {
+            "get" => {
+                let mut patterns = vec![parse.next_string()?];
+
+                loop {
+                    match parse.next_string() {
+                        Ok(pattern) => patterns.push(pattern),
+                        Err(ParseError::EndOfStream) => break,
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+
+                Ok(Config::Get(patterns))
+            }
+            "set" => {
+                let mut parameters = vec![(parse.next_string()?, parse.next_string()?)];
+
+                loop {
+                    match parse.next_string() {
+                        Ok(name) => parameters.push((name, parse.next_string()?)),
+                        Err(ParseError::EndOfStream) => break,
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+
+                Ok(Config::Set(parameters))
+            }
+            "rewrite" => Ok(Config::Rewrite),
+            _ => Err(format!("ERR unknown subcommand '{}' for 'config'", subcommand).into()),
+        }
+    }
+
+    /// Apply the `Config` command to the specified `Db` instance.
+    ///
+    /// The `Db` isn't locked, the configuration having its own lock.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = self.execute(db.config(), db.acl());
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `Config` command on the server configuration, returning the reply.
+    ///
+    /// Setting `requirepass` replaces the passwords of the `default` user of the `acl`.
+    pub(crate) fn execute(self, live: &LiveConfig, acl: &Acl) -> Frame {
+        match self {
+            Config::Get(patterns) => live.read(|config| {
+                let mut frame = Frame::array();
+
+                for name in config::Config::PARAMETERS {
+                    if patterns.iter().any(|pattern| {
+                        glob::matches(pattern.to_lowercase().as_bytes(), name.as_bytes())
+                    }) {
+                        frame.push_bulk(Bytes::from(name.as_bytes()));
+                        frame.push_bulk(Bytes::from(
+                            config.get(name).unwrap_or_default().into_bytes(),
+                        ));
+                    }
+                }
+
+                frame
+            }),
+            Config::Set(parameters) => match live.set(&parameters) {
+                Ok(()) => {
+                    if parameters
+                        .iter()
+                        .any(|(name, _)| name.eq_ignore_ascii_case("requirepass"))
+                    {
+                        acl.set_default_password(
+                            live.read(|config| config.requirepass.clone()).as_deref(),
+                        );
+                    }
+
+                    Frame::Simple("OK".to_string())
+                }
+                Err(msg) => Frame::Error(msg),
+            },
+            Config::Rewrite => live.read(|config| {
+                if config.config_file.is_none() {
+                    return Frame::Error(
+                        "ERR The server is running without a config file".to_string(),
+                    );
+                }
+
+                match config.rewrite() {
+                    Ok(()) => Frame::Simple("OK".to_string()),
+                    Err(err) => Frame::Error(format!("ERR Rewriting config file: {}", err)),
+                }
+            }),
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("config".as_bytes()));
+
+        match self {
+            Config::Get(patterns) => {
+                frame.push_bulk(Bytes::from("get".as_bytes()));
+                for pattern in patterns {
+                    frame.push_bulk(Bytes::from(pattern.into_bytes()));
+                }
+            }
+            Config::Set(parameters) => {
+                frame.push_bulk(Bytes::from("set".as_bytes()));
+                for (name, value) in parameters {
+                    frame.push_bulk(Bytes::from(name.into_bytes()));
+                    frame.push_bulk(Bytes::from(value.into_bytes()));
+                }
+            }
+            Config::Rewrite => frame.push_bulk(Bytes::from("rewrite".as_bytes())),
+        }
+
+        frame
+    }
+}
@@ -0,0 +1,51 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    connection::Connection,
+    db::{Db, DbGuard},
+    frame::Frame,
+    parse::Parse,
+};
+
+/// Return the number of keys in the selected database.
+#[derive(Debug, Default)]
+pub struct DbSize;
+
+impl DbSize {
+    pub fn new() -> DbSize {
+        DbSize
+    }
+
+    /// Parse a `DbSize` instance from a received frame.
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::FnResult<DbSize> {
+        // Note: the `DBSIZE` string has already been consumed, it has no argument
+        Ok(DbSize)
+    }
+
+    /// Apply the `DbSize` command to the specified `Db` instance.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = match db.lock_unless_busy().await {
+            Ok(mut guard) => self.execute(&mut guard),
+            Err(response) => response,
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `DbSize` command on the locked `Db`, returning the reply.
+    pub(crate) fn execute(self, db: &mut DbGuard) -> Frame {
+        Frame::Integer(db.size() as i64)
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("dbsize".as_bytes()));
+        frame
+    }
+}
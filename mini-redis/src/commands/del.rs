@@ -0,0 +1,75 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    connection::Connection,
+    db::{Db, DbGuard},
+    frame::Frame,
+    parse::{Parse, ParseError},
+};
+
+/// Remove the specified keys. A key is ignored if it does not exist.
+#[derive(Debug)]
+pub struct Del {
+    keys: Vec<String>,
+}
+
+impl Del {
+    pub fn new(keys: &[String]) -> Del {
+        Del {
+            keys: keys.to_vec(),
+        }
+    }
+
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// Parse a `Del` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<Del> {
+        // Note: the `DEL` string has already been consumed, next values are the keys, at least one is required
+        let mut keys = vec![parse.next_string()?];
+
+        loop {
+            match parse.next_string() {
+                Ok(key) => keys.push(key),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(Del { keys })
+    }
+
+    /// Apply the `Del` command to the specified `Db` instance.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        // Only the shards of the keys are locked, other keys remain available
+        let response = match db.lock_keys_unless_busy(&self.keys).await {
+            Ok(mut guard) => self.execute(&mut guard),
+            Err(response) => response,
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `Del` command on the locked `Db`, returning the reply.
+    pub(crate) fn execute(self, db: &mut DbGuard) -> Frame {
+        Frame::Integer(db.del(&self.keys) as i64)
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("del".as_bytes()));
+
+        for key in self.keys {
+            frame.push_bulk(Bytes::from(key.into_bytes()));
+        }
+
+        frame
+    }
+}
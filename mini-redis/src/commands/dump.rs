@@ -0,0 +1,261 @@
+use bytes::Bytes;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::{
+    connection::Connection,
+    db::{Db, DbGuard},
+    frame::Frame,
+    parse::{Parse, ParseError},
+    rdb,
+};
+
+/// Reply to `RESTORE` with a TTL too far in the future.
+const INVALID_EXPIRE_ERROR: &str = "ERR invalid expire time in 'restore' command";
+
+/// Serialize the value of a key, to recreate it with `RESTORE`.
+///
+/// The payload is opaque: it holds the value in the RDB encoding, along with the RDB
+/// version and a checksum.
+#[derive(Debug)]
+pub struct Dump {
+    key: String,
+}
+
+/// Create a key from a payload returned by `DUMP`.
+///
+/// Options:
+/// - `REPLACE`: overwrite the key if it already exists
+/// - `ABSTTL`: the TTL is a Unix time in milliseconds, rather than a duration
+/// - `IDLETIME seconds`: how long ago the key was last accessed, as eviction policies see it
+#[derive(Debug)]
+pub struct Restore {
+    key: String,
+
+    /// TTL in milliseconds, `0` for none
+    ttl: u64,
+
+    payload: Bytes,
+    replace: bool,
+    absttl: bool,
+    idletime: Option<u64>,
+}
+
+impl Dump {
+    pub fn new(key: impl ToString) -> Dump {
+        Dump {
+            key: key.to_string(),
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Parse a `Dump` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<Dump> {
+        // Note: the `DUMP` string has already been consumed, next value is the key
+        Ok(Dump {
+            key: parse.next_string()?,
+        })
+    }
+
+    /// Apply the `Dump` command to the specified `Db` instance.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        // Only the shard of the key is locked, other keys remain available
+        let response = match db.lock_keys_unless_busy(&[&self.key]).await {
+            Ok(mut guard) => self.execute(&mut guard),
+            Err(response) => response,
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `Dump` command on the locked `Db`, returning the reply.
+    ///
+    /// Replies a null if the key doesn't exist.
+    pub(crate) fn execute(self, db: &mut DbGuard) -> Frame {
+        match db.get(&self.key) {
+            Some(value) => Frame::Bulk(Bytes::from(rdb::dump(&value))),
+            None => Frame::Null,
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("dump".as_bytes()));
+        frame.push_bulk(Bytes::from(self.key.into_bytes()));
+        frame
+    }
+}
+
+impl Restore {
+    /// Create a `Restore` command, expiring the key after `ttl` if set.
+    pub fn new(
+        key: impl ToString,
+        ttl: Option<Duration>,
+        payload: Bytes,
+        replace: bool,
+    ) -> Restore {
+        Restore {
+            key: key.to_string(),
+            ttl: ttl.map_or(0, |ttl| ttl.as_millis() as u64),
+            payload,
+            replace,
+            absttl: false,
+            idletime: None,
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Parse a `Restore` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<Restore> {
+        // Note: the `RESTORE` string has already been consumed, next values are the key,
+        // the TTL, the payload and the options
+        let key = parse.next_string()?;
+        let ttl = parse.next_int()?;
+        let payload = parse.next_bytes()?;
+
+        let mut restore = Restore {
+            key,
+            ttl,
+            payload,
+            replace: false,
+            absttl: false,
+            idletime: None,
+        };
+
+        loop {
+            match parse.next_string() {
+                Ok(option) => match &option.to_uppercase()[..] {
+                    "REPLACE" => restore.replace = true,
+                    "ABSTTL" => restore.absttl = true,
+                    "IDLETIME" => restore.idletime = Some(parse.next_int()?),
+                    _ => return Err("ERR syntax error".into()),
+                },
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(restore)
+    }
+
+    /// Apply the `Restore` command to the specified `Db` instance.
+    ///
+    /// The payload is decoded before the shard of the key is locked.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = match rdb::undump(&self.payload) {
+            // Only the shard of the key is locked, other keys remain available
+            Ok(value) => match db.lock_keys_unless_busy(&[&self.key]).await {
+                Ok(mut guard) => self.restore(&mut guard, value),
+                Err(response) => response,
+            },
+            Err(err) => Frame::Error(format!("ERR {}", err)),
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `Restore` command on the locked `Db`, returning the reply.
+    ///
+    /// A key whose absolute TTL already passed isn't created.
+    pub(crate) fn execute(self, db: &mut DbGuard) -> Frame {
+        match rdb::undump(&self.payload) {
+            Ok(value) => self.restore(db, value),
+            Err(err) => Frame::Error(format!("ERR {}", err)),
+        }
+    }
+
+    /// Create the key with the `value` decoded from the payload.
+    fn restore(self, db: &mut DbGuard, value: Bytes) -> Frame {
+        if !self.replace && db.get(&self.key).is_some() {
+            return Frame::Error("BUSYKEY Target key name already exists.".to_string());
+        }
+
+        // As in Redis, the expiration must be a Unix time in milliseconds which fits in an
+        // `i64`, and an `Instant` on this platform
+        let now = SystemTime::now();
+        let expires_at = match self.absttl {
+            true => Some(self.ttl),
+            false => (now
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64)
+                .checked_add(self.ttl),
+        };
+
+        if self.ttl != 0 && expires_at.is_none_or(|expires_at| expires_at > i64::MAX as u64) {
+            return Frame::Error(INVALID_EXPIRE_ERROR.to_string());
+        }
+
+        let expire = match (self.ttl, self.absttl) {
+            (0, _) => None,
+            (ttl, false) => Some(Duration::from_millis(ttl)),
+            (ttl, true) => match (UNIX_EPOCH + Duration::from_millis(ttl)).duration_since(now) {
+                Ok(expire) => Some(expire),
+                Err(_) => {
+                    db.del(&[self.key]);
+                    return Frame::Simple("OK".to_string());
+                }
+            },
+        };
+
+        if expire.is_some_and(|expire| Instant::now().checked_add(expire).is_none()) {
+            return Frame::Error(INVALID_EXPIRE_ERROR.to_string());
+        }
+
+        let accessed_at = match self.idletime {
+            Some(idletime) => match Instant::now().checked_sub(Duration::from_secs(idletime)) {
+                Some(accessed_at) => Some(accessed_at),
+                None => return Frame::Error("ERR Invalid IDLETIME value".to_string()),
+            },
+            None => None,
+        };
+
+        db.set(self.key.clone(), value, expire);
+
+        if let Some(accessed_at) = accessed_at {
+            db.set_accessed_at(&self.key, accessed_at);
+        }
+
+        Frame::Simple("OK".to_string())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("restore".as_bytes()));
+        frame.push_bulk(Bytes::from(self.key.into_bytes()));
+        frame.push_int(self.ttl);
+        frame.push_bulk(self.payload);
+
+        if self.replace {
+            frame.push_bulk(Bytes::from("replace".as_bytes()));
+        }
+
+        if self.absttl {
+            frame.push_bulk(Bytes::from("absttl".as_bytes()));
+        }
+
+        if let Some(idletime) = self.idletime {
+            frame.push_bulk(Bytes::from("idletime".as_bytes()));
+            frame.push_int(idletime);
+        }
+
+        frame
+    }
+}
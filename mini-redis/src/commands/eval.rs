@@ -0,0 +1,208 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    acl::Session,
+    connection::Connection,
+    db::{Db, DbGuard},
+    frame::Frame,
+    parse::{Parse, ParseError},
+    scripting,
+};
+
+/// Run a Lua script on the server, atomically.
+///
+/// The script reads its keys from the `KEYS` table and its other arguments from `ARGV`,
+/// and applies commands with `redis.call` or `redis.pcall`. The script is cached, so that
+/// it can later be run with `EVALSHA`.
+#[derive(Debug)]
+pub struct Eval {
+    script: String,
+    keys: Vec<String>,
+    args: Vec<Bytes>,
+}
+
+/// Run a Lua script cached by `EVAL` or `SCRIPT LOAD`, given its SHA1 digest.
+#[derive(Debug)]
+pub struct EvalSha {
+    sha1: String,
+    keys: Vec<String>,
+    args: Vec<Bytes>,
+}
+
+impl Eval {
+    pub fn new(script: impl ToString, keys: Vec<String>, args: Vec<Bytes>) -> Eval {
+        Eval {
+            script: script.to_string(),
+            keys,
+            args,
+        }
+    }
+
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// Parse an `Eval` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<Eval> {
+        // Note: the `EVAL` string has already been consumed, next values are the script,
+        // the number of keys, the keys and the arguments
+        let script = parse.next_string()?;
+        let (keys, args) = parse_keys_and_args(parse)?;
+
+        Ok(Eval { script, keys, args })
+    }
+
+    /// Apply the `Eval` command to the specified `Db` instance.
+    ///
+    /// The script runs on a blocking thread, as it holds the `Db` lock until done.
+    pub(crate) async fn apply(
+        self,
+        db: &Db,
+        dst: &mut Connection,
+        session: &Session,
+    ) -> crate::FnResult<()> {
+        let db = db.clone();
+        let session = session.clone();
+        let response = tokio::task::spawn_blocking(move || {
+            // Queued before locking, for the commands to wait for the script rather than
+            // block on the lock
+            let _queued = db.scripts().queue();
+            let mut guard = db.lock();
+            self.execute(&mut guard, &session)
+        })
+        .await?;
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `Eval` command on the locked `Db`, returning the reply.
+    ///
+    /// The commands called by the script are checked against the permissions of `session`.
+    pub(crate) fn execute(self, db: &mut DbGuard, session: &Session) -> Frame {
+        db.scripts().load(&self.script);
+
+        scripting::eval(db, session, &self.script, self.keys, self.args)
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("eval".as_bytes()));
+        frame.push_bulk(Bytes::from(self.script.into_bytes()));
+        push_keys_and_args(&mut frame, self.keys, self.args);
+        frame
+    }
+}
+
+impl EvalSha {
+    pub fn new(sha1: impl ToString, keys: Vec<String>, args: Vec<Bytes>) -> EvalSha {
+        EvalSha {
+            sha1: sha1.to_string(),
+            keys,
+            args,
+        }
+    }
+
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// Parse an `EvalSha` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<EvalSha> {
+        // Note: the `EVALSHA` string has already been consumed, next values are the SHA1
+        // digest, the number of keys, the keys and the arguments
+        let sha1 = parse.next_string()?;
+        let (keys, args) = parse_keys_and_args(parse)?;
+
+        Ok(EvalSha { sha1, keys, args })
+    }
+
+    /// Apply the `EvalSha` command to the specified `Db` instance.
+    ///
+    /// The script runs on a blocking thread, as it holds the `Db` lock until done.
+    pub(crate) async fn apply(
+        self,
+        db: &Db,
+        dst: &mut Connection,
+        session: &Session,
+    ) -> crate::FnResult<()> {
+        let db = db.clone();
+        let session = session.clone();
+        let response = tokio::task::spawn_blocking(move || {
+            // Queued before locking, for the commands to wait for the script rather than
+            // block on the lock
+            let _queued = db.scripts().queue();
+            let mut guard = db.lock();
+            self.execute(&mut guard, &session)
+        })
+        .await?;
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `EvalSha` command on the locked `Db`, returning the reply.
+    ///
+    /// Replies a `NOSCRIPT` error if the script isn't cached. The commands called by the
+    /// script are checked against the permissions of `session`.
+    pub(crate) fn execute(self, db: &mut DbGuard, session: &Session) -> Frame {
+        match db.scripts().get(&self.sha1) {
+            Some(script) => scripting::eval(db, session, &script, self.keys, self.args),
+            None => Frame::Error("NOSCRIPT No matching script. Please use EVAL.".to_string()),
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("evalsha".as_bytes()));
+        frame.push_bulk(Bytes::from(self.sha1.into_bytes()));
+        push_keys_and_args(&mut frame, self.keys, self.args);
+        frame
+    }
+}
+
+/// Parse the number of keys, followed by the keys and the remaining arguments.
+fn parse_keys_and_args(parse: &mut Parse) -> crate::FnResult<(Vec<String>, Vec<Bytes>)> {
+    let num_keys = parse.next_int()?;
+
+    let mut keys = vec![];
+    for _ in 0..num_keys {
+        match parse.next_string() {
+            Ok(key) => keys.push(key),
+            Err(ParseError::EndOfStream) => {
+                return Err("ERR Number of keys can't be greater than number of args".into())
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    let mut args = vec![];
+    loop {
+        match parse.next_bytes() {
+            Ok(arg) => args.push(arg),
+            Err(ParseError::EndOfStream) => break,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok((keys, args))
+}
+
+fn push_keys_and_args(frame: &mut Frame, keys: Vec<String>, args: Vec<Bytes>) {
+    frame.push_int(keys.len() as u64);
+    for key in keys {
+        frame.push_bulk(Bytes::from(key.into_bytes()));
+    }
+    for arg in args {
+        frame.push_bulk(arg);
+    }
+}
@@ -0,0 +1,113 @@
+use bytes::Bytes;
+use std::time::Duration;
+use tracing::debug;
+
+use crate::{
+    connection::Connection,
+    db::Db,
+    frame::Frame,
+    parse::{Parse, ParseError},
+    replication,
+};
+
+/// Hand the leadership over to a replica, without losing any write.
+///
+/// The leader pauses writes from its clients and waits for the replica to acknowledge
+/// all of them. The replica is then promoted, and the leader becomes its replica. Writes
+/// held meanwhile are rejected as the server is read-only from then on, or applied if
+/// the failover is aborted.
+///
+/// `TO host port` chooses the replica, otherwise the one which acknowledged the most
+/// writes is chosen. The failover is aborted if the replica doesn't catch up within
+/// `TIMEOUT` milliseconds, if given.
+#[derive(Debug)]
+pub struct Failover {
+    /// Host and port of the replica to promote, if chosen
+    target: Option<(String, u16)>,
+
+    /// Timeout in milliseconds, if any
+    timeout: Option<u64>,
+}
+
+impl Failover {
+    /// Create a `Failover` command promoting the replica at `target` if set, aborted
+    /// after `timeout` if set.
+    pub fn new(target: Option<(&str, u16)>, timeout: Option<Duration>) -> Failover {
+        Failover {
+            target: target.map(|(host, port)| (host.to_string(), port)),
+            timeout: timeout.map(|timeout| (timeout.as_millis() as u64).max(1)),
+        }
+    }
+
+    /// Parse a `Failover` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<Failover> {
+        // Note: the `FAILOVER` string has already been consumed, next values are the
+        // optional `TO host port` and `TIMEOUT ms`
+        let mut failover = Failover {
+            target: None,
+            timeout: None,
+        };
+
+        loop {
+            match parse.next_string() {
+                Ok(s) if s.to_uppercase() == "TO" && failover.target.is_none() => {
+                    let host = parse.next_string()?;
+
+                    match parse.next_string()?.parse() {
+                        Ok(port) => failover.target = Some((host, port)),
+                        Err(_) => return Err("ERR Invalid port".into()),
+                    }
+                }
+                Ok(s) if s.to_uppercase() == "TIMEOUT" && failover.timeout.is_none() => {
+                    match parse.next_string()?.parse::<i64>() {
+                        Ok(timeout) if timeout > 0 => failover.timeout = Some(timeout as u64),
+                        Ok(_) => return Err("ERR FAILOVER timeout must be greater than 0".into()),
+                        Err(_) => return Err("ERR value is not an integer or out of range".into()),
+                    }
+                }
+                Ok(_) => return Err("ERR syntax error".into()),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(failover)
+    }
+
+    /// Apply the `Failover` command to the specified `Db` instance.
+    ///
+    /// Replies right away, the failover is performed in the background.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let timeout = self.timeout.map(Duration::from_millis);
+
+        let response = match replication::failover(db, self.target, timeout) {
+            Ok(()) => Frame::Simple("OK".to_string()),
+            Err(msg) => Frame::Error(msg.to_string()),
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("failover".as_bytes()));
+
+        if let Some((host, port)) = self.target {
+            frame.push_bulk(Bytes::from("to".as_bytes()));
+            frame.push_bulk(Bytes::from(host.into_bytes()));
+            frame.push_bulk(Bytes::from(port.to_string().into_bytes()));
+        }
+
+        if let Some(timeout) = self.timeout {
+            frame.push_bulk(Bytes::from("timeout".as_bytes()));
+            frame.push_bulk(Bytes::from(timeout.to_string().into_bytes()));
+        }
+
+        frame
+    }
+}
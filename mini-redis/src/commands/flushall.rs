@@ -0,0 +1,63 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    connection::Connection,
+    db::{Db, DbGuard},
+    frame::Frame,
+    parse::{Parse, ParseError},
+};
+
+/// Remove all keys from all databases.
+///
+/// The `ASYNC` and `SYNC` modifiers are accepted for compatibility, keys are always removed synchronously.
+#[derive(Debug, Default)]
+pub struct FlushAll;
+
+impl FlushAll {
+    pub fn new() -> FlushAll {
+        FlushAll
+    }
+
+    /// Parse a `FlushAll` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<FlushAll> {
+        // Note: the `FLUSHALL` string has already been consumed, next value is the optional flush mode
+        match parse.next_string() {
+            Ok(mode) if mode.eq_ignore_ascii_case("async") || mode.eq_ignore_ascii_case("sync") => {
+            }
+            Ok(_) => return Err("ERR syntax error".into()),
+            Err(ParseError::EndOfStream) => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        Ok(FlushAll)
+    }
+
+    /// Apply the `FlushAll` command to the specified `Db` instance.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = match db.lock_unless_busy().await {
+            Ok(mut guard) => self.execute(&mut guard),
+            Err(response) => response,
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `FlushAll` command on the locked `Db`, returning the reply.
+    pub(crate) fn execute(self, db: &mut DbGuard) -> Frame {
+        db.flush_all();
+
+        Frame::Simple("OK".to_string())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("flushall".as_bytes()));
+        frame
+    }
+}
@@ -2,7 +2,7 @@ use bytes::Bytes;
 use tracing::debug;
 
 use crate::connection::Connection;
-use crate::db::Db;
+use crate::db::{Db, DbGuard};
 use crate::frame::Frame;
 use crate::parse::Parse;
 
@@ -33,11 +33,10 @@ impl Get {
 
     /// Apply the `Get` command to the specified `Db` instance.
     pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
-        let response = if let Some(key) = db.get(&self.key) {
-            // If a value is present, it is written to the client using "bulk" frame
-            Frame::Bulk(key)
-        } else {
-            Frame::Null
+        // Only the shard of the key is locked, other keys remain available
+        let response = match db.lock_keys_unless_busy(&[&self.key]).await {
+            Ok(mut guard) => self.execute(&mut guard),
+            Err(response) => response,
         };
 
         debug!(?response);
@@ -47,6 +46,16 @@ impl Get {
         Ok(())
     }
 
+    /// Execute the `Get` command on the locked `Db`, returning the reply.
+    pub(crate) fn execute(self, db: &mut DbGuard) -> Frame {
+        if let Some(key) = db.get(&self.key) {
+            // If a value is present, it is written to the client using "bulk" frame
+            Frame::Bulk(key)
+        } else {
+            Frame::Null
+        }
+    }
+
     /// Converts the command into an equivalent `Frame`.
     pub(crate) fn into_frame(self) -> Frame {
         let mut frame = Frame::array();
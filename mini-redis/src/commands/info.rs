@@ -0,0 +1,107 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    connection::Connection,
+    db::{Db, DbGuard},
+    frame::Frame,
+    parse::{Parse, ParseError},
+};
+
+/// Returns information about the server, as `field:value` lines grouped in sections.
+///
+/// The `memory`, `stats` and `replication` sections are supported. They're returned when
+/// no section, `default`, `all` or `everything` is requested; other sections are empty.
+#[derive(Debug, Default)]
+pub struct Info {
+    sections: Vec<String>,
+}
+
+impl Info {
+    /// Create an `Info` command returning the given section, or the default ones if `None`.
+    pub fn new(section: Option<&str>) -> Info {
+        Info {
+            sections: section
+                .map(|section| section.to_string())
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// Parse an `Info` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<Info> {
+        // Note: the `INFO` string has already been consumed, next values are the sections
+        let mut sections = vec![];
+
+        loop {
+            match parse.next_string() {
+                Ok(section) => sections.push(section.to_lowercase()),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(Info { sections })
+    }
+
+    /// Apply the `Info` command to the specified `Db` instance.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = match db.lock_unless_busy().await {
+            Ok(mut guard) => self.execute(&mut guard),
+            Err(response) => response,
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `Info` command on the locked `Db`, returning the reply.
+    pub(crate) fn execute(self, db: &mut DbGuard) -> Frame {
+        let all = self.sections.is_empty()
+            || self
+                .sections
+                .iter()
+                .any(|section| matches!(&section[..], "default" | "all" | "everything"));
+
+        let requested = |name: &str| all || self.sections.iter().any(|section| section == name);
+        let mut sections = vec![];
+
+        if requested("memory") {
+            let maxmemory = db.maxmemory();
+
+            let mut memory = String::from("# Memory\r\n");
+            memory += &format!("used_memory:{}\r\n", db.used_memory());
+            memory += &format!("maxmemory:{}\r\n", maxmemory.limit);
+            memory += &format!("maxmemory_policy:{}\r\n", maxmemory.policy);
+            sections.push(memory);
+        }
+
+        if requested("stats") {
+            sections.push(format!("# Stats\r\nevicted_keys:{}\r\n", db.evicted_keys()));
+        }
+
+        if requested("replication") {
+            sections.push(db.replication().info());
+        }
+
+        // Sections are separated by an empty line
+        let info = sections.join("\r\n");
+
+        Frame::Bulk(Bytes::from(info.into_bytes()))
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("info".as_bytes()));
+
+        for section in self.sections {
+            frame.push_bulk(Bytes::from(section.into_bytes()));
+        }
+
+        frame
+    }
+}
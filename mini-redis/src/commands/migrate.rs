@@ -0,0 +1,327 @@
+use bytes::Bytes;
+use std::time::Duration;
+use tokio::{net::TcpStream, time};
+use tracing::debug;
+
+use crate::{
+    commands::{Asking, Restore, Select},
+    connection::Connection,
+    db::Db,
+    frame::Frame,
+    parse::{Parse, ParseError},
+    rdb,
+};
+
+/// Timeout used when `MIGRATE` is given a timeout of 0
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Atomically move keys to another server, restoring them there with `RESTORE` before
+/// deleting them here.
+///
+/// `MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE] [KEYS key [key ...]]`
+///
+/// The writes of the other connections are paused until the keys are moved. The timeout,
+/// in milliseconds, applies to connecting to the server and to its replies.
+///
+/// Options:
+/// - `COPY`: keep the keys on this server
+/// - `REPLACE`: overwrite the keys existing on the other server
+/// - `KEYS`: move several keys, the `key` argument being an empty string
+#[derive(Debug)]
+pub struct Migrate {
+    host: String,
+    port: u16,
+    keys: Vec<String>,
+    db: u64,
+
+    /// Timeout in milliseconds
+    timeout: u64,
+
+    copy: bool,
+    replace: bool,
+}
+
+impl Migrate {
+    /// Create a `Migrate` command, moving `keys` to the database `db` of the server at
+    /// `host:port`.
+    pub fn new(
+        host: impl ToString,
+        port: u16,
+        keys: &[String],
+        db: u64,
+        timeout: Duration,
+    ) -> Migrate {
+        Migrate {
+            host: host.to_string(),
+            port,
+            keys: keys.to_vec(),
+            db,
+            timeout: timeout.as_millis() as u64,
+            copy: false,
+            replace: false,
+        }
+    }
+
+    /// Set whether the keys are kept on this server.
+    pub fn copy(mut self, copy: bool) -> Migrate {
+        self.copy = copy;
+        self
+    }
+
+    /// Set whether the keys existing on the other server are overwritten.
+    pub fn replace(mut self, replace: bool) -> Migrate {
+        self.replace = replace;
+        self
+    }
+
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// Parse a `Migrate` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<Migrate> {
+        // Note: the `MIGRATE` string has already been consumed, next values are the host,
+        // the port, the key, the database, the timeout and the options
+        let host = parse.next_string()?;
+        let port = match parse.next_string()?.parse() {
+            Ok(port) => port,
+            Err(_) => return Err("ERR value is not an integer or out of range".into()),
+        };
+        let key = parse.next_string()?;
+        let db = parse.next_int()?;
+        let timeout = parse.next_int()?;
+
+        let mut migrate = Migrate {
+            host,
+            port,
+            keys: vec![],
+            db,
+            timeout,
+            copy: false,
+            replace: false,
+        };
+
+        loop {
+            match parse.next_string() {
+                Ok(option) => match &option.to_uppercase()[..] {
+                    "COPY" => migrate.copy = true,
+                    "REPLACE" => migrate.replace = true,
+                    "KEYS" => {
+                        if !key.is_empty() {
+                            return Err("ERR When using MIGRATE KEYS option, the key argument must be set to the empty string".into());
+                        }
+
+                        loop {
+                            match parse.next_string() {
+                                Ok(key) => migrate.keys.push(key),
+                                Err(ParseError::EndOfStream) => return Ok(migrate),
+                                Err(err) => return Err(err.into()),
+                            }
+                        }
+                    }
+                    _ => return Err("ERR syntax error".into()),
+                },
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        migrate.keys.push(key);
+
+        Ok(migrate)
+    }
+
+    /// Apply the `Migrate` command to the specified `Db` instance.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = self.migrate(db).await;
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Move the keys, returning the reply: `NOKEY` if none exists, an error if the other
+    /// server can't be reached or rejects a key.
+    ///
+    /// The keys successfully restored are deleted, unless modified meanwhile.
+    async fn migrate(self, db: &Db) -> Frame {
+        let keys = {
+            let mut guard = match db.lock_keys_unless_busy(&self.keys).await {
+                Ok(guard) => guard,
+                Err(response) => return response,
+            };
+
+            let keys: Vec<_> = self
+                .keys
+                .iter()
+                .filter_map(|key| {
+                    let value = guard.get(key)?;
+
+                    // A TTL of 0 would restore the key without one
+                    let ttl = guard.ttl(key).map(|ttl| ttl.max(Duration::from_millis(1)));
+
+                    Some((key.clone(), value, ttl))
+                })
+                .collect();
+
+            if keys.is_empty() {
+                return Frame::Simple("NOKEY".to_string());
+            }
+
+            guard.pause_writes();
+            keys
+        };
+
+        let _pause = WritePause(db);
+
+        let mut requests = vec![];
+
+        if self.db != 0 {
+            requests.push(Select::new(self.db).into_frame());
+        }
+
+        // The slot of the keys is being imported by the other node, in cluster mode
+        let asking = db.is_cluster();
+
+        for (key, value, ttl) in &keys {
+            if asking {
+                requests.push(Asking::new().into_frame());
+            }
+
+            let payload = Bytes::from(rdb::dump(value));
+            requests.push(Restore::new(key, *ttl, payload, self.replace).into_frame());
+        }
+
+        let timeout = match self.timeout {
+            0 => DEFAULT_TIMEOUT,
+            timeout => Duration::from_millis(timeout),
+        };
+
+        let mut connection =
+            match time::timeout(timeout, TcpStream::connect((self.host.as_str(), self.port))).await
+            {
+                Ok(Ok(socket)) => Connection::new(socket),
+                _ => {
+                    return Frame::Error(
+                        "IOERR error or timeout connecting to the client".to_string(),
+                    )
+                }
+            };
+
+        let replies = match time::timeout(timeout, exchange(&mut connection, &requests)).await {
+            Ok(Ok(replies)) => replies,
+            _ => {
+                return Frame::Error(
+                    "IOERR error or timeout reading to target instance".to_string(),
+                )
+            }
+        };
+
+        // Only the replies to `RESTORE` matter, the other commands can't fail unless the
+        // `RESTORE` ones fail too
+        let per_key = if asking { 2 } else { 1 };
+        let restored: Vec<_> = replies
+            .into_iter()
+            .skip(requests.len() - per_key * keys.len())
+            .collect();
+        let restored = restored.chunks(per_key).map(|replies| replies.last());
+
+        let mut error = None;
+        let mut moved = vec![];
+
+        for ((key, value, _), reply) in keys.into_iter().zip(restored) {
+            match reply {
+                Some(Frame::Error(err)) => {
+                    error.get_or_insert(err.clone());
+                }
+                _ => moved.push((key, value)),
+            }
+        }
+
+        if !self.copy {
+            let mut guard = match db.lock_keys_unless_busy(&self.keys).await {
+                Ok(guard) => guard,
+                Err(response) => return response,
+            };
+
+            let moved: Vec<_> = moved
+                .into_iter()
+                .filter(|(key, value)| guard.get(key).as_ref() == Some(value))
+                .map(|(key, _)| key)
+                .collect();
+
+            guard.del(&moved);
+        }
+
+        match error {
+            Some(err) => Frame::Error(format!("ERR Target instance replied with error: {}", err)),
+            None => Frame::Simple("OK".to_string()),
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("migrate".as_bytes()));
+        frame.push_bulk(Bytes::from(self.host.into_bytes()));
+        frame.push_bulk(Bytes::from(self.port.to_string().into_bytes()));
+
+        let single = self.keys.len() == 1;
+
+        if single {
+            frame.push_bulk(Bytes::from(self.keys[0].clone().into_bytes()));
+        } else {
+            frame.push_bulk(Bytes::new());
+        }
+
+        frame.push_bulk(Bytes::from(self.db.to_string().into_bytes()));
+        frame.push_bulk(Bytes::from(self.timeout.to_string().into_bytes()));
+
+        if self.copy {
+            frame.push_bulk(Bytes::from("copy".as_bytes()));
+        }
+
+        if self.replace {
+            frame.push_bulk(Bytes::from("replace".as_bytes()));
+        }
+
+        if !single {
+            frame.push_bulk(Bytes::from("keys".as_bytes()));
+
+            for key in self.keys {
+                frame.push_bulk(Bytes::from(key.into_bytes()));
+            }
+        }
+
+        frame
+    }
+}
+
+/// Send the `requests` to the other server, returning its replies.
+async fn exchange(connection: &mut Connection, requests: &[Frame]) -> crate::FnResult<Vec<Frame>> {
+    for request in requests {
+        connection.write_frame(request).await?;
+    }
+
+    let mut replies = vec![];
+
+    for _ in requests {
+        match connection.read_frame().await? {
+            Some(reply) => replies.push(reply),
+            None => return Err("connection reset by the target instance".into()),
+        }
+    }
+
+    Ok(replies)
+}
+
+/// Resumes the writes paused by a migration when dropped.
+struct WritePause<'a>(&'a Db);
+
+impl Drop for WritePause<'_> {
+    fn drop(&mut self) {
+        self.0.resume_writes();
+    }
+}
@@ -4,29 +4,171 @@ pub use get::Get;
 mod set;
 pub use set::Set;
 
+mod del;
+pub use del::Del;
+
+mod select;
+pub use select::Select;
+
+mod swapdb;
+pub use swapdb::SwapDb;
+
+mod move_key;
+pub use move_key::Move;
+
+mod flushdb;
+pub use flushdb::FlushDb;
+
+mod flushall;
+pub use flushall::FlushAll;
+
+mod dbsize;
+pub use dbsize::DbSize;
+
 mod publish;
 pub use publish::Publish;
 
+mod pubsub;
+pub use pubsub::PubSub;
+
 mod subscribe;
-pub use subscribe::{Subscribe, Unsubscribe};
+pub use subscribe::{PSubscribe, PUnsubscribe, Subscribe, Unsubscribe};
 
 mod ping;
 pub use ping::Ping;
 
+mod quit;
+pub use quit::Quit;
+
+mod reset;
+pub use reset::Reset;
+
+mod multi;
+pub(crate) use multi::Transaction;
+pub use multi::{Discard, Exec, Multi};
+
+mod watch;
+pub use watch::{Unwatch, Watch};
+
+mod eval;
+pub use eval::{Eval, EvalSha};
+
+mod script;
+pub use script::Script;
+
+mod save;
+pub use save::{BgSave, LastSave, Save};
+
+mod shutdown;
+pub use shutdown::Shutdown;
+
+mod bgrewriteaof;
+pub use bgrewriteaof::BgRewriteAof;
+
+mod dump;
+pub use dump::{Dump, Restore};
+
+mod object;
+pub use object::Object;
+
+mod replicaof;
+pub use replicaof::ReplicaOf;
+
+mod failover;
+pub use failover::Failover;
+
+mod cluster;
+pub use cluster::{Asking, Cluster, SetSlot};
+
+mod migrate;
+pub use migrate::Migrate;
+
+mod replconf;
+pub use replconf::ReplConf;
+
+mod psync;
+pub use psync::PSync;
+
+mod role;
+pub use role::Role;
+
+mod info;
+pub use info::Info;
+
+mod config;
+pub use config::Config;
+
+mod wait;
+pub use wait::Wait;
+
+mod auth;
+pub use auth::Auth;
+
+mod acl;
+pub use acl::Acl;
+
 mod unknown;
 pub use unknown::Unknown;
 
-use crate::{connection::Connection, db::Db, frame::Frame, parse::Parse, shutdown::Shutdown};
+use crate::{
+    acl::Session,
+    connection::Connection,
+    db::{Db, DbGuard, Watcher},
+    frame::Frame,
+    parse::Parse,
+    shutdown::Shutdown as ShutdownSignal,
+};
 
 /// Enumeration of supported Redis commands
 #[derive(Debug)]
 pub enum Command {
     Get(Get),
     Set(Set),
+    Del(Del),
+    Select(Select),
+    SwapDb(SwapDb),
+    Move(Move),
+    FlushDb(FlushDb),
+    FlushAll(FlushAll),
+    DbSize(DbSize),
     Publish(Publish),
     Subscribe(Subscribe),
     Unsubscribe(Unsubscribe),
+    PSubscribe(PSubscribe),
+    PUnsubscribe(PUnsubscribe),
+    PubSub(PubSub),
     Ping(Ping),
+    Reset(Reset),
+    Quit(Quit),
+    Multi(Multi),
+    Exec(Exec),
+    Discard(Discard),
+    Watch(Watch),
+    Unwatch(Unwatch),
+    Eval(Eval),
+    EvalSha(EvalSha),
+    Script(Script),
+    Save(Save),
+    BgSave(BgSave),
+    LastSave(LastSave),
+    Shutdown(Shutdown),
+    BgRewriteAof(BgRewriteAof),
+    Dump(Dump),
+    Restore(Restore),
+    Object(Object),
+    ReplicaOf(ReplicaOf),
+    Failover(Failover),
+    Cluster(Cluster),
+    Asking(Asking),
+    Migrate(Migrate),
+    ReplConf(ReplConf),
+    PSync(PSync),
+    Role(Role),
+    Info(Info),
+    Config(Config),
+    Wait(Wait),
+    Auth(Auth),
+    Acl(Acl),
     Unknown(Unknown),
 }
 
@@ -44,10 +186,51 @@ impl Command {
         let command = match &command_name[..] {
             "get" => Command::Get(Get::parse_frames(&mut parse)?),
             "set" => Command::Set(Set::parse_frames(&mut parse)?),
+            "del" => Command::Del(Del::parse_frames(&mut parse)?),
+            "select" => Command::Select(Select::parse_frames(&mut parse)?),
+            "swapdb" => Command::SwapDb(SwapDb::parse_frames(&mut parse)?),
+            "move" => Command::Move(Move::parse_frames(&mut parse)?),
+            "flushdb" => Command::FlushDb(FlushDb::parse_frames(&mut parse)?),
+            "flushall" => Command::FlushAll(FlushAll::parse_frames(&mut parse)?),
+            "dbsize" => Command::DbSize(DbSize::parse_frames(&mut parse)?),
             "publish" => Command::Publish(Publish::parse_frame(&mut parse)?),
             "subscribe" => Command::Subscribe(Subscribe::parse_frame(&mut parse)?),
             "unsubscribe" => Command::Unsubscribe(Unsubscribe::parse_frame(&mut parse)?),
+            "psubscribe" => Command::PSubscribe(PSubscribe::parse_frame(&mut parse)?),
+            "punsubscribe" => Command::PUnsubscribe(PUnsubscribe::parse_frame(&mut parse)?),
+            "pubsub" => Command::PubSub(PubSub::parse_frames(&mut parse)?),
             "ping" => Command::Ping(Ping::parse_frames(&mut parse)?),
+            "reset" => Command::Reset(Reset::parse_frames(&mut parse)?),
+            "quit" => Command::Quit(Quit::parse_frames(&mut parse)?),
+            "multi" => Command::Multi(Multi::parse_frames(&mut parse)?),
+            "exec" => Command::Exec(Exec::parse_frames(&mut parse)?),
+            "discard" => Command::Discard(Discard::parse_frames(&mut parse)?),
+            "watch" => Command::Watch(Watch::parse_frames(&mut parse)?),
+            "unwatch" => Command::Unwatch(Unwatch::parse_frames(&mut parse)?),
+            "eval" => Command::Eval(Eval::parse_frames(&mut parse)?),
+            "evalsha" => Command::EvalSha(EvalSha::parse_frames(&mut parse)?),
+            "script" => Command::Script(Script::parse_frames(&mut parse)?),
+            "save" => Command::Save(Save::parse_frames(&mut parse)?),
+            "bgsave" => Command::BgSave(BgSave::parse_frames(&mut parse)?),
+            "lastsave" => Command::LastSave(LastSave::parse_frames(&mut parse)?),
+            "shutdown" => Command::Shutdown(Shutdown::parse_frames(&mut parse)?),
+            "bgrewriteaof" => Command::BgRewriteAof(BgRewriteAof::parse_frames(&mut parse)?),
+            "dump" => Command::Dump(Dump::parse_frames(&mut parse)?),
+            "restore" => Command::Restore(Restore::parse_frames(&mut parse)?),
+            "object" => Command::Object(Object::parse_frames(&mut parse)?),
+            "replicaof" | "slaveof" => Command::ReplicaOf(ReplicaOf::parse_frames(&mut parse)?),
+            "failover" => Command::Failover(Failover::parse_frames(&mut parse)?),
+            "cluster" => Command::Cluster(Cluster::parse_frames(&mut parse)?),
+            "asking" => Command::Asking(Asking::parse_frames(&mut parse)?),
+            "migrate" => Command::Migrate(Migrate::parse_frames(&mut parse)?),
+            "replconf" => Command::ReplConf(ReplConf::parse_frames(&mut parse)?),
+            "psync" => Command::PSync(PSync::parse_frames(&mut parse)?),
+            "role" => Command::Role(Role::parse_frames(&mut parse)?),
+            "info" => Command::Info(Info::parse_frames(&mut parse)?),
+            "config" => Command::Config(Config::parse_frames(&mut parse)?),
+            "wait" => Command::Wait(Wait::parse_frames(&mut parse)?),
+            "auth" => Command::Auth(Auth::parse_frames(&mut parse)?),
+            "acl" => Command::Acl(Acl::parse_frames(&mut parse)?),
             _ => {
                 return Ok(Command::Unknown(Unknown::new(command_name)));
             }
@@ -61,25 +244,273 @@ impl Command {
     }
 
     /// Apply command to the specified `Db` instance.
+    ///
+    /// `db` is the connection's handle on its selected database, commands such as
+    /// `SELECT` and `RESET` replace it. `transaction` is the connection's open transaction,
+    /// if any, started by `MULTI`, and `watcher` tracks the keys it watches with `WATCH`.
+    /// `replica_port` is the port announced by a replica with `REPLCONF listening-port`.
+    /// `session` holds the user the connection is authenticated as.
+    #[allow(clippy::too_many_arguments)]
     pub(crate) async fn apply(
         self,
-        db: &Db,
+        db: &mut Db,
         dst: &mut Connection,
-        shutdown: &mut Shutdown,
+        shutdown: &mut ShutdownSignal,
+        transaction: &mut Option<Transaction>,
+        watcher: &mut Option<Watcher>,
+        replica_port: &mut Option<u16>,
+        session: &mut Session,
     ) -> crate::FnResult<()> {
         use Command::*;
 
         match self {
             Get(cmd) => cmd.apply(db, dst).await,
             Set(cmd) => cmd.apply(db, dst).await,
+            Del(cmd) => cmd.apply(db, dst).await,
+            Select(cmd) => cmd.apply(db, dst).await,
+            SwapDb(cmd) => cmd.apply(db, dst).await,
+            Move(cmd) => cmd.apply(db, dst).await,
+            FlushDb(cmd) => cmd.apply(db, dst).await,
+            FlushAll(cmd) => cmd.apply(db, dst).await,
+            DbSize(cmd) => cmd.apply(db, dst).await,
             Publish(cmd) => cmd.apply(db, dst).await,
-            Subscribe(cmd) => cmd.apply(db, dst, shutdown).await,
-            Unsubscribe(_) => Err("`Unsubscribe` is unsupported in this context".into()),
+            Subscribe(cmd) => cmd.apply(db, dst, shutdown, session).await,
+            Unsubscribe(cmd) => cmd.apply(dst).await,
+            PSubscribe(cmd) => cmd.apply(db, dst, shutdown, session).await,
+            PUnsubscribe(cmd) => cmd.apply(dst).await,
+            PubSub(cmd) => cmd.apply(db, dst).await,
             Ping(cmd) => cmd.apply(dst).await,
+            Reset(cmd) => {
+                // Resetting the connection discards its transaction and watched keys
+                *transaction = None;
+                *watcher = None;
+                cmd.apply(db, dst, session).await
+            }
+            Quit(cmd) => cmd.apply(dst).await,
+            Multi(cmd) => cmd.apply(transaction, dst).await,
+            Exec(cmd) => {
+                cmd.apply(transaction.take(), watcher, db, dst, session)
+                    .await
+            }
+            Discard(cmd) => cmd.apply(transaction.take(), watcher, dst).await,
+            Watch(cmd) => cmd.apply(db, watcher, dst).await,
+            Unwatch(cmd) => cmd.apply(watcher, dst).await,
+            Eval(cmd) => cmd.apply(db, dst, session).await,
+            EvalSha(cmd) => cmd.apply(db, dst, session).await,
+            Script(cmd) => cmd.apply(db, dst).await,
+            Save(cmd) => cmd.apply(db, dst).await,
+            BgSave(cmd) => cmd.apply(db, dst).await,
+            LastSave(cmd) => cmd.apply(db, dst).await,
+            Shutdown(cmd) => cmd.apply(db).await,
+            BgRewriteAof(cmd) => cmd.apply(db, dst).await,
+            Dump(cmd) => cmd.apply(db, dst).await,
+            Restore(cmd) => cmd.apply(db, dst).await,
+            Object(cmd) => cmd.apply(db, dst).await,
+            ReplicaOf(cmd) => cmd.apply(db, dst).await,
+            Failover(cmd) => cmd.apply(db, dst).await,
+            Cluster(cmd) => cmd.apply(db, dst).await,
+            Asking(cmd) => cmd.apply(db, dst).await,
+            Migrate(cmd) => cmd.apply(db, dst).await,
+            ReplConf(cmd) => cmd.apply(dst, replica_port).await,
+            PSync(cmd) => cmd.apply(db, dst, shutdown, *replica_port).await,
+            Role(cmd) => cmd.apply(db, dst).await,
+            Info(cmd) => cmd.apply(db, dst).await,
+            Config(cmd) => cmd.apply(db, dst).await,
+            Wait(cmd) => cmd.apply(db, dst, shutdown).await,
+            Auth(cmd) => cmd.apply(db, dst, session).await,
+            Acl(cmd) => cmd.apply(db, dst, session).await,
             Unknown(cmd) => cmd.apply(dst).await,
         }
     }
 
+    /// Execute a command queued in a transaction on the locked `Db`, returning its reply.
+    ///
+    /// `session` is the one of the connection, scripts check the commands they call against
+    /// its permissions.
+    pub(crate) fn execute_queued(self, db: &mut DbGuard, session: &Session) -> Frame {
+        match self {
+            Command::Eval(cmd) => cmd.execute(db, session),
+            Command::EvalSha(cmd) => cmd.execute(db, session),
+            cmd => cmd.execute(db),
+        }
+    }
+
+    /// Execute a command on the locked `Db`, returning its reply.
+    ///
+    /// Scripts aren't, needing the session of the connection, see `execute_queued`.
+    pub(crate) fn execute(self, db: &mut DbGuard) -> Frame {
+        use Command::*;
+
+        match self {
+            Get(cmd) => cmd.execute(db),
+            Set(cmd) => cmd.execute(db),
+            Del(cmd) => cmd.execute(db),
+            Select(cmd) => cmd.execute(db),
+            SwapDb(cmd) => cmd.execute(db),
+            Move(cmd) => cmd.execute(db),
+            FlushDb(cmd) => cmd.execute(db),
+            FlushAll(cmd) => cmd.execute(db),
+            DbSize(cmd) => cmd.execute(db),
+            Publish(cmd) => cmd.execute(db),
+            PubSub(cmd) => cmd.execute(db),
+            Ping(cmd) => cmd.execute(),
+            Unwatch(cmd) => cmd.execute(),
+            Script(cmd) => cmd.execute(db.scripts()),
+            LastSave(cmd) => cmd.execute(db),
+            Dump(cmd) => cmd.execute(db),
+            Restore(cmd) => cmd.execute(db),
+            Object(cmd) => cmd.execute(db),
+            Role(cmd) => cmd.execute(db),
+            Info(cmd) => cmd.execute(db),
+            Config(cmd) => cmd.execute(db.config(), db.acl()),
+            Cluster(cmd) => cmd.execute(db),
+            Unknown(cmd) => cmd.execute(),
+            cmd => Frame::Error(format!(
+                "ERR Command '{}' not allowed inside a transaction",
+                cmd.get_name()
+            )),
+        }
+    }
+
+    /// Returns `true` if the command can be queued in a transaction.
+    pub(crate) fn is_transactional(&self) -> bool {
+        use Command::*;
+
+        !matches!(
+            self,
+            Subscribe(_)
+                | Unsubscribe(_)
+                | PSubscribe(_)
+                | PUnsubscribe(_)
+                | Reset(_)
+                | Quit(_)
+                | Multi(_)
+                | Exec(_)
+                | Discard(_)
+                | Watch(_)
+                | Save(_)
+                | BgSave(_)
+                | Shutdown(_)
+                | BgRewriteAof(_)
+                | ReplicaOf(_)
+                | Failover(_)
+                | Asking(_)
+                | Migrate(_)
+                | ReplConf(_)
+                | PSync(_)
+                | Wait(_)
+                | Auth(_)
+                | Acl(_)
+                | Unknown(_)
+        )
+    }
+
+    /// Returns `true` if the command can be called from a Lua script.
+    pub(crate) fn is_scriptable(&self) -> bool {
+        use Command::*;
+
+        self.is_transactional()
+            && !matches!(
+                self,
+                Eval(_) | EvalSha(_) | Script(_) | Config(_) | Unwatch(_)
+            )
+    }
+
+    /// Returns `true` if the command may modify the keyspace.
+    pub(crate) fn is_write(&self) -> bool {
+        use Command::*;
+
+        matches!(
+            self,
+            Set(_)
+                | Del(_)
+                | Move(_)
+                | SwapDb(_)
+                | FlushDb(_)
+                | FlushAll(_)
+                | Restore(_)
+                | Migrate(_)
+        )
+    }
+
+    /// Returns `true` if the command may modify the keyspace, including scripts which may
+    /// call write commands.
+    pub(crate) fn may_write(&self) -> bool {
+        use Command::*;
+
+        self.is_write() || matches!(self, Eval(_) | EvalSha(_))
+    }
+
+    /// Returns `true` if the command may grow the databases, and is rejected when over
+    /// `maxmemory` with no key left to evict. Scripts are too, as they may call such commands.
+    pub(crate) fn denies_oom(&self) -> bool {
+        use Command::*;
+
+        matches!(self, Set(_) | Restore(_) | Eval(_) | EvalSha(_))
+    }
+
+    /// Returns the keys the command operates on, hashed to their slot in cluster mode.
+    pub(crate) fn keys(&self) -> Vec<&str> {
+        use Command::*;
+
+        match self {
+            Get(cmd) => vec![cmd.key()],
+            Set(cmd) => vec![cmd.key()],
+            Move(cmd) => vec![cmd.key()],
+            Dump(cmd) => vec![cmd.key()],
+            Restore(cmd) => vec![cmd.key()],
+            Object(cmd) => vec![cmd.key()],
+            Del(cmd) => cmd.keys().iter().map(String::as_str).collect(),
+            Watch(cmd) => cmd.keys().iter().map(String::as_str).collect(),
+            Eval(cmd) => cmd.keys().iter().map(String::as_str).collect(),
+            EvalSha(cmd) => cmd.keys().iter().map(String::as_str).collect(),
+            Migrate(cmd) => cmd.keys().iter().map(String::as_str).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Returns the channels the command publishes or subscribes to, or the patterns it
+    /// subscribes to.
+    pub(crate) fn channels(&self) -> Vec<&str> {
+        use Command::*;
+
+        match self {
+            Publish(cmd) => vec![cmd.channel()],
+            Subscribe(cmd) => cmd.channels().iter().map(String::as_str).collect(),
+            PSubscribe(cmd) => cmd.patterns().iter().map(String::as_str).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Returns `true` if the command is run whatever the permissions of the connection's
+    /// user, even before it authenticates. Unknown commands reply their error.
+    pub(crate) fn is_no_auth(&self) -> bool {
+        use Command::*;
+
+        matches!(self, Auth(_) | Reset(_) | Quit(_) | Unknown(_))
+    }
+
+    /// Returns `true` if the command is applied while a script runs, rather than waiting for
+    /// it to end: the ones stopping it, and the ones not touching the `Db`.
+    pub(crate) fn is_allowed_while_busy(&self) -> bool {
+        use Command::*;
+
+        match self {
+            Script(cmd) => matches!(cmd, script::Script::Kill),
+            Shutdown(cmd) => cmd.is_nosave(),
+            Auth(_) | Quit(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the command controls the transaction itself, and must be applied
+    /// right away rather than queued.
+    pub(crate) fn is_transaction_control(&self) -> bool {
+        use Command::*;
+
+        matches!(self, Multi(_) | Exec(_) | Discard(_) | Reset(_) | Quit(_))
+    }
+
     /// Return command name
     pub(crate) fn get_name(&self) -> &str {
         use Command::*;
@@ -87,11 +518,52 @@ impl Command {
         match self {
             Get(_) => "get",
             Set(_) => "set",
+            Del(_) => "del",
+            Select(_) => "select",
+            SwapDb(_) => "swapdb",
+            Move(_) => "move",
+            FlushDb(_) => "flushdb",
+            FlushAll(_) => "flushall",
+            DbSize(_) => "dbsize",
             Publish(_) => "publish",
             Subscribe(_) => "subscribe",
             Unsubscribe(_) => "unsubscribe",
+            PSubscribe(_) => "psubscribe",
+            PUnsubscribe(_) => "punsubscribe",
+            PubSub(_) => "pubsub",
             Unknown(cmd) => cmd.get_name(),
             Ping(_) => "ping",
+            Reset(_) => "reset",
+            Quit(_) => "quit",
+            Multi(_) => "multi",
+            Exec(_) => "exec",
+            Discard(_) => "discard",
+            Watch(_) => "watch",
+            Unwatch(_) => "unwatch",
+            Eval(_) => "eval",
+            EvalSha(_) => "evalsha",
+            Script(_) => "script",
+            Save(_) => "save",
+            BgSave(_) => "bgsave",
+            LastSave(_) => "lastsave",
+            Shutdown(_) => "shutdown",
+            BgRewriteAof(_) => "bgrewriteaof",
+            Dump(_) => "dump",
+            Restore(_) => "restore",
+            Object(_) => "object",
+            ReplicaOf(_) => "replicaof",
+            Failover(_) => "failover",
+            Cluster(_) => "cluster",
+            Asking(_) => "asking",
+            Migrate(_) => "migrate",
+            ReplConf(_) => "replconf",
+            PSync(_) => "psync",
+            Role(_) => "role",
+            Info(_) => "info",
+            Config(_) => "config",
+            Wait(_) => "wait",
+            Auth(_) => "auth",
+            Acl(_) => "acl",
         }
     }
 }
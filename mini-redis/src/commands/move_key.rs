@@ -0,0 +1,75 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    connection::Connection,
+    db::{Db, DbGuard},
+    frame::Frame,
+    parse::Parse,
+};
+
+/// Move a key from the selected database to another one.
+#[derive(Debug)]
+pub struct Move {
+    key: String,
+    index: u64,
+}
+
+impl Move {
+    pub fn new(key: impl ToString, index: u64) -> Move {
+        Move {
+            key: key.to_string(),
+            index,
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Parse a `Move` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<Move> {
+        // Note: the `MOVE` string has already been consumed, next values are the key and the target database
+        Ok(Move {
+            key: parse.next_string()?,
+            index: parse.next_int()?,
+        })
+    }
+
+    /// Apply the `Move` command to the specified `Db` instance.
+    ///
+    /// Replies `1` if the key was moved, `0` if it doesn't exist or already exists in the target database.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        // Only the shard of the key is locked, other keys remain available
+        let response = match db.lock_keys_unless_busy(&[&self.key]).await {
+            Ok(mut guard) => self.execute(&mut guard),
+            Err(response) => response,
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `Move` command on the locked `Db`, returning the reply.
+    pub(crate) fn execute(self, db: &mut DbGuard) -> Frame {
+        if self.index >= db.num_databases() as u64 {
+            Frame::Error("ERR DB index is out of range".to_string())
+        } else if self.index as usize == db.index() {
+            Frame::Error("ERR source and destination objects are the same".to_string())
+        } else {
+            Frame::Integer(db.move_key(&self.key, self.index as usize) as i64)
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("move".as_bytes()));
+        frame.push_bulk(Bytes::from(self.key.into_bytes()));
+        frame.push_int(self.index);
+        frame
+    }
+}
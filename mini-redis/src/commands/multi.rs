@@ -0,0 +1,284 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    acl::Session,
+    connection::Connection,
+    db::{Db, Watcher},
+    frame::Frame,
+    parse::Parse,
+};
+
+use super::Command;
+
+/// Reply to `EXEC` when the server became a replica after writes were queued.
+const READONLY_TRANSACTION_ERROR: &str =
+    "READONLY Transaction discarded, the server became a read only replica.";
+
+/// Mark the start of a transaction: next commands are queued until `EXEC` or `DISCARD`.
+#[derive(Debug, Default)]
+pub struct Multi;
+
+/// Apply all the commands queued since `MULTI`, atomically.
+#[derive(Debug, Default)]
+pub struct Exec;
+
+/// Discard all the commands queued since `MULTI`.
+#[derive(Debug, Default)]
+pub struct Discard;
+
+/// Per-connection state of an open transaction.
+#[derive(Debug, Default)]
+pub(crate) struct Transaction {
+    /// Commands queued since `MULTI`, in order
+    commands: Vec<Command>,
+
+    /// `true` when a command couldn't be queued, `EXEC` then discards the transaction
+    aborted: bool,
+
+    /// Slot the keys of the queued commands hash to, in cluster mode
+    slot: Option<u16>,
+}
+
+impl Multi {
+    pub fn new() -> Multi {
+        Multi
+    }
+
+    /// Parse a `Multi` instance from a received frame.
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::FnResult<Multi> {
+        // Note: the `MULTI` string has already been consumed, it has no argument
+        Ok(Multi)
+    }
+
+    /// Apply the `Multi` command, opening a transaction on the connection.
+    pub(crate) async fn apply(
+        self,
+        transaction: &mut Option<Transaction>,
+        dst: &mut Connection,
+    ) -> crate::FnResult<()> {
+        let response = if transaction.is_some() {
+            Frame::Error("ERR MULTI calls can not be nested".to_string())
+        } else {
+            *transaction = Some(Transaction::default());
+            Frame::Simple("OK".to_string())
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("multi".as_bytes()));
+        frame
+    }
+}
+
+impl Exec {
+    pub fn new() -> Exec {
+        Exec
+    }
+
+    /// Parse an `Exec` instance from a received frame.
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::FnResult<Exec> {
+        // Note: the `EXEC` string has already been consumed, it has no argument
+        Ok(Exec)
+    }
+
+    /// Apply the `Exec` command, closing the connection's transaction and unwatching all keys.
+    ///
+    /// The queued commands are executed while holding the `Db` lock, so that no other
+    /// connection interleaves with them. The reply is an array of their replies, or a null
+    /// reply if a watched key was modified. Scripts run with the permissions of `session`.
+    ///
+    /// A transaction with writes is discarded if the server became a replica since they
+    /// were queued, after a failover.
+    pub(crate) async fn apply(
+        self,
+        transaction: Option<Transaction>,
+        watcher: &mut Option<Watcher>,
+        db: &mut Db,
+        dst: &mut Connection,
+        session: &Session,
+    ) -> crate::FnResult<()> {
+        let response = match transaction {
+            None => Frame::Error("ERR EXEC without MULTI".to_string()),
+            Some(transaction) => {
+                let watcher = watcher.take();
+
+                if transaction.aborted {
+                    Frame::Error(
+                        "EXECABORT Transaction discarded because of previous errors.".to_string(),
+                    )
+                } else {
+                    let locked = match db.lock_unless_busy().await {
+                        Ok(mut guard) => {
+                            let response = if transaction.is_write() && guard.is_read_only() {
+                                Frame::Error(READONLY_TRANSACTION_ERROR.to_string())
+                            } else if watcher.as_ref().is_some_and(|w| guard.is_dirty(w)) {
+                                Frame::Null
+                            } else {
+                                Frame::Array(
+                                    transaction
+                                        .commands
+                                        .into_iter()
+                                        .map(|cmd| cmd.execute_queued(&mut guard, session))
+                                        .collect(),
+                                )
+                            };
+
+                            Ok((response, guard.index()))
+                        }
+                        Err(response) => Err(response),
+                    };
+
+                    match locked {
+                        Ok((response, index)) => {
+                            // Keep the database selected by the transaction, if any
+                            *db = db.select(index).expect("selected database exists");
+
+                            response
+                        }
+                        Err(response) => response,
+                    }
+                }
+            }
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("exec".as_bytes()));
+        frame
+    }
+}
+
+impl Discard {
+    pub fn new() -> Discard {
+        Discard
+    }
+
+    /// Parse a `Discard` instance from a received frame.
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::FnResult<Discard> {
+        // Note: the `DISCARD` string has already been consumed, it has no argument
+        Ok(Discard)
+    }
+
+    /// Apply the `Discard` command, dropping the connection's transaction and watched keys.
+    pub(crate) async fn apply(
+        self,
+        transaction: Option<Transaction>,
+        watcher: &mut Option<Watcher>,
+        dst: &mut Connection,
+    ) -> crate::FnResult<()> {
+        let response = match transaction {
+            None => Frame::Error("ERR DISCARD without MULTI".to_string()),
+            Some(_) => {
+                *watcher = None;
+                Frame::Simple("OK".to_string())
+            }
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+}
+
+impl Transaction {
+    /// Returns the slot the keys of the queued commands hash to, if any.
+    pub(crate) fn slot(&self) -> Option<u16> {
+        self.slot
+    }
+
+    /// Set the slot the keys of the queued commands hash to.
+    pub(crate) fn pin_slot(&mut self, slot: u16) {
+        self.slot = Some(slot);
+    }
+
+    /// Returns `true` if a queued command modifies the keyspace.
+    pub(crate) fn is_write(&self) -> bool {
+        self.commands.iter().any(Command::is_write)
+    }
+
+    /// Returns `true` if a queued command may modify the keyspace, such as a script.
+    pub(crate) fn may_write(&self) -> bool {
+        self.commands.iter().any(Command::may_write)
+    }
+
+    /// Queue a command until `EXEC`, replying `QUEUED`.
+    ///
+    /// Commands that can't run inside a transaction are answered with an error
+    /// instead, which aborts the transaction.
+    pub(crate) async fn queue(
+        &mut self,
+        cmd: Command,
+        dst: &mut Connection,
+    ) -> crate::FnResult<()> {
+        let response = match cmd {
+            Command::Unknown(cmd) => {
+                self.aborted = true;
+                cmd.execute()
+            }
+            cmd if !cmd.is_transactional() => {
+                self.aborted = true;
+                Frame::Error("ERR Command not allowed inside a transaction".to_string())
+            }
+            cmd => {
+                self.commands.push(cmd);
+                Frame::Simple("QUEUED".to_string())
+            }
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Abort the transaction because a command failed to parse, replying with the parse error.
+    pub(crate) async fn abort(
+        &mut self,
+        err: crate::GenericError,
+        dst: &mut Connection,
+    ) -> crate::FnResult<()> {
+        let msg = err.to_string();
+        let response = if msg.starts_with("ERR ") {
+            Frame::Error(msg)
+        } else {
+            Frame::Error(format!("ERR {}", msg))
+        };
+
+        self.reject(response, dst).await
+    }
+
+    /// Abort the transaction because a command can't be queued, replying with `response`.
+    pub(crate) async fn reject(
+        &mut self,
+        response: Frame,
+        dst: &mut Connection,
+    ) -> crate::FnResult<()> {
+        self.aborted = true;
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    connection::Connection,
+    db::{Db, DbGuard},
+    frame::Frame,
+    parse::Parse,
+};
+
+/// Inspect the internals of a key.
+///
+/// Supported subcommands:
+/// - `OBJECT IDLETIME key`: seconds elapsed since the key was last accessed
+#[derive(Debug)]
+pub enum Object {
+    IdleTime(String),
+}
+
+impl Object {
+    pub fn key(&self) -> &str {
+        match self {
+            Object::IdleTime(key) => key,
+        }
+    }
+
+    /// Parse an `Object` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<Object> {
+        // Note: the `OBJECT` string has already been consumed, next value is the subcommand
+        let subcommand = parse.next_string()?.to_lowercase();
+
+        match &subcommand[..] {
+            "idletime" => Ok(Object::IdleTime(parse.next_string()?)),
+            _ => Err(format!("ERR unknown subcommand '{}' for 'object'", subcommand).into()),
+        }
+    }
+
+    /// Apply the `Object` command to the specified `Db` instance.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        // Only the shard of the key is locked, other keys remain available
+        let response = match db.lock_keys_unless_busy(&[self.key()]).await {
+            Ok(mut guard) => self.execute(&mut guard),
+            Err(response) => response,
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `Object` command on the locked `Db`, returning the reply.
+    ///
+    /// Replies a null if the key doesn't exist. Inspecting a key doesn't count as an access.
+    pub(crate) fn execute(self, db: &mut DbGuard) -> Frame {
+        match self {
+            Object::IdleTime(key) => match db.idle_time(&key) {
+                Some(idle) => Frame::Integer(idle.as_secs() as i64),
+                None => Frame::Null,
+            },
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("object".as_bytes()));
+
+        match self {
+            Object::IdleTime(key) => {
+                frame.push_bulk(Bytes::from("idletime".as_bytes()));
+                frame.push_bulk(Bytes::from(key.into_bytes()));
+            }
+        }
+
+        frame
+    }
+}
@@ -30,14 +30,34 @@ impl Ping {
 
     /// Apply the `Ping` command and return the message.
     pub(crate) async fn apply(self, dst: &mut Connection) -> crate::FnResult<()> {
-        let response = match self.msg {
+        let response = self.execute();
+
+        debug!(?response);
+
+        // Write response back to the client
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Returns the reply to the `Ping` command.
+    pub(crate) fn execute(self) -> Frame {
+        match self.msg {
             None => Frame::Simple("PONG".to_string()),
             Some(msg) => Frame::Bulk(msg),
-        };
+        }
+    }
+
+    /// Apply the `Ping` command while in subscribed mode.
+    ///
+    /// The reply is then an array frame of this shape: `["pong", msg]`, `msg` being empty if not provided.
+    pub(crate) async fn apply_subscribed(self, dst: &mut Connection) -> crate::FnResult<()> {
+        let mut response = Frame::array();
+        response.push_bulk(Bytes::from_static(b"pong"));
+        response.push_bulk(self.msg.unwrap_or_default());
 
         debug!(?response);
 
-        // Write response back to the client
         dst.write_frame(&response).await?;
 
         Ok(())
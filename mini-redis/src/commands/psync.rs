@@ -0,0 +1,123 @@
+use bytes::Bytes;
+
+use crate::{
+    connection::Connection,
+    db::Db,
+    frame::Frame,
+    parse::{Parse, ParseError},
+    replication,
+    shutdown::Shutdown,
+};
+
+/// Sync a replica with its leader, sent by the replica once connected.
+///
+/// The replica gives the replication id it last synced with and the offset it reached.
+/// If the leader still holds the stream from that offset in its backlog, it replies
+/// `+CONTINUE <replid>` then sends the part of the stream the replica missed. Otherwise it
+/// replies `+FULLRESYNC <replid> <offset>` and sends a snapshot of its databases as
+/// `$<len>\r\n<payload>`. It then streams the writes applied to the databases.
+///
+/// The connection is dedicated to the replication from then on.
+///
+/// With `FAILOVER`, sent by a leader handing the leadership over to the replica, the
+/// replica promotes itself first: its replication id and offset must match the ones given.
+#[derive(Debug)]
+pub struct PSync {
+    /// Replication id the replica last synced with, `?` if none
+    replid: String,
+
+    /// Offset the replica reached, `-1` if none
+    offset: i64,
+
+    /// Whether the server must promote itself first
+    failover: bool,
+}
+
+impl PSync {
+    /// Create a `PSync` command continuing the stream with id `replid` from `offset`.
+    pub fn new(replid: impl ToString, offset: u64) -> PSync {
+        PSync {
+            replid: replid.to_string(),
+            offset: offset as i64,
+            failover: false,
+        }
+    }
+
+    /// Create a `PSync` command requesting a full sync.
+    pub fn full() -> PSync {
+        PSync {
+            replid: "?".to_string(),
+            offset: -1,
+            failover: false,
+        }
+    }
+
+    /// Set whether the server must promote itself first, the sender handing the
+    /// leadership over to it.
+    pub fn failover(mut self, failover: bool) -> PSync {
+        self.failover = failover;
+        self
+    }
+
+    /// Parse a `PSync` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<PSync> {
+        // Note: the `PSYNC` string has already been consumed, next values are the
+        // replication id and the offset
+        let replid = parse.next_string()?;
+
+        let offset = match parse.next_string()?.parse() {
+            Ok(offset) => offset,
+            Err(_) => return Err("ERR value is not an integer or out of range".into()),
+        };
+
+        let failover = match parse.next_string() {
+            Ok(s) if s.to_uppercase() == "FAILOVER" => true,
+            Ok(_) => return Err("ERR syntax error".into()),
+            Err(ParseError::EndOfStream) => false,
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(PSync {
+            replid,
+            offset,
+            failover,
+        })
+    }
+
+    /// Apply the `PSync` command to the specified `Db` instance, serving the replica
+    /// until it disconnects.
+    ///
+    /// `replica_port` is the port announced by the replica with `REPLCONF listening-port`.
+    pub(crate) async fn apply(
+        self,
+        db: &Db,
+        dst: &mut Connection,
+        shutdown: &mut Shutdown,
+        replica_port: Option<u16>,
+    ) -> crate::FnResult<()> {
+        replication::serve_replica(
+            db,
+            dst,
+            shutdown,
+            replica_port,
+            &self.replid,
+            self.offset,
+            self.failover,
+        )
+        .await
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("psync".as_bytes()));
+        frame.push_bulk(Bytes::from(self.replid.into_bytes()));
+        frame.push_bulk(Bytes::from(self.offset.to_string().into_bytes()));
+
+        if self.failover {
+            frame.push_bulk(Bytes::from("failover".as_bytes()));
+        }
+
+        frame
+    }
+}
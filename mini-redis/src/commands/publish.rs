@@ -1,6 +1,11 @@
 use bytes::Bytes;
 
-use crate::{connection::Connection, db::Db, frame::Frame, parse::Parse};
+use crate::{
+    connection::Connection,
+    db::{Db, DbGuard},
+    frame::Frame,
+    parse::Parse,
+};
 
 /// Post a message to the given channel.
 #[derive(Debug)]
@@ -17,6 +22,10 @@ impl Publish {
         }
     }
 
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
     /// Parse a `Publish` instance from a received frame.
     pub(crate) fn parse_frame(parse: &mut Parse) -> crate::FnResult<Publish> {
          // Note: the `PUBLISH` string has already been consumed, next values are `channel` and `message`
@@ -28,14 +37,22 @@ impl Publish {
 
     /// Apply the `Publish` command to the specified `Db` instance.
     pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
-        let num_subscribers = db.publish(&self.channel, self.message);
+        let num_subscribers = db.publish(&self.channel, self.message).await;
 
-        let response = Frame::Integer(num_subscribers as u64);
+        let response = Frame::Integer(num_subscribers as i64);
         dst.write_frame(&response).await?;
 
         Ok(())
     }
 
+    /// Execute the `Publish` command on the locked `Db`, returning the reply.
+    ///
+    /// Publishers can't wait for slow subscribers while the `Db` is locked, so this
+    /// doesn't apply `LagPolicy::Backpressure`.
+    pub(crate) fn execute(self, db: &mut DbGuard) -> Frame {
+        Frame::Integer(db.publish(&self.channel, self.message) as i64)
+    }
+
     pub(crate) fn into_frame(self) -> Frame {
         let mut frame = Frame::array();
         frame.push_bulk(Bytes::from("publish".as_bytes()));
@@ -0,0 +1,129 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    connection::Connection,
+    db::{Db, DbGuard},
+    frame::Frame,
+    parse::{Parse, ParseError},
+};
+
+/// Introspect the pub/sub subsystem.
+///
+/// Supported subcommands:
+/// - `PUBSUB CHANNELS [pattern]`: list the channels having at least one subscriber
+/// - `PUBSUB NUMSUB [channel ...]`: number of subscribers of each given channel
+/// - `PUBSUB NUMPAT`: number of patterns having at least one subscriber
+/// - `PUBSUB LAG`: lag statistics of every connection in subscribed mode
+#[derive(Debug)]
+pub enum PubSub {
+    Channels(Option<String>),
+    NumSub(Vec<String>),
+    NumPat,
+    Lag,
+}
+
+impl PubSub {
+    /// Parse a `PubSub` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<PubSub> {
+        // Note: the `PUBSUB` string has already been consumed, next value is the subcommand
+        let subcommand = parse.next_string()?.to_lowercase();
+
+        match &subcommand[..] {
+            "channels" => match parse.next_string() {
+                Ok(pattern) => Ok(PubSub::Channels(Some(pattern))),
+                Err(ParseError::EndOfStream) => Ok(PubSub::Channels(None)),
+                Err(err) => Err(err.into()),
+            },
+            "numsub" => {
+                let mut channels = vec![];
+
+                loop {
+                    match parse.next_string() {
+                        Ok(s) => channels.push(s),
+                        Err(ParseError::EndOfStream) => break,
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+
+                Ok(PubSub::NumSub(channels))
+            }
+            "numpat" => Ok(PubSub::NumPat),
+            "lag" => Ok(PubSub::Lag),
+            _ => Err(format!("ERR unknown subcommand '{}' for 'pubsub'", subcommand).into()),
+        }
+    }
+
+    /// Apply the `PubSub` command to the specified `Db` instance.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = match db.lock_unless_busy().await {
+            Ok(mut guard) => self.execute(&mut guard),
+            Err(response) => response,
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `PubSub` command on the locked `Db`, returning the reply.
+    pub(crate) fn execute(self, db: &mut DbGuard) -> Frame {
+        match self {
+            PubSub::Channels(pattern) => {
+                let mut frame = Frame::array();
+                for channel in db.channels(pattern.as_deref()) {
+                    frame.push_bulk(Bytes::from(channel.into_bytes()));
+                }
+                frame
+            }
+            PubSub::NumSub(channels) => {
+                let mut frame = Frame::array();
+                for channel in channels {
+                    let num_subscribers = db.num_subscribers(&channel);
+                    frame.push_bulk(Bytes::from(channel.into_bytes()));
+                    frame.push_int(num_subscribers as u64);
+                }
+                frame
+            }
+            PubSub::NumPat => Frame::Integer(db.num_patterns() as i64),
+            PubSub::Lag => {
+                // One line per subscriber, formatted like `CLIENT LIST`
+                let mut lines = String::new();
+                for info in db.subscribers() {
+                    lines.push_str(&format!(
+                        "id={} sub={} psub={} lag-events={} dropped={}\n",
+                        info.id, info.channels, info.patterns, info.lag_events, info.dropped
+                    ));
+                }
+                Frame::Bulk(Bytes::from(lines.into_bytes()))
+            }
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("pubsub".as_bytes()));
+
+        match self {
+            PubSub::Channels(pattern) => {
+                frame.push_bulk(Bytes::from("channels".as_bytes()));
+                if let Some(pattern) = pattern {
+                    frame.push_bulk(Bytes::from(pattern.into_bytes()));
+                }
+            }
+            PubSub::NumSub(channels) => {
+                frame.push_bulk(Bytes::from("numsub".as_bytes()));
+                for channel in channels {
+                    frame.push_bulk(Bytes::from(channel.into_bytes()));
+                }
+            }
+            PubSub::NumPat => frame.push_bulk(Bytes::from("numpat".as_bytes())),
+            PubSub::Lag => frame.push_bulk(Bytes::from("lag".as_bytes())),
+        }
+
+        frame
+    }
+}
@@ -0,0 +1,39 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{connection::Connection, frame::Frame, parse::Parse};
+
+/// Ask the server to close the connection, once the `OK` reply has been sent.
+#[derive(Default, Debug)]
+pub struct Quit;
+
+impl Quit {
+    pub fn new() -> Quit {
+        Quit
+    }
+
+    /// Parse a `Quit` instance from a received frame.
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::FnResult<Quit> {
+        // Note: the `QUIT` string has already been consumed, it has no argument
+        Ok(Quit)
+    }
+
+    /// Apply the `Quit` command, replying `OK` and closing the connection.
+    pub(crate) async fn apply(self, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = Frame::Simple("OK".to_string());
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+        dst.close_after_reply();
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("quit".as_bytes()));
+        frame
+    }
+}
@@ -0,0 +1,108 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{connection::Connection, frame::Frame, parse::Parse};
+
+/// Configure the replication link, sent by a replica to its leader.
+///
+/// - `REPLCONF listening-port <port>`: port the replica listens on, reported by `ROLE`
+/// - `REPLCONF capa <capability>`: capability of the replica, accepted and ignored
+/// - `REPLCONF ACK <offset>`: offset of the write stream applied by the replica, sent every
+///   second once streaming. It isn't replied to.
+/// - `REPLCONF GETACK *`: sent by the leader in the write stream, the replica acknowledges
+///   its offset right away.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReplConf {
+    ListeningPort(u16),
+    Capa(String),
+    Ack(u64),
+    GetAck,
+}
+
+impl ReplConf {
+    /// Parse a `ReplConf` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<ReplConf> {
+        // Note: the `REPLCONF` string has already been consumed, next values are the option
+        // and its value
+        let option = parse.next_string()?;
+
+        match &option.to_lowercase()[..] {
+            "listening-port" => match parse.next_string()?.parse() {
+                Ok(port) => Ok(ReplConf::ListeningPort(port)),
+                Err(_) => Err("ERR value is not an integer or out of range".into()),
+            },
+            "capa" => Ok(ReplConf::Capa(parse.next_string()?)),
+            "ack" => Ok(ReplConf::Ack(parse.next_int()?)),
+            "getack" => {
+                parse.next_string()?;
+                Ok(ReplConf::GetAck)
+            }
+            _ => Err(format!("ERR Unrecognized REPLCONF option: {}", option).into()),
+        }
+    }
+
+    /// Parse a `ReplConf` instance from a frame received while streaming to a replica.
+    pub(crate) fn from_frame(frame: Frame) -> crate::FnResult<ReplConf> {
+        let mut parse = Parse::new(frame)?;
+
+        if !parse.next_string()?.eq_ignore_ascii_case("replconf") {
+            return Err("expected REPLCONF".into());
+        }
+
+        let replconf = ReplConf::parse_frames(&mut parse)?;
+        parse.finish()?;
+
+        Ok(replconf)
+    }
+
+    /// Apply the `ReplConf` command, recording the port the replica listens on in
+    /// `replica_port`.
+    pub(crate) async fn apply(
+        self,
+        dst: &mut Connection,
+        replica_port: &mut Option<u16>,
+    ) -> crate::FnResult<()> {
+        let response = match self {
+            ReplConf::ListeningPort(port) => {
+                *replica_port = Some(port);
+                Frame::Simple("OK".to_string())
+            }
+            ReplConf::Capa(_) => Frame::Simple("OK".to_string()),
+            // Only meaningful on the replication link
+            ReplConf::Ack(_) | ReplConf::GetAck => return Ok(()),
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("replconf".as_bytes()));
+
+        match self {
+            ReplConf::ListeningPort(port) => {
+                frame.push_bulk(Bytes::from("listening-port".as_bytes()));
+                frame.push_bulk(Bytes::from(port.to_string().into_bytes()));
+            }
+            ReplConf::Capa(capability) => {
+                frame.push_bulk(Bytes::from("capa".as_bytes()));
+                frame.push_bulk(Bytes::from(capability.into_bytes()));
+            }
+            ReplConf::Ack(offset) => {
+                frame.push_bulk(Bytes::from("ack".as_bytes()));
+                frame.push_int(offset);
+            }
+            ReplConf::GetAck => {
+                frame.push_bulk(Bytes::from("getack".as_bytes()));
+                frame.push_bulk(Bytes::from("*".as_bytes()));
+            }
+        }
+
+        frame
+    }
+}
@@ -0,0 +1,84 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{connection::Connection, db::Db, frame::Frame, parse::Parse, replication};
+
+/// Make the server a replica of another server, the leader.
+///
+/// The replica drops its databases for a snapshot of the leader's, then applies the writes
+/// streamed by the leader, rejecting writes from its own clients. `REPLICAOF NO ONE` stops
+/// the replication, making the server a leader again while keeping its data.
+///
+/// `SLAVEOF` is accepted as an alias.
+#[derive(Debug)]
+pub struct ReplicaOf {
+    /// Host and port of the leader, `None` for `NO ONE`
+    leader: Option<(String, u16)>,
+}
+
+impl ReplicaOf {
+    /// Create a `ReplicaOf` command replicating the leader at `host:port`.
+    pub fn new(host: impl ToString, port: u16) -> ReplicaOf {
+        ReplicaOf {
+            leader: Some((host.to_string(), port)),
+        }
+    }
+
+    /// Create a `ReplicaOf` command stopping the replication.
+    pub fn no_one() -> ReplicaOf {
+        ReplicaOf { leader: None }
+    }
+
+    /// Parse a `ReplicaOf` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<ReplicaOf> {
+        // Note: the `REPLICAOF` string has already been consumed, next values are either
+        // the host and port of the leader, or `NO ONE`
+        let host = parse.next_string()?;
+        let port = parse.next_string()?;
+
+        if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+            return Ok(ReplicaOf::no_one());
+        }
+
+        match port.parse() {
+            Ok(port) => Ok(ReplicaOf::new(host, port)),
+            Err(_) => Err("ERR Invalid master port".into()),
+        }
+    }
+
+    /// Apply the `ReplicaOf` command to the specified `Db` instance.
+    ///
+    /// Replies right away, the replica syncs with its leader in the background.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = if replication::replicate(db, self.leader) {
+            Frame::Simple("OK".to_string())
+        } else {
+            Frame::Simple("OK Already connected to specified master".to_string())
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("replicaof".as_bytes()));
+
+        match self.leader {
+            Some((host, port)) => {
+                frame.push_bulk(Bytes::from(host.into_bytes()));
+                frame.push_bulk(Bytes::from(port.to_string().into_bytes()));
+            }
+            None => {
+                frame.push_bulk(Bytes::from("no".as_bytes()));
+                frame.push_bulk(Bytes::from("one".as_bytes()));
+            }
+        }
+
+        frame
+    }
+}
@@ -0,0 +1,50 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{acl::Session, connection::Connection, db::Db, frame::Frame, parse::Parse};
+
+/// Reset the connection state: leave subscribed mode, select database `0`, authenticate
+/// as a new connection would and reply `RESET`.
+#[derive(Default, Debug)]
+pub struct Reset;
+
+impl Reset {
+    pub fn new() -> Reset {
+        Reset
+    }
+
+    /// Parse a `Reset` instance from a received frame.
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::FnResult<Reset> {
+        // Note: the `RESET` string has already been consumed, it has no argument
+        Ok(Reset)
+    }
+
+    /// Apply the `Reset` command.
+    ///
+    /// Subscriptions are dropped by the subscribed mode before this is called,
+    /// so only the database selection and the authentication are left to restore.
+    pub(crate) async fn apply(
+        self,
+        db: &mut Db,
+        dst: &mut Connection,
+        session: &mut Session,
+    ) -> crate::FnResult<()> {
+        *db = db.select(0).expect("database 0 always exists");
+        session.reset(db.acl());
+
+        let response = Frame::Simple("RESET".to_string());
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("reset".as_bytes()));
+        frame
+    }
+}
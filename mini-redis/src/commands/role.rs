@@ -0,0 +1,56 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    connection::Connection,
+    db::{Db, DbGuard},
+    frame::Frame,
+    parse::Parse,
+};
+
+/// Returns the replication role of the server.
+///
+/// A leader replies `["master", offset, [[ip, port, offset], ...]]`, listing its replicas
+/// and the offset they acknowledged. A replica replies
+/// `["slave", host, port, state, offset]`, `state` being the state of the link to its
+/// leader: `connect`, `connecting`, `sync` or `connected`.
+#[derive(Debug, Default)]
+pub struct Role;
+
+impl Role {
+    pub fn new() -> Role {
+        Role
+    }
+
+    /// Parse a `Role` instance from a received frame.
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::FnResult<Role> {
+        // Note: the `ROLE` string has already been consumed, it has no argument
+        Ok(Role)
+    }
+
+    /// Apply the `Role` command to the specified `Db` instance.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = match db.lock_unless_busy().await {
+            Ok(mut guard) => self.execute(&mut guard),
+            Err(response) => response,
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `Role` command on the locked `Db`, returning the reply.
+    pub(crate) fn execute(self, db: &mut DbGuard) -> Frame {
+        db.replication().role()
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("role".as_bytes()));
+        frame
+    }
+}
@@ -0,0 +1,134 @@
+use bytes::Bytes;
+use std::time::UNIX_EPOCH;
+use tracing::debug;
+
+use crate::{
+    connection::Connection,
+    db::{Db, DbGuard},
+    frame::Frame,
+    parse::Parse,
+};
+
+/// Save a snapshot of all databases to the RDB file, replying once it's written.
+#[derive(Debug, Default)]
+pub struct Save;
+
+/// Save a snapshot of all databases to the RDB file in the background.
+#[derive(Debug, Default)]
+pub struct BgSave;
+
+/// Return the Unix time of the last successful snapshot.
+#[derive(Debug, Default)]
+pub struct LastSave;
+
+impl Save {
+    pub fn new() -> Save {
+        Save
+    }
+
+    /// Parse a `Save` instance from a received frame.
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::FnResult<Save> {
+        // Note: the `SAVE` string has already been consumed, it has no argument
+        Ok(Save)
+    }
+
+    /// Apply the `Save` command to the specified `Db` instance.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = match db.save().await {
+            Ok(()) => Frame::Simple("OK".to_string()),
+            Err(err) => Frame::Error(format!("ERR {}", err)),
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("save".as_bytes()));
+        frame
+    }
+}
+
+impl BgSave {
+    pub fn new() -> BgSave {
+        BgSave
+    }
+
+    /// Parse a `BgSave` instance from a received frame.
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::FnResult<BgSave> {
+        // Note: the `BGSAVE` string has already been consumed, it has no argument
+        Ok(BgSave)
+    }
+
+    /// Apply the `BgSave` command to the specified `Db` instance.
+    ///
+    /// Replies right away, the snapshot is written by a background task.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = if db.bgsave() {
+            Frame::Simple("Background saving started".to_string())
+        } else {
+            Frame::Error("ERR Background save already in progress".to_string())
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("bgsave".as_bytes()));
+        frame
+    }
+}
+
+impl LastSave {
+    pub fn new() -> LastSave {
+        LastSave
+    }
+
+    /// Parse a `LastSave` instance from a received frame.
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::FnResult<LastSave> {
+        // Note: the `LASTSAVE` string has already been consumed, it has no argument
+        Ok(LastSave)
+    }
+
+    /// Apply the `LastSave` command to the specified `Db` instance.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = match db.lock_unless_busy().await {
+            Ok(mut guard) => self.execute(&mut guard),
+            Err(response) => response,
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `LastSave` command on the locked `Db`, returning the reply.
+    pub(crate) fn execute(self, db: &mut DbGuard) -> Frame {
+        let last_save = db
+            .last_save()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Frame::Integer(last_save.as_secs() as i64)
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("lastsave".as_bytes()));
+        frame
+    }
+}
@@ -0,0 +1,111 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    connection::Connection,
+    db::Db,
+    frame::Frame,
+    parse::{Parse, ParseError},
+    scripting::Scripts,
+};
+
+/// Manage the Lua scripts cache.
+///
+/// Supported subcommands:
+/// - `SCRIPT LOAD script`: cache a script without running it, replying its SHA1 digest
+/// - `SCRIPT EXISTS sha1 [sha1 ...]`: whether each script is cached
+/// - `SCRIPT FLUSH`: remove all cached scripts
+/// - `SCRIPT KILL`: abort the running script, unless it already wrote
+#[derive(Debug)]
+pub enum Script {
+    Load(String),
+    Exists(Vec<String>),
+    Flush,
+    Kill,
+}
+
+impl Script {
+    /// Parse a `Script` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<Script> {
+        // Note: the `SCRIPT` string has already been consumed, next value is the subcommand
+        let subcommand = parse.next_string()?.to_lowercase();
+
+        match &subcommand[..] {
+            "load" => Ok(Script::Load(parse.next_string()?)),
+            "exists" => {
+                let mut sha1s = vec![parse.next_string()?];
+
+                loop {
+                    match parse.next_string() {
+                        Ok(sha1) => sha1s.push(sha1),
+                        Err(ParseError::EndOfStream) => break,
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+
+                Ok(Script::Exists(sha1s))
+            }
+            "flush" => Ok(Script::Flush),
+            "kill" => Ok(Script::Kill),
+            _ => Err(format!("ERR unknown subcommand '{}' for 'script'", subcommand).into()),
+        }
+    }
+
+    /// Apply the `Script` command to the specified `Db` instance.
+    ///
+    /// The `Db` isn't locked, so that `SCRIPT KILL` goes through while a script runs.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = self.execute(db.scripts());
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `Script` command on the scripts cache, returning the reply.
+    pub(crate) fn execute(self, scripts: &Scripts) -> Frame {
+        match self {
+            Script::Load(script) => Frame::Bulk(Bytes::from(scripts.load(&script).into_bytes())),
+            Script::Exists(sha1s) => {
+                let mut frame = Frame::array();
+                for exists in scripts.exists(&sha1s) {
+                    frame.push_int(exists as u64);
+                }
+                frame
+            }
+            Script::Flush => {
+                scripts.flush();
+                Frame::Simple("OK".to_string())
+            }
+            Script::Kill => match scripts.kill() {
+                Ok(()) => Frame::Simple("OK".to_string()),
+                Err(msg) => Frame::Error(msg),
+            },
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("script".as_bytes()));
+
+        match self {
+            Script::Load(script) => {
+                frame.push_bulk(Bytes::from("load".as_bytes()));
+                frame.push_bulk(Bytes::from(script.into_bytes()));
+            }
+            Script::Exists(sha1s) => {
+                frame.push_bulk(Bytes::from("exists".as_bytes()));
+                for sha1 in sha1s {
+                    frame.push_bulk(Bytes::from(sha1.into_bytes()));
+                }
+            }
+            Script::Flush => frame.push_bulk(Bytes::from("flush".as_bytes())),
+            Script::Kill => frame.push_bulk(Bytes::from("kill".as_bytes())),
+        }
+
+        frame
+    }
+}
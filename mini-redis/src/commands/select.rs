@@ -0,0 +1,75 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    connection::Connection,
+    db::{Db, DbGuard},
+    frame::Frame,
+    parse::Parse,
+};
+
+/// Reply to `SELECT` in cluster mode, which has a single database.
+const CLUSTER_SELECT_ERROR: &str = "ERR SELECT is not allowed in cluster mode";
+
+/// Select the logical database the connection operates on.
+#[derive(Debug)]
+pub struct Select {
+    index: u64,
+}
+
+impl Select {
+    pub fn new(index: u64) -> Select {
+        Select { index }
+    }
+
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// Parse a `Select` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<Select> {
+        // Note: the `SELECT` string has already been consumed, next value is the database index
+        Ok(Select {
+            index: parse.next_int()?,
+        })
+    }
+
+    /// Apply the `Select` command, switching the connection's `Db` handle to the selected database.
+    pub(crate) async fn apply(self, db: &mut Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = match db.select(self.index as usize) {
+            Some(selected) => {
+                *db = selected;
+                Frame::Simple("OK".to_string())
+            }
+            None if db.is_cluster() => Frame::Error(CLUSTER_SELECT_ERROR.to_string()),
+            None => Frame::Error("ERR DB index is out of range".to_string()),
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `Select` command on the locked `Db`, returning the reply.
+    ///
+    /// The selection applies to the commands executed next with the same guard.
+    pub(crate) fn execute(self, db: &mut DbGuard) -> Frame {
+        if db.select(self.index as usize) {
+            Frame::Simple("OK".to_string())
+        } else if db.cluster().is_some() {
+            Frame::Error(CLUSTER_SELECT_ERROR.to_string())
+        } else {
+            Frame::Error("ERR DB index is out of range".to_string())
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("select".as_bytes()));
+        frame.push_int(self.index);
+        frame
+    }
+}
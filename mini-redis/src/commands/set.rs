@@ -1,8 +1,17 @@
 use bytes::Bytes;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
 use tracing::debug;
-use std::time::Duration;
 
-use crate::{connection::Connection, db::Db, frame::Frame, parse::{Parse, ParseError}};
+use crate::{
+    connection::Connection,
+    db::{Db, DbGuard},
+    frame::Frame,
+    parse::{Parse, ParseError},
+};
+
+/// Reply to `SET` when the expiration is out of range.
+const INVALID_EXPIRE_ERROR: &str = "ERR invalid expire time in 'set' command";
 
 /// Set the value of a key.
 #[derive(Debug)]
@@ -53,6 +62,16 @@ impl Set {
                 let ms = parse.next_int()?;
                 expire = Some(Duration::from_millis(ms));
             }
+            Ok(s) if s.to_uppercase() == "EXAT" => {
+                // An expiration is specified as a Unix time in secs
+                let secs = parse.next_int()?;
+                expire = Some(until(Duration::from_secs(secs)));
+            }
+            Ok(s) if s.to_uppercase() == "PXAT" => {
+                // An expiration is specified as a Unix time in ms
+                let ms = parse.next_int()?;
+                expire = Some(until(Duration::from_millis(ms)));
+            }
             Ok(_) => return Err("currently `SET` only supports the expiration option".into()),
             Err(ParseError::EndOfStream) => {}
             Err(err) => return Err(err.into())
@@ -62,16 +81,56 @@ impl Set {
     }
 
     /// Apply the `Set` command to the specified `Db` instance.
+    ///
+    /// The expiration is checked before locking the shard of the key.
     pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
-        db.set(self.key, self.value, self.expire);
-
-        let response = Frame::Simple("OK".to_string());
+        let response = match self.check_expire() {
+            // Only the shard of the key is locked, other keys remain available
+            Ok(()) => match db.lock_keys_unless_busy(&[&self.key]).await {
+                Ok(mut guard) => self.execute(&mut guard),
+                Err(response) => response,
+            },
+            Err(response) => response,
+        };
         debug!(?response);
         dst.write_frame(&response).await?;
 
         Ok(())
     }
 
+    /// Execute the `Set` command on the locked `Db`, returning the reply.
+    pub(crate) fn execute(self, db: &mut DbGuard) -> Frame {
+        if let Err(response) = self.check_expire() {
+            return response;
+        }
+
+        db.set(self.key, self.value, self.expire);
+
+        Frame::Simple("OK".to_string())
+    }
+
+    /// Returns the error to reply if the expiration is out of range: as Redis does, its Unix
+    /// time in milliseconds must fit in an `i64`, and it must be representable as an `Instant`.
+    fn check_expire(&self) -> Result<(), Frame> {
+        let expire = match self.expire {
+            Some(expire) => expire,
+            None => return Ok(()),
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let in_range = now
+            .checked_add(expire)
+            .is_some_and(|at| at.as_millis() <= i64::MAX as u128);
+
+        if in_range && Instant::now().checked_add(expire).is_some() {
+            Ok(())
+        } else {
+            Err(Frame::Error(INVALID_EXPIRE_ERROR.to_string()))
+        }
+    }
+
     /// Converts the command into an equivalent `Frame`.
     pub(crate) fn into_frame(self) -> Frame {
         let mut frame = Frame::array();
@@ -90,4 +149,14 @@ impl Set {
 
         frame
     }
-}
\ No newline at end of file
+}
+/// Duration from now until the given Unix time, zero if it already passed.
+///
+/// A Unix time too far to be represented gives `Duration::MAX`, rejected as out of range
+/// when the command is applied.
+fn until(unix_time: Duration) -> Duration {
+    match UNIX_EPOCH.checked_add(unix_time) {
+        Some(time) => time.duration_since(SystemTime::now()).unwrap_or_default(),
+        None => Duration::MAX,
+    }
+}
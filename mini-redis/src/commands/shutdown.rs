@@ -0,0 +1,74 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    db::Db,
+    frame::Frame,
+    parse::{Parse, ParseError},
+};
+
+/// Stop the server, once the connections are closed.
+///
+/// The databases are saved on the way out when save rules are configured, or always with
+/// `SHUTDOWN SAVE`. `SHUTDOWN NOSAVE` never saves them, and aborts the running script even
+/// if it already wrote, which `SCRIPT KILL` can't.
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    /// `Some(true)` with `SAVE`, `Some(false)` with `NOSAVE`, `None` to follow the save rules
+    save: Option<bool>,
+}
+
+impl Shutdown {
+    pub fn new(save: Option<bool>) -> Shutdown {
+        Shutdown { save }
+    }
+
+    /// Returns `true` for `SHUTDOWN NOSAVE`.
+    pub(crate) fn is_nosave(&self) -> bool {
+        self.save == Some(false)
+    }
+
+    /// Parse a `Shutdown` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<Shutdown> {
+        // Note: the `SHUTDOWN` string has already been consumed, next value is the
+        // optional save mode
+        let save = match parse.next_string() {
+            Ok(mode) if mode.eq_ignore_ascii_case("save") => Some(true),
+            Ok(mode) if mode.eq_ignore_ascii_case("nosave") => Some(false),
+            Ok(_) => return Err("ERR syntax error".into()),
+            Err(ParseError::EndOfStream) => None,
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Shutdown { save })
+    }
+
+    /// Apply the `Shutdown` command to the specified `Db` instance.
+    ///
+    /// Nothing is replied, the connection is closed as the server stops.
+    pub(crate) async fn apply(self, db: &Db) -> crate::FnResult<()> {
+        if self.is_nosave() {
+            db.scripts().abort();
+        }
+
+        debug!(save = ?self.save, "shutdown requested");
+
+        db.request_shutdown(self.save);
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("shutdown".as_bytes()));
+
+        match self.save {
+            Some(true) => frame.push_bulk(Bytes::from("save".as_bytes())),
+            Some(false) => frame.push_bulk(Bytes::from("nosave".as_bytes())),
+            None => {}
+        }
+
+        frame
+    }
+}
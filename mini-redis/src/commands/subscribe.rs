@@ -1,14 +1,15 @@
 use bytes::Bytes;
-use tokio_stream::StreamExt;
 use std::pin::Pin;
 use tokio::select;
 use tokio::sync::broadcast;
+use tokio_stream::StreamExt;
 use tokio_stream::{Stream, StreamMap};
 
+use crate::acl::Session;
 use crate::commands::Command;
-use crate::commands::Unknown;
+use crate::config::LagPolicy;
 use crate::connection::Connection;
-use crate::db::Db;
+use crate::db::{Db, SubscriberGuard};
 use crate::frame::Frame;
 use crate::parse::{Parse, ParseError};
 use crate::shutdown::Shutdown;
@@ -16,7 +17,7 @@ use crate::shutdown::Shutdown;
 /// Subscribe the client to one of more channels.
 ///
 /// Once the client enters the subscribed state, it's not supposed to issue any
-/// other command except SUBSCRIBE, PSUBSCRIBE, UNSUBSCRIBE, PUNSUBSCRIBE, PING and QUIT.
+/// other command except SUBSCRIBE, PSUBSCRIBE, UNSUBSCRIBE, PUNSUBSCRIBE, PING, RESET and QUIT.
 #[derive(Debug)]
 pub struct Subscribe {
     channels: Vec<String>,
@@ -30,176 +31,470 @@ pub struct Unsubscribe {
     channels: Vec<String>,
 }
 
-/// Stream of messages to use with `stream!`
-type Messages = Pin<Box<dyn Stream<Item = Bytes> + Send>>;
+/// Subscribe the client to one or more glob-style channel patterns.
+///
+/// Like `SUBSCRIBE`, this enters the subscribed state.
+#[derive(Debug)]
+pub struct PSubscribe {
+    patterns: Vec<String>,
+}
+
+/// Unsubscribe the client from one or more channel patterns.
+///
+/// When no pattern is specified, client is unsubscribed from all previously subscribed patterns.
+#[derive(Debug)]
+pub struct PUnsubscribe {
+    patterns: Vec<String>,
+}
+
+/// Event received on a subscribed channel or pattern.
+#[derive(Debug)]
+enum Event {
+    /// A message published on the subscribed channel
+    Message(Bytes),
+
+    /// A message published on a channel matching the subscribed pattern
+    PMessage(String, Bytes),
+
+    /// The subscriber fell behind and missed that many messages
+    Lagged(u64),
+}
+
+/// Stream of events to use with `stream!`
+type Messages = Pin<Box<dyn Stream<Item = Event> + Send>>;
+
+/// State of a connection in subscribed mode.
+struct Subscriptions<'a> {
+    db: &'a mut Db,
+
+    // User the connection is authenticated as, whose permissions the commands are checked against
+    session: &'a mut Session,
+
+    // Active subscriptions, merging messages from individual channels and patterns as they are received
+    channels: StreamMap<String, Messages>,
+    patterns: StreamMap<String, Messages>,
+
+    // Track the subscriber lag statistics for as long as it is subscribed.
+    // Declared after the stream maps so that channel receivers are released first.
+    subscriber: SubscriberGuard,
+}
 
 impl Subscribe {
     pub fn new(channels: Vec<String>) -> Subscribe {
         Subscribe { channels }
     }
 
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+
     /// Parse a `Subscribe` instance from a received frame.
     pub(crate) fn parse_frame(parse: &mut Parse) -> crate::FnResult<Subscribe> {
         // Note: the `SUBSCRIBE` string has already been consumed, next values are `channels`
-        let mut channels = vec![parse.next_string()?];
-
-        loop {
-            match parse.next_string() {
-                Ok(s) => channels.push(s),
-                Err(ParseError::EndOfStream) => break,
-                Err(err) => return Err(err.into()),
-            }
-        }
+        let channels = parse_names(parse, true)?;
 
         Ok(Subscribe { channels })
     }
 
     /// Apply the `Subscribe` command to the specified `Db` instance.
+    ///
+    /// The connection stays in subscribed mode until it is no longer subscribed
+    /// to any channel or pattern.
     pub(crate) async fn apply(
-        mut self,
-        db: &Db,
+        self,
+        db: &mut Db,
         dst: &mut Connection,
         shutdown: &mut Shutdown,
+        session: &mut Session,
     ) -> crate::FnResult<()> {
-        // Each individual subscription is handled using `sync::broadcast`.
-        // A `StreamMap` is used to track active subscriptions, merging messages from individual channels as they are received.
-        let mut subscriptions = StreamMap::new();
+        let mut subscriptions = Subscriptions::new(db, session);
 
-        loop {
-            for channel_name in self.channels.drain(..) {
-                subscribe_to_channel(channel_name, &mut subscriptions, db, dst).await?;
-            }
+        for channel_name in self.channels {
+            subscriptions.subscribe(channel_name, dst).await?;
+        }
+
+        subscriptions.run(dst, shutdown).await
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("subscribe".as_bytes()));
+        for channel in self.channels {
+            frame.push_bulk(Bytes::from(channel.into_bytes()));
+        }
+        frame
+    }
+}
+
+impl PSubscribe {
+    pub fn new(patterns: Vec<String>) -> PSubscribe {
+        PSubscribe { patterns }
+    }
+
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    /// Parse a `PSubscribe` instance from a received frame.
+    pub(crate) fn parse_frame(parse: &mut Parse) -> crate::FnResult<PSubscribe> {
+        // Note: the `PSUBSCRIBE` string has already been consumed, next values are `patterns`
+        let patterns = parse_names(parse, true)?;
+
+        Ok(PSubscribe { patterns })
+    }
+
+    /// Apply the `PSubscribe` command to the specified `Db` instance.
+    ///
+    /// The connection stays in subscribed mode until it is no longer subscribed
+    /// to any channel or pattern.
+    pub(crate) async fn apply(
+        self,
+        db: &mut Db,
+        dst: &mut Connection,
+        shutdown: &mut Shutdown,
+        session: &mut Session,
+    ) -> crate::FnResult<()> {
+        let mut subscriptions = Subscriptions::new(db, session);
+
+        for pattern in self.patterns {
+            subscriptions.psubscribe(pattern, dst).await?;
+        }
+
+        subscriptions.run(dst, shutdown).await
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("psubscribe".as_bytes()));
+        for pattern in self.patterns {
+            frame.push_bulk(Bytes::from(pattern.into_bytes()));
+        }
+        frame
+    }
+}
+
+impl<'a> Subscriptions<'a> {
+    fn new(db: &'a mut Db, session: &'a mut Session) -> Subscriptions<'a> {
+        let subscriber = db.register_subscriber();
+
+        Subscriptions {
+            db,
+            session,
+            channels: StreamMap::new(),
+            patterns: StreamMap::new(),
+            subscriber,
+        }
+    }
+
+    /// Number of channels and patterns subscribed to.
+    fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    /// Process messages and commands until no subscription is left.
+    async fn run(mut self, dst: &mut Connection, shutdown: &mut Shutdown) -> crate::FnResult<()> {
+        while self.count() > 0 && !dst.is_closed() {
+            self.subscriber
+                .set_subscriptions(self.channels.len(), self.patterns.len());
 
             // Wait for one of the following to happen:
-            // - Receives msg from subscribed channels => emit frame
-            // - Receives subscribe/unsubscribe frame from client
+            // - Receives msg from subscribed channels or patterns => emit frame
+            // - Receives a command frame from client
             // - Server shutdown signal
             select! {
-                Some((channel_name, msg)) = subscriptions.next() => {
-                    dst.write_frame(&make_message_frame(channel_name, msg)).await?;
+                Some((channel_name, event)) = self.channels.next() => {
+                    self.forward(channel_name, event, dst).await?;
+                }
+                Some((pattern, event)) = self.patterns.next() => {
+                    self.forward(pattern, event, dst).await?;
                 }
                 res = dst.read_frame() => {
-                  let frame = match res? {
-                    Some(frame) => frame,
-                    None => return Ok(())
-                  };
-
-                  handle_sub_command(
-                    frame,
-                    &mut self.channels,
-                    &mut subscriptions,
-                    dst
-                    ).await?;
+                    let frame = match res? {
+                        Some(frame) => frame,
+                        None => return Ok(())
+                    };
+
+                    self.handle_command(frame, dst).await?;
                 }
                 _ = shutdown.recv() => {
                     return Ok(())
                 }
             }
         }
+
+        Ok(())
     }
 
-    /// Converts the command into an equivalent `Frame`.
-    pub(crate) fn into_frame(self) -> Frame {
-        let mut frame = Frame::array();
-        frame.push_bulk(Bytes::from("subscribe".as_bytes()));
-        for channel in self.channels {
-            frame.push_bulk(Bytes::from(channel.into_bytes()));
+    /// Write an event received on the channel or pattern `name` to the client.
+    async fn forward(
+        &mut self,
+        name: String,
+        event: Event,
+        dst: &mut Connection,
+    ) -> crate::FnResult<()> {
+        match event {
+            Event::Message(msg) => dst.write_frame(&make_message_frame(name, msg)).await?,
+            Event::PMessage(channel_name, msg) => {
+                dst.write_frame(&make_pmessage_frame(name, channel_name, msg))
+                    .await?
+            }
+            Event::Lagged(dropped) => self.handle_lag(name, dropped, dst).await?,
         }
-        frame
+
+        Ok(())
     }
-}
 
-async fn subscribe_to_channel(
-    channel_name: String,
-    subscriptions: &mut StreamMap<String, Messages>,
-    db: &Db,
-    dst: &mut Connection,
-) -> crate::FnResult<()> {
-    let mut rx = db.subscribe(channel_name.clone());
-
-    // Subscribe to the channel
-    let rx = Box::pin(async_stream::stream! {
-        loop {
-            match rx.recv().await {
-                Ok(msg) => yield msg,
-                // if we lagged consuming messages, just resume
-                Err(broadcast::error::RecvError::Lagged(_)) => {},
-                Err(_) => break,
+    async fn subscribe(
+        &mut self,
+        channel_name: String,
+        dst: &mut Connection,
+    ) -> crate::FnResult<()> {
+        let mut rx = self.db.subscribe(channel_name.clone());
+        let db = self.db.clone();
+
+        // Subscribe to the channel
+        let rx = Box::pin(async_stream::stream! {
+            loop {
+                match rx.recv().await {
+                    Ok(msg) => {
+                        // let publishers waiting on backpressure know there's room again
+                        db.notify_pub_sub_drained();
+                        yield Event::Message(msg)
+                    }
+                    // if we lagged consuming messages, report how many were missed
+                    Err(broadcast::error::RecvError::Lagged(dropped)) => yield Event::Lagged(dropped),
+                    Err(_) => break,
+                }
             }
-        }
-    });
+        });
 
-    // Track subscription
-    subscriptions.insert(channel_name.clone(), rx);
+        // Track subscription
+        self.channels.insert(channel_name.clone(), rx);
 
-    let response = make_subscribe_frame(channel_name, subscriptions.len());
-    dst.write_frame(&response).await?;
+        let response = make_subscription_frame("subscribe", Some(channel_name), self.count());
+        dst.write_frame(&response).await?;
 
-    Ok(())
-}
+        Ok(())
+    }
 
-async fn handle_sub_command(
-    frame: Frame,
-    subscribed_to: &mut Vec<String>,
-    subscriptions: &mut StreamMap<String, Messages>,
-    dst: &mut Connection,
-) -> crate::FnResult<()> {
-    // Only `SUBSCRIBE` and `UNSUBSCRIBE` commands are permitted in this context
-    match Command::from_frame(frame)? {
-        Command::Subscribe(subscribe) => {
-            subscribed_to.extend(subscribe.channels.into_iter());
-        }
-        Command::Unsubscribe(mut unsubscribe) => {
-            if unsubscribe.channels.is_empty() {
-                unsubscribe.channels = subscriptions
-                    .keys()
-                    .map(|channel_name| channel_name.to_string())
-                    .collect();
+    async fn psubscribe(&mut self, pattern: String, dst: &mut Connection) -> crate::FnResult<()> {
+        let mut rx = self.db.psubscribe(pattern.clone());
+        let db = self.db.clone();
+
+        // Subscribe to the pattern
+        let rx = Box::pin(async_stream::stream! {
+            loop {
+                match rx.recv().await {
+                    Ok((channel_name, msg)) => {
+                        db.notify_pub_sub_drained();
+                        yield Event::PMessage(channel_name, msg)
+                    }
+                    Err(broadcast::error::RecvError::Lagged(dropped)) => yield Event::Lagged(dropped),
+                    Err(_) => break,
+                }
             }
+        });
 
-            for channel_name in unsubscribe.channels {
-                subscriptions.remove(&channel_name);
+        self.patterns.insert(pattern.clone(), rx);
 
-                let response = make_unsubscribe_frame(channel_name, subscriptions.len());
+        let response = make_subscription_frame("psubscribe", Some(pattern), self.count());
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    async fn unsubscribe(
+        &mut self,
+        channels: Vec<String>,
+        dst: &mut Connection,
+    ) -> crate::FnResult<()> {
+        // When no channel is specified, unsubscribe from all of them
+        let channels = if channels.is_empty() {
+            self.channels.keys().cloned().collect()
+        } else {
+            channels
+        };
+
+        if channels.is_empty() {
+            // Not subscribed to any channel, a single reply is still due
+            let response = make_subscription_frame("unsubscribe", None, self.count());
+            dst.write_frame(&response).await?;
+        }
+
+        for channel_name in channels {
+            self.channels.remove(&channel_name);
+
+            let response = make_subscription_frame("unsubscribe", Some(channel_name), self.count());
+            dst.write_frame(&response).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn punsubscribe(
+        &mut self,
+        patterns: Vec<String>,
+        dst: &mut Connection,
+    ) -> crate::FnResult<()> {
+        // When no pattern is specified, unsubscribe from all of them
+        let patterns = if patterns.is_empty() {
+            self.patterns.keys().cloned().collect()
+        } else {
+            patterns
+        };
+
+        if patterns.is_empty() {
+            // Not subscribed to any pattern, a single reply is still due
+            let response = make_subscription_frame("punsubscribe", None, self.count());
+            dst.write_frame(&response).await?;
+        }
+
+        for pattern in patterns {
+            self.patterns.remove(&pattern);
+
+            let response = make_subscription_frame("punsubscribe", Some(pattern), self.count());
+            dst.write_frame(&response).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn handle_command(&mut self, frame: Frame, dst: &mut Connection) -> crate::FnResult<()> {
+        let command = Command::from_frame(frame)?;
+
+        if let Err(response) = self.db.check_permissions(self.session, &command, false) {
+            dst.write_frame(&response).await?;
+            return Ok(());
+        }
+
+        // Only a subset of commands are permitted in this context
+        match command {
+            Command::Subscribe(subscribe) => {
+                for channel_name in subscribe.channels {
+                    self.subscribe(channel_name, dst).await?;
+                }
+            }
+            Command::PSubscribe(psubscribe) => {
+                for pattern in psubscribe.patterns {
+                    self.psubscribe(pattern, dst).await?;
+                }
+            }
+            Command::Unsubscribe(unsubscribe) => {
+                self.unsubscribe(unsubscribe.channels, dst).await?;
+            }
+            Command::PUnsubscribe(punsubscribe) => {
+                self.punsubscribe(punsubscribe.patterns, dst).await?;
+            }
+            Command::Ping(ping) => ping.apply_subscribed(dst).await?,
+            Command::Reset(reset) => {
+                // Silently drop all subscriptions, which leaves subscribed mode
+                self.channels.clear();
+                self.patterns.clear();
+                reset.apply(self.db, dst, self.session).await?;
+            }
+            Command::Quit(quit) => quit.apply(dst).await?,
+            command => {
+                let response = Frame::Error(format!(
+                    "ERR Can't execute '{}': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                    command.get_name()
+                ));
                 dst.write_frame(&response).await?;
             }
         }
-        command => {
-            let cmd = Unknown::new(command.get_name());
-            cmd.apply(dst).await?;
+
+        Ok(())
+    }
+
+    /// Apply the configured `LagPolicy` to a subscriber that missed `dropped` messages.
+    ///
+    /// With `LagPolicy::Disconnect`, an error is returned so that the connection gets closed.
+    async fn handle_lag(
+        &mut self,
+        name: String,
+        dropped: u64,
+        dst: &mut Connection,
+    ) -> crate::FnResult<()> {
+        self.subscriber.record_lag(dropped);
+
+        match self.db.pub_sub_config().lag_policy {
+            LagPolicy::Disconnect => {
+                Err(format!("subscriber lagged by {} messages on `{}`", dropped, name).into())
+            }
+            LagPolicy::Skip | LagPolicy::Backpressure => {
+                dst.write_frame(&make_lagged_frame(name, dropped)).await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Parse the remaining channel or pattern names of a frame.
+///
+/// At least one name must be present if `required` is `true`.
+fn parse_names(parse: &mut Parse, required: bool) -> crate::FnResult<Vec<String>> {
+    let mut names = vec![];
+
+    if required {
+        names.push(parse.next_string()?);
+    }
+
+    loop {
+        match parse.next_string() {
+            Ok(s) => names.push(s),
+            Err(ParseError::EndOfStream) => break,
+            Err(err) => return Err(err.into()),
         }
     }
 
-    Ok(())
+    Ok(names)
 }
 
-/// Create response to a subscribe request.
-fn make_subscribe_frame(channel_name: String, num_subs: usize) -> Frame {
+/// Create response to a (p)subscribe or (p)unsubscribe request.
+///
+/// `name` is `None` when unsubscribing from all while not subscribed to anything.
+fn make_subscription_frame(kind: &'static str, name: Option<String>, num_subs: usize) -> Frame {
     let mut frame = Frame::array();
-    frame.push_bulk(Bytes::from_static(b"subscribe"));
-    frame.push_bulk(Bytes::from(channel_name.into_bytes()));
+    frame.push_bulk(Bytes::from_static(kind.as_bytes()));
+
+    match name {
+        Some(name) => frame.push_bulk(Bytes::from(name.into_bytes())),
+        None => frame.push_null(),
+    }
+
     frame.push_int(num_subs as u64);
     frame
 }
 
-/// Create response to an unsubscribe request.
-fn make_unsubscribe_frame(channel_name: String, num_subs: usize) -> Frame {
+/// Create message informing the client about a new message on specified subscribed channel
+fn make_message_frame(channel_name: String, msg: Bytes) -> Frame {
     let mut frame = Frame::array();
-    frame.push_bulk(Bytes::from_static(b"unsubscribe"));
+    frame.push_bulk(Bytes::from_static(b"message"));
     frame.push_bulk(Bytes::from(channel_name.into_bytes()));
-    frame.push_int(num_subs as u64);
+    frame.push_bulk(msg);
     frame
 }
 
-/// Create message informing the client about a new message on specified subscribed channel
-fn make_message_frame(channel_name: String, msg: Bytes) -> Frame {
+/// Create message informing the client about a new message on a channel matching a subscribed pattern
+fn make_pmessage_frame(pattern: String, channel_name: String, msg: Bytes) -> Frame {
     let mut frame = Frame::array();
-    frame.push_bulk(Bytes::from_static(b"message"));
+    frame.push_bulk(Bytes::from_static(b"pmessage"));
+    frame.push_bulk(Bytes::from(pattern.into_bytes()));
     frame.push_bulk(Bytes::from(channel_name.into_bytes()));
     frame.push_bulk(msg);
     frame
 }
 
+/// Create message informing the client it missed `dropped` messages on specified subscribed channel or pattern
+fn make_lagged_frame(name: String, dropped: u64) -> Frame {
+    let mut frame = Frame::array();
+    frame.push_bulk(Bytes::from_static(b"lagged"));
+    frame.push_bulk(Bytes::from(name.into_bytes()));
+    frame.push_int(dropped);
+    frame
+}
+
 impl Unsubscribe {
     pub fn new(channels: &[String]) -> Unsubscribe {
         Unsubscribe {
@@ -210,19 +505,18 @@ impl Unsubscribe {
     /// Parse a `Unubscribe` instance from a received frame.
     pub(crate) fn parse_frame(parse: &mut Parse) -> crate::FnResult<Unsubscribe> {
         // Note: the `UNSUBSCRIBE` string has already been consumed, next values are `channels`
-        let mut channels = vec![];
-
-        loop {
-            match parse.next_string() {
-                Ok(s) => channels.push(s),
-                Err(ParseError::EndOfStream) => break,
-                Err(err) => return Err(err.into()),
-            }
-        }
+        let channels = parse_names(parse, false)?;
 
         Ok(Unsubscribe { channels })
     }
 
+    /// Apply the `Unsubscribe` command outside of subscribed mode.
+    ///
+    /// There is nothing to unsubscribe from, but each channel still gets a reply.
+    pub(crate) async fn apply(self, dst: &mut Connection) -> crate::FnResult<()> {
+        apply_unsubscribed("unsubscribe", self.channels, dst).await
+    }
+
     /// Converts the command into an equivalent `Frame`.
     pub(crate) fn into_frame(self) -> Frame {
         let mut frame = Frame::array();
@@ -235,3 +529,57 @@ impl Unsubscribe {
         frame
     }
 }
+
+impl PUnsubscribe {
+    pub fn new(patterns: &[String]) -> PUnsubscribe {
+        PUnsubscribe {
+            patterns: patterns.to_vec(),
+        }
+    }
+
+    /// Parse a `PUnsubscribe` instance from a received frame.
+    pub(crate) fn parse_frame(parse: &mut Parse) -> crate::FnResult<PUnsubscribe> {
+        // Note: the `PUNSUBSCRIBE` string has already been consumed, next values are `patterns`
+        let patterns = parse_names(parse, false)?;
+
+        Ok(PUnsubscribe { patterns })
+    }
+
+    /// Apply the `PUnsubscribe` command outside of subscribed mode.
+    ///
+    /// There is nothing to unsubscribe from, but each pattern still gets a reply.
+    pub(crate) async fn apply(self, dst: &mut Connection) -> crate::FnResult<()> {
+        apply_unsubscribed("punsubscribe", self.patterns, dst).await
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("punsubscribe".as_bytes()));
+
+        for pattern in self.patterns {
+            frame.push_bulk(Bytes::from(pattern.into_bytes()));
+        }
+
+        frame
+    }
+}
+
+/// Reply to a (p)unsubscribe request received outside of subscribed mode.
+async fn apply_unsubscribed(
+    kind: &'static str,
+    names: Vec<String>,
+    dst: &mut Connection,
+) -> crate::FnResult<()> {
+    if names.is_empty() {
+        dst.write_frame(&make_subscription_frame(kind, None, 0))
+            .await?;
+    }
+
+    for name in names {
+        dst.write_frame(&make_subscription_frame(kind, Some(name), 0))
+            .await?;
+    }
+
+    Ok(())
+}
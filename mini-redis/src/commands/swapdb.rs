@@ -0,0 +1,66 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    connection::Connection,
+    db::{Db, DbGuard},
+    frame::Frame,
+    parse::Parse,
+};
+
+/// Swap two logical databases, so that connections see the other database's keys immediately.
+#[derive(Debug)]
+pub struct SwapDb {
+    index1: u64,
+    index2: u64,
+}
+
+impl SwapDb {
+    pub fn new(index1: u64, index2: u64) -> SwapDb {
+        SwapDb { index1, index2 }
+    }
+
+    /// Parse a `SwapDb` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<SwapDb> {
+        // Note: the `SWAPDB` string has already been consumed, next values are both database indexes
+        Ok(SwapDb {
+            index1: parse.next_int()?,
+            index2: parse.next_int()?,
+        })
+    }
+
+    /// Apply the `SwapDb` command to the specified `Db` instance.
+    pub(crate) async fn apply(self, db: &Db, dst: &mut Connection) -> crate::FnResult<()> {
+        let response = match db.lock_unless_busy().await {
+            Ok(mut guard) => self.execute(&mut guard),
+            Err(response) => response,
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Execute the `SwapDb` command on the locked `Db`, returning the reply.
+    pub(crate) fn execute(self, db: &mut DbGuard) -> Frame {
+        let num_databases = db.num_databases() as u64;
+
+        if self.index1 >= num_databases || self.index2 >= num_databases {
+            return Frame::Error("ERR DB index is out of range".to_string());
+        }
+
+        db.swap_databases(self.index1 as usize, self.index2 as usize);
+        Frame::Simple("OK".to_string())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("swapdb".as_bytes()));
+        frame.push_int(self.index1);
+        frame.push_int(self.index2);
+        frame
+    }
+}
@@ -21,7 +21,7 @@ impl Unknown {
 
     /// Apply the `Unknown` responding to client
     pub(crate) async fn apply(self, dst: &mut Connection) -> crate::FnResult<()> {
-        let response = Frame::Error(format!("ERR unknown command '{}'", self.command_name));
+        let response = self.execute();
 
         debug!(?response);
 
@@ -29,4 +29,9 @@ impl Unknown {
 
         Ok(())
     }
+
+    /// Returns the error reply to the unknown command.
+    pub(crate) fn execute(self) -> Frame {
+        Frame::Error(format!("ERR unknown command '{}'", self.command_name))
+    }
 }
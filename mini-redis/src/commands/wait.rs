@@ -0,0 +1,73 @@
+use bytes::Bytes;
+use std::time::Duration;
+use tracing::debug;
+
+use crate::{connection::Connection, db::Db, frame::Frame, parse::Parse, shutdown::Shutdown};
+
+/// Block until the given number of replicas acknowledged all the writes applied so far,
+/// or the timeout elapsed. Returns the number of replicas which acknowledged them.
+///
+/// The timeout is in milliseconds, `0` to block until enough replicas acknowledged.
+#[derive(Debug)]
+pub struct Wait {
+    num_replicas: u64,
+    timeout: u64,
+}
+
+impl Wait {
+    /// Create a `Wait` command, blocking forever if `timeout` is `None`.
+    pub fn new(num_replicas: u64, timeout: Option<Duration>) -> Wait {
+        Wait {
+            num_replicas,
+            timeout: timeout.map_or(0, |timeout| (timeout.as_millis() as u64).max(1)),
+        }
+    }
+
+    /// Parse a `Wait` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<Wait> {
+        // Note: the `WAIT` string has already been consumed, next values are the number of
+        // replicas and the timeout
+        Ok(Wait {
+            num_replicas: parse.next_int()?,
+            timeout: parse.next_int()?,
+        })
+    }
+
+    /// Apply the `Wait` command to the specified `Db` instance.
+    ///
+    /// Gives up waiting when the server shuts down.
+    pub(crate) async fn apply(
+        self,
+        db: &Db,
+        dst: &mut Connection,
+        shutdown: &mut Shutdown,
+    ) -> crate::FnResult<()> {
+        let timeout = match self.timeout {
+            0 => None,
+            timeout => Some(Duration::from_millis(timeout)),
+        };
+
+        let response = tokio::select! {
+            res = db.wait_for_replicas(self.num_replicas as usize, timeout) => match res {
+                Ok(num_acked) => Frame::Integer(num_acked as i64),
+                Err(msg) => Frame::Error(msg.to_string()),
+            },
+            _ = shutdown.recv() => return Ok(()),
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("wait".as_bytes()));
+        frame.push_int(self.num_replicas);
+        frame.push_int(self.timeout);
+        frame
+    }
+}
@@ -0,0 +1,123 @@
+use bytes::Bytes;
+use tracing::debug;
+
+use crate::{
+    connection::Connection,
+    db::{Db, Watcher},
+    frame::Frame,
+    parse::{Parse, ParseError},
+};
+
+/// Watch keys for modification: the next `EXEC` aborts if any of them is modified before.
+#[derive(Debug)]
+pub struct Watch {
+    keys: Vec<String>,
+}
+
+/// Forget about all the watched keys.
+#[derive(Debug, Default)]
+pub struct Unwatch;
+
+impl Watch {
+    pub fn new(keys: &[String]) -> Watch {
+        Watch {
+            keys: keys.to_vec(),
+        }
+    }
+
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// Parse a `Watch` instance from a received frame.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::FnResult<Watch> {
+        // Note: the `WATCH` string has already been consumed, next values are the keys, at least one is required
+        let mut keys = vec![parse.next_string()?];
+
+        loop {
+            match parse.next_string() {
+                Ok(key) => keys.push(key),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(Watch { keys })
+    }
+
+    /// Apply the `Watch` command, adding the keys of the selected database to the connection's watcher.
+    pub(crate) async fn apply(
+        self,
+        db: &Db,
+        watcher: &mut Option<Watcher>,
+        dst: &mut Connection,
+    ) -> crate::FnResult<()> {
+        let watcher = watcher.get_or_insert_with(|| db.watcher());
+
+        let response = match db.watch(watcher, &self.keys).await {
+            Ok(()) => Frame::Simple("OK".to_string()),
+            Err(response) => response,
+        };
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("watch".as_bytes()));
+
+        for key in self.keys {
+            frame.push_bulk(Bytes::from(key.into_bytes()));
+        }
+
+        frame
+    }
+}
+
+impl Unwatch {
+    pub fn new() -> Unwatch {
+        Unwatch
+    }
+
+    /// Parse an `Unwatch` instance from a received frame.
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::FnResult<Unwatch> {
+        // Note: the `UNWATCH` string has already been consumed, it has no argument
+        Ok(Unwatch)
+    }
+
+    /// Apply the `Unwatch` command, dropping the connection's watcher.
+    pub(crate) async fn apply(
+        self,
+        watcher: &mut Option<Watcher>,
+        dst: &mut Connection,
+    ) -> crate::FnResult<()> {
+        *watcher = None;
+
+        let response = self.execute();
+
+        debug!(?response);
+
+        dst.write_frame(&response).await?;
+
+        Ok(())
+    }
+
+    /// Returns the reply to the `Unwatch` command.
+    ///
+    /// When queued in a transaction, there is nothing left to unwatch: `EXEC` already did.
+    pub(crate) fn execute(self) -> Frame {
+        Frame::Simple("OK".to_string())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("unwatch".as_bytes()));
+        frame
+    }
+}
@@ -0,0 +1,1592 @@
+use std::{
+    collections::HashSet,
+    fmt, fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::RwLock,
+    time::Duration,
+};
+
+use crate::constants::{
+    CLUSTER_SLOTS, DEFAULT_ACLLOG_MAX_LEN, DEFAULT_AOF_FILENAME, DEFAULT_BIND,
+    DEFAULT_CLUSTER_ANNOUNCE_HOST, DEFAULT_CLUSTER_NODE_TIMEOUT, DEFAULT_DATABASES,
+    DEFAULT_DBFILENAME, DEFAULT_LUA_TIME_LIMIT, DEFAULT_MAXCLIENTS, DEFAULT_MAXMEMORY_SAMPLES,
+    DEFAULT_PORT, DEFAULT_PUB_SUB_CAPACITY, DEFAULT_REPL_BACKLOG_SIZE, DEFAULT_SHARDS,
+};
+
+/// Server configuration.
+///
+/// Every field has a sensible default, use `Config::default()` and override
+/// what's needed.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Addresses `mini-redis-server` listens on, IPv4 or IPv6
+    pub bind: Vec<IpAddr>,
+
+    /// Port `mini-redis-server` listens on, 0 to only listen on the Unix socket
+    pub port: u16,
+
+    /// Path of the Unix domain socket `mini-redis-server` also listens on, none by default
+    pub unixsocket: Option<PathBuf>,
+
+    /// Permissions of the Unix socket file, as an octal mode (e.g. `0o770`), left to the
+    /// umask when zero
+    pub unixsocketperm: u32,
+
+    /// TLS listener configuration, disabled by default
+    pub tls: TlsConfig,
+
+    /// Maximum number of connected clients, the connections over it are refused with an error
+    pub maxclients: usize,
+
+    /// Duration after which an idle client connection is closed, never when zero
+    pub timeout: Duration,
+
+    /// Password of the `default` user, which connections then authenticate as with `AUTH`
+    pub requirepass: Option<String>,
+
+    /// File the ACL users are loaded from when the server starts, in the format listed by
+    /// `ACL LIST`
+    pub aclfile: Option<PathBuf>,
+
+    /// Maximum number of entries of the ACL log, the oldest ones being dropped
+    pub acllog_max_len: usize,
+
+    /// User a replica authenticates as with its leader, the `default` user when `None`
+    pub masteruser: Option<String>,
+
+    /// Password a replica authenticates with to its leader, which isn't sent when `None`
+    pub masterauth: Option<String>,
+
+    /// Number of logical databases, selected with `SELECT`
+    pub databases: usize,
+
+    /// Number of shards the keys are partitioned into by hash, each locked on its own so
+    /// that commands on keys of different shards run in parallel
+    pub shards: usize,
+
+    /// Duration after which a running Lua script is aborted
+    pub lua_time_limit: Duration,
+
+    /// Pub/sub channels configuration
+    pub pub_sub: PubSubConfig,
+
+    /// Classes of keyspace events published thru pub/sub, see `KeyspaceEvents`
+    pub notify_keyspace_events: KeyspaceEvents,
+
+    /// Directory holding the snapshot and append-only files
+    pub dir: PathBuf,
+
+    /// Snapshot persistence configuration
+    pub rdb: RdbConfig,
+
+    /// Append-only file persistence configuration
+    pub aof: AofConfig,
+
+    /// Size of the replication backlog, in bytes: a replica which lost its connection
+    /// resyncs from where it left off if the backlog still holds the writes it missed
+    pub repl_backlog_size: usize,
+
+    /// Cluster mode configuration, disabled when `None`
+    pub cluster: Option<ClusterConfig>,
+
+    /// Memory budget of the databases, and how keys are evicted to stay within it
+    pub maxmemory: MaxMemoryConfig,
+
+    /// Minimum level of the logged messages
+    pub loglevel: LogLevel,
+
+    /// File the logs are appended to, the standard output when `None`
+    pub logfile: Option<PathBuf>,
+
+    /// Configuration file the server was started with, written back by `CONFIG REWRITE`
+    pub config_file: Option<PathBuf>,
+}
+
+/// Configuration of the TLS listener, accepting encrypted connections on its own port.
+///
+/// The certificate and key files, and the CA certificates file, are in the PEM format.
+#[derive(Clone, Debug, Default)]
+pub struct TlsConfig {
+    /// Port of the TLS listener, on each of the `bind` addresses, 0 for none
+    pub port: u16,
+
+    /// Certificate chain presented to the clients
+    pub cert_file: Option<PathBuf>,
+
+    /// Private key of the certificate
+    pub key_file: Option<PathBuf>,
+
+    /// CA certificates the client certificates are verified against
+    pub ca_cert_file: Option<PathBuf>,
+
+    /// Whether clients must authenticate with a certificate, mutual TLS
+    pub auth_clients: TlsAuthClients,
+}
+
+/// Whether TLS clients must present a certificate signed by the CA, as Redis's
+/// `tls-auth-clients`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TlsAuthClients {
+    /// No client certificate is requested, unlike Redis which requires one by default
+    #[default]
+    No,
+
+    /// Clients must present a valid certificate
+    Yes,
+
+    /// Clients may present a certificate, which must then be valid
+    Optional,
+}
+
+/// TLS configuration of a client connection, as `redis-cli --tls`.
+#[derive(Clone, Debug, Default)]
+pub struct TlsClientConfig {
+    /// CA certificates the server certificate is verified against, in the PEM format
+    pub ca_cert_file: PathBuf,
+
+    /// Certificate chain presented to servers authenticating their clients
+    pub cert_file: Option<PathBuf>,
+
+    /// Private key of the client certificate
+    pub key_file: Option<PathBuf>,
+
+    /// Name the server certificate must be valid for, the host connected to by default
+    pub sni: Option<String>,
+}
+
+/// Minimum level of the logged messages, as Redis's `loglevel`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogLevel {
+    /// Everything, including the commands received
+    Debug,
+
+    /// Debug information
+    Verbose,
+
+    /// Lifecycle of the server and errors
+    #[default]
+    Notice,
+
+    /// Warnings and errors only
+    Warning,
+}
+
+/// Configuration of the memory budget of the databases, as Redis's `maxmemory`.
+///
+/// The memory used by the keys and values is approximated. Once over the limit, keys are
+/// evicted before applying write commands, following the policy. Writes which may grow
+/// the databases are rejected with an `OOM` error when no key can be evicted.
+#[derive(Clone, Copy, Debug)]
+pub struct MaxMemoryConfig {
+    /// Memory limit in bytes, 0 for no limit
+    pub limit: usize,
+
+    /// Keys evicted once over the limit
+    pub policy: MaxMemoryPolicy,
+
+    /// Number of keys sampled per database and shard to pick the one to evict, the higher
+    /// the more accurate and the slower
+    pub samples: usize,
+}
+
+/// Keys evicted once the memory limit is reached, as Redis's `maxmemory-policy`.
+///
+/// Like Redis, the key to evict is the best of a few sampled ones rather than the best of
+/// all keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MaxMemoryPolicy {
+    /// Nothing is evicted, writes fail instead
+    #[default]
+    NoEviction,
+
+    /// Evict the least recently used keys
+    AllKeysLru,
+
+    /// Evict the least frequently used keys
+    AllKeysLfu,
+
+    /// Evict random keys
+    AllKeysRandom,
+
+    /// Evict the least recently used keys among the ones with a TTL
+    VolatileLru,
+
+    /// Evict the least frequently used keys among the ones with a TTL
+    VolatileLfu,
+
+    /// Evict random keys among the ones with a TTL
+    VolatileRandom,
+
+    /// Evict the keys expiring first
+    VolatileTtl,
+}
+
+/// Configuration of the cluster mode: the keys are sharded across the nodes of the
+/// cluster by hash slot, out of `CLUSTER_SLOTS`, each node serving a set of slots.
+///
+/// Commands on keys served by another node are redirected to it with a `MOVED` error.
+/// The nodes exchange their view of the cluster on a secondary port, the cluster bus.
+#[derive(Clone, Debug)]
+pub struct ClusterConfig {
+    /// Host announced to clients, along with the listening port
+    pub announce_host: String,
+
+    /// Port of the cluster bus, the listening port plus `CLUSTER_BUS_PORT_OFFSET` when
+    /// `None`, any free port when 0
+    pub bus_port: Option<u16>,
+
+    /// Duration after which a node not answering pings is flagged as failing
+    pub node_timeout: Duration,
+
+    /// Slots served by this node
+    pub slots: Vec<SlotRange>,
+
+    /// Other nodes of the cluster, and the slots they serve
+    pub nodes: Vec<ClusterNodeConfig>,
+}
+
+/// Another node of the cluster, parsed from `host:port[@bus_port]=slots`, e.g.
+/// `127.0.0.1:7001=5461-10922`. The node is met on the cluster bus when the server starts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClusterNodeConfig {
+    pub host: String,
+    pub port: u16,
+
+    /// Port of the node's cluster bus, its port plus `CLUSTER_BUS_PORT_OFFSET` when `None`
+    pub bus_port: Option<u16>,
+
+    pub slots: Vec<SlotRange>,
+}
+
+/// Range of hash slots, both ends included, parsed from `start-end` or a single slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlotRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+/// Configuration of the snapshots saved in the RDB format.
+///
+/// The snapshot is loaded when the server starts, if the file exists, the append-only file
+/// is disabled and `Config::loads_on_startup` holds.
+#[derive(Clone, Debug)]
+pub struct RdbConfig {
+    /// Name of the snapshot file, within `Config::dir`
+    pub dbfilename: String,
+
+    /// Rules triggering a background save, none by default. When at least one rule is
+    /// set, a snapshot is also saved on shutdown.
+    pub save: Vec<SaveRule>,
+}
+
+/// Save a snapshot once `seconds` elapsed and at least `changes` writes were applied
+/// since the last one, as Redis's `save <seconds> <changes>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+/// Configuration of the append-only file, logging every write applied to the databases.
+#[derive(Clone, Debug)]
+pub struct AofConfig {
+    /// Log writes to the append-only file, disabled by default. When enabled, the databases
+    /// are restored from it rather than from the snapshot when the server starts.
+    pub enabled: bool,
+
+    /// Name of the append-only file, within `Config::dir`
+    pub filename: String,
+
+    /// When writes are synced to disk
+    pub fsync: AppendFsync,
+
+    /// Annotate the logged writes with the Unix time they were applied at, so that the
+    /// databases can be recovered as of a given time
+    pub timestamps: bool,
+
+    /// Point-in-time recovery: only replay the writes logged up to this point when the
+    /// server starts. The rest of the file is moved aside.
+    pub recover_to: Option<RecoveryPoint>,
+}
+
+/// Point up to which the append-only file is replayed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecoveryPoint {
+    /// Unix time in seconds: the writes annotated with a later time are skipped
+    Timestamp(u64),
+
+    /// Offset in the file, in bytes: the writes ending after it are skipped
+    Offset(u64),
+}
+
+/// Policy syncing the append-only file to disk, as Redis's `appendfsync`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AppendFsync {
+    /// Sync every write before replying, the safest and slowest
+    Always,
+
+    /// Sync once per second, a crash loses at most a second of writes
+    #[default]
+    EverySec,
+
+    /// Leave syncing to the operating system
+    No,
+}
+
+/// Configuration of the pub/sub broadcast channels.
+#[derive(Clone, Copy, Debug)]
+pub struct PubSubConfig {
+    /// Number of messages a channel retains for its slowest subscriber
+    pub capacity: usize,
+
+    /// What to do when a subscriber falls behind by more than `capacity` messages
+    pub lag_policy: LagPolicy,
+}
+
+/// Policy applied to subscribers that are too slow to consume their messages.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LagPolicy {
+    /// Skip the dropped messages and notify the subscriber with a `lagged` frame
+    /// holding the number of messages it missed.
+    #[default]
+    Skip,
+
+    /// Close the subscriber connection, as Redis does when a client exceeds its
+    /// `client-output-buffer-limit`.
+    Disconnect,
+
+    /// Make publishers wait until the slowest subscriber has room for new messages.
+    ///
+    /// Only `PUBLISH` sent on its own waits. The messages published by a `MULTI`/`EXEC`
+    /// transaction, a script or a keyspace notification can't wait while the `Db` is
+    /// locked, and are handled as with `Skip`.
+    Backpressure,
+}
+
+/// Classes of keyspace events to publish, as configured by Redis's `notify-keyspace-events`.
+///
+/// Parsed from the same flags string:
+/// - `K`: keyspace events, published on `__keyspace@<db>__:<key>`
+/// - `E`: keyevent events, published on `__keyevent@<db>__:<event>`
+/// - `g`: generic commands (e.g. `DEL`)
+/// - `$`: string commands (e.g. `SET`)
+/// - `l`, `s`, `h`, `z`, `t`: list, set, hash, sorted set and stream commands
+/// - `x`: expired events, when a key expires
+/// - `e`: evicted events, when a key is evicted for maxmemory
+/// - `m`: key miss events, when a key that doesn't exist is accessed
+/// - `n`: new key events
+/// - `A`: alias for `g$lshzxet`
+///
+/// Nothing is published unless at least one of `K` or `E` is set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyspaceEvents(u16);
+
+impl KeyspaceEvents {
+    pub const KEYSPACE: KeyspaceEvents = KeyspaceEvents(1 << 0);
+    pub const KEYEVENT: KeyspaceEvents = KeyspaceEvents(1 << 1);
+    pub const GENERIC: KeyspaceEvents = KeyspaceEvents(1 << 2);
+    pub const STRING: KeyspaceEvents = KeyspaceEvents(1 << 3);
+    pub const LIST: KeyspaceEvents = KeyspaceEvents(1 << 4);
+    pub const SET: KeyspaceEvents = KeyspaceEvents(1 << 5);
+    pub const HASH: KeyspaceEvents = KeyspaceEvents(1 << 6);
+    pub const ZSET: KeyspaceEvents = KeyspaceEvents(1 << 7);
+    pub const EXPIRED: KeyspaceEvents = KeyspaceEvents(1 << 8);
+    pub const EVICTED: KeyspaceEvents = KeyspaceEvents(1 << 9);
+    pub const STREAM: KeyspaceEvents = KeyspaceEvents(1 << 10);
+    pub const KEY_MISS: KeyspaceEvents = KeyspaceEvents(1 << 11);
+    pub const NEW: KeyspaceEvents = KeyspaceEvents(1 << 12);
+
+    /// Classes included in the `A` alias.
+    pub const ALL: KeyspaceEvents = KeyspaceEvents(
+        Self::GENERIC.0
+            | Self::STRING.0
+            | Self::LIST.0
+            | Self::SET.0
+            | Self::HASH.0
+            | Self::ZSET.0
+            | Self::EXPIRED.0
+            | Self::EVICTED.0
+            | Self::STREAM.0,
+    );
+
+    /// Flags and the class they enable, in the order Redis prints them.
+    const FLAGS: [(char, KeyspaceEvents); 13] = [
+        ('g', Self::GENERIC),
+        ('$', Self::STRING),
+        ('l', Self::LIST),
+        ('s', Self::SET),
+        ('h', Self::HASH),
+        ('z', Self::ZSET),
+        ('x', Self::EXPIRED),
+        ('e', Self::EVICTED),
+        ('t', Self::STREAM),
+        ('K', Self::KEYSPACE),
+        ('E', Self::KEYEVENT),
+        ('m', Self::KEY_MISS),
+        ('n', Self::NEW),
+    ];
+
+    /// Returns `true` if every class of `other` is enabled.
+    pub fn contains(self, other: KeyspaceEvents) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns `true` if events of `class` must be published on at least one channel.
+    pub fn is_enabled(self, class: KeyspaceEvents) -> bool {
+        self.contains(class) && (self.contains(Self::KEYSPACE) || self.contains(Self::KEYEVENT))
+    }
+}
+
+impl std::ops::BitOr for KeyspaceEvents {
+    type Output = KeyspaceEvents;
+
+    fn bitor(self, rhs: KeyspaceEvents) -> KeyspaceEvents {
+        KeyspaceEvents(self.0 | rhs.0)
+    }
+}
+
+impl FromStr for KeyspaceEvents {
+    type Err = crate::GenericError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut events = KeyspaceEvents::default();
+
+        for flag in s.chars() {
+            events = match flag {
+                'A' => events | KeyspaceEvents::ALL,
+                flag => match KeyspaceEvents::FLAGS.iter().find(|(f, _)| *f == flag) {
+                    Some((_, class)) => events | *class,
+                    None => return Err(format!("invalid keyspace events flag `{}`", flag).into()),
+                },
+            };
+        }
+
+        Ok(events)
+    }
+}
+
+impl fmt::Display for KeyspaceEvents {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Use the `A` alias when possible, like Redis does
+        let (mut flags, all) = if self.contains(KeyspaceEvents::ALL) {
+            ("A".to_string(), KeyspaceEvents::ALL)
+        } else {
+            (String::new(), KeyspaceEvents::default())
+        };
+
+        for (flag, class) in KeyspaceEvents::FLAGS {
+            if self.contains(class) && !all.contains(class) {
+                flags.push(flag);
+            }
+        }
+
+        flags.fmt(fmt)
+    }
+}
+
+impl Config {
+    /// Parameters read from the configuration file and returned by `CONFIG GET`, in the
+    /// order `CONFIG REWRITE` appends them to the file.
+    pub const PARAMETERS: [&'static str; 35] = [
+        "bind",
+        "port",
+        "unixsocket",
+        "unixsocketperm",
+        "tls-port",
+        "tls-cert-file",
+        "tls-key-file",
+        "tls-ca-cert-file",
+        "tls-auth-clients",
+        "maxclients",
+        "timeout",
+        "requirepass",
+        "aclfile",
+        "acllog-max-len",
+        "masteruser",
+        "masterauth",
+        "databases",
+        "shards",
+        "lua-time-limit",
+        "pubsub-capacity",
+        "pubsub-lag-policy",
+        "notify-keyspace-events",
+        "dir",
+        "dbfilename",
+        "save",
+        "appendonly",
+        "appendfilename",
+        "appendfsync",
+        "aof-timestamp-enabled",
+        "repl-backlog-size",
+        "maxmemory",
+        "maxmemory-policy",
+        "maxmemory-samples",
+        "loglevel",
+        "logfile",
+    ];
+
+    /// Parameters `CONFIG SET` changes while the server runs, the others are only read
+    /// when it starts.
+    pub const MUTABLE_PARAMETERS: [&'static str; 12] = [
+        "maxclients",
+        "timeout",
+        "requirepass",
+        "acllog-max-len",
+        "masteruser",
+        "masterauth",
+        "notify-keyspace-events",
+        "save",
+        "maxmemory",
+        "maxmemory-policy",
+        "maxmemory-samples",
+        "loglevel",
+    ];
+
+    /// Path of the snapshot file.
+    pub fn rdb_path(&self) -> PathBuf {
+        self.dir.join(&self.rdb.dbfilename)
+    }
+
+    /// Path of the append-only file.
+    pub fn aof_path(&self) -> PathBuf {
+        self.dir.join(&self.aof.filename)
+    }
+
+    /// Returns `true` if the databases are restored when the server starts: once persistence
+    /// is enabled, or `dir` or `dbfilename` is set. With the defaults, a server embedded in
+    /// another program doesn't read `./dump.rdb`.
+    pub fn loads_on_startup(&self) -> bool {
+        self.aof.enabled
+            || !self.rdb.save.is_empty()
+            || self.dir != Path::new(".")
+            || self.rdb.dbfilename != DEFAULT_DBFILENAME
+    }
+
+    /// Apply the configuration file at `path`, which `CONFIG REWRITE` then writes back to.
+    ///
+    /// The file follows the format of Redis's `redis.conf`: one `parameter value...`
+    /// directive per line, values with spaces being quoted, and `#` starting a comment.
+    /// Repeated `save` directives add up, the others override the previous ones.
+    pub fn load_file(&mut self, path: &Path) -> Result<(), crate::GenericError> {
+        let contents = fs::read_to_string(path).map_err(|err| {
+            format!(
+                "failed to read the configuration file `{}`: {}",
+                path.display(),
+                err
+            )
+        })?;
+
+        self.apply_directives(&contents)
+            .map_err(|err| format!("invalid configuration file `{}`: {}", path.display(), err))?;
+        self.config_file = Some(path.to_path_buf());
+
+        Ok(())
+    }
+
+    /// Apply the directives of a configuration file, see `load_file`.
+    pub fn apply_directives(&mut self, contents: &str) -> Result<(), crate::GenericError> {
+        let mut save = None;
+
+        for (number, line) in contents.lines().enumerate() {
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+
+            let args = split_args(line).map_err(|err| format!("line {}: {}", number + 1, err))?;
+
+            let (name, values) = match args.split_first() {
+                Some((name, values)) => (name.to_lowercase(), values.join(" ")),
+                None => continue,
+            };
+
+            if name == "save" {
+                // Rules accumulate over the `save` directives, `save ""` clearing them
+                let rules = save.get_or_insert_with(Vec::new);
+                match &values[..] {
+                    "" => rules.clear(),
+                    values => rules.extend(
+                        SaveRule::parse_rules(values)
+                            .map_err(|err| format!("line {}: {}", number + 1, err))?,
+                    ),
+                }
+            } else {
+                self.set(&name, &values)
+                    .map_err(|err| format!("line {}: {}", number + 1, err))?;
+            }
+        }
+
+        if let Some(save) = save {
+            self.rdb.save = save;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the value of a parameter, as `CONFIG GET` replies it and as written to the
+    /// configuration file, or `None` if there's no such parameter.
+    pub fn get(&self, name: &str) -> Option<String> {
+        let value = match &name.to_lowercase()[..] {
+            "bind" => self
+                .bind
+                .iter()
+                .map(IpAddr::to_string)
+                .collect::<Vec<_>>()
+                .join(" "),
+            "port" => self.port.to_string(),
+            "unixsocket" => display_path(&self.unixsocket),
+            "unixsocketperm" => format!("{:o}", self.unixsocketperm),
+            "tls-port" => self.tls.port.to_string(),
+            "tls-cert-file" => display_path(&self.tls.cert_file),
+            "tls-key-file" => display_path(&self.tls.key_file),
+            "tls-ca-cert-file" => display_path(&self.tls.ca_cert_file),
+            "tls-auth-clients" => self.tls.auth_clients.to_string(),
+            "maxclients" => self.maxclients.to_string(),
+            "timeout" => self.timeout.as_secs().to_string(),
+            "requirepass" => self.requirepass.clone().unwrap_or_default(),
+            "aclfile" => display_path(&self.aclfile),
+            "acllog-max-len" => self.acllog_max_len.to_string(),
+            "masteruser" => self.masteruser.clone().unwrap_or_default(),
+            "masterauth" => self.masterauth.clone().unwrap_or_default(),
+            "databases" => self.databases.to_string(),
+            "shards" => self.shards.to_string(),
+            "lua-time-limit" => self.lua_time_limit.as_millis().to_string(),
+            "pubsub-capacity" => self.pub_sub.capacity.to_string(),
+            "pubsub-lag-policy" => self.pub_sub.lag_policy.to_string(),
+            "notify-keyspace-events" => self.notify_keyspace_events.to_string(),
+            "dir" => self.dir.display().to_string(),
+            "dbfilename" => self.rdb.dbfilename.clone(),
+            "save" => self
+                .rdb
+                .save
+                .iter()
+                .map(|rule| format!("{} {}", rule.seconds, rule.changes))
+                .collect::<Vec<_>>()
+                .join(" "),
+            "appendonly" => yes_no(self.aof.enabled),
+            "appendfilename" => self.aof.filename.clone(),
+            "appendfsync" => self.aof.fsync.to_string(),
+            "aof-timestamp-enabled" => yes_no(self.aof.timestamps),
+            "repl-backlog-size" => self.repl_backlog_size.to_string(),
+            "maxmemory" => self.maxmemory.limit.to_string(),
+            "maxmemory-policy" => self.maxmemory.policy.to_string(),
+            "maxmemory-samples" => self.maxmemory.samples.to_string(),
+            "loglevel" => self.loglevel.to_string(),
+            "logfile" => display_path(&self.logfile),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    /// Set a parameter from its value as written to the configuration file, as
+    /// `CONFIG SET` does.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), crate::GenericError> {
+        match &name.to_lowercase()[..] {
+            "bind" => {
+                let addrs = value
+                    .split_whitespace()
+                    .map(|addr| {
+                        addr.parse()
+                            .map_err(|_| format!("invalid bind address `{}`", addr))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                if addrs.is_empty() {
+                    return Err("at least one bind address is required".into());
+                }
+
+                self.bind = addrs;
+            }
+            "port" => self.port = parse_number(name, value)?,
+            "unixsocket" => self.unixsocket = parse_path(value),
+            "unixsocketperm" => match u32::from_str_radix(value, 8) {
+                Ok(mode) if mode <= 0o777 => self.unixsocketperm = mode,
+                _ => {
+                    return Err(
+                        format!("invalid {} `{}`, expected an octal mode", name, value).into(),
+                    )
+                }
+            },
+            "tls-port" => self.tls.port = parse_number(name, value)?,
+            "tls-cert-file" => self.tls.cert_file = parse_path(value),
+            "tls-key-file" => self.tls.key_file = parse_path(value),
+            "tls-ca-cert-file" => self.tls.ca_cert_file = parse_path(value),
+            "tls-auth-clients" => self.tls.auth_clients = value.parse()?,
+            "maxclients" => self.maxclients = parse_positive(name, value)?,
+            "timeout" => self.timeout = Duration::from_secs(parse_number(name, value)?),
+            "requirepass" => {
+                self.requirepass = Some(value.to_string()).filter(|_| !value.is_empty())
+            }
+            "aclfile" => self.aclfile = parse_path(value),
+            "acllog-max-len" => self.acllog_max_len = parse_number(name, value)?,
+            "masteruser" => self.masteruser = Some(value.to_string()).filter(|_| !value.is_empty()),
+            "masterauth" => self.masterauth = Some(value.to_string()).filter(|_| !value.is_empty()),
+            "databases" => self.databases = parse_positive(name, value)?,
+            "shards" => self.shards = parse_positive(name, value)?,
+            "lua-time-limit" => {
+                self.lua_time_limit = Duration::from_millis(parse_number(name, value)?)
+            }
+            "pubsub-capacity" => self.pub_sub.capacity = parse_positive(name, value)?,
+            "pubsub-lag-policy" => self.pub_sub.lag_policy = value.parse()?,
+            "notify-keyspace-events" => self.notify_keyspace_events = value.parse()?,
+            "dir" => self.dir = PathBuf::from(value),
+            "dbfilename" => self.rdb.dbfilename = parse_filename(name, value)?,
+            "save" => self.rdb.save = SaveRule::parse_rules(value)?,
+            "appendonly" => self.aof.enabled = parse_yes_no(name, value)?,
+            "appendfilename" => self.aof.filename = parse_filename(name, value)?,
+            "appendfsync" => self.aof.fsync = value.parse()?,
+            "aof-timestamp-enabled" => self.aof.timestamps = parse_yes_no(name, value)?,
+            "repl-backlog-size" => self.repl_backlog_size = MaxMemoryConfig::parse_size(value)?,
+            "maxmemory" => self.maxmemory.limit = MaxMemoryConfig::parse_size(value)?,
+            "maxmemory-policy" => self.maxmemory.policy = value.parse()?,
+            "maxmemory-samples" => self.maxmemory.samples = parse_positive(name, value)?,
+            "loglevel" => self.loglevel = value.parse()?,
+            "logfile" => self.logfile = parse_path(value),
+            _ => return Err(format!("unknown parameter `{}`", name).into()),
+        }
+
+        Ok(())
+    }
+
+    /// Write the configuration back to `config_file`, as `CONFIG REWRITE`.
+    ///
+    /// The directives of the file are updated in place, keeping its comments and layout.
+    /// The parameters it doesn't set yet are appended when they differ from their default.
+    pub fn rewrite(&self) -> Result<(), crate::GenericError> {
+        let path = match &self.config_file {
+            Some(path) => path,
+            None => return Err("the server is running without a config file".into()),
+        };
+
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut lines = vec![];
+        let mut written = HashSet::new();
+
+        for line in contents.lines() {
+            let name = split_args(line)
+                .ok()
+                .and_then(|args| args.into_iter().next())
+                .map(|name| name.to_lowercase());
+
+            match name {
+                Some(name) if Config::PARAMETERS.contains(&&name[..]) => {
+                    // Repeated directives are merged into the first one
+                    if written.insert(name.clone()) {
+                        lines.extend(self.directives(&name));
+                    }
+                }
+                _ => lines.push(line.to_string()),
+            }
+        }
+
+        let default = Config::default();
+        let mut appended = Config::PARAMETERS
+            .iter()
+            .filter(|name| !written.contains(**name) && self.get(name) != default.get(name))
+            .peekable();
+
+        if appended.peek().is_some() {
+            lines.push("# Generated by CONFIG REWRITE".to_string());
+            lines.extend(appended.flat_map(|name| self.directives(name)));
+        }
+
+        // Written aside then renamed, so that the file is never left half written
+        let temp_path = path.with_extension("rewrite.tmp");
+        fs::write(&temp_path, lines.join("\n") + "\n")?;
+        fs::rename(&temp_path, path)?;
+
+        Ok(())
+    }
+
+    /// Returns the lines of the configuration file setting the parameter `name`.
+    fn directives(&self, name: &str) -> Vec<String> {
+        let value = self.get(name).unwrap_or_default();
+
+        match name {
+            // One directive per rule, as Redis writes them
+            "save" if !self.rdb.save.is_empty() => self
+                .rdb
+                .save
+                .iter()
+                .map(|rule| format!("save {} {}", rule.seconds, rule.changes))
+                .collect(),
+            "bind" => vec![format!("bind {}", value)],
+            _ => vec![format!("{} {}", name, quote_arg(&value))],
+        }
+    }
+}
+
+/// Configuration of a running server, shared by the connections and changed with `CONFIG SET`.
+#[derive(Debug)]
+pub(crate) struct LiveConfig {
+    config: RwLock<Config>,
+}
+
+impl LiveConfig {
+    pub(crate) fn new(config: Config) -> LiveConfig {
+        LiveConfig {
+            config: RwLock::new(config),
+        }
+    }
+
+    /// Returns what `f` reads from the configuration.
+    pub(crate) fn read<T>(&self, f: impl FnOnce(&Config) -> T) -> T {
+        f(&self.config.read().unwrap())
+    }
+
+    /// Set the parameters, either all of them or none if one can't be set.
+    ///
+    /// Returns the error to reply otherwise: the parameter is unknown, only read when the
+    /// server starts, or its value is invalid.
+    pub(crate) fn set(&self, parameters: &[(String, String)]) -> Result<(), String> {
+        let mut config = self.config.write().unwrap();
+        let mut updated = config.clone();
+
+        for (name, value) in parameters {
+            let name = name.to_lowercase();
+
+            if !Config::PARAMETERS.contains(&&name[..]) {
+                return Err(format!(
+                    "ERR Unknown option or number of arguments for CONFIG SET - '{}'",
+                    name
+                ));
+            }
+
+            if !Config::MUTABLE_PARAMETERS.contains(&&name[..]) {
+                return Err(format!(
+                    "ERR CONFIG SET failed (possibly related to argument '{}') - can't set immutable config",
+                    name
+                ));
+            }
+
+            if let Err(err) = updated.set(&name, value) {
+                return Err(format!(
+                    "ERR CONFIG SET failed (possibly related to argument '{}') - {}",
+                    name, err
+                ));
+            }
+        }
+
+        if updated.loglevel != config.loglevel {
+            crate::logging::set_level(updated.loglevel);
+        }
+
+        *config = updated;
+
+        Ok(())
+    }
+}
+
+/// Split a line of the configuration file into arguments, separated by whitespace.
+///
+/// Arguments may be quoted: within double quotes, `\` escapes the next character, e.g.
+/// `\"` or `\n`; single quotes keep their content as is.
+pub(crate) fn split_args(line: &str) -> Result<Vec<String>, crate::GenericError> {
+    let mut args = vec![];
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let quote = match chars.peek() {
+            None => return Ok(args),
+            Some(&c) if c == '"' || c == '\'' => chars.next(),
+            Some(_) => None,
+        };
+
+        let mut arg = String::new();
+
+        loop {
+            match (quote, chars.next()) {
+                (Some(_), None) => return Err("unbalanced quotes".into()),
+                (Some(q), Some(c)) if c == q => {
+                    // A closing quote must end the argument
+                    if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                        return Err("closing quote must be followed by a space".into());
+                    }
+                    break;
+                }
+                (Some('"'), Some('\\')) => match chars.next() {
+                    Some('n') => arg.push('\n'),
+                    Some('r') => arg.push('\r'),
+                    Some('t') => arg.push('\t'),
+                    Some(c) => arg.push(c),
+                    None => return Err("unbalanced quotes".into()),
+                },
+                (None, None) => break,
+                (None, Some(c)) if c.is_whitespace() => break,
+                (_, Some(c)) => arg.push(c),
+            }
+        }
+
+        args.push(arg);
+    }
+}
+
+/// Quote a value written to the configuration file if it's empty or holds spaces or quotes.
+fn quote_arg(value: &str) -> String {
+    if !value.is_empty()
+        && !value.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '\\')
+    {
+        return value.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+fn parse_number<T: FromStr>(name: &str, value: &str) -> Result<T, crate::GenericError> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid {} `{}`", name, value).into())
+}
+
+fn parse_positive(name: &str, value: &str) -> Result<usize, crate::GenericError> {
+    match parse_number(name, value)? {
+        0 => Err(format!("{} must be greater than 0", name).into()),
+        number => Ok(number),
+    }
+}
+
+fn parse_filename(name: &str, value: &str) -> Result<String, crate::GenericError> {
+    if value.is_empty() || value.contains(std::path::is_separator) {
+        return Err(format!("invalid {} `{}`, expected a file name", name, value).into());
+    }
+
+    Ok(value.to_string())
+}
+
+/// Parse an optional path, unset when empty.
+fn parse_path(value: &str) -> Option<PathBuf> {
+    Some(PathBuf::from(value)).filter(|_| !value.is_empty())
+}
+
+fn display_path(path: &Option<PathBuf>) -> String {
+    path.as_ref()
+        .map(|path| path.display().to_string())
+        .unwrap_or_default()
+}
+
+fn parse_yes_no(name: &str, value: &str) -> Result<bool, crate::GenericError> {
+    match &value.to_lowercase()[..] {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!("invalid {} `{}`, expected yes or no", name, value).into()),
+    }
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+impl SlotRange {
+    /// Parse comma separated slot ranges, e.g. `0-5460,16000`.
+    pub fn parse_ranges(s: &str) -> Result<Vec<SlotRange>, crate::GenericError> {
+        s.split(',')
+            .filter(|range| !range.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+}
+
+impl FromStr for SlotRange {
+    type Err = crate::GenericError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s.split_once('-').unwrap_or((s, s));
+
+        match (start.parse(), end.parse()) {
+            (Ok(start), Ok(end)) if start <= end && end < CLUSTER_SLOTS => {
+                Ok(SlotRange { start, end })
+            }
+            _ => Err(format!("invalid slot range `{}`", s).into()),
+        }
+    }
+}
+
+impl FromStr for ClusterNodeConfig {
+    type Err = crate::GenericError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid cluster node `{}`, expected `host:port[@bus_port]=slots`",
+                s
+            )
+        };
+
+        let (addr, slots) = s.split_once('=').ok_or_else(invalid)?;
+        let (addr, bus_port) = match addr.split_once('@') {
+            Some((addr, bus_port)) => (addr, Some(bus_port.parse().map_err(|_| invalid())?)),
+            None => (addr, None),
+        };
+        let (host, port) = addr.rsplit_once(':').ok_or_else(invalid)?;
+
+        Ok(ClusterNodeConfig {
+            host: host.to_string(),
+            port: port.parse().map_err(|_| invalid())?,
+            bus_port,
+            slots: SlotRange::parse_ranges(slots)?,
+        })
+    }
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        ClusterConfig {
+            announce_host: DEFAULT_CLUSTER_ANNOUNCE_HOST.to_string(),
+            bus_port: None,
+            node_timeout: DEFAULT_CLUSTER_NODE_TIMEOUT,
+            slots: vec![],
+            nodes: vec![],
+        }
+    }
+}
+
+impl SaveRule {
+    /// Parse save rules from pairs of `seconds changes` values, e.g. `3600 1 300 100`.
+    ///
+    /// An empty string disables saving.
+    pub fn parse_rules(s: &str) -> Result<Vec<SaveRule>, crate::GenericError> {
+        let values = s
+            .split_whitespace()
+            .map(|value| value.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("invalid save rules `{}`", s))?;
+
+        if values.len() % 2 != 0 {
+            return Err(format!("invalid save rules `{}`", s).into());
+        }
+
+        Ok(values
+            .chunks(2)
+            .map(|rule| SaveRule {
+                seconds: rule[0],
+                changes: rule[1],
+            })
+            .collect())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: vec![DEFAULT_BIND],
+            port: DEFAULT_PORT,
+            unixsocket: None,
+            unixsocketperm: 0,
+            tls: TlsConfig::default(),
+            maxclients: DEFAULT_MAXCLIENTS,
+            timeout: Duration::ZERO,
+            requirepass: None,
+            aclfile: None,
+            acllog_max_len: DEFAULT_ACLLOG_MAX_LEN,
+            masteruser: None,
+            masterauth: None,
+            databases: DEFAULT_DATABASES,
+            shards: DEFAULT_SHARDS,
+            lua_time_limit: DEFAULT_LUA_TIME_LIMIT,
+            pub_sub: PubSubConfig::default(),
+            notify_keyspace_events: KeyspaceEvents::default(),
+            dir: PathBuf::from("."),
+            rdb: RdbConfig::default(),
+            aof: AofConfig::default(),
+            repl_backlog_size: DEFAULT_REPL_BACKLOG_SIZE,
+            cluster: None,
+            maxmemory: MaxMemoryConfig::default(),
+            loglevel: LogLevel::default(),
+            logfile: None,
+            config_file: None,
+        }
+    }
+}
+
+impl Default for RdbConfig {
+    fn default() -> Self {
+        RdbConfig {
+            dbfilename: DEFAULT_DBFILENAME.to_string(),
+            save: vec![],
+        }
+    }
+}
+
+impl Default for AofConfig {
+    fn default() -> Self {
+        AofConfig {
+            enabled: false,
+            filename: DEFAULT_AOF_FILENAME.to_string(),
+            fsync: AppendFsync::default(),
+            timestamps: false,
+            recover_to: None,
+        }
+    }
+}
+
+impl Default for PubSubConfig {
+    fn default() -> Self {
+        PubSubConfig {
+            capacity: DEFAULT_PUB_SUB_CAPACITY,
+            lag_policy: LagPolicy::default(),
+        }
+    }
+}
+
+impl Default for MaxMemoryConfig {
+    fn default() -> Self {
+        MaxMemoryConfig {
+            limit: 0,
+            policy: MaxMemoryPolicy::default(),
+            samples: DEFAULT_MAXMEMORY_SAMPLES,
+        }
+    }
+}
+
+impl MaxMemoryConfig {
+    /// Parse a memory size in bytes, with an optional unit as Redis accepts them: `k`,
+    /// `m` and `g` are powers of 1000, `kb`, `mb` and `gb` powers of 1024, e.g. `100mb`.
+    pub fn parse_size(s: &str) -> Result<usize, crate::GenericError> {
+        let s = s.to_lowercase();
+        let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+
+        let unit = match &s[digits..] {
+            "" | "b" => 1,
+            "k" => 1000,
+            "kb" => 1024,
+            "m" => 1000 * 1000,
+            "mb" => 1024 * 1024,
+            "g" => 1000 * 1000 * 1000,
+            "gb" => 1024 * 1024 * 1024,
+            _ => return Err(format!("invalid memory size `{}`", s).into()),
+        };
+
+        match s[..digits]
+            .parse::<usize>()
+            .ok()
+            .and_then(|size| size.checked_mul(unit))
+        {
+            Some(size) => Ok(size),
+            None => Err(format!("invalid memory size `{}`", s).into()),
+        }
+    }
+}
+
+impl MaxMemoryPolicy {
+    /// Returns `true` if only the keys with a TTL are evicted.
+    pub fn is_volatile(&self) -> bool {
+        matches!(
+            self,
+            MaxMemoryPolicy::VolatileLru
+                | MaxMemoryPolicy::VolatileLfu
+                | MaxMemoryPolicy::VolatileRandom
+                | MaxMemoryPolicy::VolatileTtl
+        )
+    }
+}
+
+impl FromStr for MaxMemoryPolicy {
+    type Err = crate::GenericError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "noeviction" => Ok(MaxMemoryPolicy::NoEviction),
+            "allkeys-lru" => Ok(MaxMemoryPolicy::AllKeysLru),
+            "allkeys-lfu" => Ok(MaxMemoryPolicy::AllKeysLfu),
+            "allkeys-random" => Ok(MaxMemoryPolicy::AllKeysRandom),
+            "volatile-lru" => Ok(MaxMemoryPolicy::VolatileLru),
+            "volatile-lfu" => Ok(MaxMemoryPolicy::VolatileLfu),
+            "volatile-random" => Ok(MaxMemoryPolicy::VolatileRandom),
+            "volatile-ttl" => Ok(MaxMemoryPolicy::VolatileTtl),
+            _ => Err(format!("invalid maxmemory policy `{}`", s).into()),
+        }
+    }
+}
+
+impl fmt::Display for MaxMemoryPolicy {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaxMemoryPolicy::NoEviction => "noeviction".fmt(fmt),
+            MaxMemoryPolicy::AllKeysLru => "allkeys-lru".fmt(fmt),
+            MaxMemoryPolicy::AllKeysLfu => "allkeys-lfu".fmt(fmt),
+            MaxMemoryPolicy::AllKeysRandom => "allkeys-random".fmt(fmt),
+            MaxMemoryPolicy::VolatileLru => "volatile-lru".fmt(fmt),
+            MaxMemoryPolicy::VolatileLfu => "volatile-lfu".fmt(fmt),
+            MaxMemoryPolicy::VolatileRandom => "volatile-random".fmt(fmt),
+            MaxMemoryPolicy::VolatileTtl => "volatile-ttl".fmt(fmt),
+        }
+    }
+}
+
+impl FromStr for LagPolicy {
+    type Err = crate::GenericError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "skip" => Ok(LagPolicy::Skip),
+            "disconnect" => Ok(LagPolicy::Disconnect),
+            "backpressure" => Ok(LagPolicy::Backpressure),
+            _ => Err(format!("invalid lag policy `{}`", s).into()),
+        }
+    }
+}
+
+impl fmt::Display for LagPolicy {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LagPolicy::Skip => "skip".fmt(fmt),
+            LagPolicy::Disconnect => "disconnect".fmt(fmt),
+            LagPolicy::Backpressure => "backpressure".fmt(fmt),
+        }
+    }
+}
+
+impl FromStr for TlsAuthClients {
+    type Err = crate::GenericError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "no" => Ok(TlsAuthClients::No),
+            "yes" => Ok(TlsAuthClients::Yes),
+            "optional" => Ok(TlsAuthClients::Optional),
+            _ => Err(format!(
+                "invalid tls-auth-clients `{}`, expected yes, no or optional",
+                s
+            )
+            .into()),
+        }
+    }
+}
+
+impl fmt::Display for TlsAuthClients {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsAuthClients::No => "no".fmt(fmt),
+            TlsAuthClients::Yes => "yes".fmt(fmt),
+            TlsAuthClients::Optional => "optional".fmt(fmt),
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = crate::GenericError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "debug" => Ok(LogLevel::Debug),
+            "verbose" => Ok(LogLevel::Verbose),
+            "notice" => Ok(LogLevel::Notice),
+            "warning" => Ok(LogLevel::Warning),
+            _ => Err(format!("invalid log level `{}`", s).into()),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogLevel::Debug => "debug".fmt(fmt),
+            LogLevel::Verbose => "verbose".fmt(fmt),
+            LogLevel::Notice => "notice".fmt(fmt),
+            LogLevel::Warning => "warning".fmt(fmt),
+        }
+    }
+}
+
+impl FromStr for AppendFsync {
+    type Err = crate::GenericError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "always" => Ok(AppendFsync::Always),
+            "everysec" => Ok(AppendFsync::EverySec),
+            "no" => Ok(AppendFsync::No),
+            _ => Err(format!("invalid appendfsync policy `{}`", s).into()),
+        }
+    }
+}
+
+impl fmt::Display for AppendFsync {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppendFsync::Always => "always".fmt(fmt),
+            AppendFsync::EverySec => "everysec".fmt(fmt),
+            AppendFsync::No => "no".fmt(fmt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_keyspace_events() {
+        let events: KeyspaceEvents = "KEA".parse().unwrap();
+        assert!(events.contains(KeyspaceEvents::KEYSPACE | KeyspaceEvents::KEYEVENT));
+        assert!(events.is_enabled(KeyspaceEvents::EXPIRED));
+        assert!(!events.is_enabled(KeyspaceEvents::KEY_MISS));
+
+        let events: KeyspaceEvents = "Ex".parse().unwrap();
+        assert!(events.is_enabled(KeyspaceEvents::EXPIRED));
+        assert!(!events.is_enabled(KeyspaceEvents::STRING));
+
+        // Classes aren't published without `K` or `E`
+        let events: KeyspaceEvents = "g$".parse().unwrap();
+        assert!(!events.is_enabled(KeyspaceEvents::GENERIC));
+
+        assert!("Kq".parse::<KeyspaceEvents>().is_err());
+    }
+
+    #[test]
+    fn should_load_on_startup_once_configured() {
+        let mut config = Config::default();
+        assert!(!config.loads_on_startup());
+
+        config.rdb.save = SaveRule::parse_rules("60 1").unwrap();
+        assert!(config.loads_on_startup());
+
+        let config = Config {
+            dir: PathBuf::from("/var/lib/mini-redis"),
+            ..Config::default()
+        };
+        assert!(config.loads_on_startup());
+
+        let mut config = Config::default();
+        config.aof.enabled = true;
+        assert!(config.loads_on_startup());
+    }
+
+    #[test]
+    fn should_parse_memory_sizes() {
+        assert_eq!(100, MaxMemoryConfig::parse_size("100").unwrap());
+        assert_eq!(2000, MaxMemoryConfig::parse_size("2k").unwrap());
+        assert_eq!(2048, MaxMemoryConfig::parse_size("2KB").unwrap());
+        assert_eq!(
+            100 * 1024 * 1024,
+            MaxMemoryConfig::parse_size("100mb").unwrap()
+        );
+        assert_eq!(1_000_000_000, MaxMemoryConfig::parse_size("1g").unwrap());
+        assert!(MaxMemoryConfig::parse_size("mb").is_err());
+        assert!(MaxMemoryConfig::parse_size("1tb").is_err());
+
+        let policy: MaxMemoryPolicy = "Volatile-TTL".parse().unwrap();
+        assert_eq!(MaxMemoryPolicy::VolatileTtl, policy);
+        assert!(policy.is_volatile());
+        assert_eq!("allkeys-lfu", MaxMemoryPolicy::AllKeysLfu.to_string());
+    }
+
+    #[test]
+    fn should_parse_save_rules() {
+        let rules = SaveRule::parse_rules("3600 1 300 100").unwrap();
+        assert_eq!(
+            rules,
+            vec![
+                SaveRule {
+                    seconds: 3600,
+                    changes: 1
+                },
+                SaveRule {
+                    seconds: 300,
+                    changes: 100
+                },
+            ]
+        );
+
+        assert!(SaveRule::parse_rules("").unwrap().is_empty());
+        assert!(SaveRule::parse_rules("3600").is_err());
+        assert!(SaveRule::parse_rules("3600 x").is_err());
+    }
+
+    #[test]
+    fn should_parse_cluster_nodes() {
+        let node: ClusterNodeConfig = "127.0.0.1:7001=0-5460,16000".parse().unwrap();
+        assert_eq!(
+            node,
+            ClusterNodeConfig {
+                host: "127.0.0.1".to_string(),
+                port: 7001,
+                bus_port: None,
+                slots: vec![
+                    SlotRange {
+                        start: 0,
+                        end: 5460
+                    },
+                    SlotRange {
+                        start: 16000,
+                        end: 16000
+                    }
+                ],
+            }
+        );
+
+        let node: ClusterNodeConfig = "127.0.0.1:7001@17005=".parse().unwrap();
+        assert_eq!(Some(17005), node.bus_port);
+        assert!(node.slots.is_empty());
+
+        assert!("127.0.0.1:7001".parse::<ClusterNodeConfig>().is_err());
+        assert!("127.0.0.1=0-10".parse::<ClusterNodeConfig>().is_err());
+        assert!(SlotRange::parse_ranges("10-5").is_err());
+        assert!(SlotRange::parse_ranges("0-16384").is_err());
+    }
+
+    #[test]
+    fn should_apply_config_file_directives() {
+        let mut config = Config::default();
+        config
+            .apply_directives(
+                "# Network\n\
+                 bind 0.0.0.0 ::1\n\
+                 port 7000\n\
+                 unixsocket /tmp/mini-redis.sock\n\
+                 unixsocketperm 770\n\
+                 \n\
+                 MAXCLIENTS 100\n\
+                 save 3600 1\n\
+                 save 300 100\n\
+                 notify-keyspace-events \"\"\n\
+                 appendonly yes\n\
+                 logfile '/var/log/mini redis.log'\n\
+                 maxmemory 2mb\n",
+            )
+            .unwrap();
+
+        assert_eq!(
+            config.bind,
+            vec!["0.0.0.0".parse::<IpAddr>().unwrap(), "::1".parse().unwrap()]
+        );
+        assert_eq!(config.port, 7000);
+        assert_eq!(
+            config.unixsocket,
+            Some(PathBuf::from("/tmp/mini-redis.sock"))
+        );
+        assert_eq!(config.unixsocketperm, 0o770);
+        assert_eq!(config.maxclients, 100);
+        assert_eq!(config.get("save").unwrap(), "3600 1 300 100");
+        assert!(config.aof.enabled);
+        assert_eq!(
+            config.logfile,
+            Some(PathBuf::from("/var/log/mini redis.log"))
+        );
+        assert_eq!(config.maxmemory.limit, 2 * 1024 * 1024);
+
+        // `save ""` clears the rules
+        config.apply_directives("save \"\"").unwrap();
+        assert!(config.rdb.save.is_empty());
+
+        let err = config.apply_directives("port 7000\nport x").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: invalid port `x`");
+        assert!(config.apply_directives("databases 0").is_err());
+        assert!(config.apply_directives("unixsocketperm 1777").is_err());
+        assert!(config.apply_directives("unknown 1").is_err());
+        assert!(config.apply_directives("logfile \"unbalanced").is_err());
+    }
+
+    #[test]
+    fn should_get_and_set_every_parameter() {
+        let default = Config::default();
+
+        for name in Config::PARAMETERS {
+            let value = default.get(name).unwrap();
+
+            let mut config = Config::default();
+            config.set(name, &value).unwrap();
+            assert_eq!(config.get(name).unwrap(), value, "{}", name);
+        }
+
+        assert!(Config::MUTABLE_PARAMETERS
+            .iter()
+            .all(|name| Config::PARAMETERS.contains(name)));
+        assert_eq!(default.get("unknown"), None);
+    }
+
+    #[test]
+    fn should_quote_config_file_values() {
+        for value in [
+            "",
+            "a b",
+            "say \"hi\"",
+            "back\\slash",
+            "line\nbreak",
+            "plain",
+        ] {
+            assert_eq!(
+                split_args(&format!("name {}", quote_arg(value))).unwrap(),
+                vec!["name", value]
+            );
+        }
+    }
+
+    #[test]
+    fn should_rewrite_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mini-redis.conf");
+        fs::write(
+            &path,
+            "# Port to listen on\nport 7000\n\nsave 900 1\nsave 60 1000\nunknown-directive kept\n",
+        )
+        .unwrap();
+
+        let mut config = Config::default();
+        config.apply_directives("port 7000").unwrap();
+        config.config_file = Some(path.clone());
+        config.set("port", "7001").unwrap();
+        config.set("save", "3600 1").unwrap();
+        config.set("logfile", "mini redis.log").unwrap();
+        config.rewrite().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# Port to listen on\n\
+             port 7001\n\
+             \n\
+             save 3600 1\n\
+             unknown-directive kept\n\
+             # Generated by CONFIG REWRITE\n\
+             logfile \"mini redis.log\"\n"
+        );
+
+        // Rewriting again leaves the file as is
+        config.rewrite().unwrap();
+        assert_eq!(
+            fs::read_to_string(&path)
+                .unwrap()
+                .matches("logfile")
+                .count(),
+            1
+        );
+
+        assert!(Config::default().rewrite().is_err());
+    }
+
+    #[test]
+    fn should_format_keyspace_events() {
+        let events: KeyspaceEvents = "EKA".parse().unwrap();
+        assert_eq!(events.to_string(), "AKE");
+
+        let events: KeyspaceEvents = "nx$K".parse().unwrap();
+        assert_eq!(events.to_string(), "$xKn");
+
+        assert_eq!(KeyspaceEvents::default().to_string(), "");
+    }
+}
@@ -1,15 +1,23 @@
-use bytes::{Buf, BytesMut};
-use std::io::{self, Cursor};
+use bytes::{Buf, Bytes, BytesMut};
+use std::{
+    io::{self, Cursor},
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+#[cfg(unix)]
+use tokio::net::UnixStream;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, BufWriter},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter, ReadBuf},
     net::TcpStream,
 };
+use tokio_rustls::{client, server, TlsStream};
 
 use crate::frame::{Error, Frame};
 
 /// Send and receive `Frame` chunks from a remote peer.
 ///
-/// Use an underlying `TcpStream` and an internal buffer which is filled
+/// Use an underlying `Stream` and an internal buffer which is filled
 /// up until there are enough bytes to create a full frame. Once this happens,
 /// the `Connection` creates the frame and returns it to the caller.
 ///
@@ -17,16 +25,54 @@ use crate::frame::{Error, Frame};
 /// The content of the write buffer is then written to the socket.
 #[derive(Debug)]
 pub struct Connection {
-    stream: BufWriter<TcpStream>,
+    stream: BufWriter<Stream>,
     buffer: BytesMut,
+
+    // `true` once the connection must be closed after the current reply (e.g. on `QUIT`)
+    closed: bool,
+}
+
+/// Socket of a connection, over TCP, TLS or a Unix domain socket.
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+
+    Tls(Box<TlsStream<TcpStream>>),
+
+    #[cfg(unix)]
+    Unix(UnixStream),
 }
 
 impl Connection {
-    pub fn new(socket: TcpStream) -> Connection {
+    pub fn new(socket: impl Into<Stream>) -> Connection {
         Connection {
-            stream: BufWriter::new(socket),
+            stream: BufWriter::new(socket.into()),
             // Defaults to 4KB read buffer
             buffer: BytesMut::with_capacity(4 * 1024),
+            closed: false,
+        }
+    }
+
+    /// Mark the connection to be closed once the current reply has been written.
+    pub(crate) fn close_after_reply(&mut self) {
+        self.closed = true;
+    }
+
+    /// Returns `true` if no more frame must be processed on this connection.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Address of the remote peer, only known for TCP connections.
+    pub(crate) fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self.stream.get_ref() {
+            Stream::Tcp(socket) => socket.peer_addr(),
+            Stream::Tls(stream) => stream.get_ref().0.peer_addr(),
+            #[cfg(unix)]
+            Stream::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "not a TCP connection",
+            )),
         }
     }
 
@@ -36,6 +82,27 @@ impl Connection {
     /// Any data remaining in the buffer after the frame has been parsed
     /// is kept there for the next call to `read_frame`.
     pub async fn read_frame(&mut self) -> crate::FnResult<Option<Frame>> {
+        match self.read_encoded_frame().await? {
+            Some((frame, len)) => {
+                // Discard parsed data from the read buffer.
+                self.buffer.advance(len);
+                Ok(Some(frame))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Read a single frame, along with the data it was encoded as.
+    pub(crate) async fn read_frame_with_data(&mut self) -> crate::FnResult<Option<(Frame, Bytes)>> {
+        match self.read_encoded_frame().await? {
+            Some((frame, len)) => Ok(Some((frame, self.buffer.split_to(len).freeze()))),
+            None => Ok(None),
+        }
+    }
+
+    /// Read a single frame, returning it along with the length it was encoded on, at the
+    /// start of the read buffer.
+    async fn read_encoded_frame(&mut self) -> crate::FnResult<Option<(Frame, usize)>> {
         loop {
             // Attempt to parse a frame. If enough data has been buffered, a frame is returned.
             if let Some(frame) = self.parse_frame()? {
@@ -54,7 +121,42 @@ impl Connection {
         }
     }
 
-    fn parse_frame(&mut self) -> crate::FnResult<Option<Frame>> {
+    /// Read a bulk payload which isn't followed by CRLF, as the snapshot sent for a full
+    /// resynchronization.
+    pub(crate) async fn read_payload(&mut self) -> crate::FnResult<Bytes> {
+        let len = loop {
+            if let Some(end) = self.buffer.windows(2).position(|window| window == b"\r\n") {
+                let line = self.buffer.split_to(end + 2);
+
+                break match line.strip_prefix(b"$") {
+                    Some(len) => atoi::atoi::<usize>(&len[..len.len() - 2])
+                        .ok_or("protocol error; invalid payload length")?,
+                    None => return Err("protocol error; expected a payload".into()),
+                };
+            }
+
+            self.fill_buffer().await?;
+        };
+
+        while self.buffer.len() < len {
+            self.fill_buffer().await?;
+        }
+
+        Ok(self.buffer.split_to(len).freeze())
+    }
+
+    /// Read more data from the socket, failing if it was closed.
+    async fn fill_buffer(&mut self) -> crate::FnResult<()> {
+        if self.stream.read_buf(&mut self.buffer).await? == 0 {
+            return Err("connection reset by peer".into());
+        }
+
+        Ok(())
+    }
+
+    /// Parse a frame from the read buffer, returning it along with the length it was
+    /// encoded on. The parsed data is left in the buffer.
+    fn parse_frame(&mut self) -> crate::FnResult<Option<(Frame, usize)>> {
         // Track the "current" location in the buffer.
         let mut buf = Cursor::new(&self.buffer[..]);
 
@@ -69,10 +171,7 @@ impl Connection {
 
                 let frame = Frame::parse(&mut buf)?;
 
-                // Discard parsed data from the read buffer.
-                self.buffer.advance(len);
-
-                Ok(Some(frame))
+                Ok(Some((frame, len)))
             }
             Err(Error::Incomplete) => Ok(None),
             Err(err) => Err(err.into()),
@@ -87,7 +186,7 @@ impl Connection {
                 self.stream.write_u8(b'*').await?;
 
                 // Encode the length of the array.
-                self.write_decimal(array.len() as u64).await?;
+                self.write_decimal(array.len() as i64).await?;
 
                 // Encode entries in the array.
                 for entry in &**array {
@@ -100,6 +199,12 @@ impl Connection {
         self.stream.flush().await
     }
 
+    /// Write data already encoded in the Redis protocol to the underlying stream.
+    pub(crate) async fn write_raw(&mut self, data: &[u8]) -> io::Result<()> {
+        self.stream.write_all(data).await?;
+        self.stream.flush().await
+    }
+
     async fn write_value(&mut self, frame: &Frame) -> io::Result<()> {
         match frame {
             Frame::Simple(value) => {
@@ -121,16 +226,24 @@ impl Connection {
             }
             Frame::Bulk(value) => {
                 self.stream.write_u8(b'$').await?;
-                self.write_decimal(value.len() as u64).await?;
+                self.write_decimal(value.len() as i64).await?;
                 self.stream.write_all(value).await?;
                 self.stream.write_all(b"\r\n").await?;
             }
-            Frame::Array(_) => unreachable!(),
+            // Nested arrays, such as the replicas listed by `ROLE`
+            Frame::Array(array) => {
+                self.stream.write_u8(b'*').await?;
+                self.write_decimal(array.len() as i64).await?;
+
+                for entry in &**array {
+                    Box::pin(self.write_value(entry)).await?;
+                }
+            }
         }
         Ok(())
     }
 
-    async fn write_decimal(&mut self, value: u64) -> io::Result<()> {
+    async fn write_decimal(&mut self, value: i64) -> io::Result<()> {
         use std::io::Write;
 
         // Convert value to a string
@@ -145,3 +258,76 @@ impl Connection {
         Ok(())
     }
 }
+
+impl From<TcpStream> for Stream {
+    fn from(socket: TcpStream) -> Stream {
+        Stream::Tcp(socket)
+    }
+}
+
+impl From<server::TlsStream<TcpStream>> for Stream {
+    fn from(stream: server::TlsStream<TcpStream>) -> Stream {
+        Stream::Tls(Box::new(stream.into()))
+    }
+}
+
+impl From<client::TlsStream<TcpStream>> for Stream {
+    fn from(stream: client::TlsStream<TcpStream>) -> Stream {
+        Stream::Tls(Box::new(stream.into()))
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for Stream {
+    fn from(socket: UnixStream) -> Stream {
+        Stream::Unix(socket)
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(socket) => Pin::new(socket).poll_read(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(socket) => Pin::new(socket).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(socket) => Pin::new(socket).poll_write(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(socket) => Pin::new(socket).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(socket) => Pin::new(socket).poll_flush(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Stream::Unix(socket) => Pin::new(socket).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(socket) => Pin::new(socket).poll_shutdown(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            Stream::Unix(socket) => Pin::new(socket).poll_shutdown(cx),
+        }
+    }
+}
@@ -1,5 +1,59 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
 /// Default listening port
 pub const DEFAULT_PORT: u16 = 6379;
 
-/// Maximum number of connections the server will accept
-pub const MAX_CONNECTIONS: usize = 250;
\ No newline at end of file
+/// Default address the server listens on
+pub const DEFAULT_BIND: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+/// Default number of logical databases
+pub const DEFAULT_DATABASES: usize = 16;
+
+/// Default number of shards the keyspace is partitioned into, each locked on its own
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// Default duration after which a running Lua script is aborted
+pub const DEFAULT_LUA_TIME_LIMIT: Duration = Duration::from_secs(5);
+
+/// Default name of the RDB snapshot file
+pub const DEFAULT_DBFILENAME: &str = "dump.rdb";
+
+/// Default name of the append-only file
+pub const DEFAULT_AOF_FILENAME: &str = "appendonly.aof";
+
+/// Save rules used by `mini-redis-server` unless configured, as Redis's defaults
+pub const DEFAULT_SAVE_RULES: &str = "3600 1 300 100 60 10000";
+
+/// Default number of keys sampled per database to pick the one to evict, once over `maxmemory`
+pub const DEFAULT_MAXMEMORY_SAMPLES: usize = 5;
+
+/// Default maximum number of connected clients, as Redis's `maxclients`
+pub const DEFAULT_MAXCLIENTS: usize = 10000;
+
+/// Default maximum number of entries of the ACL log
+pub const DEFAULT_ACLLOG_MAX_LEN: usize = 128;
+
+/// Default number of messages retained by a pub/sub channel for its slowest subscriber
+pub const DEFAULT_PUB_SUB_CAPACITY: usize = 1024;
+
+/// Default size of the replication backlog, in bytes
+pub const DEFAULT_REPL_BACKLOG_SIZE: usize = 1024 * 1024;
+
+/// Number of write batches retained by the replication stream for the slowest replica
+pub const REPLICATION_STREAM_CAPACITY: usize = 4096;
+
+/// Number of hash slots the keys are sharded into in cluster mode
+pub const CLUSTER_SLOTS: u16 = 16384;
+
+/// Default host announced to clients by a cluster node
+pub const DEFAULT_CLUSTER_ANNOUNCE_HOST: &str = "127.0.0.1";
+
+/// Offset from the listening port of the default cluster bus port
+pub const CLUSTER_BUS_PORT_OFFSET: u16 = 10000;
+
+/// Default duration after which a cluster node not answering pings is flagged as failing
+pub const DEFAULT_CLUSTER_NODE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Duration during which a node forgotten with `CLUSTER FORGET` is ignored by the gossip
+pub const CLUSTER_FORGET_TTL: Duration = Duration::from_secs(60);
@@ -1,14 +1,56 @@
 use bytes::Bytes;
 use std::{
-    collections::{BTreeSet, HashMap},
-    sync::{Arc, Mutex},
-    time::Duration,
+    cell::OnceCell,
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, SystemTime},
 };
 use tokio::{
-    sync::{broadcast, Notify},
+    sync::{broadcast, futures::Notified, Notify},
     time::{self, Instant},
 };
-use tracing::debug;
+use tracing::{debug, error, info};
+
+use crate::acl::{Acl, Session};
+use crate::aof::{self, Aof};
+use crate::cluster::{self, ClusterState};
+use crate::commands::{Command, Del, FlushAll, FlushDb, Move, SwapDb};
+use crate::config::{
+    AofConfig, AppendFsync, Config, KeyspaceEvents, LagPolicy, LiveConfig, MaxMemoryConfig,
+    MaxMemoryPolicy, PubSubConfig,
+};
+use crate::frame::Frame;
+use crate::glob;
+use crate::rdb::{self, Record, Snapshot};
+use crate::replication::{self, Replication};
+use crate::scripting::{Scripts, BUSY_ERROR};
+use crate::wheel::{TimerId, TimingWheel};
+
+/// Reply to a write command which may grow the databases, when over `maxmemory`.
+pub(crate) const OOM_ERROR: &str = "OOM command not allowed when used memory > 'maxmemory'.";
+
+/// Approximate memory used by an entry besides its key and value: the hash table slot,
+/// the `Entry` itself, the key's reference count and its position in `Keyspace::keys`.
+const ENTRY_OVERHEAD: usize = 96;
+
+/// Approximate memory used by the timer of a key with a TTL.
+const EXPIRATION_OVERHEAD: usize = 32;
+
+/// Access frequency of new keys, so that they aren't evicted right away by the LFU policies
+const LFU_INIT_VAL: u8 = 5;
+
+/// The higher, the more accesses it takes to increment the access frequency counter
+const LFU_LOG_FACTOR: f64 = 10.0;
+
+/// Period after which the access frequency of a key not accessed is decremented
+const LFU_DECAY_PERIOD: Duration = Duration::from_secs(60);
+
+/// Number of random keys drawn per sample looked for among the keys with a TTL
+const VOLATILE_SAMPLING_TRIES: usize = 4;
 
 /// A wrapper around `Db` instances to allow orderly cleanup of
 /// `Db` by signaling the background purge task to shutdown when
@@ -20,8 +62,14 @@ pub(crate) struct DbDropGuard {
 
 /// Server state shared across all connections.
 ///
-/// `Db` contains a `HashMap` storing the KV data and all
-/// `broadcast::Sender` values from acive pub/sub channels.
+/// `Db` contains the numbered databases, each a `HashMap` storing the KV data,
+/// and all `broadcast::Sender` values from acive pub/sub channels.
+///
+/// The keys are partitioned into shards by hash, each locked on its own, so that
+/// commands on keys of different shards run in parallel.
+///
+/// A `Db` handle operates on the database selected with `select` (`0` by default),
+/// while the pub/sub key space is shared by all databases.
 ///
 /// When a `Db` is created, a background task is spawned. It is
 /// set to expire values after the requested duration as elapsed.
@@ -30,53 +78,277 @@ pub(crate) struct DbDropGuard {
 #[derive(Clone, Debug)]
 pub(crate) struct Db {
     shared: Arc<Shared>,
+
+    /// Index of the selected database.
+    index: usize,
 }
 
 #[derive(Debug)]
 struct Shared {
-    /// Note: it is a `std::sync::Mutex` and not a Tokio mutex as there is not
-    /// asynchronous operations being performed while holding this mutex.
+    /// Partitions of the keyspace, the keys being assigned to a shard by their hash slot.
     ///
-    /// As the critical sections are small, it won't block for long.
+    /// Note: they are `std::sync::Mutex` and not Tokio mutexes as there is not
+    /// asynchronous operations being performed while holding these mutexes.
+    ///
+    /// As the critical sections are small, they won't block for long.
     ///
     /// (consider using a Tokio mutex and `tokio::task::spawn_blocking` for longer operations).
+    ///
+    /// To avoid deadlocks, shards are always locked in ascending order, and before `state`.
+    shards: Vec<Mutex<Shard>>,
+
+    /// Server state besides the keyspace: persistence, replication and cluster.
     state: Mutex<State>,
 
+    /// Pub/sub channels and subscribers. Nothing else is locked while holding them.
+    channels: Mutex<Channels>,
+
+    /// Number of databases.
+    num_databases: usize,
+
+    /// `true` if the server runs in cluster mode.
+    cluster_enabled: bool,
+
+    /// Number of writes since the last snapshot, checked against the save rules.
+    dirty: AtomicU64,
+
+    /// Approximate memory used by the keys and values, as of the last release of each shard.
+    used_memory: AtomicUsize,
+
+    /// Configuration of the server, including the parameters changed at runtime with
+    /// `CONFIG SET`: memory limit, keyspace events, save rules...
+    config: LiveConfig,
+
+    /// `true` when writes are logged to the append-only file or streamed to replicas, so
+    /// that commands on keys only lock `state` when there are writes to propagate.
+    ///
+    /// Both only start while all shards are locked, no write is missed.
+    propagating: AtomicBool,
+
+    /// `true` while a failover or a migration pauses the writes.
+    writes_paused: AtomicBool,
+
     /// Notify the background task handling entry expiration and shutdown.
     background_task: Notify,
+
+    /// Notify publishers waiting for subscribers to catch up (see `LagPolicy::Backpressure`).
+    pub_sub_drained: Notify,
+
+    /// Notify the connections waiting for replicas to acknowledge their offset with `WAIT`.
+    replica_acked: Notify,
+
+    /// Notify the connections waiting for a failover or a migration to end to resume
+    /// their writes.
+    writes_resumed: Notify,
+
+    /// Notify the server to shut down, on `SHUTDOWN`.
+    shutdown_requested: Notify,
+
+    /// Whether `SHUTDOWN` asked to save the databases on the way out, or not to, overriding
+    /// the save rules.
+    shutdown_save: Mutex<Option<bool>>,
+
+    /// Pub/sub channels capacity and lag policy.
+    pub_sub_config: PubSubConfig,
+
+    /// Lua scripts cache, and the script being run.
+    scripts: Scripts,
+
+    /// Users connections authenticate as, and their permissions.
+    acl: Acl,
+
+    /// Path of the snapshot file.
+    rdb_path: PathBuf,
+
+    /// Append-only file configuration.
+    aof: AofConfig,
+
+    /// Path of the append-only file.
+    aof_path: PathBuf,
+
+    /// `true` while the server replicates a leader: only the writes streamed by the
+    /// leader are applied.
+    read_only: AtomicBool,
 }
 
 #[derive(Debug)]
 struct State {
-    /// KV store
-    entries: HashMap<String, Entry>,
+    /// Wall-clock time of the last successful snapshot, or of the server start.
+    last_save: SystemTime,
+
+    /// `true` while a background save is running.
+    bgsave_in_progress: bool,
+
+    /// Append-only file logging the writes, if enabled, once the databases are loaded.
+    aof: Option<Aof>,
+
+    /// Write stream sent to the replicas, and link to the leader.
+    replication: Replication,
+
+    /// Slots served by the nodes of the cluster, in cluster mode.
+    cluster: Option<ClusterState>,
+
+    /// Number of keys migrations in progress with `MIGRATE`, which pause the writes.
+    migrations: usize,
+
+    /// Number of keys evicted to stay under the memory limit.
+    evicted_keys: u64,
+
+    /// `true` when the `Db` instance is shutting down. It will signal to the background task to exit.
+    shutdown: bool,
+}
+
+/// Partition of the keyspace: the keys of every database hashing to the shard.
+#[derive(Debug)]
+struct Shard {
+    /// Numbered databases, selected with `SELECT`
+    databases: Vec<Keyspace>,
+
+    /// Dirty flags of the connections watching a key with `WATCH`, keyed by database index and key.
+    ///
+    /// The flags are raised whenever the key is modified, so that `EXEC` aborts.
+    watched_keys: HashMap<(usize, String), Vec<Arc<AtomicBool>>>,
+
+    /// Memory used by the keys of the shard, as accounted for in `Shared::used_memory`.
+    accounted_memory: usize,
+}
 
-    /// Pub/sub key space (as Redis uses a separate key space for KV and pub/sub).
+/// Pub/sub key space (as Redis uses a separate key space for KV and pub/sub).
+#[derive(Debug)]
+struct Channels {
+    /// Channel subscriptions, keyed by channel name.
     pub_sub: HashMap<String, broadcast::Sender<Bytes>>,
 
-    /// Tracks key TTLs.
+    /// Pattern subscriptions, keyed by glob-style pattern. Messages carry the channel they were published on.
+    pattern_pub_sub: HashMap<String, broadcast::Sender<(String, Bytes)>>,
+
+    /// Statistics of the connections currently in subscribed mode, keyed by subscriber id.
+    subscribers: BTreeMap<u64, Arc<SubscriberStats>>,
+
+    /// Id given to the next registered subscriber.
+    next_subscriber_id: u64,
+}
+
+/// The keys of a database hashing to a shard: its KV store and key TTLs.
+#[derive(Debug, Default)]
+struct Keyspace {
+    /// KV store
     ///
-    /// A `BTreeSet` is used to maintain expiration sorted by when they expire.
-    /// This allows the background task to interate this map to find the value expiring next.
+    /// Keys are shared with `expirations` and `keys` rather than copied.
+    entries: HashMap<Arc<str>, Entry>,
+
+    /// Tracks key TTLs.
     ///
-    /// While unlikely, it is possible for more than one expiration to be created for the same `Instant`.
-    /// Hence we're adding a unique key `String` to our key.
-    expirations: BTreeSet<(Instant, String)>,
+    /// A timing wheel lets the background task find the keys expiring next, while adding
+    /// and removing a TTL are O(1), as many keys often have a short TTL.
+    expirations: TimingWheel<Arc<str>>,
 
-    /// `true` when the `Db` instance is shutting down. It will signal to the background task to exit.
-    shutdown: bool,
+    /// Keys, in no particular order, to sample them at random for eviction.
+    keys: Vec<Arc<str>>,
+
+    /// Approximate memory used by the keys and values.
+    used_memory: usize,
 }
 
 #[derive(Debug)]
 struct Entry {
     data: Bytes,
     expires_at: Option<Instant>,
+
+    /// Last access to the key, by which the LRU policies evict keys. It's also the last
+    /// time the access frequency decayed.
+    accessed_at: Instant,
+
+    /// Logarithmic access frequency counter, by which the LFU policies evict keys.
+    frequency: u8,
+
+    /// Position of the key in `Keyspace::keys`.
+    position: usize,
+
+    /// Timer of the key in `Keyspace::expirations`, when it has a TTL.
+    timer: Option<TimerId>,
+}
+
+/// Lag statistics of a connection in subscribed mode.
+#[derive(Debug, Default)]
+struct SubscriberStats {
+    /// Number of channels the subscriber listens to
+    channels: AtomicUsize,
+
+    /// Number of patterns the subscriber listens to
+    patterns: AtomicUsize,
+
+    /// Number of times the subscriber fell behind its channels capacity
+    lag_events: AtomicU64,
+
+    /// Total number of messages the subscriber missed
+    dropped: AtomicU64,
+}
+
+/// Exclusive access to the server state, obtained with `Db::lock` or `Db::lock_keys_unless_busy`.
+///
+/// Operates on the database selected by the `Db` handle it was obtained from, and on the
+/// keys of the shards it locked: all of them, or the ones of the keys given to
+/// `lock_keys_unless_busy`.
+#[derive(Debug)]
+pub(crate) struct DbGuard<'a> {
+    shared: &'a Shared,
+
+    /// Locked shards by index, `None` for the ones not locked.
+    shards: Vec<Option<MutexGuard<'a, Shard>>>,
+
+    /// Server state, locked on first access, so that commands on keys only contend on
+    /// their shards.
+    state: OnceCell<MutexGuard<'a, State>>,
+
+    /// Index of the selected database.
+    index: usize,
+
+    /// `true` when an expiration earlier than the purge task's next deadline was set.
+    notify_background_task: bool,
+}
+
+/// Keys watched by a connection with `WATCH`.
+///
+/// The keys are unwatched when the watcher is dropped, which must not happen while
+/// holding a `DbGuard`.
+#[derive(Debug)]
+pub(crate) struct Watcher {
+    /// Raised when one of the keys is modified
+    dirty: Arc<AtomicBool>,
+
+    /// Watched keys, along with the index of their database
+    keys: Vec<(usize, String)>,
+
+    db: Db,
+}
+
+/// Registration of a connection in subscribed mode.
+///
+/// The subscriber is unregistered when the guard is dropped.
+#[derive(Debug)]
+pub(crate) struct SubscriberGuard {
+    id: u64,
+    stats: Arc<SubscriberStats>,
+    db: Db,
+}
+
+/// Snapshot of a subscriber lag statistics, as reported by `PUBSUB LAG`.
+#[derive(Debug)]
+pub(crate) struct SubscriberInfo {
+    pub(crate) id: u64,
+    pub(crate) channels: usize,
+    pub(crate) patterns: usize,
+    pub(crate) lag_events: u64,
+    pub(crate) dropped: u64,
 }
 
 impl DbDropGuard {
     /// Create a new `DbDropGuard` wrapping a `Db` instance.
-    pub(crate) fn new() -> DbDropGuard {
-        DbDropGuard { db: Db::new() }
+    pub(crate) fn new(config: &Config) -> DbDropGuard {
+        DbDropGuard {
+            db: Db::new(config),
+        }
     }
 
     /// Get the shared database.
@@ -95,72 +367,184 @@ impl Drop for DbDropGuard {
 impl Db {
     /// Create a new, empty, `Db` instance. Allocate shared state and spawn
     /// a background task to manage key expiration.
-    pub(crate) fn new() -> Db {
+    pub(crate) fn new(config: &Config) -> Db {
+        // A cluster node has a single database
+        let num_databases = match config.cluster {
+            Some(_) => 1,
+            None => config.databases.max(1),
+        };
+
+        let shards = (0..config.shards.max(1)).map(|_| {
+            Mutex::new(Shard {
+                databases: (0..num_databases).map(|_| Keyspace::default()).collect(),
+                watched_keys: HashMap::new(),
+                accounted_memory: 0,
+            })
+        });
+
         let shared = Arc::new(Shared {
+            shards: shards.collect(),
             state: Mutex::new(State {
-                entries: HashMap::new(),
-                pub_sub: HashMap::new(),
-                expirations: BTreeSet::new(),
+                last_save: SystemTime::now(),
+                bgsave_in_progress: false,
+                aof: None,
+                replication: Replication::new(config.repl_backlog_size),
+                cluster: config.cluster.as_ref().map(ClusterState::new),
+                migrations: 0,
+                evicted_keys: 0,
                 shutdown: false,
             }),
+            channels: Mutex::new(Channels {
+                pub_sub: HashMap::new(),
+                pattern_pub_sub: HashMap::new(),
+                subscribers: BTreeMap::new(),
+                next_subscriber_id: 1,
+            }),
+            num_databases,
+            cluster_enabled: config.cluster.is_some(),
+            dirty: AtomicU64::new(0),
+            used_memory: AtomicUsize::new(0),
+            config: LiveConfig::new(config.clone()),
+            propagating: AtomicBool::new(false),
+            writes_paused: AtomicBool::new(false),
             background_task: Notify::new(),
+            pub_sub_drained: Notify::new(),
+            replica_acked: Notify::new(),
+            writes_resumed: Notify::new(),
+            shutdown_requested: Notify::new(),
+            shutdown_save: Mutex::new(None),
+            pub_sub_config: config.pub_sub,
+            scripts: Scripts::new(config.lua_time_limit),
+            acl: Acl::new(config),
+            rdb_path: config.rdb_path(),
+            aof: config.aof.clone(),
+            aof_path: config.aof_path(),
+            read_only: AtomicBool::new(false),
         });
 
         // Start background task.
         tokio::spawn(purge_expired_tasks(shared.clone()));
 
-        Db { shared }
-    }
+        let db = Db { shared, index: 0 };
+
+        // Save rules may be set later on with `CONFIG SET save`
+        tokio::spawn(apply_save_rules(db.clone()));
+
+        if config.aof.enabled && config.aof.fsync == AppendFsync::EverySec {
+            tokio::spawn(sync_append_only_file(db.shared.clone()));
+        }
 
-    /// Get value associated with key.
-    pub(crate) fn get(&self, key: &str) -> Option<Bytes> {
-        let state = self.shared.state.lock().unwrap();
-        state.entries.get(key).map(|entry| entry.data.clone())
+        db
     }
 
-    /// Set value associated with key and optional expiration duration.
+    /// Returns a handle operating on the database at `index`.
     ///
-    /// If a value is already associated with the key, it is removed.
-    pub(crate) fn set(&self, key: String, data: Bytes, expire: Option<Duration>) {
-        let mut state = self.shared.state.lock().unwrap();
-        let mut notify_background_task = false;
-
-        let expires_at = expire.map(|duration| {
-            let expires_at = Instant::now() + duration;
+    /// Returns `None` if there is no such database.
+    pub(crate) fn select(&self, index: usize) -> Option<Db> {
+        if index >= self.num_databases() {
+            return None;
+        }
 
-            // only notify background task if the newly inserted expiration is the
-            // next key to evict
-            notify_background_task = state
-                .next_expiration()
-                .map(|expiration| expiration > expires_at)
-                .unwrap_or(true);
+        Some(Db {
+            shared: self.shared.clone(),
+            index,
+        })
+    }
 
-            expires_at
-        });
+    /// Number of databases.
+    pub(crate) fn num_databases(&self) -> usize {
+        self.shared.num_databases
+    }
 
-        let prev = state
-            .entries
-            .insert(key.clone(), Entry { data, expires_at });
+    /// Returns `true` if the server runs in cluster mode.
+    pub(crate) fn is_cluster(&self) -> bool {
+        self.shared.cluster_enabled
+    }
 
-        // If there is a value previously associated with the key **and** it has an expiration time,
-        // the associated entry in `expirations` must be removed for avoiding leaking data.
-        if let Some(prev) = prev {
-            if let Some(prev_expires_at) = prev.expires_at {
-                // clear expiration
-                state.expirations.remove(&(prev_expires_at, key.clone()));
-            }
+    /// In cluster mode, check that `keys` hash to the same slot, and that this node serves
+    /// it, returning the slot if there is any key. `pinned` is the slot of the keys of the
+    /// previous commands of a transaction, if any.
+    ///
+    /// Otherwise, returns the error to reply, redirecting the client to another node.
+    pub(crate) async fn check_slot(
+        &self,
+        keys: &[&str],
+        pinned: Option<u16>,
+        asking: bool,
+    ) -> Result<Option<u16>, Frame> {
+        // Not locking the state lets `SCRIPT KILL` through while a script runs
+        if keys.is_empty() || !self.shared.cluster_enabled {
+            return Ok(None);
         }
 
-        // Track new entry expiration
-        if let Some(expires_at) = expires_at {
-            state.expirations.insert((expires_at, key));
+        let guard = self.lock_keys_unless_busy(keys).await?;
+
+        match &guard.state().cluster {
+            Some(cluster) => cluster.check(keys, pinned, asking, |key| {
+                guard.keyspace(key).entries.contains_key(key)
+            }),
+            None => Ok(None),
         }
+    }
 
-        // Release mutex before notifying background task
-        drop(state);
+    /// Lock the server state and all shards, operating on the selected database.
+    ///
+    /// Nothing else accesses the state until the returned guard is dropped, which lets
+    /// a transaction apply several commands atomically. The guard must not be held across
+    /// an `.await`.
+    pub(crate) fn lock(&self) -> DbGuard<'_> {
+        self.shared
+            .lock_shards(0..self.shared.shards.len(), self.index)
+    }
 
-        if notify_background_task {
-            self.shared.background_task.notify_one();
+    /// Lock the server state and all shards for a client command, as `lock` does, once the
+    /// scripts ended.
+    ///
+    /// Returns the `BUSY` error to reply if a script runs past the time limit having written.
+    pub(crate) async fn lock_unless_busy(&self) -> Result<DbGuard<'_>, Frame> {
+        self.lock_shards_unless_busy((0..self.shared.shards.len()).collect())
+            .await
+    }
+
+    /// Lock the shards of `keys` only for a client command, once the scripts ended, operating
+    /// on the selected database. The server state is locked on first access.
+    ///
+    /// Commands on keys of other shards run meanwhile. The returned guard must only access
+    /// `keys`, and must not be held across an `.await`.
+    ///
+    /// Returns the `BUSY` error to reply if a script runs past the time limit having written.
+    pub(crate) async fn lock_keys_unless_busy<K: AsRef<str>>(
+        &self,
+        keys: &[K],
+    ) -> Result<DbGuard<'_>, Frame> {
+        let mut shards: Vec<_> = keys
+            .iter()
+            .map(|key| self.shared.shard_index(key.as_ref()))
+            .collect();
+        shards.sort_unstable();
+        shards.dedup();
+
+        self.lock_shards_unless_busy(shards).await
+    }
+
+    /// Wait for the scripts to end, then lock `shards` unless another script got queued
+    /// meanwhile, which would hold them for as long as it runs.
+    async fn lock_shards_unless_busy(&self, shards: Vec<usize>) -> Result<DbGuard<'_>, Frame> {
+        loop {
+            self.shared
+                .scripts
+                .wait_until_done()
+                .await
+                .map_err(Frame::Error)?;
+
+            let guard = self
+                .shared
+                .scripts
+                .unless_queued(|| self.shared.lock_shards(shards.iter().copied(), self.index));
+
+            if let Some(guard) = guard {
+                return Ok(guard);
+            }
         }
     }
 
@@ -170,106 +554,1672 @@ impl Db {
     pub(crate) fn subscribe(&self, key: String) -> broadcast::Receiver<Bytes> {
         use std::collections::hash_map::Entry;
 
-        let mut state = self.shared.state.lock().unwrap();
+        let mut channels = self.shared.channels.lock().unwrap();
 
         // If there is no entry for the requested channel, then create a new broadcast
         // channel and associate it with the key.
-        match state.pub_sub.entry(key) {
+        match channels.pub_sub.entry(key) {
             Entry::Occupied(entry) => entry.get().subscribe(),
             Entry::Vacant(entry) => {
-                // channel is created with a capacity of `1024` messages
-                let (tx, rx) = broadcast::channel(1024);
+                // channel is created with the configured capacity
+                let (tx, rx) = broadcast::channel(self.shared.pub_sub_config.capacity);
                 entry.insert(tx);
                 rx
             }
         }
     }
 
-    /// Publish a message to the channel. Returns the number of subscribers listening to that channel.
-    pub(crate) fn publish(&self, key: &str, value: Bytes) -> usize {
-        let state = self.shared.state.lock().unwrap();
+    /// Returns a `Receiver` for the requested channel pattern.
+    ///
+    /// The returned `Receiver` is used to receive values broadcast by `PUBLISH` commands
+    /// on any channel matching the pattern, along with the channel name.
+    pub(crate) fn psubscribe(&self, pattern: String) -> broadcast::Receiver<(String, Bytes)> {
+        use std::collections::hash_map::Entry;
 
-        state
-            .pub_sub
-            .get(key)
-            // On a successful message sent to the broadcast channel, the number
-            // of subscribers is returned
-            .map(|tx| tx.send(value).unwrap_or(0))
-            // If there's no entry for that key, then there's no subscriber
-            .unwrap_or(0)
+        let mut channels = self.shared.channels.lock().unwrap();
+
+        match channels.pattern_pub_sub.entry(pattern) {
+            Entry::Occupied(entry) => entry.get().subscribe(),
+            Entry::Vacant(entry) => {
+                let (tx, rx) = broadcast::channel(self.shared.pub_sub_config.capacity);
+                entry.insert(tx);
+                rx
+            }
+        }
     }
 
-    /// Signals the purge background task to shut down.
-    fn shutdown_purge_task(&self) {
-        let mut state = self.shared.state.lock().unwrap();
-        state.shutdown = true;
+    /// Publish a message to the channel. Returns the number of subscribers listening to that channel,
+    /// directly or through a matching pattern.
+    ///
+    /// With `LagPolicy::Backpressure`, waits until the slowest subscriber of the channel
+    /// has room for the message.
+    pub(crate) async fn publish(&self, key: &str, value: Bytes) -> usize {
+        let config = self.shared.pub_sub_config;
 
-        // drop lock before signaling the background task
-        drop(state);
-        self.shared.background_task.notify_one();
+        loop {
+            // Register interest in subscribers progress before checking the channel,
+            // so that a notification sent in between isn't missed.
+            let drained = self.shared.pub_sub_drained.notified();
+            tokio::pin!(drained);
+            drained.as_mut().enable();
+
+            {
+                let channels = self.shared.channels.lock().unwrap();
+
+                if config.lag_policy != LagPolicy::Backpressure
+                    || !channels.is_pub_sub_full(key, config.capacity)
+                {
+                    return channels.publish(key, value);
+                }
+            }
+
+            drained.await;
+        }
     }
-}
 
-impl Shared {
-    /// Purge expired keys and return the `Instant` at which the next key will expire.
-    fn purge_expired_keys(&self) -> Option<Instant> {
-        let mut state = self.state.lock().unwrap();
+    /// Returns the Lua scripts cache.
+    pub(crate) fn scripts(&self) -> &Scripts {
+        &self.shared.scripts
+    }
 
-        if state.shutdown {
-            return None;
+    /// Returns the configuration of the server.
+    pub(crate) fn config(&self) -> &LiveConfig {
+        &self.shared.config
+    }
+
+    /// Returns the users and their permissions.
+    pub(crate) fn acl(&self) -> &Acl {
+        &self.shared.acl
+    }
+
+    /// Ask the server to shut down. `save` overrides whether the databases are saved on the
+    /// way out, which otherwise depends on the save rules.
+    pub(crate) fn request_shutdown(&self, save: Option<bool>) {
+        *self.shared.shutdown_save.lock().unwrap() = save;
+        self.shared.shutdown_requested.notify_one();
+    }
+
+    /// Completes once `SHUTDOWN` is received, returning whether it overrides saving the
+    /// databases.
+    pub(crate) async fn shutdown_requested(&self) -> Option<bool> {
+        self.shared.shutdown_requested.notified().await;
+        *self.shared.shutdown_save.lock().unwrap()
+    }
+
+    /// Check `cmd` against the permissions of the user `session` is authenticated as, see
+    /// `Acl::check`. Returns the error to reply when denied.
+    pub(crate) fn check_permissions(
+        &self,
+        session: &Session,
+        cmd: &Command,
+        in_transaction: bool,
+    ) -> Result<(), Frame> {
+        let log_max_len = self.config().read(|config| config.acllog_max_len);
+
+        let context = if in_transaction { "multi" } else { "toplevel" };
+
+        self.shared.acl.check(session, cmd, context, log_max_len)
+    }
+
+    /// Restore the databases saved by a previous run, then start logging writes to the
+    /// append-only file if enabled.
+    ///
+    /// The databases are replayed from the append-only file when enabled, as it holds the
+    /// latest writes. Otherwise, or when it doesn't exist yet, they're loaded from the
+    /// snapshot file, and the append-only file is created from them.
+    ///
+    /// With a recovery point configured, only the writes logged up to it are replayed.
+    pub(crate) fn load(&self) -> crate::FnResult<()> {
+        let config = &self.shared.aof;
+
+        if !config.enabled {
+            return self.load_snapshot();
         }
 
-        // This is needed to make the borrow checker happy. In short, `lock()`
-        // returns a `MutexGuard` and not a `&mut State`. The borrow checker is
-        // not able to see "through" the mutex guard and determine that it is
-        // safe to access both `state.expirations` and `state.entries` mutably,
-        // so we get a "real" mutable reference to `State` outside of the loop.
-        let state = &mut *state;
+        let path = &self.shared.aof_path;
 
-        let now = Instant::now();
+        match aof::load(path, config.recover_to)? {
+            Some((commands, skipped)) => {
+                self.replay(commands)?;
+                info!(path = %path.display(), "append-only file loaded");
 
-        while let Some(&(when, ref key)) = state.expirations.iter().next() {
-            if when > now {
-                return Some(when);
+                match skipped {
+                    Some(skipped) => info!("point-in-time recovery: {}", skipped),
+                    None if config.recover_to.is_some() => {
+                        info!("point-in-time recovery: no write logged after the recovery point")
+                    }
+                    None => {}
+                }
+            }
+            None if config.recover_to.is_some() => {
+                return Err(format!("no append-only file to recover at {}", path.display()).into())
             }
+            None => {
+                self.load_snapshot()?;
+                aof::create(path, &self.lock().snapshot(), config.timestamps)?;
+            }
+        }
 
-            // the key has expired, remove it
-            state.entries.remove(key);
-            state.expirations.remove(&(when, key.clone()));
+        let aof = Aof::open(path, config)?;
+
+        let mut state = self.shared.state.lock().unwrap();
+        state.aof = Some(aof);
+        self.shared.update_flags(&state);
+
+        Ok(())
+    }
+
+    /// Apply the commands read from the append-only file.
+    fn replay(&self, commands: Vec<Frame>) -> crate::FnResult<()> {
+        let mut guard = self.lock();
+        guard.apply_writes(commands)?;
+
+        // The databases now match the file
+        self.shared.dirty.store(0, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Load the snapshot file, if it exists, into the databases.
+    fn load_snapshot(&self) -> crate::FnResult<()> {
+        let path = &self.shared.rdb_path;
+
+        if let Some(snapshot) = rdb::load(path, self.num_databases())? {
+            self.lock().restore(snapshot)?;
+            info!(path = %path.display(), "snapshot loaded");
         }
 
-        None
+        Ok(())
     }
 
-    fn is_shutdown(&self) -> bool {
-        self.state.lock().unwrap().shutdown
+    /// Save a snapshot of all databases, returning once it's written.
+    pub(crate) async fn save(&self) -> crate::FnResult<()> {
+        let (snapshot, dirty) = {
+            let guard = self.lock();
+
+            if guard.state().bgsave_in_progress {
+                return Err("Background save already in progress".into());
+            }
+
+            (guard.snapshot(), self.shared.dirty.load(Ordering::Relaxed))
+        };
+
+        let path = self.shared.rdb_path.clone();
+        tokio::task::spawn_blocking(move || rdb::save(&path, &snapshot)).await??;
+
+        self.shared
+            .saved(&mut self.shared.state.lock().unwrap(), dirty);
+
+        Ok(())
     }
-}
 
-impl State {
-    fn next_expiration(&self) -> Option<Instant> {
-        self.expirations
-            .iter()
-            .next()
-            .map(|expiration| expiration.0)
+    /// Start saving a snapshot of all databases in the background.
+    ///
+    /// The databases are copied while locked, then written without blocking other
+    /// connections. Returns `false` if a background save is already running.
+    pub(crate) fn bgsave(&self) -> bool {
+        let (snapshot, dirty) = {
+            let mut guard = self.lock();
+
+            if guard.state().bgsave_in_progress {
+                return false;
+            }
+
+            guard.state_mut().bgsave_in_progress = true;
+            (guard.snapshot(), self.shared.dirty.load(Ordering::Relaxed))
+        };
+
+        let shared = self.shared.clone();
+
+        tokio::spawn(async move {
+            let path = shared.rdb_path.clone();
+            let result = tokio::task::spawn_blocking(move || rdb::save(&path, &snapshot)).await;
+
+            let mut state = shared.state.lock().unwrap();
+            state.bgsave_in_progress = false;
+
+            match result {
+                Ok(Ok(())) => {
+                    shared.saved(&mut state, dirty);
+                    info!("background saving terminated with success");
+                }
+                Ok(Err(err)) => error!(cause = %err, "background saving failed"),
+                Err(err) => error!(cause = %err, "background saving failed"),
+            }
+        });
+
+        true
     }
-}
 
-/// Once notified, purge any expired key from the state handle.
-///
-/// If shutdown is set, terminates the task
-async fn purge_expired_tasks(shared: Arc<Shared>) {
-    while !shared.is_shutdown() {
-        if let Some(when) = shared.purge_expired_keys() {
-            // wait until the next key expires, or until background task is notified.
-            tokio::select! {
-                _=time::sleep_until(when) => {},
-                _=shared.background_task.notified() => {},
+    /// Start rewriting the append-only file in the background, as the shortest sequence
+    /// of commands recreating the databases.
+    ///
+    /// The databases are copied while locked, then written without blocking other
+    /// connections. Writes applied meanwhile are still logged to the current file, and
+    /// appended to the new one before it replaces the current one.
+    pub(crate) fn bgrewriteaof(&self) -> Result<(), &'static str> {
+        let (snapshot, path) = {
+            let mut guard = self.lock();
+            let snapshot = guard.snapshot();
+
+            let aof = match guard.state_mut().aof.as_mut() {
+                Some(aof) => aof,
+                None => return Err("ERR Append only file is disabled"),
+            };
+
+            if aof.is_rewriting() {
+                return Err("ERR Background append only file rewriting already in progress");
             }
-        } else {
-            shared.background_task.notified().await;
+
+            aof.start_rewrite();
+            (snapshot, aof.path().to_path_buf())
+        };
+
+        let shared = self.shared.clone();
+        let timestamps = shared.aof.timestamps;
+
+        tokio::spawn(async move {
+            let result =
+                tokio::task::spawn_blocking(move || aof::rewrite(&path, &snapshot, timestamps))
+                    .await;
+
+            let mut state = shared.state.lock().unwrap();
+            let aof = match state.aof.as_mut() {
+                Some(aof) => aof,
+                None => return,
+            };
+
+            let result = match result {
+                Ok(Ok(temp_path)) => aof.finish_rewrite(&temp_path).map_err(Into::into),
+                Ok(Err(err)) => Err(crate::GenericError::from(err)),
+                Err(err) => Err(err.into()),
+            };
+
+            match result {
+                Ok(()) => info!("background append only file rewriting terminated with success"),
+                Err(err) => {
+                    aof.abort_rewrite();
+                    error!(cause = %err, "background append only file rewriting failed");
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Returns the pub/sub configuration.
+    pub(crate) fn pub_sub_config(&self) -> PubSubConfig {
+        self.shared.pub_sub_config
+    }
+
+    /// Signals publishers waiting on backpressure that a subscriber consumed messages.
+    pub(crate) fn notify_pub_sub_drained(&self) {
+        if self.shared.pub_sub_config.lag_policy == LagPolicy::Backpressure {
+            self.shared.pub_sub_drained.notify_waiters();
         }
     }
 
-    debug!("Purge background task shut down");
+    /// Returns a watcher, with no key watched yet.
+    pub(crate) fn watcher(&self) -> Watcher {
+        Watcher {
+            dirty: Arc::new(AtomicBool::new(false)),
+            keys: vec![],
+            db: self.clone(),
+        }
+    }
+
+    /// Watch `keys` of the selected database, until the watcher is dropped.
+    ///
+    /// Returns the `BUSY` error to reply if a script runs past the time limit having written.
+    pub(crate) async fn watch(&self, watcher: &mut Watcher, keys: &[String]) -> Result<(), Frame> {
+        let mut guard = self.lock_keys_unless_busy(keys).await?;
+
+        for key in keys {
+            let watched = (self.index, key.clone());
+
+            if watcher.keys.contains(&watched) {
+                continue;
+            }
+
+            guard
+                .shard_mut(key)
+                .watched_keys
+                .entry(watched.clone())
+                .or_default()
+                .push(watcher.dirty.clone());
+
+            watcher.keys.push(watched);
+        }
+
+        Ok(())
+    }
+
+    /// Register a connection entering subscribed mode, to track its lag statistics.
+    pub(crate) fn register_subscriber(&self) -> SubscriberGuard {
+        let mut channels = self.shared.channels.lock().unwrap();
+
+        let id = channels.next_subscriber_id;
+        channels.next_subscriber_id += 1;
+
+        let stats = Arc::new(SubscriberStats::default());
+        channels.subscribers.insert(id, stats.clone());
+
+        SubscriberGuard {
+            id,
+            stats,
+            db: self.clone(),
+        }
+    }
+
+    /// Wait until `num_replicas` replicas acknowledged all the writes applied so far, or
+    /// `timeout` elapsed if set, returning the number of replicas which did.
+    pub(crate) async fn wait_for_replicas(
+        &self,
+        num_replicas: usize,
+        timeout: Option<Duration>,
+    ) -> Result<usize, &'static str> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        let offset = {
+            let mut guard = self.lock_unless_busy().await.map_err(|_| BUSY_ERROR)?;
+            let replication = guard.replication();
+
+            if replication.is_replica() {
+                return Err("ERR WAIT cannot be used with replica instances.");
+            }
+
+            let offset = replication.offset();
+
+            if replication.num_acked(offset) < num_replicas {
+                replication.request_ack();
+            }
+
+            offset
+        };
+
+        loop {
+            // Register interest in acknowledgements before counting them, so that one
+            // received in between isn't missed.
+            let acked = self.replica_acked();
+            tokio::pin!(acked);
+            acked.as_mut().enable();
+
+            let num_acked = self
+                .lock_unless_busy()
+                .await
+                .map_err(|_| BUSY_ERROR)?
+                .replication()
+                .num_acked(offset);
+
+            if num_acked >= num_replicas {
+                return Ok(num_acked);
+            }
+
+            match deadline {
+                Some(deadline) => tokio::select! {
+                    _ = acked => {}
+                    _ = time::sleep_until(deadline) => return Ok(num_acked),
+                },
+                None => acked.await,
+            }
+        }
+    }
+
+    /// Future completing once a replica acknowledged its offset.
+    pub(crate) fn replica_acked(&self) -> Notified<'_> {
+        self.shared.replica_acked.notified()
+    }
+
+    /// Signals the connections waiting with `WAIT` that a replica acknowledged its offset.
+    pub(crate) fn notify_replica_acked(&self) {
+        self.shared.replica_acked.notify_waiters();
+    }
+
+    /// Wait until no failover or migration is in progress, as writes are paused meanwhile.
+    pub(crate) async fn wait_until_writes_resume(&self) {
+        loop {
+            // Register interest before checking, so that the end of the failover in
+            // between isn't missed.
+            let resumed = self.shared.writes_resumed.notified();
+            tokio::pin!(resumed);
+            resumed.as_mut().enable();
+
+            if !self.shared.writes_paused.load(Ordering::Relaxed) {
+                return;
+            }
+
+            resumed.await;
+        }
+    }
+
+    /// Signals the connections waiting for a failover or a migration to end that writes
+    /// resumed.
+    pub(crate) fn notify_writes_resumed(&self) {
+        self.shared.writes_resumed.notify_waiters();
+    }
+
+    /// End a migration started with `DbGuard::pause_writes`, resuming the writes once no
+    /// other is in progress.
+    pub(crate) fn resume_writes(&self) {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            state.migrations -= 1;
+            self.shared.update_flags(&state);
+        }
+
+        self.notify_writes_resumed();
+    }
+
+    /// Returns `true` if the server replicates a leader, rejecting writes from clients.
+    pub(crate) fn is_read_only(&self) -> bool {
+        self.shared.read_only.load(Ordering::Relaxed)
+    }
+
+    /// Set whether the server rejects writes from clients.
+    pub(crate) fn set_read_only(&self, read_only: bool) {
+        self.shared.read_only.store(read_only, Ordering::Relaxed);
+    }
+
+    /// Evict keys while the memory used exceeds `maxmemory`, following the eviction policy.
+    ///
+    /// Returns `false` if there weren't enough keys to evict to go under the limit, or the
+    /// `BUSY` error to reply if a script runs past the time limit having written.
+    pub(crate) async fn free_memory(&self) -> Result<bool, Frame> {
+        // Replicas leave eviction to their leader, which streams the evicted keys
+        if self.is_read_only() {
+            return Ok(true);
+        }
+
+        // Under the limit, writes go through without locking all shards
+        let limit = self.shared.config.read(|config| config.maxmemory.limit);
+        if limit == 0 || self.shared.used_memory.load(Ordering::Relaxed) <= limit {
+            return Ok(true);
+        }
+
+        Ok(self.lock_unless_busy().await?.evict())
+    }
+
+    /// Signals the purge background task to shut down.
+    fn shutdown_purge_task(&self) {
+        replication::stop(self);
+
+        let mut state = self.shared.state.lock().unwrap();
+        state.shutdown = true;
+
+        // drop lock before signaling the background task
+        drop(state);
+        self.shared.background_task.notify_one();
+    }
+}
+
+impl<'a> DbGuard<'a> {
+    /// Index of the selected database.
+    pub(crate) fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Number of databases.
+    pub(crate) fn num_databases(&self) -> usize {
+        self.shared.num_databases
+    }
+
+    /// Returns the Lua scripts cache.
+    pub(crate) fn scripts(&self) -> &Scripts {
+        &self.shared.scripts
+    }
+
+    /// Returns the configuration of the server.
+    pub(crate) fn config(&self) -> &LiveConfig {
+        &self.shared.config
+    }
+
+    /// Returns the users and their permissions.
+    pub(crate) fn acl(&self) -> &Acl {
+        &self.shared.acl
+    }
+
+    /// Returns the wall-clock time of the last successful snapshot.
+    pub(crate) fn last_save(&self) -> SystemTime {
+        self.state().last_save
+    }
+
+    /// Returns `true` if the server replicates a leader, rejecting writes from clients.
+    pub(crate) fn is_read_only(&self) -> bool {
+        self.shared.read_only.load(Ordering::Relaxed)
+    }
+
+    /// Returns the replication state.
+    pub(crate) fn replication(&mut self) -> &mut Replication {
+        &mut self.state_mut().replication
+    }
+
+    /// Returns the cluster state, in cluster mode.
+    pub(crate) fn cluster(&mut self) -> Option<&mut ClusterState> {
+        self.state_mut().cluster.as_mut()
+    }
+
+    /// Returns the number of keys of the selected database hashing to `slot`.
+    pub(crate) fn count_keys_in_slot(&self, slot: u16) -> usize {
+        self.shards()
+            .flat_map(|shard| shard.databases[self.index].entries.keys())
+            .filter(|key| cluster::key_slot(key.as_bytes()) == slot)
+            .count()
+    }
+
+    /// Returns up to `count` keys of the selected database hashing to `slot`.
+    pub(crate) fn keys_in_slot(&self, slot: u16, count: usize) -> Vec<String> {
+        self.shards()
+            .flat_map(|shard| shard.databases[self.index].entries.keys())
+            .filter(|key| cluster::key_slot(key.as_bytes()) == slot)
+            .take(count)
+            .map(|key| key.to_string())
+            .collect()
+    }
+
+    /// Apply write commands, read from the append-only file or streamed by the leader,
+    /// starting on the selected database.
+    ///
+    /// `MULTI` and `EXEC` are skipped, all commands are applied under the same lock anyway.
+    pub(crate) fn apply_writes(&mut self, commands: Vec<Frame>) -> crate::FnResult<()> {
+        for frame in commands {
+            let command = Command::from_frame(frame)?;
+
+            match command {
+                Command::Multi(_) | Command::Exec(_) => continue,
+                Command::Select(_) => {}
+                _ if command.is_write() => {}
+                _ => return Err(format!("unexpected `{}` command", command.get_name()).into()),
+            }
+
+            if let Frame::Error(err) = command.execute(self) {
+                return Err(format!("invalid command: {}", err).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Copy all databases into a snapshot, leaving out the keys which already expired.
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        // Expirations are saved as wall-clock times
+        let now = Instant::now();
+        let wall_clock_now = SystemTime::now();
+
+        let databases = (0..self.num_databases())
+            .map(|index| {
+                self.shards()
+                    .flat_map(|shard| shard.databases[index].entries.iter())
+                    .filter(|(_, entry)| entry.expires_at.is_none_or(|when| when > now))
+                    .map(|(key, entry)| Record {
+                        key: key.to_string(),
+                        value: entry.data.clone(),
+                        expires_at: entry.expires_at.map(|when| wall_clock_now + (when - now)),
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Snapshot { databases }
+    }
+
+    /// Replace the content of all databases with the snapshot.
+    ///
+    /// Keys which expired since the snapshot was taken are left out.
+    pub(crate) fn restore(&mut self, snapshot: Snapshot) -> crate::FnResult<()> {
+        if snapshot.databases.len() > self.num_databases() {
+            return Err(format!(
+                "snapshot has {} databases, only {} are configured",
+                snapshot.databases.len(),
+                self.num_databases()
+            )
+            .into());
+        }
+
+        let selected = self.index;
+        self.flush_all();
+
+        for (index, records) in snapshot.databases.into_iter().enumerate() {
+            self.index = index;
+
+            for record in records {
+                let expire = match record.expires_at {
+                    Some(when) => match when.duration_since(SystemTime::now()) {
+                        Ok(expire) => Some(expire),
+                        Err(_) => continue,
+                    },
+                    None => None,
+                };
+
+                self.set(record.key, record.value, expire);
+            }
+        }
+
+        self.index = selected;
+
+        // The databases now match the snapshot
+        self.shared.dirty.store(0, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Select the database at `index` for the remaining operations.
+    ///
+    /// Returns `false` if there is no such database.
+    pub(crate) fn select(&mut self, index: usize) -> bool {
+        if index >= self.num_databases() {
+            return false;
+        }
+
+        self.index = index;
+        true
+    }
+
+    /// Returns the time to live of a key, `None` if it doesn't exist or doesn't expire.
+    pub(crate) fn ttl(&self, key: &str) -> Option<Duration> {
+        let entry = self.keyspace(key).entries.get(key)?;
+        entry
+            .expires_at
+            .map(|expires_at| expires_at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Pause the writes of the connections, while keys are migrated with `MIGRATE`, until
+    /// `Db::resume_writes` is called.
+    pub(crate) fn pause_writes(&mut self) {
+        self.state_mut().migrations += 1;
+    }
+
+    /// Returns the approximate memory used by the keys and values of all databases,
+    /// including the changes to the shards of the guard.
+    pub(crate) fn used_memory(&self) -> usize {
+        let accounted = self.shared.used_memory.load(Ordering::Relaxed);
+
+        self.shards.iter().flatten().fold(accounted, |used, shard| {
+            used + shard.used_memory() - shard.accounted_memory
+        })
+    }
+
+    /// Returns the memory limit of the databases and the eviction policy.
+    pub(crate) fn maxmemory(&self) -> MaxMemoryConfig {
+        self.shared.config.read(|config| config.maxmemory)
+    }
+
+    /// Returns the number of keys evicted to stay under the memory limit.
+    pub(crate) fn evicted_keys(&self) -> u64 {
+        self.state().evicted_keys
+    }
+
+    /// Get value associated with key, recording the access for the eviction policies.
+    pub(crate) fn get(&mut self, key: &str) -> Option<Bytes> {
+        let entry = self.keyspace_mut(key).entries.get_mut(key)?;
+        entry.touch();
+
+        Some(entry.data.clone())
+    }
+
+    /// Returns how long ago `key` was last accessed, without counting this as an access.
+    pub(crate) fn idle_time(&self, key: &str) -> Option<Duration> {
+        let entry = self.keyspace(key).entries.get(key)?;
+
+        Some(entry.accessed_at.elapsed())
+    }
+
+    /// Set when `key` was last accessed, as restored with its value. Returns `false` if the
+    /// key doesn't exist.
+    pub(crate) fn set_accessed_at(&mut self, key: &str, accessed_at: Instant) -> bool {
+        match self.keyspace_mut(key).entries.get_mut(key) {
+            Some(entry) => {
+                entry.accessed_at = accessed_at;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set value associated with key and optional expiration duration.
+    ///
+    /// If a value is already associated with the key, it is removed.
+    pub(crate) fn set(&mut self, key: String, data: Bytes, expire: Option<Duration>) {
+        let index = self.index;
+        let shard = self.shard_mut(&key);
+
+        let expires_at = expire.map(|duration| Instant::now() + duration);
+
+        // only notify background task if the newly inserted expiration is the
+        // next key of the shard to evict
+        let notify = expires_at.is_some_and(|expires_at| {
+            shard
+                .next_expiration()
+                .is_none_or(|expiration| expiration > expires_at)
+        });
+
+        let value = data.clone();
+        shard.databases[index].insert(key.clone(), Entry::new(data, expires_at));
+        shard.touch(index, &key);
+
+        self.notify_background_task |= notify;
+        self.shared.dirty.fetch_add(1, Ordering::Relaxed);
+        self.shared
+            .notify_keyspace_event(index, KeyspaceEvents::STRING, "set", &key);
+
+        // Expirations are logged as wall-clock times, to be replayed after a restart
+        let wall_clock_expires_at = expire.map(|duration| SystemTime::now() + duration);
+        self.propagate(Some(index), || {
+            aof::set_command(&key, value, wall_clock_expires_at)
+        });
+    }
+
+    /// Remove the given keys. Returns the number of keys that were removed.
+    pub(crate) fn del(&mut self, keys: &[String]) -> usize {
+        let index = self.index;
+        let mut removed = 0;
+
+        for key in keys {
+            let shard = self.shard_mut(key);
+
+            if shard.databases[index].remove(key).is_some() {
+                shard.touch(index, key);
+                self.shared.dirty.fetch_add(1, Ordering::Relaxed);
+                self.shared
+                    .notify_keyspace_event(index, KeyspaceEvents::GENERIC, "del", key);
+                self.propagate(Some(index), || {
+                    Del::new(std::slice::from_ref(key)).into_frame()
+                });
+                removed += 1;
+            }
+        }
+
+        removed
+    }
+
+    /// Move a key to the database at `index`, keeping its expiration.
+    ///
+    /// Returns `false` if the key doesn't exist in the selected database, or already
+    /// exists in the target one.
+    pub(crate) fn move_key(&mut self, key: &str, index: usize) -> bool {
+        let selected = self.index;
+
+        // The key belongs to the same shard in every database
+        let shard = self.shard_mut(key);
+
+        if index == selected || shard.databases[index].entries.contains_key(key) {
+            return false;
+        }
+
+        let entry = match shard.databases[selected].remove(key) {
+            Some(entry) => entry,
+            None => return false,
+        };
+
+        shard.databases[index].insert(key.to_string(), entry);
+        shard.touch(selected, key);
+        shard.touch(index, key);
+
+        self.shared.dirty.fetch_add(1, Ordering::Relaxed);
+        self.shared
+            .notify_keyspace_event(selected, KeyspaceEvents::GENERIC, "move_from", key);
+        self.shared
+            .notify_keyspace_event(index, KeyspaceEvents::GENERIC, "move_to", key);
+        self.propagate(Some(selected), || Move::new(key, index as u64).into_frame());
+
+        true
+    }
+
+    /// Swap the content of two databases.
+    ///
+    /// Connections having selected one of them immediately see the other one's keys.
+    pub(crate) fn swap_databases(&mut self, index1: usize, index2: usize) {
+        for shard in self.shards_mut() {
+            // Watched keys existing in either database see their value change
+            shard.touch_watched(|index, key, databases| {
+                (index == index1 || index == index2)
+                    && (databases[index1].entries.contains_key(key)
+                        || databases[index2].entries.contains_key(key))
+            });
+
+            // Expirations are swapped along with their keys, so the purge task
+            // has nothing to reschedule.
+            shard.databases.swap(index1, index2);
+        }
+
+        self.shared.dirty.fetch_add(1, Ordering::Relaxed);
+        self.propagate(None, || {
+            SwapDb::new(index1 as u64, index2 as u64).into_frame()
+        });
+    }
+
+    /// Remove all keys from the selected database.
+    pub(crate) fn flush(&mut self) {
+        let selected = self.index;
+        let mut removed = 0;
+
+        for shard in self.shards_mut() {
+            shard.touch_watched(|index, key, databases| {
+                index == selected && databases[index].entries.contains_key(key)
+            });
+
+            removed += std::mem::take(&mut shard.databases[selected]).entries.len();
+        }
+
+        self.shared
+            .dirty
+            .fetch_add(removed as u64, Ordering::Relaxed);
+        self.propagate(Some(selected), || FlushDb::new().into_frame());
+    }
+
+    /// Remove all keys from all databases.
+    pub(crate) fn flush_all(&mut self) {
+        let mut removed = 0;
+
+        for shard in self.shards_mut() {
+            shard.touch_watched(|index, key, databases| databases[index].entries.contains_key(key));
+
+            for keyspace in &mut shard.databases {
+                removed += std::mem::take(keyspace).entries.len();
+            }
+        }
+
+        self.shared
+            .dirty
+            .fetch_add(removed as u64, Ordering::Relaxed);
+        self.propagate(None, || FlushAll::new().into_frame());
+    }
+
+    /// Number of keys in the selected database.
+    pub(crate) fn size(&self) -> usize {
+        self.shards()
+            .map(|shard| shard.databases[self.index].entries.len())
+            .sum()
+    }
+
+    /// Returns `true` if a key watched by `watcher` was modified since it was watched,
+    /// or has expired.
+    pub(crate) fn is_dirty(&self, watcher: &Watcher) -> bool {
+        if watcher.dirty.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        // The purge task may not have removed keys which just expired
+        let now = Instant::now();
+
+        watcher.keys.iter().any(|(index, key)| {
+            self.shard(key).databases[*index]
+                .entries
+                .get(key.as_str())
+                .and_then(|entry| entry.expires_at)
+                .is_some_and(|expires_at| expires_at <= now)
+        })
+    }
+
+    /// Returns the names of the channels having at least one subscriber,
+    /// optionally restricted to the ones matching `pattern`.
+    pub(crate) fn channels(&self, pattern: Option<&str>) -> Vec<String> {
+        let mut channels: Vec<String> = self
+            .shared
+            .channels
+            .lock()
+            .unwrap()
+            .pub_sub
+            .iter()
+            .filter(|(_, tx)| tx.receiver_count() > 0)
+            .filter(|(channel, _)| {
+                pattern.is_none_or(|pattern| glob::matches(pattern.as_bytes(), channel.as_bytes()))
+            })
+            .map(|(channel, _)| channel.clone())
+            .collect();
+
+        channels.sort();
+        channels
+    }
+
+    /// Returns the number of patterns having at least one subscriber.
+    pub(crate) fn num_patterns(&self) -> usize {
+        self.shared
+            .channels
+            .lock()
+            .unwrap()
+            .pattern_pub_sub
+            .values()
+            .filter(|tx| tx.receiver_count() > 0)
+            .count()
+    }
+
+    /// Returns the number of subscribers listening to the channel.
+    pub(crate) fn num_subscribers(&self, key: &str) -> usize {
+        self.shared
+            .channels
+            .lock()
+            .unwrap()
+            .pub_sub
+            .get(key)
+            .map(|tx| tx.receiver_count())
+            .unwrap_or(0)
+    }
+
+    /// Returns the lag statistics of all registered subscribers, ordered by id.
+    pub(crate) fn subscribers(&self) -> Vec<SubscriberInfo> {
+        self.shared
+            .channels
+            .lock()
+            .unwrap()
+            .subscribers
+            .iter()
+            .map(|(&id, stats)| SubscriberInfo {
+                id,
+                channels: stats.channels.load(Ordering::Relaxed),
+                patterns: stats.patterns.load(Ordering::Relaxed),
+                lag_events: stats.lag_events.load(Ordering::Relaxed),
+                dropped: stats.dropped.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Publish a message to the channel, without waiting for slow subscribers.
+    /// Returns the number of subscribers listening to that channel, directly or through a matching pattern.
+    pub(crate) fn publish(&self, key: &str, value: Bytes) -> usize {
+        self.shared.channels.lock().unwrap().publish(key, value)
+    }
+
+    /// Evict keys while the memory used exceeds the limit, picking the best of sampled
+    /// keys each time. Returns `false` if no key could be evicted while still over it.
+    ///
+    /// All shards must be locked.
+    fn evict(&mut self) -> bool {
+        let MaxMemoryConfig {
+            limit,
+            policy,
+            samples,
+        } = self.maxmemory();
+
+        if limit == 0 {
+            return true;
+        }
+
+        while self.used_memory() > limit {
+            if policy == MaxMemoryPolicy::NoEviction {
+                return false;
+            }
+
+            let (index, key) = match self.eviction_candidate(policy, samples) {
+                Some(candidate) => candidate,
+                None => return false,
+            };
+
+            let shard = self.shard_mut(&key);
+            shard.databases[index].remove(&key);
+            shard.touch(index, &key);
+
+            self.state_mut().evicted_keys += 1;
+            self.shared.dirty.fetch_add(1, Ordering::Relaxed);
+            self.shared
+                .notify_keyspace_event(index, KeyspaceEvents::EVICTED, "evicted", &key);
+            self.propagate(Some(index), || {
+                Del::new(std::slice::from_ref(&key)).into_frame()
+            });
+        }
+
+        true
+    }
+
+    /// Returns the database index and the key to evict next: the best of `samples` keys
+    /// sampled from each database of each shard.
+    fn eviction_candidate(
+        &self,
+        policy: MaxMemoryPolicy,
+        samples: usize,
+    ) -> Option<(usize, String)> {
+        let now = Instant::now();
+        let mut candidate: Option<(u64, usize, &str)> = None;
+
+        for shard in self.shards() {
+            for (index, keyspace) in shard.databases.iter().enumerate() {
+                for key in keyspace.sample(samples, policy.is_volatile()) {
+                    let score = keyspace.entries[key].eviction_score(policy, now);
+
+                    if candidate.is_none_or(|(best, _, _)| score > best) {
+                        candidate = Some((score, index, key));
+                    }
+                }
+            }
+        }
+
+        candidate.map(|(_, index, key)| (index, key.to_string()))
+    }
+
+    /// Remove the keys of the locked shards expired at `now`.
+    fn expire(&mut self, now: Instant) {
+        for shard in 0..self.shards.len() {
+            for index in 0..self.num_databases() {
+                let expired = match &mut self.shards[shard] {
+                    Some(shard) => shard.databases[index].expire(now),
+                    None => break,
+                };
+
+                for key in expired {
+                    self.shard(&key).touch(index, &key);
+                    self.shared.dirty.fetch_add(1, Ordering::Relaxed);
+                    self.shared.notify_keyspace_event(
+                        index,
+                        KeyspaceEvents::EXPIRED,
+                        "expired",
+                        &key,
+                    );
+                    self.propagate(Some(index), || Del::new(&[key.to_string()]).into_frame());
+                }
+            }
+        }
+    }
+
+    /// Returns the `Instant` at which the next key of the locked shards will expire.
+    fn next_expiration(&self) -> Option<Instant> {
+        self.shards
+            .iter()
+            .flatten()
+            .filter_map(|shard| shard.next_expiration())
+            .min()
+    }
+
+    /// Log a write applied to the database at `index`, or to the whole server when `None`,
+    /// to the append-only file if enabled, and stream it to the replicas. `command` builds
+    /// the equivalent command.
+    ///
+    /// The server state is only locked when there is somewhere to propagate the write to.
+    fn propagate(&mut self, index: Option<usize>, command: impl FnOnce() -> Frame) {
+        if self.state.get().is_none() && !self.shared.propagating.load(Ordering::Relaxed) {
+            return;
+        }
+
+        self.state_mut().propagate(index, command);
+    }
+
+    /// Returns the server state, locking it on first access.
+    fn state(&self) -> &State {
+        self.state.get_or_init(|| self.shared.state.lock().unwrap())
+    }
+
+    /// Returns the server state mutably, locking it on first access.
+    fn state_mut(&mut self) -> &mut State {
+        self.state();
+        self.state.get_mut().expect("state is locked")
+    }
+
+    /// Returns the shard of `key`, which must be locked by the guard.
+    fn shard(&self, key: &str) -> &Shard {
+        self.shards[self.shared.shard_index(key)]
+            .as_ref()
+            .expect("shard of the key is locked")
+    }
+
+    /// Returns the shard of `key` mutably, which must be locked by the guard.
+    fn shard_mut(&mut self, key: &str) -> &mut MutexGuard<'a, Shard> {
+        self.shards[self.shared.shard_index(key)]
+            .as_mut()
+            .expect("shard of the key is locked")
+    }
+
+    /// Returns the keys of the selected database in the shard of `key`.
+    fn keyspace(&self, key: &str) -> &Keyspace {
+        &self.shard(key).databases[self.index]
+    }
+
+    /// Returns mutably the keys of the selected database in the shard of `key`.
+    fn keyspace_mut(&mut self, key: &str) -> &mut Keyspace {
+        let index = self.index;
+        &mut self.shard_mut(key).databases[index]
+    }
+
+    /// Returns all shards, which must be locked by the guard.
+    fn shards(&self) -> impl Iterator<Item = &Shard> + '_ {
+        self.shards
+            .iter()
+            .map(|shard| &**shard.as_ref().expect("all shards are locked"))
+    }
+
+    /// Returns all shards mutably, which must be locked by the guard.
+    fn shards_mut(&mut self) -> impl Iterator<Item = &mut MutexGuard<'a, Shard>> + '_ {
+        self.shards
+            .iter_mut()
+            .map(|shard| shard.as_mut().expect("all shards are locked"))
+    }
+}
+
+impl Drop for DbGuard<'_> {
+    fn drop(&mut self) {
+        // The memory used by the keys is accounted for before releasing their shards
+        for shard in self.shards.iter_mut().flatten() {
+            let used = shard.used_memory();
+
+            if used > shard.accounted_memory {
+                self.shared
+                    .used_memory
+                    .fetch_add(used - shard.accounted_memory, Ordering::Relaxed);
+            } else {
+                self.shared
+                    .used_memory
+                    .fetch_sub(shard.accounted_memory - used, Ordering::Relaxed);
+            }
+
+            shard.accounted_memory = used;
+        }
+
+        // The writes are logged and streamed before any other connection sees them
+        if let Some(state) = self.state.get_mut() {
+            state.flush_propagated();
+            self.shared.update_flags(state);
+        }
+
+        // The state is still locked here, the background task gets to run
+        // once the guard is fully released.
+        if self.notify_background_task {
+            self.shared.background_task.notify_one();
+        }
+    }
+}
+
+impl SubscriberGuard {
+    /// Update the number of channels and patterns the subscriber listens to.
+    pub(crate) fn set_subscriptions(&self, channels: usize, patterns: usize) {
+        self.stats.channels.store(channels, Ordering::Relaxed);
+        self.stats.patterns.store(patterns, Ordering::Relaxed);
+    }
+
+    /// Record that the subscriber missed `dropped` messages.
+    pub(crate) fn record_lag(&self, dropped: u64) {
+        self.stats.lag_events.fetch_add(1, Ordering::Relaxed);
+        self.stats.dropped.fetch_add(dropped, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        for watched in self.keys.drain(..) {
+            let mut shard = self.db.shared.shards[self.db.shared.shard_index(&watched.1)]
+                .lock()
+                .unwrap();
+
+            if let Some(watchers) = shard.watched_keys.get_mut(&watched) {
+                watchers.retain(|dirty| !Arc::ptr_eq(dirty, &self.dirty));
+
+                if watchers.is_empty() {
+                    shard.watched_keys.remove(&watched);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for SubscriberGuard {
+    fn drop(&mut self) {
+        let mut channels = self.db.shared.channels.lock().unwrap();
+        channels.subscribers.remove(&self.id);
+
+        // drop lock before waking up publishers, the subscriber receivers are gone
+        // and no longer hold messages back
+        drop(channels);
+        self.db.notify_pub_sub_drained();
+    }
+}
+
+impl Shared {
+    /// Lock the shards at the given indices, which must be in ascending order, operating
+    /// on the database at `index`.
+    fn lock_shards(&self, shards: impl IntoIterator<Item = usize>, index: usize) -> DbGuard<'_> {
+        let mut guard = DbGuard {
+            shared: self,
+            shards: (0..self.shards.len()).map(|_| None).collect(),
+            state: OnceCell::new(),
+            index,
+            notify_background_task: false,
+        };
+
+        for shard in shards {
+            guard.shards[shard] = Some(self.shards[shard].lock().unwrap());
+        }
+
+        guard
+    }
+
+    /// Returns the index of the shard holding `key`.
+    ///
+    /// Keys are assigned by hash slot, so that keys sharing a hash tag share a shard too.
+    fn shard_index(&self, key: &str) -> usize {
+        cluster::key_slot(key.as_bytes()) as usize % self.shards.len()
+    }
+
+    /// Purge expired keys and return the `Instant` at which the next key will expire.
+    ///
+    /// Shards are purged one at a time, the other ones remain available meanwhile.
+    fn purge_expired_keys(&self) -> Option<Instant> {
+        {
+            let state = self.state.lock().unwrap();
+
+            if state.shutdown {
+                return None;
+            }
+
+            // Writes are paused during a failover, the replica being promoted must catch up
+            // with all of them
+            if state.replication.is_failing_over() {
+                return Some(Instant::now() + Duration::from_millis(100));
+            }
+        }
+
+        let now = Instant::now();
+
+        (0..self.shards.len())
+            .filter_map(|shard| {
+                let mut guard = self.lock_shards([shard], 0);
+                guard.expire(now);
+                guard.next_expiration()
+            })
+            .min()
+    }
+
+    /// Publish a keyspace notification for `event` on `key` of database `index`,
+    /// if events of `class` are enabled.
+    ///
+    /// Keyspace channels receive the event name, keyevent channels receive the key.
+    fn notify_keyspace_event(&self, index: usize, class: KeyspaceEvents, event: &str, key: &str) {
+        let events = self.config.read(|config| config.notify_keyspace_events);
+
+        if !events.is_enabled(class) {
+            return;
+        }
+
+        let channels = self.channels.lock().unwrap();
+
+        if events.contains(KeyspaceEvents::KEYSPACE) {
+            let channel = format!("__keyspace@{}__:{}", index, key);
+            channels.publish(&channel, Bytes::from(event.to_string()));
+        }
+
+        if events.contains(KeyspaceEvents::KEYEVENT) {
+            let channel = format!("__keyevent@{}__:{}", index, event);
+            channels.publish(&channel, Bytes::from(key.to_string()));
+        }
+    }
+
+    /// Record a successful snapshot, which included the first `dirty` writes.
+    fn saved(&self, state: &mut State, dirty: u64) {
+        let _ = self
+            .dirty
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+                Some(current.saturating_sub(dirty))
+            });
+        state.last_save = SystemTime::now();
+    }
+
+    /// Update the flags mirroring the server state, read without locking it.
+    fn update_flags(&self, state: &State) {
+        let propagating = state.aof.is_some() || state.replication.is_streaming();
+        self.propagating.store(propagating, Ordering::Relaxed);
+
+        let writes_paused = state.migrations > 0 || state.replication.is_failing_over();
+        self.writes_paused.store(writes_paused, Ordering::Relaxed);
+    }
+
+    fn is_shutdown(&self) -> bool {
+        self.state.lock().unwrap().shutdown
+    }
+}
+
+impl Shard {
+    /// Returns the `Instant` at which the next key of the shard will expire, across all databases.
+    fn next_expiration(&self) -> Option<Instant> {
+        self.databases
+            .iter()
+            .filter_map(Keyspace::next_expiration)
+            .min()
+    }
+
+    /// Returns the approximate memory used by the keys and values of the shard.
+    fn used_memory(&self) -> usize {
+        self.databases
+            .iter()
+            .map(|keyspace| keyspace.used_memory)
+            .sum()
+    }
+
+    /// Flag the connections watching `key` of database `index` as dirty.
+    fn touch(&self, index: usize, key: &str) {
+        if let Some(watchers) = self.watched_keys.get(&(index, key.to_string())) {
+            for dirty in watchers {
+                dirty.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Flag the connections watching keys for which `modified(index, key, databases)` is
+    /// `true` as dirty.
+    fn touch_watched(&self, modified: impl Fn(usize, &str, &[Keyspace]) -> bool) {
+        for ((index, key), watchers) in &self.watched_keys {
+            if modified(*index, key, &self.databases) {
+                for dirty in watchers {
+                    dirty.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+impl Keyspace {
+    /// Returns the `Instant` at which the next key expires, up to a millisecond later.
+    fn next_expiration(&self) -> Option<Instant> {
+        self.expirations.next_expiration()
+    }
+
+    /// Insert an entry along with its expiration, replacing the key's previous entry
+    /// whose access statistics carry over. Returns the previous entry, if any.
+    fn insert(&mut self, key: impl Into<Arc<str>>, mut entry: Entry) -> Option<Entry> {
+        let key = key.into();
+        let prev = self.remove(&key);
+
+        if let Some(prev) = &prev {
+            entry.accessed_at = prev.accessed_at;
+            entry.frequency = prev.frequency;
+            entry.touch();
+        }
+
+        entry.timer = entry
+            .expires_at
+            .map(|expires_at| self.expirations.insert(expires_at, key.clone()));
+
+        entry.position = self.keys.len();
+        self.keys.push(key.clone());
+        self.used_memory += entry.memory_usage(&key);
+        self.entries.insert(key, entry);
+
+        prev
+    }
+
+    /// Remove a key and its expiration. Returns the removed entry, if the key existed.
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+
+        if let Some(timer) = entry.timer {
+            self.expirations.remove(timer);
+        }
+
+        // The last key takes the place of the removed one
+        self.keys.swap_remove(entry.position);
+        if let Some(moved) = self.keys.get(entry.position) {
+            if let Some(moved) = self.entries.get_mut(moved) {
+                moved.position = entry.position;
+            }
+        }
+
+        self.used_memory -= entry.memory_usage(key);
+
+        Some(entry)
+    }
+
+    /// Remove the keys expired at `now`, returning them.
+    fn expire(&mut self, now: Instant) -> Vec<Arc<str>> {
+        let expired = self.expirations.poll(now);
+
+        for key in &expired {
+            // Their timers are already gone from the wheel
+            if let Some(entry) = self.entries.get_mut(key) {
+                entry.timer = None;
+            }
+
+            self.remove(key);
+        }
+
+        expired
+    }
+
+    /// Pick up to `count` keys at random, among the keys with a TTL if `volatile`.
+    fn sample(&self, count: usize, volatile: bool) -> Vec<&str> {
+        if self.keys.is_empty() {
+            return vec![];
+        }
+
+        let random_keys = (0..).map(|_| self.keys[fastrand::usize(..self.keys.len())].as_ref());
+
+        if !volatile {
+            return random_keys.take(count).collect();
+        }
+
+        let sampled: Vec<_> = random_keys
+            .take(count * VOLATILE_SAMPLING_TRIES)
+            .filter(|key| self.entries[*key].expires_at.is_some())
+            .take(count)
+            .collect();
+
+        // When the keys with a TTL are too few to be drawn, pick the ones expiring first
+        if sampled.is_empty() {
+            return self
+                .expirations
+                .iter()
+                .take(count)
+                .map(|(_, key)| key.as_ref())
+                .collect();
+        }
+
+        sampled
+    }
+}
+
+impl Entry {
+    fn new(data: Bytes, expires_at: Option<Instant>) -> Entry {
+        Entry {
+            data,
+            expires_at,
+            accessed_at: Instant::now(),
+            frequency: LFU_INIT_VAL,
+            position: 0,
+            timer: None,
+        }
+    }
+
+    /// Record an access to the entry, incrementing its access frequency counter with a
+    /// probability decreasing as it grows, as Redis does.
+    fn touch(&mut self) {
+        let now = Instant::now();
+        let mut frequency = self.frequency(now);
+
+        if frequency < u8::MAX {
+            let base = frequency.saturating_sub(LFU_INIT_VAL) as f64;
+
+            if fastrand::f64() < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+                frequency += 1;
+            }
+        }
+
+        self.frequency = frequency;
+        self.accessed_at = now;
+    }
+
+    /// Returns the access frequency, decremented once per `LFU_DECAY_PERIOD` elapsed since
+    /// the last access.
+    fn frequency(&self, now: Instant) -> u8 {
+        let periods =
+            now.saturating_duration_since(self.accessed_at).as_secs() / LFU_DECAY_PERIOD.as_secs();
+        self.frequency
+            .saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+
+    /// Returns how much the entry should be evicted by `policy`, the higher the better.
+    fn eviction_score(&self, policy: MaxMemoryPolicy, now: Instant) -> u64 {
+        match policy {
+            MaxMemoryPolicy::AllKeysLru | MaxMemoryPolicy::VolatileLru => {
+                now.saturating_duration_since(self.accessed_at).as_millis() as u64
+            }
+            MaxMemoryPolicy::AllKeysLfu | MaxMemoryPolicy::VolatileLfu => {
+                (u8::MAX - self.frequency(now)) as u64
+            }
+            MaxMemoryPolicy::VolatileTtl => match self.expires_at {
+                Some(expires_at) => {
+                    u64::MAX - expires_at.saturating_duration_since(now).as_millis() as u64
+                }
+                None => 0,
+            },
+            MaxMemoryPolicy::AllKeysRandom | MaxMemoryPolicy::VolatileRandom => fastrand::u64(..),
+            MaxMemoryPolicy::NoEviction => 0,
+        }
+    }
+
+    /// Returns the approximate memory used by the entry and its key.
+    fn memory_usage(&self, key: &str) -> usize {
+        let expiration = match self.expires_at {
+            Some(_) => EXPIRATION_OVERHEAD,
+            None => 0,
+        };
+
+        ENTRY_OVERHEAD + key.len() + self.data.len() + expiration
+    }
+}
+
+impl State {
+    /// Log a write applied to the database at `index`, or to the whole server when `None`,
+    /// to the append-only file if enabled, and stream it to the replicas. `command` builds
+    /// the equivalent command.
+    fn propagate(&mut self, index: Option<usize>, command: impl FnOnce() -> Frame) {
+        if self.aof.is_none() && !self.replication.is_streaming() {
+            return;
+        }
+
+        let command = command();
+
+        if let Some(aof) = &mut self.aof {
+            aof.feed(index, &command);
+        }
+
+        self.replication.feed(index, &command);
+    }
+
+    /// Write the logged commands to the append-only file, and send them to the replicas.
+    fn flush_propagated(&mut self) {
+        if let Some(aof) = &mut self.aof {
+            if let Err(err) = aof.flush() {
+                error!(cause = %err, "failed to write the append-only file");
+            }
+        }
+
+        self.replication.flush();
+    }
+}
+
+impl Channels {
+    /// Returns `true` if the slowest subscriber of `key`, directly or through a pattern,
+    /// has `capacity` messages waiting.
+    fn is_pub_sub_full(&self, key: &str, capacity: usize) -> bool {
+        let tx_len = self.pub_sub.get(key).map(|tx| tx.len());
+
+        tx_len
+            .into_iter()
+            .chain(self.matching_patterns(key).map(|tx| tx.len()))
+            .any(|len| len >= capacity)
+    }
+
+    /// Publish a message to the channel, without waiting for slow subscribers.
+    /// Returns the number of subscribers listening to that channel, directly or through a matching pattern.
+    fn publish(&self, key: &str, value: Bytes) -> usize {
+        // On a successful message sent to a broadcast channel, the number
+        // of subscribers is returned. If there's no entry for that key,
+        // then there's no subscriber.
+        let num_subscribers = self
+            .pub_sub
+            .get(key)
+            .map(|tx| tx.send(value.clone()).unwrap_or(0))
+            .unwrap_or(0);
+
+        self.matching_patterns(key)
+            .fold(num_subscribers, |num, tx| {
+                num + tx.send((key.to_string(), value.clone())).unwrap_or(0)
+            })
+    }
+
+    /// Pattern channels with subscribers whose pattern matches `key`.
+    fn matching_patterns<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> impl Iterator<Item = &'a broadcast::Sender<(String, Bytes)>> + 'a {
+        self.pattern_pub_sub
+            .iter()
+            .filter(move |(pattern, tx)| {
+                tx.receiver_count() > 0 && glob::matches(pattern.as_bytes(), key.as_bytes())
+            })
+            .map(|(_, tx)| tx)
+    }
+}
+
+/// Once notified, purge any expired key from the state handle.
+///
+/// If shutdown is set, terminates the task
+async fn purge_expired_tasks(shared: Arc<Shared>) {
+    while !shared.is_shutdown() {
+        if let Some(when) = shared.purge_expired_keys() {
+            // wait until the next key expires, or until background task is notified.
+            tokio::select! {
+                _=time::sleep_until(when) => {},
+                _=shared.background_task.notified() => {},
+            }
+        } else {
+            shared.background_task.notified().await;
+        }
+    }
+
+    debug!("Purge background task shut down");
+}
+
+/// Start a background save whenever one of the save rules is met.
+///
+/// Terminates once the `Db` shuts down.
+async fn apply_save_rules(db: Db) {
+    let mut interval = time::interval(Duration::from_secs(1));
+
+    while !db.shared.is_shutdown() {
+        interval.tick().await;
+
+        let due = {
+            let state = db.shared.state.lock().unwrap();
+            let elapsed = state.last_save.elapsed().unwrap_or_default().as_secs();
+            let dirty = db.shared.dirty.load(Ordering::Relaxed);
+
+            !state.bgsave_in_progress
+                && db.shared.config.read(|config| {
+                    config
+                        .rdb
+                        .save
+                        .iter()
+                        .any(|rule| dirty >= rule.changes && elapsed >= rule.seconds)
+                })
+        };
+
+        if due {
+            db.bgsave();
+        }
+    }
+
+    debug!("Save rules background task shut down");
+}
+
+/// Sync the append-only file to disk every second, if it was written to.
+///
+/// Terminates once the `Db` shuts down.
+async fn sync_append_only_file(shared: Arc<Shared>) {
+    let mut interval = time::interval(Duration::from_secs(1));
+
+    while !shared.is_shutdown() {
+        interval.tick().await;
+
+        let file = shared
+            .state
+            .lock()
+            .unwrap()
+            .aof
+            .as_mut()
+            .and_then(Aof::take_unsynced);
+
+        if let Some(file) = file {
+            match tokio::task::spawn_blocking(move || file.sync_data()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => error!(cause = %err, "failed to sync the append-only file"),
+                Err(err) => error!(cause = %err, "failed to sync the append-only file"),
+            }
+        }
+    }
+
+    debug!("Append-only file sync task shut down");
 }
@@ -6,7 +6,7 @@ use std::{fmt, io::Cursor, num::TryFromIntError, string::FromUtf8Error};
 pub enum Frame {
     Null,
     Simple(String),
-    Integer(u64),
+    Integer(i64),
     Bulk(Bytes),
     Array(Vec<Frame>),
     Error(String),
@@ -49,7 +49,21 @@ impl Frame {
     pub(crate) fn push_int(&mut self, value: u64) {
         match self {
             Frame::Array(vec) => {
-                vec.push(Frame::Integer(value));
+                vec.push(Frame::Integer(value as i64));
+            }
+            _ => panic!("not an array frame"),
+        }
+    }
+
+    /// Push a null frame into the array. `self` must be an Array frame.
+    ///
+    /// # Panics
+    ///
+    /// panics if `self` is not an array
+    pub(crate) fn push_null(&mut self) {
+        match self {
+            Frame::Array(vec) => {
+                vec.push(Frame::Null);
             }
             _ => panic!("not an array frame"),
         }
@@ -67,7 +81,7 @@ impl Frame {
                 Ok(())
             }
             b':' => {
-                let _ = parse_utils::get_decimal(src)?;
+                let _ = parse_utils::get_integer(src)?;
                 Ok(())
             }
             b'$' => {
@@ -117,8 +131,8 @@ impl Frame {
                 Ok(Frame::Error(string))
             }
             b':' => {
-                let len = parse_utils::get_decimal(src)?;
-                Ok(Frame::Integer(len))
+                let value = parse_utils::get_integer(src)?;
+                Ok(Frame::Integer(value))
             }
             b'$' => {
                 if b'-' == parse_utils::peek_u8(src)? {
@@ -161,7 +175,7 @@ impl Frame {
     }
 
     /// Converts the frame to an "unexpected frame" error.
-    pub(crate) fn to_error(self) -> crate::GenericError {
+    pub(crate) fn to_error(&self) -> crate::GenericError {
         format!("unexpected frame: {}", self).into()
     }
 }
@@ -278,6 +292,14 @@ mod parse_utils {
         atoi::<u64>(line).ok_or_else(|| "protocol error; invalid frame format".into())
     }
 
+    pub(super) fn get_integer(src: &mut Cursor<&[u8]>) -> Result<i64, Error> {
+        use atoi::atoi;
+
+        let line = get_line(src)?;
+
+        atoi::<i64>(line).ok_or_else(|| "protocol error; invalid frame format".into())
+    }
+
     pub (super) fn get_line<'a>(src: &'a mut Cursor<&[u8]>) -> Result<&'a [u8], Error> {
         // scan the bytes directly
         let start = src.position() as usize;
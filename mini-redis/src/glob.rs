@@ -0,0 +1,161 @@
+/// Match `string` against a Redis glob-style `pattern`.
+///
+/// Supported syntax:
+/// - `?` matches any single byte
+/// - `*` matches any sequence of bytes, including an empty one
+/// - `[abc]`, `[a-z]` and `[^abc]` match a byte (not) in the set
+/// - `\x` matches the byte `x` literally
+pub(crate) fn matches(pattern: &[u8], string: &[u8]) -> bool {
+    // Position to resume from when a later part of the pattern fails to match:
+    // the byte following the last `*` and the string position it was tried at.
+    let mut backtrack: Option<(usize, usize)> = None;
+    let (mut p, mut s) = (0, 0);
+
+    while s < string.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                b'*' => {
+                    // Collapse consecutive stars, then try to match the empty sequence first
+                    while p < pattern.len() && pattern[p] == b'*' {
+                        p += 1;
+                    }
+
+                    if p == pattern.len() {
+                        return true;
+                    }
+
+                    backtrack = Some((p, s));
+                    continue;
+                }
+                b'?' => {
+                    p += 1;
+                    s += 1;
+                    continue;
+                }
+                b'[' => {
+                    if let Some((matched, next)) = match_class(pattern, p, string[s]) {
+                        if matched {
+                            p = next;
+                            s += 1;
+                            continue;
+                        }
+                    }
+                }
+                b'\\' if p + 1 < pattern.len() => {
+                    if pattern[p + 1] == string[s] {
+                        p += 2;
+                        s += 1;
+                        continue;
+                    }
+                }
+                byte => {
+                    if byte == string[s] {
+                        p += 1;
+                        s += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        // Mismatch: let the last `*` absorb one more byte, or fail
+        match backtrack {
+            Some((star_p, star_s)) => {
+                backtrack = Some((star_p, star_s + 1));
+                p = star_p;
+                s = star_s + 1;
+            }
+            None => return false,
+        }
+    }
+
+    // The string is consumed, only trailing stars may remain in the pattern
+    pattern[p..].iter().all(|&byte| byte == b'*')
+}
+
+/// Match `byte` against the `[...]` class starting at `pattern[start]`.
+///
+/// Returns whether the byte matched and the position following the class,
+/// or `None` if the class isn't terminated.
+fn match_class(pattern: &[u8], start: usize, byte: u8) -> Option<(bool, usize)> {
+    let mut p = start + 1;
+    let negate = pattern.get(p) == Some(&b'^');
+
+    if negate {
+        p += 1;
+    }
+
+    let mut matched = false;
+
+    loop {
+        match *pattern.get(p)? {
+            b']' => break,
+            b'\\' => {
+                p += 1;
+                if *pattern.get(p)? == byte {
+                    matched = true;
+                }
+            }
+            low if pattern.get(p + 1) == Some(&b'-')
+                && pattern.get(p + 2).is_some_and(|&c| c != b']') =>
+            {
+                let high = pattern[p + 2];
+                let (low, high) = if low <= high {
+                    (low, high)
+                } else {
+                    (high, low)
+                };
+
+                if (low..=high).contains(&byte) {
+                    matched = true;
+                }
+                p += 2;
+            }
+            other => {
+                if other == byte {
+                    matched = true;
+                }
+            }
+        }
+
+        p += 1;
+    }
+
+    Some((matched != negate, p + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_match_literals_and_wildcards() {
+        assert!(matches(b"hello", b"hello"));
+        assert!(!matches(b"hello", b"hell"));
+        assert!(matches(b"h?llo", b"hallo"));
+        assert!(matches(b"h*llo", b"heeeello"));
+        assert!(matches(b"h*llo", b"hllo"));
+        assert!(matches(b"*", b""));
+        assert!(matches(b"news.*", b"news.tech"));
+        assert!(!matches(b"news.*", b"weather.tech"));
+        assert!(matches(b"*.*.*", b"a.b.c"));
+        assert!(!matches(b"a*b", b"acbd"));
+    }
+
+    #[test]
+    fn should_match_classes() {
+        assert!(matches(b"h[ae]llo", b"hello"));
+        assert!(!matches(b"h[ae]llo", b"hillo"));
+        assert!(matches(b"h[^e]llo", b"hallo"));
+        assert!(!matches(b"h[^e]llo", b"hello"));
+        assert!(matches(b"h[a-b]llo", b"hbllo"));
+        assert!(!matches(b"h[a-b]llo", b"hcllo"));
+    }
+
+    #[test]
+    fn should_match_escaped_bytes() {
+        assert!(matches(b"h\\*llo", b"h*llo"));
+        assert!(!matches(b"h\\*llo", b"hello"));
+        assert!(matches(b"h[\\]]llo", b"h]llo"));
+    }
+}
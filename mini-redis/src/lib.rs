@@ -1,13 +1,24 @@
+mod acl;
+mod aof;
+mod cluster;
 mod connection;
 mod db;
 mod frame;
+mod glob;
 mod parse;
+mod rdb;
+mod replication;
+mod scripting;
 mod shutdown;
+mod tls;
 
 pub mod clients;
 pub mod commands;
+pub mod config;
 pub mod constants;
+pub mod logging;
 pub mod server;
+pub mod wheel;
 
 // Global types
 pub type GenericError = Box<dyn std::error::Error + Send + Sync>; // boxed generic error type
@@ -0,0 +1,57 @@
+//! Logging of `mini-redis-server`, whose level is changed at runtime with `CONFIG SET loglevel`.
+
+use std::{fs::OpenOptions, io, sync::Mutex, sync::OnceLock};
+use tracing_subscriber::{
+    filter::LevelFilter,
+    fmt::{self, writer::BoxMakeWriter},
+    prelude::*,
+    reload, Registry,
+};
+
+use crate::config::{Config, LogLevel};
+
+/// Handle changing the level of the installed subscriber, if any.
+static LEVEL: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+
+/// Install the global subscriber, logging the messages of `config.loglevel` and above to
+/// `config.logfile`, or to the standard output.
+pub fn init(config: &Config) -> crate::FnResult<()> {
+    let (filter, handle) = reload::Layer::new(level_filter(config.loglevel));
+
+    let writer = match &config.logfile {
+        Some(path) => BoxMakeWriter::new(Mutex::new(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        )),
+        None => BoxMakeWriter::new(io::stdout),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            fmt::layer()
+                .with_writer(writer)
+                .with_ansi(config.logfile.is_none()),
+        )
+        .try_init()?;
+
+    let _ = LEVEL.set(handle);
+
+    Ok(())
+}
+
+/// Change the level of the logged messages, when logging was set up with `init`.
+pub(crate) fn set_level(level: LogLevel) {
+    if let Some(handle) = LEVEL.get() {
+        let _ = handle.reload(level_filter(level));
+    }
+}
+
+/// Tracing level matching Redis's log level.
+fn level_filter(level: LogLevel) -> LevelFilter {
+    match level {
+        LogLevel::Debug => LevelFilter::TRACE,
+        LogLevel::Verbose => LevelFilter::DEBUG,
+        LogLevel::Notice => LevelFilter::INFO,
+        LogLevel::Warning => LevelFilter::WARN,
+    }
+}
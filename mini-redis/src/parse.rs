@@ -74,10 +74,10 @@ impl Parse {
     pub(crate) fn next_int(&mut self) -> Result<u64, ParseError> {
         use atoi::atoi;
 
-        const MSG: &'static str = "protocol error; invalid number";
+        const MSG: &str = "protocol error; invalid number";
 
         match self.next()? {
-            Frame::Integer(value) => Ok(value),
+            Frame::Integer(value) => u64::try_from(value).map_err(|_| MSG.into()),
             Frame::Simple(s) => atoi::<u64>(s.as_bytes()).ok_or_else(|| MSG.into()),
             Frame::Bulk(data) => atoi::<u64>(&data).ok_or_else(|| MSG.into()),
             frame => Err(format!("protocol error; expect int frame but got {:?}", frame).into()),
@@ -139,27 +139,27 @@ mod tests {
                 Frame::Error("error".to_string()),
             ],
             empty_frame: Frame::array(),
-            string_frame: (|| {
+            string_frame: {
                 let mut frame = Frame::array();
                 frame.push_bulk(Bytes::from("test"));
                 frame
-            })(),
-            bytes_frame: (|| {
+            },
+            bytes_frame: {
                 let mut frame = Frame::array();
                 frame.push_bulk(Bytes::from("x"));
                 frame
-            })(),
-            integer_frame: (|| {
+            },
+            integer_frame: {
                 let mut frame = Frame::array();
                 frame.push_int(1);
                 frame
-            })(),
-            long_frame: (|| {
+            },
+            long_frame: {
                 let mut frame = Frame::array();
                 frame.push_int(1);
                 frame.push_bulk(Bytes::from("x"));
                 frame
-            })(),
+            },
         }
     }
 
@@ -186,7 +186,7 @@ mod tests {
             }
         }
 
-        assert!(matches!(Parse::new(supported_frame), Ok(_)));
+        assert!(Parse::new(supported_frame).is_ok());
     }
 
     #[test]
@@ -233,8 +233,8 @@ mod tests {
         let Mocks { long_frame, .. } = setup();
 
         let mut parse = Parse::new(long_frame).unwrap();
-        assert!(matches!(parse.next(), Ok(_)));
-        assert!(matches!(parse.next(), Ok(_)));
-        assert!(matches!(parse.finish(), Ok(_)));
+        assert!(parse.next().is_ok());
+        assert!(parse.next().is_ok());
+        assert!(parse.finish().is_ok());
     }
 }
@@ -0,0 +1,590 @@
+use bytes::Bytes;
+use crc::{Crc, CRC_64_REDIS};
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Version of the RDB format written by `encode`.
+const RDB_VERSION: u32 = 9;
+
+/// Latest RDB format version `decode` accepts.
+const RDB_MAX_VERSION: u32 = 12;
+
+// Opcodes preceding the special entries of an RDB file
+const OPCODE_FUNCTION: u8 = 0xF5;
+const OPCODE_IDLE: u8 = 0xF7;
+const OPCODE_FREQ: u8 = 0xF8;
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_EXPIRETIME: u8 = 0xFD;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+/// Type of a string value
+const TYPE_STRING: u8 = 0;
+
+// Special encodings of strings, flagged by the two most significant bits of their length
+const ENCODING_INT8: u8 = 0;
+const ENCODING_INT16: u8 = 1;
+const ENCODING_INT32: u8 = 2;
+const ENCODING_LZF: u8 = 3;
+
+/// Longest string decompressed from LZF, as the longest bulk string Redis accepts.
+const LZF_MAX_LEN: usize = 512 * 1024 * 1024;
+
+/// Most bytes LZF decompresses a byte of input to: a back reference of 3 bytes copies
+/// up to 264 bytes.
+const LZF_MAX_EXPANSION: usize = 88;
+
+const CRC64: Crc<u64> = Crc::<u64>::new(&CRC_64_REDIS);
+
+/// Point-in-time copy of all databases, as saved in an RDB file.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Snapshot {
+    /// Keys of each database, by database index
+    pub(crate) databases: Vec<Vec<Record>>,
+}
+
+/// A key of a snapshot, along with its value and expiration.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Record {
+    pub(crate) key: String,
+    pub(crate) value: Bytes,
+
+    /// Wall-clock time at which the key expires, as `Instant`s don't survive a restart
+    pub(crate) expires_at: Option<SystemTime>,
+}
+
+/// Cursor over the content of an RDB file.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+/// Write the snapshot to `path` atomically: it's written to a temporary file of the same
+/// directory, synced to disk, then renamed.
+pub(crate) fn save(path: &Path, snapshot: &Snapshot) -> io::Result<()> {
+    // Distinguishes the temporary files of concurrent saves within the process
+    static NEXT_TEMP_FILE: AtomicUsize = AtomicUsize::new(0);
+
+    let temp_path = path.with_file_name(format!(
+        "temp-{}-{}.rdb",
+        std::process::id(),
+        NEXT_TEMP_FILE.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = (|| {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(&encode(snapshot))?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+/// Read the snapshot saved at `path`, of at most `num_databases` databases. Returns `None`
+/// if there is no such file.
+pub(crate) fn load(path: &Path, num_databases: usize) -> crate::FnResult<Option<Snapshot>> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(decode(&data, num_databases)?)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Encode the snapshot in the RDB format, ending with the CRC64 checksum of the content.
+pub(crate) fn encode(snapshot: &Snapshot) -> Vec<u8> {
+    let mut buf = format!("REDIS{:04}", RDB_VERSION).into_bytes();
+
+    let ctime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    for (name, value) in [
+        ("redis-ver", env!("CARGO_PKG_VERSION").to_string()),
+        ("redis-bits", "64".to_string()),
+        ("ctime", ctime.to_string()),
+    ] {
+        buf.push(OPCODE_AUX);
+        write_string(&mut buf, name.as_bytes());
+        write_string(&mut buf, value.as_bytes());
+    }
+
+    for (index, records) in snapshot.databases.iter().enumerate() {
+        if records.is_empty() {
+            continue;
+        }
+
+        buf.push(OPCODE_SELECTDB);
+        write_length(&mut buf, index as u64);
+
+        let expires = records.iter().filter(|r| r.expires_at.is_some()).count();
+        buf.push(OPCODE_RESIZEDB);
+        write_length(&mut buf, records.len() as u64);
+        write_length(&mut buf, expires as u64);
+
+        for record in records {
+            if let Some(expires_at) = record.expires_at {
+                let millis = expires_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64;
+
+                buf.push(OPCODE_EXPIRETIME_MS);
+                buf.extend_from_slice(&millis.to_le_bytes());
+            }
+
+            buf.push(TYPE_STRING);
+            write_string(&mut buf, record.key.as_bytes());
+            write_string(&mut buf, &record.value);
+        }
+    }
+
+    buf.push(OPCODE_EOF);
+
+    let checksum = crc64(&buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+
+    buf
+}
+
+/// Decode a snapshot of at most `num_databases` databases from the content of an RDB file.
+///
+/// Only string values are supported, whether stored raw, as integers or LZF compressed.
+/// The checksum is verified before anything else is read, so that lengths and database
+/// indexes of a corrupted file aren't trusted.
+pub(crate) fn decode(data: &[u8], num_databases: usize) -> crate::FnResult<Snapshot> {
+    let mut reader = Reader { data, pos: 0 };
+
+    if reader.take(5)? != b"REDIS" {
+        return Err("invalid RDB file, wrong signature".into());
+    }
+
+    let version = std::str::from_utf8(reader.take(4)?)
+        .ok()
+        .and_then(|version| version.parse::<u32>().ok())
+        .filter(|version| (1..=RDB_MAX_VERSION).contains(version))
+        .ok_or("invalid RDB file, unsupported version")?;
+
+    // Files from version 5 end with a checksum, zero when disabled
+    if version >= 5 {
+        let content_len = data.len().checked_sub(8).filter(|len| *len >= reader.pos);
+        let content_len = content_len.ok_or("invalid RDB file, unexpected end of file")?;
+        let checksum = u64::from_le_bytes(data[content_len..].try_into()?);
+
+        if checksum != 0 && checksum != crc64(&data[..content_len]) {
+            return Err("invalid RDB file, wrong checksum".into());
+        }
+
+        reader.data = &data[..content_len];
+    }
+
+    let mut snapshot = Snapshot::default();
+    let mut index = 0;
+    let mut expires_at = None;
+
+    loop {
+        match reader.next_u8()? {
+            OPCODE_EOF => break,
+            OPCODE_AUX => {
+                // Metadata such as the version of the server which saved the file
+                reader.next_string()?;
+                reader.next_string()?;
+            }
+            OPCODE_SELECTDB => {
+                index = reader.next_length()? as usize;
+
+                if index >= num_databases {
+                    return Err(format!(
+                        "invalid RDB file, database {} out of the {} configured",
+                        index, num_databases
+                    )
+                    .into());
+                }
+            }
+            OPCODE_RESIZEDB => {
+                reader.next_length()?;
+                reader.next_length()?;
+            }
+            OPCODE_EXPIRETIME_MS => {
+                let millis = u64::from_le_bytes(reader.take(8)?.try_into()?);
+                expires_at = Some(UNIX_EPOCH + Duration::from_millis(millis));
+            }
+            OPCODE_EXPIRETIME => {
+                let secs = u32::from_le_bytes(reader.take(4)?.try_into()?);
+                expires_at = Some(UNIX_EPOCH + Duration::from_secs(secs as u64));
+            }
+            // Eviction hints, not used
+            OPCODE_FREQ => {
+                reader.next_u8()?;
+            }
+            OPCODE_IDLE => {
+                reader.next_length()?;
+            }
+            OPCODE_FUNCTION => return Err("invalid RDB file, functions are not supported".into()),
+            TYPE_STRING => {
+                let key = String::from_utf8(reader.next_string()?)?;
+                let value = Bytes::from(reader.next_string()?);
+
+                if snapshot.databases.len() <= index {
+                    snapshot.databases.resize_with(index + 1, Vec::new);
+                }
+
+                snapshot.databases[index].push(Record {
+                    key,
+                    value,
+                    expires_at: expires_at.take(),
+                });
+            }
+            value_type => {
+                return Err(
+                    format!("invalid RDB file, unsupported value type {}", value_type).into(),
+                )
+            }
+        }
+    }
+
+    Ok(snapshot)
+}
+
+/// Serialize a value as a `DUMP` payload: its type and RDB encoding, followed by the RDB
+/// version on 2 bytes and the CRC64 checksum of the whole, as Redis does.
+pub(crate) fn dump(value: &[u8]) -> Vec<u8> {
+    let mut buf = vec![TYPE_STRING];
+    write_string(&mut buf, value);
+    buf.extend_from_slice(&(RDB_VERSION as u16).to_le_bytes());
+
+    let checksum = crc64(&buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+
+    buf
+}
+
+/// Deserialize a `DUMP` payload, verifying its version and checksum.
+///
+/// Payloads dumped by Redis are accepted, as long as they hold a string value.
+pub(crate) fn undump(payload: &[u8]) -> crate::FnResult<Bytes> {
+    let invalid = || -> crate::GenericError { "DUMP payload version or checksum are wrong".into() };
+
+    if payload.len() < 10 {
+        return Err(invalid());
+    }
+
+    let (content, footer) = payload.split_at(payload.len() - 10);
+    let version = u16::from_le_bytes([footer[0], footer[1]]) as u32;
+    let checksum = u64::from_le_bytes(footer[2..].try_into()?);
+
+    if version > RDB_MAX_VERSION
+        || (checksum != 0 && checksum != crc64(&payload[..payload.len() - 8]))
+    {
+        return Err(invalid());
+    }
+
+    let mut reader = Reader {
+        data: content,
+        pos: 0,
+    };
+
+    let value = match reader.next_u8() {
+        Ok(TYPE_STRING) => reader.next_string().map_err(|_| "Bad data format")?,
+        _ => return Err("Bad data format".into()),
+    };
+
+    if reader.pos != content.len() {
+        return Err("Bad data format".into());
+    }
+
+    Ok(Bytes::from(value))
+}
+
+/// CRC64 checksum, using the Jones polynomial as Redis does.
+pub(crate) fn crc64(data: &[u8]) -> u64 {
+    CRC64.checksum(data)
+}
+
+/// Write a length, on 1, 2, 5 or 9 bytes depending on its value.
+fn write_length(buf: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        buf.push(len as u8);
+    } else if len < 1 << 14 {
+        buf.push(0x40 | (len >> 8) as u8);
+        buf.push(len as u8);
+    } else if len <= u32::MAX as u64 {
+        buf.push(0x80);
+        buf.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        buf.push(0x81);
+        buf.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+/// Write a length-prefixed string.
+fn write_string(buf: &mut Vec<u8>, data: &[u8]) {
+    write_length(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> crate::FnResult<&'a [u8]> {
+        if self.data.len() - self.pos < len {
+            return Err("invalid RDB file, unexpected end of file".into());
+        }
+
+        let data = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+
+        Ok(data)
+    }
+
+    fn next_u8(&mut self) -> crate::FnResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn next_length(&mut self) -> crate::FnResult<u64> {
+        match self.next_length_or_encoding()? {
+            (len, false) => Ok(len),
+            (_, true) => Err("invalid RDB file, unexpected string encoding".into()),
+        }
+    }
+
+    /// Read a length, or the special encoding of a string when flagged as such.
+    fn next_length_or_encoding(&mut self) -> crate::FnResult<(u64, bool)> {
+        let first = self.next_u8()?;
+
+        match first >> 6 {
+            0 => Ok(((first & 0x3F) as u64, false)),
+            1 => Ok((
+                (((first & 0x3F) as u64) << 8) | self.next_u8()? as u64,
+                false,
+            )),
+            2 if first == 0x80 => Ok((u32::from_be_bytes(self.take(4)?.try_into()?) as u64, false)),
+            2 if first == 0x81 => Ok((u64::from_be_bytes(self.take(8)?.try_into()?), false)),
+            2 => Err("invalid RDB file, unknown length encoding".into()),
+            _ => Ok(((first & 0x3F) as u64, true)),
+        }
+    }
+
+    fn next_string(&mut self) -> crate::FnResult<Vec<u8>> {
+        match self.next_length_or_encoding()? {
+            (len, false) => Ok(self.take(len as usize)?.to_vec()),
+            (encoding, true) => match encoding as u8 {
+                ENCODING_INT8 => Ok((self.next_u8()? as i8).to_string().into_bytes()),
+                ENCODING_INT16 => {
+                    let value = i16::from_le_bytes(self.take(2)?.try_into()?);
+                    Ok(value.to_string().into_bytes())
+                }
+                ENCODING_INT32 => {
+                    let value = i32::from_le_bytes(self.take(4)?.try_into()?);
+                    Ok(value.to_string().into_bytes())
+                }
+                ENCODING_LZF => {
+                    let compressed_len = self.next_length()? as usize;
+                    let len = self.next_length()? as usize;
+                    lzf_decompress(self.take(compressed_len)?, len)
+                }
+                _ => Err("invalid RDB file, unknown string encoding".into()),
+            },
+        }
+    }
+}
+
+/// Decompress a string compressed with LZF, as Redis does for long strings.
+///
+/// The length, read from untrusted data, is checked against how much `input` may
+/// decompress to before anything is allocated.
+fn lzf_decompress(input: &[u8], len: usize) -> crate::FnResult<Vec<u8>> {
+    let invalid = || -> crate::GenericError { "invalid RDB file, corrupted LZF string".into() };
+
+    if len > LZF_MAX_LEN || len > input.len().saturating_mul(LZF_MAX_EXPANSION) {
+        return Err(invalid());
+    }
+
+    let mut output = Vec::with_capacity(len);
+    let mut pos = 0;
+
+    while pos < input.len() {
+        let ctrl = input[pos] as usize;
+        pos += 1;
+
+        if ctrl < 1 << 5 {
+            // Literal run of `ctrl + 1` bytes
+            let run = input.get(pos..pos + ctrl + 1).ok_or_else(invalid)?;
+            if output.len() + run.len() > len {
+                return Err(invalid());
+            }
+
+            output.extend_from_slice(run);
+            pos += ctrl + 1;
+        } else {
+            // Back reference to bytes already decompressed
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += *input.get(pos).ok_or_else(invalid)? as usize;
+                pos += 1;
+            }
+            run += 2;
+
+            let offset = ((ctrl & 0x1F) << 8) + *input.get(pos).ok_or_else(invalid)? as usize + 1;
+            pos += 1;
+
+            let start = output.len().checked_sub(offset).ok_or_else(invalid)?;
+            if output.len() + run > len {
+                return Err(invalid());
+            }
+
+            for i in start..start + run {
+                output.push(output[i]);
+            }
+        }
+    }
+
+    if output.len() != len {
+        return Err(invalid());
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_checksum_like_redis() {
+        assert_eq!(0xe9c6d914c4b8d9ca, crc64(b"123456789"));
+    }
+
+    #[test]
+    fn should_round_trip_snapshots() {
+        // RDB files store expirations in milliseconds
+        let expires_at = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+
+        let snapshot = Snapshot {
+            databases: vec![
+                vec![Record {
+                    key: "hello".to_string(),
+                    value: Bytes::from("world"),
+                    expires_at: None,
+                }],
+                vec![],
+                vec![Record {
+                    key: "long".to_string(),
+                    value: Bytes::from(vec![b'x'; 20_000]),
+                    expires_at: Some(expires_at),
+                }],
+            ],
+        };
+
+        let data = encode(&snapshot);
+        assert!(data.starts_with(b"REDIS0009"));
+        assert_eq!(snapshot, decode(&data, 16).unwrap());
+
+        // The snapshot has more databases than configured
+        assert!(decode(&data, 2).is_err());
+    }
+
+    #[test]
+    fn should_reject_corrupted_snapshots() {
+        let snapshot = Snapshot {
+            databases: vec![vec![Record {
+                key: "hello".to_string(),
+                value: Bytes::from("world"),
+                expires_at: None,
+            }]],
+        };
+
+        let mut data = encode(&snapshot);
+        let len = data.len();
+
+        assert!(decode(&data[..len - 4], 16).is_err());
+
+        data[len - 12] ^= 0xFF;
+        assert!(decode(&data, 16).is_err());
+
+        // The database index is beyond the ones configured, and isn't trusted until the
+        // checksum is verified
+        let mut data = encode(&snapshot);
+        let selectdb = data
+            .iter()
+            .position(|byte| *byte == OPCODE_SELECTDB)
+            .unwrap();
+        data[selectdb + 1] = 0x81;
+        data.splice(selectdb + 2..selectdb + 2, [0xFF; 8]);
+        assert!(decode(&data, 16)
+            .unwrap_err()
+            .to_string()
+            .contains("wrong checksum"));
+
+        let len = data.len();
+        let checksum = crc64(&data[..len - 8]);
+        data[len - 8..].copy_from_slice(&checksum.to_le_bytes());
+        assert!(decode(&data, 16)
+            .unwrap_err()
+            .to_string()
+            .contains("out of the 16 configured"));
+    }
+
+    #[test]
+    fn should_decode_encoded_strings() {
+        let mut data = b"REDIS0009".to_vec();
+        data.push(OPCODE_SELECTDB);
+        data.push(0);
+
+        // Integer encoded value
+        data.extend_from_slice(&[TYPE_STRING, 3, b'k', b'e', b'y', 0xC1, 0x39, 0x30]);
+
+        // LZF compressed value: a literal `a` repeated by a back reference
+        data.extend_from_slice(&[TYPE_STRING, 3, b'l', b'z', b'f', 0xC3, 5, 10]);
+        data.extend_from_slice(&[0, b'a', 0xE0, 0x00, 0x00]);
+
+        data.push(OPCODE_EOF);
+        data.extend_from_slice(&0u64.to_le_bytes());
+
+        let snapshot = decode(&data, 16).unwrap();
+        assert_eq!(Bytes::from("12345"), snapshot.databases[0][0].value);
+        assert_eq!(Bytes::from("aaaaaaaaaa"), snapshot.databases[0][1].value);
+    }
+
+    #[test]
+    fn should_round_trip_dump_payloads() {
+        let payload = dump(b"hello");
+
+        // Layout of a Redis payload: type, value, RDB version and checksum
+        assert_eq!(&payload[..7], b"\x00\x05hello");
+        assert_eq!(&payload[7..9], &(RDB_VERSION as u16).to_le_bytes());
+        assert_eq!(undump(&payload).unwrap(), Bytes::from("hello"));
+
+        let mut corrupted = payload.clone();
+        corrupted[3] = b'L';
+        assert!(undump(&corrupted).is_err());
+        assert!(undump(&payload[1..]).is_err());
+        assert!(undump(b"short").is_err());
+    }
+
+    #[test]
+    fn should_reject_lzf_lengths_beyond_the_input() {
+        // A zero checksum skips its verification, the LZF length is all there is to check
+        let mut payload = vec![TYPE_STRING, 0xC0 | ENCODING_LZF, 1, 0x81];
+        payload.extend_from_slice(&u64::MAX.to_be_bytes());
+        payload.push(0);
+        payload.extend_from_slice(&(RDB_VERSION as u16).to_le_bytes());
+        payload.extend_from_slice(&0u64.to_le_bytes());
+
+        assert!(undump(&payload).is_err());
+
+        // The decompressed string is longer than announced
+        assert!(lzf_decompress(&[0, b'a', 0xE0, 0x00, 0x00], 5).is_err());
+        assert!(lzf_decompress(&[1, b'a', b'b'], 1).is_err());
+    }
+}
@@ -0,0 +1,1153 @@
+use bytes::Bytes;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    net::{IpAddr, SocketAddr},
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+use tokio::{
+    net::TcpStream,
+    sync::broadcast,
+    task::AbortHandle,
+    time::{self, Duration, Instant},
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    aof::Feed,
+    commands::{Auth, PSync, Ping, ReplConf},
+    connection::Connection,
+    constants::REPLICATION_STREAM_CAPACITY,
+    db::Db,
+    frame::Frame,
+    rdb::{self, Snapshot},
+    scripting,
+    shutdown::Shutdown,
+};
+
+/// Reply to the writes sent by clients to a replica
+pub(crate) const READONLY_ERROR: &str = "READONLY You can't write against a read only replica.";
+
+/// Replication state of the server: the write stream sent to its replicas, and the
+/// leader it replicates, if any.
+#[derive(Debug)]
+pub(crate) struct Replication {
+    /// Id of the history of the dataset, given to the replicas on a full sync
+    replid: String,
+
+    /// Id of the history the dataset followed before the server was promoted, and the
+    /// offset up to which it's shared with the current one. Replicas of the former leader
+    /// continue their stream from the backlog up to that offset.
+    replid2: Option<(String, u64)>,
+
+    /// Number of bytes of the write stream sent to the replicas, or applied from the leader
+    offset: u64,
+
+    /// Write commands waiting to be streamed to the replicas
+    feed: Feed,
+
+    /// Write stream sent to the replicas
+    stream: broadcast::Sender<Bytes>,
+
+    /// Latest part of the write stream, letting a replica which lost its connection
+    /// resync from where it left off. Allocated once a replica connects, or once synced
+    /// with a leader: replicas keep the stream they apply, to serve it once promoted.
+    backlog: Option<Backlog>,
+
+    /// Size of the backlog, in bytes
+    backlog_size: usize,
+
+    /// Database selected by the write stream applied by a replica, as of `offset`
+    stream_index: usize,
+
+    /// Replicas connected to this server, by id
+    replicas: BTreeMap<u64, ReplicaInfo>,
+
+    /// Id given to the next connected replica
+    next_replica_id: u64,
+
+    /// Leader replicated by this server, if any
+    leader: Option<LeaderLink>,
+
+    /// Port this server listens on, announced to its leader
+    listening_port: u16,
+
+    /// Failover to a replica in progress, if any
+    failover: Option<PendingFailover>,
+}
+
+/// A replica connected to this server.
+#[derive(Debug)]
+struct ReplicaInfo {
+    addr: IpAddr,
+
+    /// Port the replica listens on, if announced with `REPLCONF listening-port`
+    port: u16,
+
+    /// `false` while the snapshot of the full sync is being sent
+    online: bool,
+
+    /// Offset acknowledged by the replica
+    ack_offset: u64,
+
+    /// When the replica last acknowledged its offset
+    last_ack: Instant,
+}
+
+/// Link of a replica to its leader.
+#[derive(Debug)]
+struct LeaderLink {
+    host: String,
+    port: u16,
+    state: LinkState,
+
+    /// When data was last received from the leader
+    last_io: Instant,
+
+    /// Task syncing with the leader
+    task: AbortHandle,
+}
+
+/// State of the link to the leader, as reported by `ROLE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LinkState {
+    /// Waiting to connect
+    Connect,
+
+    /// Connected, performing the handshake
+    Connecting,
+
+    /// Receiving the snapshot of a full sync
+    Sync,
+
+    /// Applying the write stream
+    Connected,
+}
+
+/// Failover started by `FAILOVER`, handing the leadership over to a replica.
+#[derive(Debug)]
+struct PendingFailover {
+    state: FailoverState,
+
+    /// Task performing the failover
+    task: AbortHandle,
+}
+
+/// State of a failover, as reported by `INFO`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FailoverState {
+    /// Writes are paused, waiting for the replica to acknowledge all of them
+    WaitingForSync,
+
+    /// The replica is being promoted
+    InProgress,
+}
+
+/// Circular buffer holding the latest bytes of the write stream.
+#[derive(Debug)]
+struct Backlog {
+    data: VecDeque<u8>,
+    size: usize,
+}
+
+/// Unregisters a replica from its leader when the replica's connection is dropped.
+#[derive(Debug)]
+pub(crate) struct ReplicaGuard {
+    id: u64,
+    db: Db,
+}
+
+impl Replication {
+    pub(crate) fn new(backlog_size: usize) -> Replication {
+        Replication {
+            replid: new_replid(),
+            replid2: None,
+            offset: 0,
+            feed: Feed::default(),
+            stream: broadcast::channel(REPLICATION_STREAM_CAPACITY).0,
+            backlog: None,
+            backlog_size,
+            stream_index: 0,
+            replicas: BTreeMap::new(),
+            next_replica_id: 1,
+            leader: None,
+            listening_port: 0,
+            failover: None,
+        }
+    }
+
+    /// Returns `true` if this server replicates a leader.
+    pub(crate) fn is_replica(&self) -> bool {
+        self.leader.is_some()
+    }
+
+    /// Returns `true` while a failover is in progress: writes are paused until it ends.
+    pub(crate) fn is_failing_over(&self) -> bool {
+        self.failover.is_some()
+    }
+
+    /// Returns `true` if writes are streamed to replicas, or kept in the backlog for the
+    /// replicas which lost their connection.
+    ///
+    /// Replicas don't stream the writes they apply, they don't serve replicas themselves.
+    pub(crate) fn is_streaming(&self) -> bool {
+        !self.is_replica() && self.backlog.is_some()
+    }
+
+    /// Offset of the write stream.
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Number of replicas which acknowledged `offset`.
+    pub(crate) fn num_acked(&self, offset: u64) -> usize {
+        self.replicas
+            .values()
+            .filter(|replica| replica.online && replica.ack_offset >= offset)
+            .count()
+    }
+
+    /// Ask the replicas to acknowledge their offset right away, rather than within a second.
+    pub(crate) fn request_ack(&mut self) {
+        if self.is_streaming() {
+            self.feed.feed(None, &ReplConf::GetAck.into_frame());
+        }
+    }
+
+    /// Add a write applied to the database at `index`, or to the whole server when `None`,
+    /// to the stream sent to the replicas, if any.
+    pub(crate) fn feed(&mut self, index: Option<usize>, command: &Frame) {
+        if self.is_streaming() {
+            self.feed.feed(index, command);
+        }
+    }
+
+    /// Send the writes fed since the last call to the replicas.
+    pub(crate) fn flush(&mut self) {
+        if let Some(data) = self.feed.take() {
+            self.offset += data.len() as u64;
+
+            if let Some(backlog) = &mut self.backlog {
+                backlog.append(&data);
+            }
+
+            // No receiver is left once all replicas disconnected, the data is dropped
+            let _ = self.stream.send(Bytes::from(data));
+        }
+    }
+
+    /// Make the server a leader, with a new replication id. Replicas of the former leader
+    /// may continue their stream up to the current offset.
+    fn promote(&mut self) {
+        if let Some(link) = self.leader.take() {
+            link.task.abort();
+        }
+
+        let replid = std::mem::replace(&mut self.replid, new_replid());
+        self.replid2 = Some((replid, self.offset));
+
+        // The database selected by the stream applied isn't known to the new replicas
+        self.feed.reset_selected();
+    }
+
+    /// Make the server a replica of the leader at `host:port`, synced by `task`.
+    fn demote(&mut self, host: String, port: u16, state: LinkState, task: AbortHandle) {
+        if let Some(link) = self.leader.take() {
+            link.task.abort();
+        }
+
+        // The stream continues from the database it last selected
+        self.stream_index = self.feed.selected().unwrap_or(0);
+
+        // Replicas don't serve replicas: the streams are closed
+        self.stream = broadcast::channel(REPLICATION_STREAM_CAPACITY).0;
+
+        self.leader = Some(LeaderLink {
+            host,
+            port,
+            state,
+            last_io: Instant::now(),
+            task,
+        });
+    }
+
+    /// Returns `true` if a replica which synced with the stream of id `replid` can
+    /// continue it from `offset`, whether the backlog still holds it or not.
+    fn is_continuation(&self, replid: &str, offset: u64) -> bool {
+        replid == self.replid
+            || self
+                .replid2
+                .as_ref()
+                .is_some_and(|(replid2, until)| replid == replid2 && offset <= *until)
+    }
+
+    /// Set the port this server listens on.
+    pub(crate) fn set_listening_port(&mut self, port: u16) {
+        self.listening_port = port;
+    }
+
+    /// Returns the reply of `ROLE`.
+    pub(crate) fn role(&self) -> Frame {
+        let mut frame = Frame::array();
+
+        match &self.leader {
+            Some(leader) => {
+                frame.push_bulk(Bytes::from("slave".as_bytes()));
+                frame.push_bulk(Bytes::from(leader.host.clone().into_bytes()));
+                frame.push_int(leader.port as u64);
+                frame.push_bulk(Bytes::from(leader.state.as_str().as_bytes()));
+                frame.push_int(self.offset);
+            }
+            None => {
+                frame.push_bulk(Bytes::from("master".as_bytes()));
+                frame.push_int(self.offset);
+
+                let replicas = self
+                    .replicas
+                    .values()
+                    .filter(|replica| replica.online)
+                    .map(|replica| {
+                        Frame::Array(vec![
+                            Frame::Bulk(Bytes::from(replica.addr.to_string().into_bytes())),
+                            Frame::Bulk(Bytes::from(replica.port.to_string().into_bytes())),
+                            Frame::Bulk(Bytes::from(replica.ack_offset.to_string().into_bytes())),
+                        ])
+                    })
+                    .collect();
+
+                if let Frame::Array(frames) = &mut frame {
+                    frames.push(Frame::Array(replicas));
+                }
+            }
+        }
+
+        frame
+    }
+
+    /// Returns the `replication` section of `INFO`.
+    pub(crate) fn info(&self) -> String {
+        let mut info = String::from("# Replication\r\n");
+
+        match &self.leader {
+            Some(leader) => {
+                let link_up = leader.state == LinkState::Connected;
+
+                info += "role:slave\r\n";
+                info += &format!("master_host:{}\r\n", leader.host);
+                info += &format!("master_port:{}\r\n", leader.port);
+                info += &format!(
+                    "master_link_status:{}\r\n",
+                    if link_up { "up" } else { "down" }
+                );
+                info += &format!(
+                    "master_last_io_seconds_ago:{}\r\n",
+                    if link_up {
+                        leader.last_io.elapsed().as_secs() as i64
+                    } else {
+                        -1
+                    }
+                );
+                info += &format!(
+                    "master_sync_in_progress:{}\r\n",
+                    (leader.state == LinkState::Sync) as u8
+                );
+                info += &format!("slave_repl_offset:{}\r\n", self.offset);
+                info += "slave_read_only:1\r\n";
+                info += "connected_slaves:0\r\n";
+            }
+            None => {
+                let online = self.replicas.values().filter(|replica| replica.online);
+
+                info += "role:master\r\n";
+                info += &format!(
+                    "master_failover_state:{}\r\n",
+                    self.failover
+                        .as_ref()
+                        .map_or("no-failover", |failover| failover.state.as_str())
+                );
+                info += &format!("connected_slaves:{}\r\n", online.clone().count());
+
+                for (i, replica) in online.enumerate() {
+                    info += &format!(
+                        "slave{}:ip={},port={},state=online,offset={},lag={}\r\n",
+                        i,
+                        replica.addr,
+                        replica.port,
+                        replica.ack_offset,
+                        replica.last_ack.elapsed().as_secs()
+                    );
+                }
+            }
+        }
+
+        info += &format!("master_replid:{}\r\n", self.replid);
+
+        match &self.replid2 {
+            Some((replid2, until)) => {
+                info += &format!("master_replid2:{}\r\n", replid2);
+                info += &format!("master_repl_offset:{}\r\n", self.offset);
+                info += &format!("second_repl_offset:{}\r\n", until);
+            }
+            None => {
+                info += &format!("master_replid2:{}\r\n", "0".repeat(40));
+                info += &format!("master_repl_offset:{}\r\n", self.offset);
+                info += "second_repl_offset:-1\r\n";
+            }
+        }
+
+        info
+    }
+}
+
+impl Backlog {
+    fn new(size: usize) -> Backlog {
+        Backlog {
+            data: VecDeque::with_capacity(size),
+            size,
+        }
+    }
+
+    /// Append data to the backlog, dropping the oldest bytes past its size.
+    fn append(&mut self, data: &[u8]) {
+        self.data.extend(data);
+
+        if self.data.len() > self.size {
+            self.data.drain(..self.data.len() - self.size);
+        }
+    }
+
+    /// Returns the bytes of the stream from `offset` up to `end`, the offset of the last
+    /// byte appended, or `None` if they are no longer held.
+    fn since(&self, offset: u64, end: u64) -> Option<Vec<u8>> {
+        let start = end - self.data.len() as u64;
+
+        if offset < start || offset > end {
+            return None;
+        }
+
+        Some(
+            self.data
+                .range((offset - start) as usize..)
+                .copied()
+                .collect(),
+        )
+    }
+}
+
+impl LinkState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LinkState::Connect => "connect",
+            LinkState::Connecting => "connecting",
+            LinkState::Sync => "sync",
+            LinkState::Connected => "connected",
+        }
+    }
+}
+
+impl FailoverState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FailoverState::WaitingForSync => "waiting-for-sync",
+            FailoverState::InProgress => "failover-in-progress",
+        }
+    }
+}
+
+impl Drop for ReplicaGuard {
+    fn drop(&mut self) {
+        self.db.lock().replication().replicas.remove(&self.id);
+    }
+}
+
+/// Make the server a replica of the leader at `host:port`, or a leader again when `None`.
+///
+/// Returns `false` if the server already replicates that leader.
+pub(crate) fn replicate(db: &Db, leader: Option<(String, u16)>) -> bool {
+    let mut guard = db.lock();
+    let replication = guard.replication();
+
+    if let (Some((host, port)), Some(link)) = (&leader, &replication.leader) {
+        if link.host == *host && link.port == *port {
+            return false;
+        }
+    }
+
+    match leader {
+        Some((host, port)) => {
+            info!(%host, port, "replicating leader");
+
+            let task = tokio::spawn(sync_with_leader(db.clone(), host.clone(), port, None));
+            replication.demote(host, port, LinkState::Connect, task.abort_handle());
+            db.set_read_only(true);
+        }
+        None if replication.is_replica() => {
+            info!("replication stopped, now a leader");
+
+            replication.promote();
+            db.set_read_only(false);
+        }
+        None => {}
+    }
+
+    true
+}
+
+/// Hand the leadership over to the replica at `host:port`, or to the replica which
+/// acknowledged the most writes when `None`, in the background.
+///
+/// Writes are paused until the replica acknowledged all of them, then the replica is
+/// promoted and the server becomes its replica. The failover is aborted if the replica
+/// doesn't catch up within `timeout`, if set.
+pub(crate) fn failover(
+    db: &Db,
+    target: Option<(String, u16)>,
+    timeout: Option<Duration>,
+) -> Result<(), &'static str> {
+    let mut guard = db.lock();
+    let replication = guard.replication();
+
+    if replication.is_replica() {
+        return Err("ERR FAILOVER is not valid when server is a replica.");
+    }
+
+    if replication.is_failing_over() {
+        return Err("ERR FAILOVER already in progress.");
+    }
+
+    let mut online = replication
+        .replicas
+        .iter()
+        .filter(|(_, replica)| replica.online);
+
+    let target = match target {
+        Some((host, port)) => online
+            .find(|(_, replica)| replica.addr.to_string() == host && replica.port == port)
+            .ok_or("ERR FAILOVER target HOST and PORT is not a replica.")?,
+        None => online
+            .max_by_key(|(_, replica)| replica.ack_offset)
+            .ok_or("ERR FAILOVER requires connected replicas.")?,
+    };
+
+    let target = *target.0;
+    info!("failover started, pausing writes");
+
+    let task = tokio::spawn(run_failover(db.clone(), target, timeout));
+
+    replication.failover = Some(PendingFailover {
+        state: FailoverState::WaitingForSync,
+        task: task.abort_handle(),
+    });
+
+    // The target acknowledges the writes it already applied right away
+    replication.request_ack();
+
+    Ok(())
+}
+
+/// Stop syncing with the leader, or failing over, as the server shuts down.
+pub(crate) fn stop(db: &Db) {
+    let mut guard = db.lock();
+    let replication = guard.replication();
+
+    if let Some(link) = &replication.leader {
+        link.task.abort();
+    }
+
+    if let Some(failover) = &replication.failover {
+        failover.task.abort();
+    }
+}
+
+/// Perform a failover to the replica `target`, resuming the writes once done.
+async fn run_failover(db: Db, target: u64, timeout: Option<Duration>) {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let result = hand_over(&db, target, deadline).await;
+
+    let mut guard = db.lock();
+    let replication = guard.replication();
+    replication.failover = None;
+
+    match result {
+        Ok((host, port, connection, replid)) => {
+            info!(%host, port, "failover completed, now replicating the new leader");
+
+            let task = tokio::spawn(sync_with_leader(
+                db.clone(),
+                host.clone(),
+                port,
+                Some(connection),
+            ));
+            replication.demote(host, port, LinkState::Connected, task.abort_handle());
+            continue_stream(replication, replid);
+            db.set_read_only(true);
+        }
+        Err(err) => warn!(cause = %err, "failover aborted, resuming writes"),
+    }
+
+    drop(guard);
+    db.notify_writes_resumed();
+}
+
+/// Promote the replica `target` once it acknowledged all the writes, before `deadline`
+/// if set. Returns the address it listens on, along with the connection it continues
+/// the stream on and its new replication id.
+async fn hand_over(
+    db: &Db,
+    target: u64,
+    deadline: Option<Instant>,
+) -> crate::FnResult<(String, u16, Connection, String)> {
+    let (host, port) = wait_for_target(db, target, deadline).await?;
+
+    let (replid, offset) = {
+        let mut guard = db.lock();
+        let replication = guard.replication();
+
+        if let Some(failover) = &mut replication.failover {
+            failover.state = FailoverState::InProgress;
+        }
+
+        (replication.replid.clone(), replication.offset)
+    };
+
+    let promotion = promote_target(db, &host, port, &replid, offset);
+
+    let (connection, replid) = match deadline {
+        Some(deadline) => match time::timeout_at(deadline, promotion).await {
+            Ok(result) => result?,
+            Err(_) => return Err("timed out promoting the replica".into()),
+        },
+        None => promotion.await?,
+    };
+
+    Ok((host, port, connection, replid))
+}
+
+/// Send `PSYNC ... FAILOVER` to the replica at `host:port`, which promotes itself then
+/// continues the stream as the new leader. Returns the connection the stream continues
+/// on, and the new replication id.
+async fn promote_target(
+    db: &Db,
+    host: &str,
+    port: u16,
+    replid: &str,
+    offset: u64,
+) -> crate::FnResult<(Connection, String)> {
+    let mut connection = connect(db, host, port).await?;
+
+    match psync(&mut connection, replid, offset, true).await? {
+        PSyncReply::Continue(replid) => Ok((connection, replid)),
+        PSyncReply::FullResync(..) => Err("the promoted replica requires a full sync".into()),
+    }
+}
+
+/// Wait until the replica `target` acknowledged all the writes, returning the address
+/// it listens on.
+async fn wait_for_target(
+    db: &Db,
+    target: u64,
+    deadline: Option<Instant>,
+) -> crate::FnResult<(String, u16)> {
+    loop {
+        // Register interest in acknowledgements before checking the replica's, so that
+        // one received in between isn't missed.
+        let acked = db.replica_acked();
+        tokio::pin!(acked);
+        acked.as_mut().enable();
+
+        {
+            let mut guard = db.lock();
+            let replication = guard.replication();
+
+            match replication.replicas.get(&target) {
+                Some(replica) if replica.ack_offset >= replication.offset => {
+                    return Ok((replica.addr.to_string(), replica.port));
+                }
+                Some(_) => {}
+                None => return Err("the replica disconnected".into()),
+            }
+        }
+
+        match deadline {
+            Some(deadline) => tokio::select! {
+                _ = acked => {}
+                _ = time::sleep_until(deadline) => {
+                    return Err("timed out waiting for the replica to catch up".into());
+                }
+            },
+            None => acked.await,
+        }
+    }
+}
+
+/// Serve a replica on `dst` after its `PSYNC`, then stream the writes applied to the
+/// databases.
+///
+/// The replica gets the part of the stream it missed if it last synced with this server,
+/// or with its former leader, and the backlog still holds the stream from `offset`: the
+/// leader replies `+CONTINUE <replid>` then sends it. Otherwise a full sync is performed.
+///
+/// With `failover`, the replica is the former leader handing the leadership over: the
+/// server promotes itself first.
+///
+/// Returns once the replica disconnects, or the server shuts down. A replica lagging too
+/// far behind the stream gets an error, closing its connection: it syncs again when
+/// reconnecting.
+pub(crate) async fn serve_replica(
+    db: &Db,
+    dst: &mut Connection,
+    shutdown: &mut Shutdown,
+    listening_port: Option<u16>,
+    replid: &str,
+    offset: i64,
+    failover: bool,
+) -> crate::FnResult<()> {
+    let addr = dst.peer_addr()?;
+
+    let resync = {
+        let mut guard = db.lock();
+        let replication = guard.replication();
+
+        // Only the replica which applied the whole stream of its leader is promoted
+        let promotable = replication.is_replica()
+            && replid == replication.replid
+            && offset == replication.offset as i64;
+
+        if failover && promotable {
+            info!(%addr, "promoted by a failover");
+            replication.promote();
+            db.set_read_only(false);
+        }
+
+        if failover && !promotable {
+            Err("ERR PSYNC FAILOVER replid must match my replid.")
+        } else if replication.is_replica() {
+            Err("ERR Chained replication is not supported")
+        } else {
+            let missed = match u64::try_from(offset) {
+                Ok(offset) if replication.is_continuation(replid, offset) => replication
+                    .backlog
+                    .as_ref()
+                    .and_then(|backlog| backlog.since(offset, replication.offset))
+                    .map(|missed| (offset, missed)),
+                _ => None,
+            };
+
+            match missed {
+                Some((offset, missed)) => {
+                    let registered =
+                        register_replica(replication, db, addr, listening_port, Some(offset));
+                    Ok((registered, Resync::Partial(missed)))
+                }
+                None => {
+                    // The replica starts reading the stream from the snapshot, on no
+                    // selected database
+                    replication.feed.reset_selected();
+                    let registered =
+                        register_replica(guard.replication(), db, addr, listening_port, None);
+                    Ok((registered, Resync::Full(guard.snapshot())))
+                }
+            }
+        }
+    };
+
+    let ((guard, replid, offset, mut stream), resync) = match resync {
+        Ok(resync) => resync,
+        Err(err) => {
+            dst.write_frame(&Frame::Error(err.to_string())).await?;
+            return Ok(());
+        }
+    };
+
+    let id = guard.id;
+
+    match resync {
+        Resync::Partial(missed) => {
+            info!(%addr, missed = missed.len(), "replica reconnected, continuing the stream");
+
+            dst.write_frame(&Frame::Simple(format!("CONTINUE {}", replid)))
+                .await?;
+            dst.write_raw(&missed).await?;
+        }
+        Resync::Full(snapshot) => {
+            info!(%addr, "replica connected, starting full sync");
+
+            let response = Frame::Simple(format!("FULLRESYNC {} {}", replid, offset));
+            dst.write_frame(&response).await?;
+
+            let payload = tokio::task::spawn_blocking(move || rdb::encode(&snapshot)).await?;
+            dst.write_raw(format!("${}\r\n", payload.len()).as_bytes())
+                .await?;
+            dst.write_raw(&payload).await?;
+
+            if let Some(replica) = db.lock().replication().replicas.get_mut(&id) {
+                replica.online = true;
+                replica.last_ack = Instant::now();
+            }
+        }
+    }
+
+    loop {
+        tokio::select! {
+            res = stream.recv() => match res {
+                Ok(data) => dst.write_raw(&data).await?,
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    return Err("replica lagging too far behind the write stream".into());
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+            res = dst.read_frame() => match res? {
+                Some(frame) => {
+                    if let Ok(ReplConf::Ack(ack_offset)) = ReplConf::from_frame(frame) {
+                        if let Some(replica) = db.lock().replication().replicas.get_mut(&id) {
+                            replica.ack_offset = ack_offset;
+                            replica.last_ack = Instant::now();
+                        }
+
+                        db.notify_replica_acked();
+                    }
+                }
+                None => {
+                    info!(%addr, "replica disconnected");
+                    return Ok(());
+                }
+            },
+            _ = shutdown.recv() => return Ok(()),
+        }
+    }
+}
+
+/// How a replica is synced with its leader.
+enum Resync {
+    /// The replica gets the part of the stream it missed
+    Partial(Vec<u8>),
+
+    /// The replica gets a snapshot of the databases
+    Full(Snapshot),
+}
+
+/// Register a replica connected from `addr`, returning the guard unregistering it, the
+/// replication id and offset it starts from, and the write stream it receives.
+///
+/// A replica resuming the stream from `resumed` is online right away, otherwise it's
+/// online once it received the snapshot of the full sync.
+fn register_replica(
+    replication: &mut Replication,
+    db: &Db,
+    addr: SocketAddr,
+    listening_port: Option<u16>,
+    resumed: Option<u64>,
+) -> (ReplicaGuard, String, u64, broadcast::Receiver<Bytes>) {
+    let id = replication.next_replica_id;
+    replication.next_replica_id += 1;
+
+    replication.replicas.insert(
+        id,
+        ReplicaInfo {
+            addr: addr.ip(),
+            port: listening_port.unwrap_or(addr.port()),
+            online: resumed.is_some(),
+            ack_offset: resumed.unwrap_or(0),
+            last_ack: Instant::now(),
+        },
+    );
+
+    if replication.backlog.is_none() {
+        replication.backlog = Some(Backlog::new(replication.backlog_size));
+    }
+
+    let stream = replication.stream.subscribe();
+    let replica_guard = ReplicaGuard { id, db: db.clone() };
+
+    (
+        replica_guard,
+        replication.replid.clone(),
+        replication.offset,
+        stream,
+    )
+}
+
+/// Sync with the leader at `host:port`, reconnecting every second when the link is lost.
+/// The stream is first applied from `connection`, if already synced.
+///
+/// Runs until aborted, when the server stops replicating that leader.
+async fn sync_with_leader(db: Db, host: String, port: u16, mut connection: Option<Connection>) {
+    loop {
+        let result = match connection.take() {
+            Some(connection) => apply_stream(&db, connection).await,
+            None => sync_once(&db, &host, port).await,
+        };
+
+        match result {
+            Ok(()) => info!(%host, port, "connection to leader closed"),
+            Err(err) => warn!(%host, port, cause = %err, "replication failed"),
+        }
+
+        set_link_state(&db, LinkState::Connect);
+        time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Connect to the leader, sync with it, then apply its write stream until the connection
+/// is closed.
+///
+/// The replica asks to continue the stream from the offset it reached, falling back to
+/// a full sync if the leader can't.
+async fn sync_once(db: &Db, host: &str, port: u16) -> crate::FnResult<()> {
+    let mut connection = connect(db, host, port).await?;
+
+    let (replid, offset) = {
+        let mut guard = db.lock();
+        let replication = guard.replication();
+        (replication.replid.clone(), replication.offset)
+    };
+
+    match psync(&mut connection, &replid, offset, false).await? {
+        PSyncReply::Continue(replid) => {
+            info!(host, port, offset, "continuing the stream of the leader");
+            continue_stream(db.lock().replication(), replid);
+        }
+        PSyncReply::FullResync(replid, offset) => {
+            set_link_state(db, LinkState::Sync);
+
+            let payload = connection.read_payload().await?;
+            let num_databases = db.num_databases();
+            let snapshot =
+                tokio::task::spawn_blocking(move || rdb::decode(&payload, num_databases)).await??;
+
+            let mut guard = db.lock();
+            guard.restore(snapshot)?;
+
+            let replication = guard.replication();
+            replication.replid = replid;
+            replication.replid2 = None;
+            replication.offset = offset;
+            replication.stream_index = 0;
+            replication.backlog = Some(Backlog::new(replication.backlog_size));
+
+            info!(host, port, "full sync with leader completed");
+        }
+    }
+
+    apply_stream(db, connection).await
+}
+
+/// Connect to the leader at `host:port`, authenticating with `masterauth` if set, and
+/// announcing the port this server listens on.
+async fn connect(db: &Db, host: &str, port: u16) -> crate::FnResult<Connection> {
+    let socket = TcpStream::connect((host, port)).await?;
+    let mut connection = Connection::new(socket);
+
+    set_link_state(db, LinkState::Connecting);
+
+    let (user, password) = db
+        .config()
+        .read(|config| (config.masteruser.clone(), config.masterauth.clone()));
+
+    if let Some(password) = password {
+        request(
+            &mut connection,
+            Auth::new(user.as_deref(), &password).into_frame(),
+        )
+        .await?;
+    }
+
+    request(&mut connection, Ping::new(None).into_frame()).await?;
+
+    let listening_port = db.lock().replication().listening_port;
+    request(
+        &mut connection,
+        ReplConf::ListeningPort(listening_port).into_frame(),
+    )
+    .await?;
+
+    Ok(connection)
+}
+
+/// Reply of the leader to `PSYNC`.
+enum PSyncReply {
+    /// The leader continues the stream, under the given replication id
+    Continue(String),
+
+    /// The leader sends a snapshot, followed by the stream of the given id from the given
+    /// offset
+    FullResync(String, u64),
+}
+
+/// Ask the leader to continue the stream of id `replid` from `offset`, promoting it first
+/// with `failover`.
+async fn psync(
+    connection: &mut Connection,
+    replid: &str,
+    offset: u64,
+    failover: bool,
+) -> crate::FnResult<PSyncReply> {
+    let psync = PSync::new(replid, offset).failover(failover).into_frame();
+
+    let response = match request(connection, psync).await? {
+        Frame::Simple(response) => response,
+        frame => return Err(format!("unexpected reply to PSYNC: {}", frame).into()),
+    };
+
+    let parts: Vec<_> = response.split(' ').collect();
+
+    match parts[..] {
+        ["CONTINUE"] => Ok(PSyncReply::Continue(replid.to_string())),
+        ["CONTINUE", replid] => Ok(PSyncReply::Continue(replid.to_string())),
+        ["FULLRESYNC", replid, offset] => {
+            Ok(PSyncReply::FullResync(replid.to_string(), offset.parse()?))
+        }
+        _ => Err(format!("unexpected reply to PSYNC: {}", response).into()),
+    }
+}
+
+/// Continue the stream under the replication id `replid`, the leader's, which may have
+/// changed if it was promoted since.
+fn continue_stream(replication: &mut Replication, replid: String) {
+    if replid != replication.replid {
+        let replid2 = std::mem::replace(&mut replication.replid, replid);
+        replication.replid2 = Some((replid2, replication.offset));
+    }
+
+    if replication.backlog.is_none() {
+        replication.backlog = Some(Backlog::new(replication.backlog_size));
+    }
+}
+
+/// Apply the write stream of the leader until the connection is closed, acknowledging
+/// the offset reached every second, or when asked by the leader.
+async fn apply_stream(db: &Db, mut connection: Connection) -> crate::FnResult<()> {
+    set_link_state(db, LinkState::Connected);
+
+    // Transaction being received, and data of the frames received but not applied yet
+    let mut transaction: Option<Vec<Frame>> = None;
+    let mut pending = vec![];
+
+    let mut ack = time::interval(Duration::from_secs(1));
+
+    loop {
+        tokio::select! {
+            res = connection.read_frame_with_data() => {
+                let (frame, data) = match res? {
+                    Some(frame) => frame,
+                    None => return Ok(()),
+                };
+
+                pending.extend_from_slice(&data);
+
+                // Commands of a transaction are applied all together
+                let (commands, getack) = match (transaction.as_mut(), command_name(&frame).as_deref()) {
+                    (None, Some("multi")) => {
+                        transaction = Some(vec![]);
+                        continue;
+                    }
+                    (Some(_), Some("exec")) => (transaction.take().unwrap_or_default(), false),
+                    (Some(commands), _) => {
+                        commands.push(frame);
+                        continue;
+                    }
+                    (None, Some("replconf")) => (vec![], true),
+                    (None, _) => (vec![frame], false),
+                };
+
+                let offset = {
+                    let mut guard = db.lock();
+                    let index = guard.replication().stream_index;
+                    guard.select(index);
+                    guard.apply_writes(commands)?;
+
+                    let index = guard.index();
+                    let replication = guard.replication();
+                    replication.stream_index = index;
+                    replication.offset += pending.len() as u64;
+
+                    if let Some(backlog) = &mut replication.backlog {
+                        backlog.append(&pending);
+                    }
+
+                    pending.clear();
+
+                    if let Some(leader) = &mut replication.leader {
+                        leader.last_io = Instant::now();
+                    }
+
+                    replication.offset
+                };
+
+                // Sent by `WAIT` or `FAILOVER`, waiting for the replicas
+                if getack {
+                    connection.write_frame(&ReplConf::Ack(offset).into_frame()).await?;
+                }
+            }
+            _ = ack.tick() => {
+                let offset = db.lock().replication().offset;
+                connection.write_frame(&ReplConf::Ack(offset).into_frame()).await?;
+            }
+        }
+    }
+}
+
+/// Send a request to the leader, returning its reply. Error replies are returned as errors.
+async fn request(connection: &mut Connection, frame: Frame) -> crate::FnResult<Frame> {
+    connection.write_frame(&frame).await?;
+
+    match connection.read_frame().await? {
+        Some(Frame::Error(err)) => Err(err.into()),
+        Some(frame) => {
+            debug!(response = ?frame, "leader replied");
+            Ok(frame)
+        }
+        None => Err("connection closed by the leader".into()),
+    }
+}
+
+fn set_link_state(db: &Db, state: LinkState) {
+    if let Some(leader) = &mut db.lock().replication().leader {
+        leader.state = state;
+        leader.last_io = Instant::now();
+    }
+}
+
+/// Lowercase name of a command received from the leader.
+fn command_name(frame: &Frame) -> Option<String> {
+    match frame {
+        Frame::Array(args) => match args.first() {
+            Some(Frame::Bulk(name)) => Some(String::from_utf8_lossy(name).to_lowercase()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns a new random replication id, of 40 hexadecimal characters.
+fn new_replid() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+
+    scripting::sha1_hex(&format!(
+        "{}-{:?}-{}",
+        std::process::id(),
+        SystemTime::now(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backlog_holds_the_latest_bytes() {
+        let mut backlog = Backlog::new(8);
+
+        backlog.append(b"hello");
+        assert_eq!(Some(b"hello".to_vec()), backlog.since(0, 5));
+        assert_eq!(Some(b"lo".to_vec()), backlog.since(3, 5));
+        assert_eq!(Some(vec![]), backlog.since(5, 5));
+        assert_eq!(None, backlog.since(6, 5));
+
+        // The oldest bytes are dropped past the size of the backlog
+        backlog.append(b" world");
+        assert_eq!(Some(b"lo world".to_vec()), backlog.since(3, 11));
+        assert_eq!(None, backlog.since(2, 11));
+    }
+}
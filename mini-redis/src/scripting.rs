@@ -0,0 +1,566 @@
+use bytes::Bytes;
+use mlua::{HookTriggers, Lua, LuaOptions, MultiValue, StdLib, Table, Value};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{sync::Notify, time};
+use tracing::debug;
+
+use crate::{
+    acl::Session, commands::Command, db::DbGuard, frame::Frame, replication::READONLY_ERROR,
+};
+
+/// Reply to the commands received while a script runs past the time limit.
+pub(crate) const BUSY_ERROR: &str =
+    "BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSAVE.";
+
+/// Scripts cached by `EVAL` and `SCRIPT LOAD`, and the script being run.
+///
+/// Kept out of the `Db` state, so that the connections can tell a script is running, and
+/// `SCRIPT KILL` be applied, while it holds the `Db` lock.
+///
+/// `EVAL` queues its script before locking the `Db`, and the commands lock it only while
+/// no script is queued: they wait for the script rather than block a thread on the lock.
+#[derive(Debug)]
+pub(crate) struct Scripts {
+    /// Script bodies, keyed by their SHA1 digest
+    cache: Mutex<HashMap<String, String>>,
+
+    /// The script being run, if any
+    running: Mutex<Option<Arc<RunningScript>>>,
+
+    /// Number of scripts queued by `EVAL`, waiting for or holding the `Db` lock.
+    ///
+    /// Write locked to queue a script, read locked by the commands while they lock the `Db`.
+    queued: RwLock<usize>,
+
+    /// Notify the connections waiting for a script, once it starts or ends
+    changed: Notify,
+
+    /// Duration after which a running script is aborted, unless it wrote
+    time_limit: Duration,
+}
+
+/// State of a running script, shared with `SCRIPT KILL`.
+#[derive(Debug)]
+struct RunningScript {
+    /// When the script started
+    started: Instant,
+
+    /// Set by `SCRIPT KILL`, or by `SHUTDOWN NOSAVE` once the script wrote
+    killed: AtomicBool,
+
+    /// `true` once the script ran a write command, it can no longer be killed nor time out
+    wrote: AtomicBool,
+}
+
+impl RunningScript {
+    /// Returns `true` once the script runs longer than `time_limit` without having written.
+    fn is_timed_out(&self, time_limit: Duration) -> bool {
+        !self.wrote.load(Ordering::Relaxed) && self.started.elapsed() > time_limit
+    }
+}
+
+/// Error reply of a command called with `redis.call`, raised as a Lua error.
+#[derive(Debug)]
+struct ReplyError(String);
+
+impl Scripts {
+    pub(crate) fn new(time_limit: Duration) -> Scripts {
+        Scripts {
+            cache: Mutex::new(HashMap::new()),
+            running: Mutex::new(None),
+            queued: RwLock::new(0),
+            changed: Notify::new(),
+            time_limit,
+        }
+    }
+
+    /// Cache a script and return its SHA1 digest.
+    pub(crate) fn load(&self, script: &str) -> String {
+        let sha1 = sha1_hex(script);
+
+        self.cache
+            .lock()
+            .unwrap()
+            .entry(sha1.clone())
+            .or_insert_with(|| script.to_string());
+
+        sha1
+    }
+
+    /// Returns the cached script having the SHA1 digest `sha1`.
+    pub(crate) fn get(&self, sha1: &str) -> Option<String> {
+        self.cache
+            .lock()
+            .unwrap()
+            .get(&sha1.to_lowercase())
+            .cloned()
+    }
+
+    /// Returns whether each of the SHA1 digests is cached.
+    pub(crate) fn exists(&self, sha1s: &[String]) -> Vec<bool> {
+        let cache = self.cache.lock().unwrap();
+
+        sha1s
+            .iter()
+            .map(|sha1| cache.contains_key(&sha1.to_lowercase()))
+            .collect()
+    }
+
+    /// Remove all cached scripts.
+    pub(crate) fn flush(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    /// Abort the running script, as long as it didn't write yet.
+    ///
+    /// Returns the error reply if there is nothing to kill.
+    pub(crate) fn kill(&self) -> Result<(), String> {
+        match &*self.running.lock().unwrap() {
+            None => Err("NOTBUSY No scripts in execution right now.".to_string()),
+            Some(running) if running.wrote.load(Ordering::Relaxed) => Err(
+                "UNKILLABLE Sorry the script already executed write commands against the dataset. \
+                 You can either wait the script termination or kill the server in a hard way \
+                 using the SHUTDOWN NOSAVE command."
+                    .to_string(),
+            ),
+            Some(running) => {
+                running.killed.store(true, Ordering::Relaxed);
+                Ok(())
+            }
+        }
+    }
+
+    /// Abort the running script, if any, even if it already wrote, for `SHUTDOWN NOSAVE`
+    /// to stop the server without waiting for it.
+    pub(crate) fn abort(&self) {
+        if let Some(running) = &*self.running.lock().unwrap() {
+            running.killed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Wait for the running script and the queued ones, if any, to end.
+    ///
+    /// Returns the `BUSY` error reply once the script runs past the time limit having
+    /// written, as it then keeps the `Db` locked until it ends, or the server shuts down.
+    pub(crate) async fn wait_until_done(&self) -> Result<(), String> {
+        loop {
+            // Registered before checking for the script, not to miss its end
+            let done = self.changed.notified();
+            tokio::pin!(done);
+            done.as_mut().enable();
+
+            let running = self.running.lock().unwrap().clone();
+
+            let (started, wrote) = match running {
+                Some(running) => (running.started, running.wrote.load(Ordering::Relaxed)),
+                None if *self.queued.read().unwrap() == 0 => return Ok(()),
+                // A queued script is waiting for the `Db` lock
+                None => {
+                    done.await;
+                    continue;
+                }
+            };
+
+            match self.time_limit.checked_sub(started.elapsed()) {
+                Some(remaining) => tokio::select! {
+                    _ = done => {}
+                    _ = time::sleep(remaining) => {}
+                },
+                // Past the time limit, a script which didn't write is being aborted
+                None if !wrote => done.await,
+                None => return Err(BUSY_ERROR.to_string()),
+            }
+        }
+    }
+
+    /// Queue a script, before locking the `Db` to run it, until the returned guard is dropped.
+    ///
+    /// Blocks while commands lock the `Db`, so must not be called from an async context.
+    pub(crate) fn queue(&self) -> QueuedScript<'_> {
+        *self.queued.write().unwrap() += 1;
+
+        QueuedScript { scripts: self }
+    }
+
+    /// Call `f` to lock the `Db`, unless a script is queued, in which case it would block
+    /// until the script ends.
+    pub(crate) fn unless_queued<T>(&self, f: impl FnOnce() -> T) -> Option<T> {
+        let queued = self.queued.read().unwrap();
+
+        if *queued > 0 {
+            return None;
+        }
+
+        Some(f())
+    }
+
+    /// Register a script starting to run.
+    fn start(&self) -> Arc<RunningScript> {
+        let running = Arc::new(RunningScript {
+            started: Instant::now(),
+            killed: AtomicBool::new(false),
+            wrote: AtomicBool::new(false),
+        });
+
+        *self.running.lock().unwrap() = Some(running.clone());
+        self.changed.notify_waiters();
+
+        running
+    }
+
+    /// Unregister the running script, and notify the connections waiting for it.
+    fn finish(&self) {
+        *self.running.lock().unwrap() = None;
+        self.changed.notify_waiters();
+    }
+}
+
+/// A script queued by `EVAL`, removed from the queue when dropped.
+#[derive(Debug)]
+pub(crate) struct QueuedScript<'a> {
+    scripts: &'a Scripts,
+}
+
+impl Drop for QueuedScript<'_> {
+    fn drop(&mut self) {
+        *self.scripts.queued.write().unwrap() -= 1;
+        self.scripts.changed.notify_waiters();
+    }
+}
+
+/// Run `script` on the locked `Db`, with the `KEYS` and `ARGV` globals set, returning its reply.
+///
+/// `redis.call` and `redis.pcall` apply commands through the same path as transactions, so the
+/// script is atomic, once checked against the permissions of `session`, the one of the
+/// connection running the script. The script is aborted when killed by `SCRIPT KILL`, or once it runs
+/// longer than the time limit, as long as it didn't write: aborting it then would break its
+/// atomicity. It runs to the end otherwise, the other connections getting a `BUSY` error.
+pub(crate) fn eval(
+    db: &mut DbGuard,
+    session: &Session,
+    script: &str,
+    keys: Vec<String>,
+    args: Vec<Bytes>,
+) -> Frame {
+    let running = db.scripts().start();
+
+    let time_limit = db.scripts().time_limit;
+    let index = db.index();
+
+    let response = match run(db, session, script, keys, args, &running, time_limit) {
+        Ok(response) => response,
+        Err(err) => Frame::Error(error_reply(&err)),
+    };
+
+    // Scripts selecting another database don't affect the caller
+    db.select(index);
+
+    db.scripts().finish();
+
+    debug!(?response, "script done");
+
+    response
+}
+
+fn run(
+    db: &mut DbGuard,
+    session: &Session,
+    script: &str,
+    keys: Vec<String>,
+    args: Vec<Bytes>,
+    running: &Arc<RunningScript>,
+    time_limit: Duration,
+) -> mlua::Result<Frame> {
+    // Scripts get no access to files or the operating system
+    let lua = Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+        LuaOptions::default(),
+    )?;
+
+    let globals = lua.globals();
+    globals.set("dofile", Value::Nil)?;
+    globals.set("loadfile", Value::Nil)?;
+    globals.set("KEYS", keys)?;
+    globals.set(
+        "ARGV",
+        args.iter()
+            .map(|arg| lua.create_string(arg))
+            .collect::<mlua::Result<Vec<_>>>()?,
+    )?;
+
+    let hook_running = running.clone();
+
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(1000),
+        move |_lua, _debug| {
+            if hook_running.killed.load(Ordering::Relaxed) {
+                Err(mlua::Error::external(ReplyError(
+                    "ERR Script killed by user with SCRIPT KILL...".to_string(),
+                )))
+            } else if hook_running.is_timed_out(time_limit) {
+                Err(timed_out_error(time_limit))
+            } else {
+                Ok(())
+            }
+        },
+    );
+
+    let redis = lua.create_table()?;
+    redis.set(
+        "error_reply",
+        lua.create_function(|lua, msg: String| {
+            let reply = lua.create_table()?;
+            reply.set("err", msg)?;
+            Ok(reply)
+        })?,
+    )?;
+    redis.set(
+        "status_reply",
+        lua.create_function(|lua, msg: String| {
+            let reply = lua.create_table()?;
+            reply.set("ok", msg)?;
+            Ok(reply)
+        })?,
+    )?;
+    redis.set(
+        "sha1hex",
+        lua.create_function(|_, script: mlua::String| Ok(sha1_hex(&script.to_string_lossy())))?,
+    )?;
+    globals.set("redis", redis.clone())?;
+
+    // Both `redis.call` and `redis.pcall` apply commands on the locked `Db`
+    let db = RefCell::new(db);
+
+    let value = lua.scope(|scope| {
+        redis.set(
+            "call",
+            scope.create_function(|lua, args: MultiValue| {
+                match call(&mut db.borrow_mut(), session, running, args)? {
+                    Frame::Error(msg) => Err(mlua::Error::external(ReplyError(msg))),
+                    frame => to_lua(lua, frame),
+                }
+            })?,
+        )?;
+        redis.set(
+            "pcall",
+            scope.create_function(|lua, args: MultiValue| {
+                match call(&mut db.borrow_mut(), session, running, args) {
+                    Ok(frame) => to_lua(lua, frame),
+                    Err(err) => to_lua(lua, Frame::Error(error_reply(&err))),
+                }
+            })?,
+        )?;
+
+        lua.load(script).set_name("@user_script").eval::<Value>()
+    })?;
+
+    Ok(to_frame(value))
+}
+
+/// Apply the command given as arguments to `redis.call` or `redis.pcall`.
+///
+/// Command errors are returned as an error frame, while invalid commands are raised as errors.
+fn call(
+    db: &mut DbGuard,
+    session: &Session,
+    running: &RunningScript,
+    args: MultiValue,
+) -> mlua::Result<Frame> {
+    if args.is_empty() {
+        return Err(reply_error(
+            "ERR Please specify at least one argument for this redis lib call",
+        ));
+    }
+
+    let mut frame = Frame::array();
+
+    for arg in args {
+        let bytes = match arg {
+            Value::String(s) => Bytes::copy_from_slice(s.as_bytes()),
+            Value::Integer(i) => Bytes::from(i.to_string()),
+            Value::Number(n) => Bytes::from(n.to_string()),
+            _ => {
+                return Err(reply_error(
+                    "ERR Lua redis lib command arguments must be strings or integers",
+                ))
+            }
+        };
+        frame.push_bulk(bytes);
+    }
+
+    let cmd = Command::from_frame(frame).map_err(|err| reply_error(&err.to_string()))?;
+
+    if let Command::Unknown(_) = cmd {
+        return Err(reply_error("ERR Unknown Redis command called from script"));
+    }
+
+    if !cmd.is_scriptable() {
+        return Err(reply_error(
+            "ERR This Redis command is not allowed from script",
+        ));
+    }
+
+    // The script may only do what its caller is allowed to
+    let log_max_len = db.config().read(|config| config.acllog_max_len);
+    if let Err(Frame::Error(msg)) = db.acl().check(session, &cmd, "lua", log_max_len) {
+        return Err(reply_error(&msg));
+    }
+
+    if cmd.is_write() {
+        if db.is_read_only() {
+            return Err(reply_error(READONLY_ERROR));
+        }
+
+        // Past the time limit, the script is aborted before its first write, after which
+        // it no longer could be
+        let time_limit = db.scripts().time_limit;
+        if running.is_timed_out(time_limit) {
+            return Err(timed_out_error(time_limit));
+        }
+
+        running.wrote.store(true, Ordering::Relaxed);
+    }
+
+    Ok(cmd.execute(db))
+}
+
+/// Convert the value returned by a script into a reply, following Redis conversion rules.
+fn to_frame(value: Value) -> Frame {
+    match value {
+        Value::Nil | Value::Boolean(false) => Frame::Null,
+        Value::Boolean(true) => Frame::Integer(1),
+        Value::Integer(i) => Frame::Integer(i),
+        // Numbers are truncated to integers, as Redis does
+        Value::Number(n) => Frame::Integer(n as i64),
+        Value::String(s) => Frame::Bulk(Bytes::copy_from_slice(s.as_bytes())),
+        Value::Table(table) => table_to_frame(table),
+        _ => Frame::Null,
+    }
+}
+
+fn table_to_frame(table: Table) -> Frame {
+    if let Ok(Value::String(err)) = table.raw_get("err") {
+        return Frame::Error(err.to_string_lossy().into_owned());
+    }
+
+    if let Ok(Value::String(ok)) = table.raw_get("ok") {
+        return Frame::Simple(ok.to_string_lossy().into_owned());
+    }
+
+    // The array part, up to the first `nil`
+    Frame::Array(
+        table
+            .sequence_values::<Value>()
+            .map_while(Result::ok)
+            .map(to_frame)
+            .collect(),
+    )
+}
+
+/// Convert a command reply into a Lua value, following Redis conversion rules.
+fn to_lua(lua: &Lua, frame: Frame) -> mlua::Result<Value<'_>> {
+    let value = match frame {
+        Frame::Null => Value::Boolean(false),
+        Frame::Integer(i) => Value::Integer(i),
+        Frame::Bulk(data) => Value::String(lua.create_string(&data)?),
+        Frame::Simple(ok) => {
+            let table = lua.create_table()?;
+            table.set("ok", ok)?;
+            Value::Table(table)
+        }
+        Frame::Error(err) => {
+            let table = lua.create_table()?;
+            table.set("err", err)?;
+            Value::Table(table)
+        }
+        Frame::Array(frames) => {
+            let table = lua.create_table()?;
+            for frame in frames {
+                table.raw_push(to_lua(lua, frame)?)?;
+            }
+            Value::Table(table)
+        }
+    };
+
+    Ok(value)
+}
+
+fn reply_error(msg: &str) -> mlua::Error {
+    mlua::Error::external(ReplyError(msg.to_string()))
+}
+
+fn timed_out_error(time_limit: Duration) -> mlua::Error {
+    reply_error(&format!(
+        "ERR Script timed out after {} ms",
+        time_limit.as_millis()
+    ))
+}
+
+/// Returns the error reply for a script error.
+fn error_reply(err: &mlua::Error) -> String {
+    match err {
+        mlua::Error::CallbackError { cause, .. } => error_reply(cause),
+        mlua::Error::ExternalError(err) => match err.downcast_ref::<ReplyError>() {
+            Some(ReplyError(msg)) => msg.clone(),
+            None => format!("ERR {}", err),
+        },
+        mlua::Error::SyntaxError { message, .. } => {
+            format!("ERR Error compiling script: {}", message)
+        }
+        mlua::Error::RuntimeError(msg) => format!("ERR Error running script: {}", msg),
+        err => format!("ERR Error running script: {}", err),
+    }
+}
+
+/// Returns the SHA1 digest of a script, as lowercase hexadecimal.
+pub(crate) fn sha1_hex(script: &str) -> String {
+    sha1_smol::Sha1::from(script).digest().to_string()
+}
+
+impl fmt::Display for ReplyError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(fmt)
+    }
+}
+
+impl std::error::Error for ReplyError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_hash_scripts() {
+        assert_eq!(
+            "e0e1f9fabfc9d4800c877a703b823ac0578ff8db",
+            sha1_hex("return 1")
+        );
+    }
+
+    #[tokio::test]
+    async fn should_wait_for_queued_scripts() {
+        let scripts = Scripts::new(Duration::from_secs(5));
+
+        let queued = scripts.queue();
+        assert_eq!(None, scripts.unless_queued(|| ()));
+
+        let waiting = scripts.wait_until_done();
+        tokio::pin!(waiting);
+        assert!(time::timeout(Duration::from_millis(50), waiting.as_mut())
+            .await
+            .is_err());
+
+        drop(queued);
+        assert_eq!(Ok(()), waiting.await);
+        assert_eq!(Some(()), scripts.unless_queued(|| ()));
+    }
+}
@@ -1,28 +1,48 @@
-use std::future::Future;
+use std::future::{self, Future};
+use std::io;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::task::{Context, Poll};
+#[cfg(unix)]
+use tokio::net::UnixListener;
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{broadcast, mpsc, Semaphore};
+use tokio::sync::{broadcast, mpsc};
 use tokio::time::{self, Duration};
+use tokio_rustls::TlsAcceptor;
 use tracing::{debug, error, info};
 
-use crate::commands::Command;
-use crate::connection::Connection;
-use crate::db::{Db, DbDropGuard};
+use crate::acl::Session;
+use crate::cluster;
+use crate::commands::{Command, Transaction};
+use crate::config::Config;
+use crate::connection::{Connection, Stream};
+use crate::constants::CLUSTER_BUS_PORT_OFFSET;
+use crate::db::{Db, DbDropGuard, Watcher, OOM_ERROR};
+use crate::frame::Frame;
+use crate::replication::READONLY_ERROR;
 use crate::shutdown::Shutdown;
+use crate::tls;
+
+/// Reply to the connections refused once `maxclients` are connected.
+const MAX_CLIENTS_ERROR: &str = "ERR max number of clients reached";
 
 /// Server listener state.
 ///
 /// Use `run()` to perform TCP listening and initialization of per-connection state.
-#[derive(Debug)]
 struct Listener {
     // Shared database handle
     db_holder: DbDropGuard,
 
-    // TCP listener supplied by the `run` caller
-    listener: TcpListener,
+    // Listeners supplied by the `run` caller, one per bound address or socket
+    listeners: Vec<ListenSocket>,
+
+    // Performs the TLS handshakes of the connections accepted by the TLS listeners
+    tls_acceptor: Option<TlsAcceptor>,
 
-    // A `Semaphore` used to limit the max number of connections thru permits
-    limit_connections: Arc<Semaphore>,
+    // Number of connected clients, limited to `maxclients`
+    connected_clients: Arc<AtomicUsize>,
 
     // Broadcast shutdown signal to all active connections
     notify_shutdown: broadcast::Sender<()>,
@@ -36,7 +56,7 @@ struct Listener {
 /// commands to `db`.
 #[derive(Debug)]
 struct Handler {
-    // Shared database handle
+    // Shared database handle, on the database selected by the connection
     db: Db,
 
     // TCP connection decorated with Redis protocol encoder / decoder
@@ -45,24 +65,234 @@ struct Handler {
     // Listen for shutdown notifications
     shutdown: Shutdown,
 
+    // Transaction opened by `MULTI`, queuing commands until `EXEC` or `DISCARD`
+    transaction: Option<Transaction>,
+
+    // Keys watched with `WATCH`, unwatched when the connection is dropped
+    watcher: Option<Watcher>,
+
+    // Port announced by a replica with `REPLCONF listening-port`, before its `PSYNC`
+    replica_port: Option<u16>,
+
+    // Whether the previous command was `ASKING`, in cluster mode
+    asking: bool,
+
+    // User the connection is authenticated as
+    session: Session,
+
     // Used when `Handler` is dropped
     _shutdown_complete: mpsc::Sender<()>,
 }
 
+/// Socket the server accepts connections on.
+#[derive(Debug)]
+pub enum ListenSocket {
+    Tcp(TcpListener),
+
+    /// TCP listener whose connections are encrypted, as configured by `Config::tls`
+    Tls(TcpListener),
+
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl From<TcpListener> for ListenSocket {
+    fn from(listener: TcpListener) -> ListenSocket {
+        ListenSocket::Tcp(listener)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixListener> for ListenSocket {
+    fn from(listener: UnixListener) -> ListenSocket {
+        ListenSocket::Unix(listener)
+    }
+}
+
+/// Slot of a connected client, counted against `maxclients` until dropped.
+struct ClientSlot(Arc<AtomicUsize>);
+
+/// Connection accepted by a listener.
+enum Accepted {
+    /// Ready to exchange frames
+    Plain(Stream),
+
+    /// Awaiting its TLS handshake
+    Tls(TcpStream, TlsAcceptor),
+}
+
+impl ClientSlot {
+    fn new(connected_clients: Arc<AtomicUsize>) -> ClientSlot {
+        connected_clients.fetch_add(1, Ordering::Relaxed);
+        ClientSlot(connected_clients)
+    }
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Accepted {
+    /// Perform the TLS handshake if needed, returning the stream to exchange frames on.
+    async fn handshake(self) -> io::Result<Stream> {
+        match self {
+            Accepted::Plain(socket) => Ok(socket),
+            Accepted::Tls(socket, acceptor) => Ok(acceptor.accept(socket).await?.into()),
+        }
+    }
+}
+
+/// Bind a Unix domain socket at `path`, replacing the socket file left by a previous run.
+///
+/// The permissions of the socket file are set to `mode` unless zero, e.g. `0o770` to only
+/// let the users of the group connect.
+#[cfg(unix)]
+pub fn bind_unix_socket(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        _ => {}
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    if mode != 0 {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+
+    Ok(listener)
+}
+
+/// Run the server with the default configuration.
+///
+/// Accepts connections from `listener` until `shutdown` completes.
 pub async fn run(listener: TcpListener, shutdown: impl Future) {
+    run_with_config(listener, Config::default(), shutdown).await
+}
+
+/// Run the server with the supplied configuration.
+///
+/// Accepts connections from `listener` until `shutdown` completes.
+pub async fn run_with_config(listener: TcpListener, config: Config, shutdown: impl Future) {
+    run_with_listeners(vec![listener.into()], config, shutdown).await
+}
+
+/// Run the server with the supplied configuration, on several listeners, e.g. one per
+/// address in `Config::bind` and one on `Config::unixsocket`.
+///
+/// Accepts connections from `listeners` until `shutdown` completes. The first TCP
+/// listener's port is the one announced to the leader and to the other nodes of the cluster,
+/// which require one.
+pub async fn run_with_listeners(
+    listeners: Vec<ListenSocket>,
+    config: Config,
+    shutdown: impl Future,
+) {
     // Broadcast channel used to send shutdown message to all active connections
     let (notify_shutdown, _) = broadcast::channel(1);
     let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel(1);
 
+    let db_holder = DbDropGuard::new(&config);
+
+    // Restore the databases saved by a previous run, if persistence is configured
+    if config.loads_on_startup() {
+        if let Err(err) = db_holder.db().load() {
+            error!(cause = %err, "failed to load the databases");
+            return;
+        }
+    }
+
+    if let Some(path) = &config.aclfile {
+        if let Err(err) = db_holder.db().acl().load_file(path) {
+            error!(cause = %err, "failed to load the ACL file");
+            return;
+        }
+    }
+
+    let tcp_addr = listeners.iter().find_map(|listener| match listener {
+        ListenSocket::Tcp(listener) => listener.local_addr().ok(),
+        ListenSocket::Tls(_) => None,
+        #[cfg(unix)]
+        ListenSocket::Unix(_) => None,
+    });
+
+    let tls_acceptor = if listeners
+        .iter()
+        .any(|listener| matches!(listener, ListenSocket::Tls(_)))
+    {
+        match tls::acceptor(&config.tls) {
+            Ok(acceptor) => Some(acceptor),
+            Err(err) => {
+                error!(cause = %err, "failed to set up TLS");
+                return;
+            }
+        }
+    } else {
+        None
+    };
+
+    if tcp_addr.is_none() && config.cluster.is_some() {
+        error!("cluster mode requires a TCP port");
+        return;
+    }
+
+    // Announced to the leader when replicating one, and to the other nodes in cluster mode
+    if let Some(addr) = tcp_addr {
+        db_holder
+            .db()
+            .lock()
+            .replication()
+            .set_listening_port(addr.port());
+
+        if let Some(cluster) = &config.cluster {
+            let bus_port = match cluster.bus_port {
+                Some(bus_port) => Some(bus_port),
+                None => addr.port().checked_add(CLUSTER_BUS_PORT_OFFSET),
+            };
+
+            let bus_listener = match bus_port {
+                Some(bus_port) => TcpListener::bind((addr.ip(), bus_port)).await,
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "port out of range",
+                )),
+            };
+
+            let bus_listener = match bus_listener {
+                Ok(bus_listener) => bus_listener,
+                Err(err) => {
+                    error!(cause = %err, "failed to bind the cluster bus");
+                    return;
+                }
+            };
+
+            let db = db_holder.db();
+
+            if let (Some(cluster), Ok(bus_addr)) = (db.lock().cluster(), bus_listener.local_addr())
+            {
+                cluster.set_ports(addr.port(), bus_addr.port());
+            }
+
+            tokio::spawn(cluster::bus::run(db, bus_listener, notify_shutdown.clone()));
+        }
+    }
+
     let mut server = Listener {
-        listener,
-        db_holder: DbDropGuard::new(),
-        limit_connections: Arc::new(Semaphore::new(crate::constants::MAX_CONNECTIONS)),
+        listeners,
+        tls_acceptor,
+        db_holder,
+        connected_clients: Arc::new(AtomicUsize::new(0)),
         notify_shutdown,
         shutdown_complete_tx,
     };
 
-    // Concurrently run the server and listen for the 'shutdown' signal
+    let db = server.db_holder.db();
+    let mut save = None;
+
+    // Concurrently run the server and listen for the 'shutdown' signal, or `SHUTDOWN`
     tokio::select! {
         res = server.run() => {
             if let Err(err) = res {
@@ -72,6 +302,10 @@ pub async fn run(listener: TcpListener, shutdown: impl Future) {
         _ = shutdown => {
             info!("shutting down");
         }
+        requested = db.shutdown_requested() => {
+            info!("shutting down on SHUTDOWN");
+            save = requested;
+        }
     }
 
     let Listener {
@@ -85,6 +319,14 @@ pub async fn run(listener: TcpListener, shutdown: impl Future) {
 
     // Wait for all active connections to finish processing
     let _ = shutdown_complete_rx.recv().await;
+
+    // Save the databases for the next run, when persistence is enabled, unless `SHUTDOWN`
+    // says otherwise
+    if save.unwrap_or_else(|| db.config().read(|config| !config.rdb.save.is_empty())) {
+        if let Err(err) = db.save().await {
+            error!(cause = %err, "failed to save the snapshot");
+        }
+    }
 }
 
 impl Listener {
@@ -95,30 +337,67 @@ impl Listener {
         info!("accepting inbound connections");
 
         loop {
-            // ait for permit to become available
-            let permit = self
-                .limit_connections
-                .clone()
-                .acquire_owned()
-                .await?;
-
             // Accept a new socket. This will attempt to perform error handling
-            let socket = self.accept().await?;
-
-            // Create necessary per-connection handler state
-            let mut handler = Handler {
-                db: self.db_holder.db(),
-                connection: Connection::new(socket),
-                shutdown: Shutdown::new(self.notify_shutdown.subscribe()),
-                _shutdown_complete: self.shutdown_complete_tx.clone(),
-            };
+            let accepted = self.accept().await?;
+            let db = self.db_holder.db();
+
+            // Over `maxclients`, the connection is refused with an error, as Redis does
+            if self.connected_clients.load(Ordering::Relaxed)
+                >= db.config().read(|config| config.maxclients)
+            {
+                tokio::spawn(async move {
+                    if let Ok(socket) = accepted.handshake().await {
+                        let mut connection = Connection::new(socket);
+                        let _ = connection
+                            .write_frame(&Frame::Error(MAX_CLIENTS_ERROR.to_string()))
+                            .await;
+                    }
+                });
+
+                continue;
+            }
+
+            let client_slot = ClientSlot::new(self.connected_clients.clone());
+
+            let mut shutdown = Shutdown::new(self.notify_shutdown.subscribe());
+            let shutdown_complete = self.shutdown_complete_tx.clone();
 
             // Spawn new task to process connections. Tokio tasks are like async green threads
             tokio::spawn(async move {
-                if let Err(err) = handler.run().await {
-                    error!(cause = ?err, "connection error");
+                // Released when the task ends, even if it panics
+                let _client_slot = client_slot;
+
+                // The TLS handshake runs on the connection's task, not to hold up the others
+                let socket = tokio::select! {
+                    res = accepted.handshake() => Some(res),
+                    _ = shutdown.recv() => None,
+                };
+
+                match socket {
+                    Some(Ok(socket)) => {
+                        let connection = Connection::new(socket);
+                        let session = Session::new(db.acl(), &connection);
+
+                        // Create necessary per-connection handler state
+                        let mut handler = Handler {
+                            db,
+                            connection,
+                            shutdown,
+                            transaction: None,
+                            watcher: None,
+                            replica_port: None,
+                            asking: false,
+                            session,
+                            _shutdown_complete: shutdown_complete,
+                        };
+
+                        if let Err(err) = handler.run().await {
+                            error!(cause = ?err, "connection error");
+                        }
+                    }
+                    Some(Err(err)) => debug!(cause = %err, "TLS handshake failed"),
+                    None => {}
                 }
-                drop(permit);
             });
         }
     }
@@ -126,12 +405,12 @@ impl Listener {
     /// Accept an inbound connection.
     ///
     /// Errors are handled using exponential backoff.
-    async fn accept(&mut self) -> crate::FnResult<TcpStream> {
+    async fn accept(&mut self) -> crate::FnResult<Accepted> {
         let mut backoff: u64 = 1;
 
         loop {
-            match self.listener.accept().await {
-                Ok((socket, _)) => return Ok(socket),
+            match future::poll_fn(|cx| self.poll_accept(cx)).await {
+                Ok(socket) => return Ok(socket),
                 Err(err) => {
                     if backoff > 64 {
                         // Accept has failed too many times, returns the error
@@ -147,18 +426,53 @@ impl Listener {
             backoff *= 2;
         }
     }
+
+    /// Poll each listener in turn for an inbound connection.
+    fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<Accepted>> {
+        for listener in &self.listeners {
+            let poll = match listener {
+                ListenSocket::Tcp(listener) => listener
+                    .poll_accept(cx)
+                    .map_ok(|(socket, _)| Accepted::Plain(socket.into())),
+                ListenSocket::Tls(listener) => listener.poll_accept(cx).map_ok(|(socket, _)| {
+                    let acceptor = self
+                        .tls_acceptor
+                        .clone()
+                        .expect("TLS acceptor set up with the listeners");
+                    Accepted::Tls(socket, acceptor)
+                }),
+                #[cfg(unix)]
+                ListenSocket::Unix(listener) => listener
+                    .poll_accept(cx)
+                    .map_ok(|(socket, _)| Accepted::Plain(socket.into())),
+            };
+
+            if poll.is_ready() {
+                return poll;
+            }
+        }
+
+        Poll::Pending
+    }
 }
 
 impl Handler {
     /// Process a single connection
     async fn run(&mut self) -> crate::FnResult<()> {
-        while !self.shutdown.is_shutdown() {
-            // While reading a request frame, also listen for the shutdown
+        while !self.shutdown.is_shutdown() && !self.connection.is_closed() {
+            // While reading a request frame, also listen for the shutdown, and close the
+            // connection once idle for longer than the timeout
+            let timeout = self.db.config().read(|config| config.timeout);
+
             let maybe_frame = tokio::select! {
                 res = self.connection.read_frame() => res?,
                 _ = self.shutdown.recv() => {
                     return Ok(());
                 }
+                _ = idle(timeout) => {
+                    debug!("closing idle connection");
+                    return Ok(());
+                }
             };
 
             let frame = match maybe_frame {
@@ -167,16 +481,163 @@ impl Handler {
             };
 
             // Convert Redis frame into a command struct
-            let cmd = Command::from_frame(frame)?;
+            let cmd = match Command::from_frame(frame) {
+                Ok(cmd) => cmd,
+                // An invalid command aborts the open transaction rather than the connection
+                Err(err) => match self.transaction.as_mut() {
+                    Some(transaction) => {
+                        transaction.abort(err, &mut self.connection).await?;
+                        continue;
+                    }
+                    None => return Err(err),
+                },
+            };
 
             // Shorthand for `debug!(cmd = format!("{:?}", cmd));`
             debug!(?cmd);
 
+            // The user must be authenticated, and allowed to run the command on its keys
+            // and channels
+            if let Err(response) =
+                self.db
+                    .check_permissions(&self.session, &cmd, self.transaction.is_some())
+            {
+                match self.transaction.as_mut() {
+                    Some(transaction) => transaction.reject(response, &mut self.connection).await?,
+                    None => self.connection.write_frame(&response).await?,
+                }
+
+                continue;
+            }
+
+            // A running script holds the `Db` lock: commands wait for it to end without
+            // blocking a thread, and are rejected once it runs past the time limit, having
+            // written. `SCRIPT KILL` and `SHUTDOWN NOSAVE` go through, to stop it. Commands
+            // check again as they lock the `Db`, in case a script started meanwhile.
+            if !cmd.is_allowed_while_busy() {
+                let busy = tokio::select! {
+                    res = self.db.scripts().wait_until_done() => res,
+                    _ = self.shutdown.recv() => return Ok(()),
+                };
+
+                if let Err(msg) = busy {
+                    let response = Frame::Error(msg);
+
+                    match self.transaction.as_mut() {
+                        Some(transaction) => {
+                            transaction.reject(response, &mut self.connection).await?
+                        }
+                        None => self.connection.write_frame(&response).await?,
+                    }
+
+                    continue;
+                }
+            }
+
+            // In cluster mode, the keys must be served by this node, and the keys of a
+            // transaction must all hash to the same slot. `ASKING` lets the next command
+            // through to a slot being imported.
+            let pinned = self.transaction.as_ref().and_then(Transaction::slot);
+            let asking = std::mem::replace(&mut self.asking, matches!(cmd, Command::Asking(_)));
+
+            match self.db.check_slot(&cmd.keys(), pinned, asking).await {
+                Ok(Some(slot)) => {
+                    if let Some(transaction) = self.transaction.as_mut() {
+                        transaction.pin_slot(slot);
+                    }
+                }
+                Ok(None) => {}
+                Err(response) => {
+                    match self.transaction.as_mut() {
+                        Some(transaction) => {
+                            transaction.reject(response, &mut self.connection).await?
+                        }
+                        None => self.connection.write_frame(&response).await?,
+                    }
+
+                    continue;
+                }
+            }
+
+            // Writes, including the ones of a transaction, are held during a failover
+            let writes = match (&cmd, self.transaction.as_ref()) {
+                (Command::Exec(_), Some(transaction)) => transaction.may_write(),
+                (_, Some(_)) => false,
+                (_, None) => cmd.may_write(),
+            };
+
+            if writes {
+                tokio::select! {
+                    _ = self.db.wait_until_writes_resume() => {}
+                    _ = self.shutdown.recv() => return Ok(()),
+                }
+            }
+
+            // Replicas only apply the writes streamed by their leader
+            if cmd.is_write() && self.db.is_read_only() {
+                let response = Frame::Error(READONLY_ERROR.to_string());
+
+                match self.transaction.as_mut() {
+                    Some(transaction) => transaction.reject(response, &mut self.connection).await?,
+                    None => self.connection.write_frame(&response).await?,
+                }
+
+                continue;
+            }
+
+            // Over `maxmemory`, keys are evicted before writes. The ones which may grow the
+            // databases are rejected if there isn't enough to evict.
+            let freed = if writes || cmd.may_write() {
+                self.db.free_memory().await
+            } else {
+                Ok(true)
+            };
+
+            let rejected = match freed {
+                Ok(false) if cmd.denies_oom() => Some(Frame::Error(OOM_ERROR.to_string())),
+                Ok(_) => None,
+                Err(response) => Some(response),
+            };
+
+            if let Some(response) = rejected {
+                match self.transaction.as_mut() {
+                    Some(transaction) => transaction.reject(response, &mut self.connection).await?,
+                    None => self.connection.write_frame(&response).await?,
+                }
+
+                continue;
+            }
+
+            // Within a transaction, commands are queued until `EXEC`
+            if let Some(transaction) = self.transaction.as_mut() {
+                if !cmd.is_transaction_control() {
+                    transaction.queue(cmd, &mut self.connection).await?;
+                    continue;
+                }
+            }
+
             // Perform work needed to apply the command
-            cmd.apply(&self.db, &mut self.connection, &mut self.shutdown)
-                .await?;
+            cmd.apply(
+                &mut self.db,
+                &mut self.connection,
+                &mut self.shutdown,
+                &mut self.transaction,
+                &mut self.watcher,
+                &mut self.replica_port,
+                &mut self.session,
+            )
+            .await?;
         }
 
         Ok(())
     }
 }
+
+/// Completes once `timeout` elapsed, or never if it's zero.
+async fn idle(timeout: Duration) {
+    if timeout.is_zero() {
+        future::pending().await
+    } else {
+        time::sleep(timeout).await
+    }
+}
@@ -39,10 +39,10 @@ mod tests {
     async fn receives_shutdown_notification() {
         let (tx, rx) = broadcast::channel(1);
         let mut shutdown = Shutdown::new(rx);
-        assert_eq!(shutdown.is_shutdown(), false);
+        assert!(!shutdown.is_shutdown());
 
         tx.send(()).unwrap();
         shutdown.recv().await;
-        assert_eq!(shutdown.is_shutdown(), true);
+        assert!(shutdown.is_shutdown());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,122 @@
+//! TLS configuration of the server listener and of the client connections, using rustls
+//! with the `ring` crypto provider.
+
+use rustls::{
+    crypto::{ring, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+    server::WebPkiClientVerifier,
+    ClientConfig, RootCertStore, ServerConfig,
+};
+use std::{path::Path, sync::Arc};
+use tokio::net::TcpStream;
+use tokio_rustls::{client::TlsStream, TlsAcceptor, TlsConnector};
+
+use crate::config::{TlsAuthClients, TlsClientConfig, TlsConfig};
+
+/// Build the acceptor performing the server side of the TLS handshakes.
+pub(crate) fn acceptor(config: &TlsConfig) -> crate::FnResult<TlsAcceptor> {
+    let (cert_file, key_file) = match (&config.cert_file, &config.key_file) {
+        (Some(cert_file), Some(key_file)) => (cert_file, key_file),
+        _ => return Err("tls-cert-file and tls-key-file are required by the TLS port".into()),
+    };
+
+    let builder =
+        ServerConfig::builder_with_provider(provider()).with_safe_default_protocol_versions()?;
+
+    let builder = match (config.auth_clients, &config.ca_cert_file) {
+        (TlsAuthClients::No, _) => builder.with_no_client_auth(),
+        (_, None) => return Err("tls-ca-cert-file is required to authenticate the clients".into()),
+        (auth_clients, Some(ca_cert_file)) => {
+            let verifier = WebPkiClientVerifier::builder_with_provider(
+                Arc::new(load_roots(ca_cert_file)?),
+                provider(),
+            );
+
+            let verifier = match auth_clients {
+                TlsAuthClients::Optional => verifier.allow_unauthenticated(),
+                _ => verifier,
+            };
+
+            builder.with_client_cert_verifier(verifier.build()?)
+        }
+    };
+
+    let config = builder.with_single_cert(load_certs(cert_file)?, load_key(key_file)?)?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Establish a TLS connection over `socket` with the server `host`, verifying its
+/// certificate is valid for that host, or for `config.sni` if set.
+pub(crate) async fn connect(
+    socket: TcpStream,
+    host: &str,
+    config: &TlsClientConfig,
+) -> crate::FnResult<TlsStream<TcpStream>> {
+    let builder = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(load_roots(&config.ca_cert_file)?);
+
+    let client_config = match (&config.cert_file, &config.key_file) {
+        (Some(cert_file), Some(key_file)) => {
+            builder.with_client_auth_cert(load_certs(cert_file)?, load_key(key_file)?)?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => return Err("both a client certificate and its key are required".into()),
+    };
+
+    // IPv6 addresses are written within brackets in URLs, but not in certificates
+    let name = config.sni.as_deref().unwrap_or(host);
+    let name = ServerName::try_from(
+        name.trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string(),
+    )?;
+
+    let connector = TlsConnector::from(Arc::new(client_config));
+
+    Ok(connector.connect(name, socket).await?)
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn load_certs(path: &Path) -> crate::FnResult<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| {
+            format!(
+                "failed to read certificates from `{}`: {}",
+                path.display(),
+                err
+            )
+        })?;
+
+    if certs.is_empty() {
+        return Err(format!("no certificate in `{}`", path.display()).into());
+    }
+
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> crate::FnResult<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path).map_err(|err| {
+        format!(
+            "failed to read the private key from `{}`: {}",
+            path.display(),
+            err
+        )
+        .into()
+    })
+}
+
+fn load_roots(path: &Path) -> crate::FnResult<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+
+    for cert in load_certs(path)? {
+        roots.add(cert)?;
+    }
+
+    Ok(roots)
+}
@@ -0,0 +1,405 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Number of slots of a level, as a power of 2
+const SLOT_BITS: u32 = 6;
+
+/// Number of slots of a level, each spanning all the slots of the level below
+const SLOTS: usize = 1 << SLOT_BITS;
+
+/// Number of levels: slots span 1 ms on the first level, about 12 days on the last one
+const LEVELS: usize = 6;
+
+/// Longest delay, in milliseconds, the wheel spans. Timers expiring later are placed at
+/// this delay, then placed again once reached.
+const MAX_DELAY: u64 = (1 << (SLOT_BITS * LEVELS as u32)) - 1;
+
+/// Hierarchical timing wheel, tracking when items such as keys expire.
+///
+/// Timers are hashed to a slot of a level by their deadline, in milliseconds: the first
+/// level has a slot per millisecond for the next 64 ms, the second one a slot per 64 ms
+/// for the next 4 s, and so on. Inserting and removing a timer are O(1), unlike with a
+/// sorted set, and an item is stored once, however many levels it cascades through.
+///
+/// When the slot of a higher level is reached, its timers are placed again on the lower
+/// levels, until they expire. Timers fire at most a millisecond late, never early.
+#[derive(Debug)]
+pub struct TimingWheel<T> {
+    /// Instant deadlines are counted from
+    origin: Instant,
+
+    /// Milliseconds since `origin` up to which the wheel was polled
+    elapsed: u64,
+
+    levels: [Level; LEVELS],
+
+    /// Timers by id, `None` for the ids free to reuse
+    timers: Vec<Option<Timer<T>>>,
+
+    /// Ids of the removed timers, reused first
+    free: Vec<usize>,
+
+    /// Number of timers
+    len: usize,
+}
+
+/// Handle of a timer, to remove it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimerId(usize);
+
+#[derive(Debug, Default)]
+struct Level {
+    /// Bit set of the slots holding timers
+    occupied: u64,
+
+    /// Ids of the timers of each slot, allocated with the first timer
+    slots: Vec<Vec<usize>>,
+}
+
+#[derive(Debug)]
+struct Timer<T> {
+    when: Instant,
+    item: T,
+
+    /// Location of the timer: its level, slot and position within the slot
+    level: usize,
+    slot: usize,
+    position: usize,
+}
+
+impl<T> TimingWheel<T> {
+    pub fn new() -> TimingWheel<T> {
+        TimingWheel {
+            origin: Instant::now(),
+            elapsed: 0,
+            levels: Default::default(),
+            timers: vec![],
+            free: vec![],
+            len: 0,
+        }
+    }
+
+    /// Number of timers.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Add a timer for `item`, expiring at `when`. Returns its id, to remove it.
+    pub fn insert(&mut self, when: Instant, item: T) -> TimerId {
+        let timer = Timer {
+            when,
+            item,
+            level: 0,
+            slot: 0,
+            position: 0,
+        };
+
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.timers[id] = Some(timer);
+                id
+            }
+            None => {
+                self.timers.push(Some(timer));
+                self.timers.len() - 1
+            }
+        };
+
+        self.place(id);
+        self.len += 1;
+
+        TimerId(id)
+    }
+
+    /// Remove a timer which didn't expire yet, returning its item.
+    pub fn remove(&mut self, id: TimerId) -> Option<T> {
+        let timer = self.timers.get_mut(id.0)?.take()?;
+
+        self.unlink(timer.level, timer.slot, timer.position);
+        self.free.push(id.0);
+        self.len -= 1;
+
+        Some(timer.item)
+    }
+
+    /// Remove the timers expired at `now`, returning their items.
+    pub fn poll(&mut self, now: Instant) -> Vec<T> {
+        let now_millis = self.millis(now, false).max(self.elapsed);
+
+        // The slots reached hold the expired timers, and the ones to cascade down
+        let mut reached = vec![];
+
+        for level in 0..LEVELS {
+            let mut occupied = self.levels[level].occupied;
+
+            while occupied != 0 {
+                let slot = occupied.trailing_zeros() as usize;
+                occupied &= occupied - 1;
+
+                if self.slot_deadline(level, slot) <= now_millis {
+                    reached.append(&mut self.levels[level].slots[slot]);
+                    self.levels[level].occupied &= !(1 << slot);
+                }
+            }
+        }
+
+        self.elapsed = now_millis;
+
+        let mut expired = vec![];
+
+        for id in reached {
+            let when = self.timers[id].as_ref().map(|timer| timer.when);
+
+            if when.is_some_and(|when| when <= now) {
+                if let Some(timer) = self.timers[id].take() {
+                    self.free.push(id);
+                    self.len -= 1;
+                    expired.push(timer.item);
+                }
+            } else {
+                self.place(id);
+            }
+        }
+
+        expired
+    }
+
+    /// Returns the `Instant` at which the wheel should be polled next: no timer expires
+    /// before, and the next one does by the following millisecond.
+    pub fn next_expiration(&self) -> Option<Instant> {
+        (0..LEVELS)
+            .filter(|&level| self.levels[level].occupied != 0)
+            .map(|level| {
+                let current = self.current_slot(level);
+                let occupied = self.levels[level].occupied.rotate_right(current as u32);
+                let slot = (occupied.trailing_zeros() as usize + current) % SLOTS;
+
+                self.slot_deadline(level, slot)
+            })
+            .min()
+            .map(|deadline| self.origin + Duration::from_millis(deadline))
+    }
+
+    /// Iterate the timers in the order they expire, up to the span of their slots.
+    pub fn iter(&self) -> impl Iterator<Item = (Instant, &T)> + '_ {
+        self.levels
+            .iter()
+            .enumerate()
+            .flat_map(move |(level, timers)| {
+                let current = self.current_slot(level);
+                (0..timers.slots.len()).map(move |i| &timers.slots[(current + i) % SLOTS])
+            })
+            .flatten()
+            .filter_map(move |&id| {
+                self.timers[id]
+                    .as_ref()
+                    .map(|timer| (timer.when, &timer.item))
+            })
+    }
+
+    /// Place a timer in the slot of its deadline.
+    fn place(&mut self, id: usize) {
+        let when = match &self.timers[id] {
+            Some(timer) => timer.when,
+            None => return,
+        };
+
+        // Timers already expired are placed in the current slot, the ones expiring after
+        // the span of the wheel at its end
+        let deadline = self
+            .millis(when, true)
+            .clamp(self.elapsed, self.elapsed + MAX_DELAY);
+
+        let level = level_for(self.elapsed, deadline);
+        let slot = (deadline >> (level as u32 * SLOT_BITS)) as usize % SLOTS;
+
+        let timers = &mut self.levels[level];
+        if timers.slots.is_empty() {
+            timers.slots.resize_with(SLOTS, Vec::new);
+        }
+
+        timers.slots[slot].push(id);
+        timers.occupied |= 1 << slot;
+        let position = timers.slots[slot].len() - 1;
+
+        if let Some(timer) = &mut self.timers[id] {
+            timer.level = level;
+            timer.slot = slot;
+            timer.position = position;
+        }
+    }
+
+    /// Remove the timer at `position` from its slot.
+    fn unlink(&mut self, level: usize, slot: usize, position: usize) {
+        let timers = &mut self.levels[level];
+        let ids = &mut timers.slots[slot];
+
+        // The last timer of the slot takes the place of the removed one
+        ids.swap_remove(position);
+        if let Some(&moved) = ids.get(position) {
+            if let Some(timer) = &mut self.timers[moved] {
+                timer.position = position;
+            }
+        }
+
+        if ids.is_empty() {
+            timers.occupied &= !(1 << slot);
+        }
+    }
+
+    /// Returns the milliseconds elapsed from `origin` to `when`, rounded up if `ceil`, so
+    /// that timers don't expire early.
+    fn millis(&self, when: Instant, ceil: bool) -> u64 {
+        let elapsed = when.saturating_duration_since(self.origin);
+        let millis = elapsed.as_millis() as u64;
+
+        if ceil && !elapsed.subsec_nanos().is_multiple_of(1_000_000) {
+            millis + 1
+        } else {
+            millis
+        }
+    }
+
+    /// Returns the slot of `level` the wheel is at.
+    fn current_slot(&self, level: usize) -> usize {
+        (self.elapsed >> (level as u32 * SLOT_BITS)) as usize % SLOTS
+    }
+
+    /// Returns the deadline, in milliseconds from `origin`, at which a slot is reached.
+    ///
+    /// The slots before the current one are reached once the wheel turned.
+    fn slot_deadline(&self, level: usize, slot: usize) -> u64 {
+        let shift = level as u32 * SLOT_BITS;
+        let slot_span = 1u64 << shift;
+        let level_span = slot_span << SLOT_BITS;
+
+        let deadline = (self.elapsed & !(level_span - 1)) + slot as u64 * slot_span;
+
+        if slot < self.current_slot(level) {
+            deadline + level_span
+        } else {
+            deadline
+        }
+    }
+}
+
+impl<T> Default for TimingWheel<T> {
+    fn default() -> Self {
+        TimingWheel::new()
+    }
+}
+
+/// Returns the level of a timer expiring at `deadline`: the level of the most significant
+/// slot bits it doesn't share with `elapsed`.
+fn level_for(elapsed: u64, deadline: u64) -> usize {
+    let masked = (elapsed ^ deadline) | (SLOTS as u64 - 1);
+    let significant = 63 - masked.leading_zeros();
+
+    (significant / SLOT_BITS).min(LEVELS as u32 - 1) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn timers_expire_in_order_and_never_early() {
+        let mut wheel = TimingWheel::new();
+        let origin = wheel.origin;
+        let at = |millis: u64| origin + Duration::from_millis(millis);
+
+        wheel.insert(at(5), "a");
+        wheel.insert(at(70), "b");
+        let c = wheel.insert(at(5000), "c");
+        wheel.insert(at(3_600_000), "d");
+        wheel.insert(origin + Duration::from_micros(5500), "e");
+        assert_eq!(5, wheel.len());
+        assert_eq!(Some(at(5)), wheel.next_expiration());
+
+        assert_eq!(Vec::<&str>::new(), wheel.poll(at(4)));
+        assert_eq!(vec!["a"], wheel.poll(at(5)));
+        assert_eq!(vec!["e"], wheel.poll(at(6)));
+        assert_eq!(vec!["b"], wheel.poll(at(100)));
+
+        assert_eq!(Some("c"), wheel.remove(c));
+        assert_eq!(None, wheel.remove(c));
+
+        assert_eq!(Vec::<&str>::new(), wheel.poll(at(3_599_999)));
+        assert_eq!(vec!["d"], wheel.poll(at(3_600_000)));
+        assert!(wheel.is_empty());
+        assert_eq!(None, wheel.next_expiration());
+    }
+
+    #[test]
+    fn timers_match_a_sorted_set() {
+        fastrand::seed(42);
+
+        let mut wheel = TimingWheel::new();
+        let origin = wheel.origin;
+        let mut expected = BTreeSet::new();
+        let mut ids = vec![];
+
+        let mut now = 0;
+
+        for round in 0..200 {
+            for i in 0..50 {
+                let item = round * 50 + i;
+                let delay = match fastrand::u8(..4) {
+                    0 => fastrand::u64(..100),
+                    1 => fastrand::u64(..100_000),
+                    2 => fastrand::u64(..100_000_000),
+                    _ => fastrand::u64(..MAX_DELAY * 2),
+                };
+
+                let when =
+                    origin + Duration::from_micros((now + delay) * 1000 + fastrand::u64(..1000));
+                ids.push((wheel.insert(when, item), item));
+                expected.insert((when, item));
+            }
+
+            // Some timers are removed before they expire
+            for _ in 0..10 {
+                let (id, item) = ids.swap_remove(fastrand::usize(..ids.len()));
+                assert_eq!(Some(item), wheel.remove(id));
+                expected.retain(|&(_, expected)| expected != item);
+            }
+
+            let next = wheel.next_expiration();
+            let first = expected
+                .first()
+                .map(|(when, _)| *when + Duration::from_millis(1));
+            assert!(next <= first, "{:?} > {:?}", next, first);
+
+            now += match fastrand::u8(..3) {
+                0 => fastrand::u64(..10),
+                1 => fastrand::u64(..10_000),
+                _ => fastrand::u64(..10_000_000),
+            };
+
+            let deadline = origin + Duration::from_millis(now);
+            let mut expired = wheel.poll(deadline);
+            expired.sort();
+
+            let due: Vec<_> = expected
+                .iter()
+                .filter(|(when, _)| *when <= deadline)
+                .copied()
+                .collect();
+            let mut due_items: Vec<_> = due.iter().map(|(_, item)| *item).collect();
+            due_items.sort();
+
+            assert_eq!(due_items, expired);
+            for key in due {
+                expected.remove(&key);
+            }
+            ids.retain(|(_, item)| expired.binary_search(item).is_err());
+
+            assert_eq!(expected.len(), wheel.len());
+        }
+    }
+}
@@ -0,0 +1,377 @@
+use bytes::Bytes;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+use mini_redis::{
+    clients::client::{Client, Reply},
+    config::Config,
+    server,
+};
+
+mod integration_tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn requires_the_password_of_the_default_user() {
+        let addr = start_server(Config {
+            requirepass: Some("secret".to_string()),
+            ..Default::default()
+        })
+        .await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+
+        let err = client.get("hello").await.unwrap_err();
+        assert_eq!("NOAUTH Authentication required.", err.to_string());
+
+        let err = client.auth(None, "wrong").await.unwrap_err();
+        assert_eq!(
+            "WRONGPASS invalid username-password pair or user is disabled.",
+            err.to_string()
+        );
+
+        client.auth(None, "secret").await.unwrap();
+        client.set("hello", "world".into()).await.unwrap();
+        assert_eq!(
+            Some(Bytes::from("world")),
+            client.get("hello").await.unwrap()
+        );
+
+        // The connection authenticates again after a reset
+        let mut client = client
+            .subscribe(vec!["news".to_string()])
+            .await
+            .unwrap()
+            .reset()
+            .await
+            .unwrap();
+        assert!(client.get("hello").await.is_err());
+
+        client.auth(Some("default"), "secret").await.unwrap();
+        assert_eq!("default", client.acl_whoami().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn sets_the_password_at_runtime() {
+        let addr = start_server(Config::default()).await;
+        let mut client = Client::connect(addr).await.unwrap();
+
+        // Without password, `AUTH password` is a configuration mistake
+        let err = client.auth(None, "secret").await.unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("ERR AUTH <password> called without any password configured"));
+
+        client.config_set("requirepass", "secret").await.unwrap();
+
+        // Connections already authenticated stay so, new ones must authenticate
+        client.set("hello", "world".into()).await.unwrap();
+
+        let mut other = Client::connect(addr).await.unwrap();
+        assert!(other.get("hello").await.is_err());
+        other.auth(None, "secret").await.unwrap();
+        assert_eq!(
+            Some(Bytes::from("world")),
+            other.get("hello").await.unwrap()
+        );
+
+        client.config_set("requirepass", "").await.unwrap();
+
+        let mut other = Client::connect(addr).await.unwrap();
+        assert_eq!(
+            Some(Bytes::from("world")),
+            other.get("hello").await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn restricts_commands_keys_and_channels() {
+        let addr = start_server(Config::default()).await;
+        let mut admin = Client::connect(addr).await.unwrap();
+
+        admin
+            .acl_setuser(
+                "alice",
+                &[
+                    "on",
+                    ">pass",
+                    "~cache:*",
+                    "&news.*",
+                    "+@read",
+                    "+@transaction",
+                    "+set",
+                    "+publish",
+                    "+subscribe",
+                ],
+            )
+            .await
+            .unwrap();
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.auth(Some("alice"), "pass").await.unwrap();
+
+        let err = client.acl_whoami().await.unwrap_err();
+        assert_eq!(
+            "NOPERM User alice has no permissions to run the 'acl' command",
+            err.to_string()
+        );
+
+        client.set("cache:hello", "world".into()).await.unwrap();
+        assert_eq!(
+            Some(Bytes::from("world")),
+            client.get("cache:hello").await.unwrap()
+        );
+
+        let err = client.set("hello", "world".into()).await.unwrap_err();
+        assert_eq!("NOPERM No permissions to access a key", err.to_string());
+
+        let err = client.flushall().await.unwrap_err();
+        assert_eq!(
+            "NOPERM User alice has no permissions to run the 'flushall' command",
+            err.to_string()
+        );
+
+        assert_eq!(
+            0,
+            client.publish("news.tech", "hello".into()).await.unwrap()
+        );
+
+        let err = client.publish("sports", "hello".into()).await.unwrap_err();
+        assert_eq!("NOPERM No permissions to access a channel", err.to_string());
+
+        assert!(client.subscribe(vec!["sports".to_string()]).await.is_err());
+
+        // Commands queued in a transaction are checked as they're queued
+        let mut client = Client::connect(addr).await.unwrap();
+        client.auth(Some("alice"), "pass").await.unwrap();
+        let reply = client
+            .transaction()
+            .set("hello", "world".into())
+            .exec()
+            .await;
+        assert!(reply.is_err());
+
+        let log = client_log(&mut admin).await;
+        assert_eq!(
+            vec![
+                ("key", "multi", "hello"),
+                ("channel", "toplevel", "sports"),
+                ("command", "toplevel", "flushall"),
+                ("key", "toplevel", "hello"),
+                ("command", "toplevel", "acl"),
+            ],
+            log.iter()
+                .map(|(reason, context, object, _)| (&reason[..], &context[..], &object[..]))
+                .collect::<Vec<_>>()
+        );
+
+        // Both denied publish and subscribe count on the same entry
+        assert_eq!(2, log[1].3);
+    }
+
+    #[tokio::test]
+    async fn restricts_the_commands_called_by_scripts() {
+        let addr = start_server(Config::default()).await;
+        let mut admin = Client::connect(addr).await.unwrap();
+
+        admin
+            .acl_setuser("alice", &["on", ">pass", "~cache:*", "+@scripting", "+set"])
+            .await
+            .unwrap();
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.auth(Some("alice"), "pass").await.unwrap();
+
+        let reply = client
+            .eval(
+                "return redis.call('set', KEYS[1], 'v')",
+                &["cache:a".to_string()],
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!(Reply::Simple("OK".into()), reply);
+
+        // Keys not declared as such are checked as the script accesses them
+        let err = client
+            .eval(
+                "return redis.call('set', ARGV[1], 'v')",
+                &[],
+                &[Bytes::from("admin:x")],
+            )
+            .await
+            .unwrap_err();
+        assert_eq!("NOPERM No permissions to access a key", err.to_string());
+        assert_eq!(None, admin.get("admin:x").await.unwrap());
+
+        let err = client
+            .eval("return redis.call('flushall')", &[], &[])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "NOPERM User alice has no permissions to run the 'flushall' command",
+            err.to_string()
+        );
+        assert_eq!(Some(Bytes::from("v")), admin.get("cache:a").await.unwrap());
+
+        let log = client_log(&mut admin).await;
+        assert_eq!(
+            vec![("command", "lua", "flushall"), ("key", "lua", "admin:x")],
+            log.iter()
+                .map(|(reason, context, object, _)| (&reason[..], &context[..], &object[..]))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn manages_users() {
+        let addr = start_server(Config::default()).await;
+        let mut client = Client::connect(addr).await.unwrap();
+
+        assert_eq!("default", client.acl_whoami().await.unwrap());
+        assert_eq!(
+            vec!["user default on nopass ~* &* +@all"],
+            client.acl_list().await.unwrap()
+        );
+
+        client
+            .acl_setuser("bob", &["on", "nopass", "+@all", "-flushall", "allkeys"])
+            .await
+            .unwrap();
+
+        let user = client.acl_getuser("bob").await.unwrap();
+        let fields = match user {
+            Reply::Array(fields) => fields,
+            reply => panic!("unexpected reply {:?}", reply),
+        };
+        assert_eq!(Reply::Bulk("commands".into()), fields[4]);
+        assert_eq!(Reply::Bulk("+@all -flushall".into()), fields[5]);
+        assert_eq!(Reply::Bulk("~*".into()), fields[7]);
+
+        let err = client
+            .acl_setuser("bob", &["+get", "+nosuchcommand"])
+            .await
+            .unwrap_err();
+        assert_eq!("ERR Error in ACL SETUSER modifier '+nosuchcommand': Unknown command or category name in ACL", err.to_string());
+
+        // The connections of a deleted user must authenticate again
+        let mut other = Client::connect(addr).await.unwrap();
+        other.auth(Some("bob"), "anything").await.unwrap();
+        other.get("hello").await.unwrap();
+
+        assert_eq!(
+            1,
+            client
+                .acl_deluser(&["bob".to_string(), "carol".to_string()])
+                .await
+                .unwrap()
+        );
+        assert_eq!(Reply::Null, client.acl_getuser("bob").await.unwrap());
+
+        let err = other.get("hello").await.unwrap_err();
+        assert_eq!("NOAUTH Authentication required.", err.to_string());
+
+        let err = client
+            .acl_deluser(&["default".to_string()])
+            .await
+            .unwrap_err();
+        assert_eq!("ERR The 'default' user cannot be removed", err.to_string());
+
+        assert!(client
+            .acl_cat(None)
+            .await
+            .unwrap()
+            .contains(&"keyspace".to_string()));
+        assert!(client
+            .acl_cat(Some("pubsub"))
+            .await
+            .unwrap()
+            .contains(&"publish".to_string()));
+        assert!(client.acl_cat(Some("nosuchcategory")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn logs_failed_authentications() {
+        let addr = start_server(Config::default()).await;
+        let mut client = Client::connect(addr).await.unwrap();
+
+        client.acl_setuser("alice", &["on", ">pass"]).await.unwrap();
+
+        for _ in 0..3 {
+            assert!(client.auth(Some("alice"), "wrong").await.is_err());
+        }
+
+        assert_eq!(
+            vec![(
+                "auth".to_string(),
+                "toplevel".to_string(),
+                "AUTH".to_string(),
+                3
+            )],
+            client_log(&mut client).await
+        );
+
+        client.acl_log_reset().await.unwrap();
+        assert!(client_log(&mut client).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn loads_users_from_the_acl_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.acl");
+        std::fs::write(
+            &path,
+            "# Read-only users\nuser reader on >pass ~* +@read\nuser default off\n",
+        )
+        .unwrap();
+
+        let addr = start_server(Config {
+            aclfile: Some(path),
+            ..Default::default()
+        })
+        .await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        assert!(client.get("hello").await.is_err());
+        assert!(client.auth(None, "").await.is_err());
+
+        client.auth(Some("reader"), "pass").await.unwrap();
+        assert_eq!(None, client.get("hello").await.unwrap());
+        assert!(client.set("hello", "world".into()).await.is_err());
+    }
+
+    /// Returns the reason, context, object and count of the entries of the ACL log.
+    async fn client_log(client: &mut Client) -> Vec<(String, String, String, i64)> {
+        let entries = match client.acl_log(None).await.unwrap() {
+            Reply::Array(entries) => entries,
+            reply => panic!("unexpected reply {:?}", reply),
+        };
+
+        entries
+            .into_iter()
+            .map(|entry| match &entry {
+                Reply::Array(fields) => match &fields[..] {
+                    [_, Reply::Integer(count), _, Reply::Bulk(reason), _, Reply::Bulk(context), _, Reply::Bulk(object), ..] => (
+                        String::from_utf8(reason.to_vec()).unwrap(),
+                        String::from_utf8(context.to_vec()).unwrap(),
+                        String::from_utf8(object.to_vec()).unwrap(),
+                        *count,
+                    ),
+                    _ => panic!("unexpected entry {:?}", entry),
+                },
+                _ => panic!("unexpected entry {:?}", entry),
+            })
+            .collect()
+    }
+
+    async fn start_server(config: Config) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            server::run_with_config(listener, config, tokio::signal::ctrl_c()).await;
+        });
+
+        addr
+    }
+}
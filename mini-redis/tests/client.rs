@@ -1,7 +1,19 @@
-use std::net::SocketAddr;
+use bytes::Bytes;
+use std::{net::SocketAddr, time::Duration};
 use tokio::{net::TcpListener, task::JoinHandle};
 
-use mini_redis::{clients::client::Client, server};
+use mini_redis::{
+    clients::{
+        client::{Client, MigrateOptions, Reply, ServerRole},
+        cluster_client::ClusterClient,
+    },
+    commands::SetSlot,
+    config::{
+        AofConfig, AppendFsync, ClusterConfig, Config, KeyspaceEvents, MaxMemoryConfig,
+        MaxMemoryPolicy, RdbConfig, RecoveryPoint, SaveRule, SlotRange,
+    },
+    server::{self, ListenSocket},
+};
 
 mod integration_tests {
     use super::*;
@@ -105,12 +117,2293 @@ mod integration_tests {
         assert_eq!(subscriber.get_subscribed().len(), 0);
     }
 
+    /// test that a client receives messages on channels matching a subscribed pattern
+    #[tokio::test]
+    async fn receive_message_subscribed_pattern() {
+        let (addr, _) = start_server().await;
+
+        let client = Client::connect(addr).await.unwrap();
+        let mut subscriber = client.psubscribe(vec!["news.*".into()]).await.unwrap();
+
+        tokio::spawn(async move {
+            let mut client = Client::connect(addr).await.unwrap();
+            client.publish("news.tech", "rust".into()).await.unwrap()
+        });
+
+        let message = subscriber.next_message().await.unwrap().unwrap();
+        assert_eq!("news.tech", &message.channel);
+        assert_eq!(Some("news.*"), message.pattern.as_deref());
+        assert_eq!(b"rust", &message.content[..]);
+
+        subscriber.punsubscribe(&[]).await.unwrap();
+        assert_eq!(subscriber.get_subscribed_patterns().len(), 0);
+    }
+
+    /// test that a subscriber can be health checked and reset to a regular client
+    #[tokio::test]
+    async fn ping_and_reset_subscriber() {
+        let (addr, _) = start_server().await;
+
+        let client = Client::connect(addr).await.unwrap();
+        let mut subscriber = client.subscribe(vec!["hello".into()]).await.unwrap();
+
+        let pong = subscriber.ping(Some("alive".into())).await.unwrap();
+        assert_eq!(b"alive", &pong[..]);
+
+        let mut client = subscriber.reset().await.unwrap();
+        client.set("hello", "world".into()).await.unwrap();
+
+        let value = client.get("hello").await.unwrap().unwrap();
+        assert_eq!(b"world", &value[..]);
+
+        client.quit().await.unwrap();
+    }
+
+    /// test that keyspace and keyevent notifications are published when keys
+    /// are set, deleted and expired
+    #[tokio::test]
+    async fn receive_keyspace_notifications() {
+        let config = Config {
+            notify_keyspace_events: "KEA".parse().unwrap(),
+            ..Default::default()
+        };
+        let (addr, _) = start_server_with_config(config).await;
+
+        let client = Client::connect(addr).await.unwrap();
+        let mut subscriber = client
+            .psubscribe(vec!["__key*@0__:*".into()])
+            .await
+            .unwrap();
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.set("hello", "world".into()).await.unwrap();
+        assert_eq!(
+            1,
+            client
+                .del(&["hello".into(), "missing".into()])
+                .await
+                .unwrap()
+        );
+        client
+            .set_expires("session", "token".into(), Duration::from_millis(10))
+            .await
+            .unwrap();
+
+        let expected = [
+            ("__keyspace@0__:hello", "set"),
+            ("__keyevent@0__:set", "hello"),
+            ("__keyspace@0__:hello", "del"),
+            ("__keyevent@0__:del", "hello"),
+            ("__keyspace@0__:session", "set"),
+            ("__keyevent@0__:set", "session"),
+            ("__keyspace@0__:session", "expired"),
+            ("__keyevent@0__:expired", "session"),
+        ];
+
+        for (channel, content) in expected {
+            let message = subscriber.next_message().await.unwrap().unwrap();
+            assert_eq!(channel, &message.channel);
+            assert_eq!(content.as_bytes(), &message.content[..]);
+        }
+    }
+
+    /// test that only the configured classes of keyspace events are published
+    #[tokio::test]
+    async fn receive_configured_keyspace_notifications() {
+        let config = Config {
+            notify_keyspace_events: KeyspaceEvents::KEYEVENT | KeyspaceEvents::GENERIC,
+            ..Default::default()
+        };
+        let (addr, _) = start_server_with_config(config).await;
+
+        let client = Client::connect(addr).await.unwrap();
+        let mut subscriber = client
+            .psubscribe(vec!["__key*@0__:*".into()])
+            .await
+            .unwrap();
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.set("hello", "world".into()).await.unwrap();
+        client.del(&["hello".into()]).await.unwrap();
+
+        // `SET` is a string command, only `DEL` is notified
+        let message = subscriber.next_message().await.unwrap().unwrap();
+        assert_eq!("__keyevent@0__:del", &message.channel);
+        assert_eq!(b"hello", &message.content[..]);
+    }
+
+    /// test that databases are isolated from each other
+    #[tokio::test]
+    async fn select_database() {
+        let (addr, _) = start_server().await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.set("hello", "world".into()).await.unwrap();
+
+        client.select(1).await.unwrap();
+        assert_eq!(None, client.get("hello").await.unwrap());
+
+        client.set("hello", "there".into()).await.unwrap();
+        assert_eq!(1, client.dbsize().await.unwrap());
+
+        // Other connections still use database 0
+        let mut other = Client::connect(addr).await.unwrap();
+        let value = other.get("hello").await.unwrap().unwrap();
+        assert_eq!(b"world", &value[..]);
+
+        let err = client.select(16).await.unwrap_err();
+        assert_eq!("ERR DB index is out of range", err.to_string());
+    }
+
+    /// test that swapping databases is immediately visible to all connections
+    #[tokio::test]
+    async fn swap_databases() {
+        let (addr, _) = start_server().await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.set("hello", "world".into()).await.unwrap();
+
+        let mut other = Client::connect(addr).await.unwrap();
+        other.select(1).await.unwrap();
+
+        client.swapdb(0, 1).await.unwrap();
+
+        assert_eq!(None, client.get("hello").await.unwrap());
+        let value = other.get("hello").await.unwrap().unwrap();
+        assert_eq!(b"world", &value[..]);
+    }
+
+    /// test moving a key between databases, keeping its expiration
+    #[tokio::test]
+    async fn move_key_between_databases() {
+        let (addr, _) = start_server().await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client
+            .set_expires("hello", "world".into(), Duration::from_millis(500))
+            .await
+            .unwrap();
+
+        assert!(client.move_key("hello", 1).await.unwrap());
+        assert!(!client.move_key("hello", 1).await.unwrap());
+        assert_eq!(0, client.dbsize().await.unwrap());
+
+        client.select(1).await.unwrap();
+        let value = client.get("hello").await.unwrap().unwrap();
+        assert_eq!(b"world", &value[..]);
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(None, client.get("hello").await.unwrap());
+    }
+
+    /// test flushing one or all databases
+    #[tokio::test]
+    async fn flush_databases() {
+        let (addr, _) = start_server().await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.set("hello", "world".into()).await.unwrap();
+        client.select(1).await.unwrap();
+        client.set("hello", "world".into()).await.unwrap();
+        client.set("foo", "bar".into()).await.unwrap();
+
+        client.flushdb().await.unwrap();
+        assert_eq!(0, client.dbsize().await.unwrap());
+
+        client.select(0).await.unwrap();
+        assert_eq!(1, client.dbsize().await.unwrap());
+
+        client.flushall().await.unwrap();
+        assert_eq!(0, client.dbsize().await.unwrap());
+    }
+
+    /// test that keyspace notifications carry the index of the database
+    #[tokio::test]
+    async fn receive_keyspace_notifications_from_selected_database() {
+        let config = Config {
+            notify_keyspace_events: KeyspaceEvents::ALL | KeyspaceEvents::KEYEVENT,
+            ..Default::default()
+        };
+        let (addr, _) = start_server_with_config(config).await;
+
+        let client = Client::connect(addr).await.unwrap();
+        let mut subscriber = client
+            .psubscribe(vec!["__keyevent@*__:*".into()])
+            .await
+            .unwrap();
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.select(3).await.unwrap();
+        client
+            .set_expires("hello", "world".into(), Duration::from_millis(100))
+            .await
+            .unwrap();
+
+        let message = subscriber.next_message().await.unwrap().unwrap();
+        assert_eq!("__keyevent@3__:set", &message.channel);
+
+        let message = subscriber.next_message().await.unwrap().unwrap();
+        assert_eq!("__keyevent@3__:expired", &message.channel);
+        assert_eq!(b"hello", &message.content[..]);
+    }
+
+    /// test that a transaction returns the replies of its commands, in order
+    #[tokio::test]
+    async fn transaction_exec() {
+        let (addr, _) = start_server().await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.set("hello", "world".into()).await.unwrap();
+
+        let replies = client
+            .transaction()
+            .get("hello")
+            .set("hello", "there".into())
+            .del(&["hello".into(), "foo".into()])
+            .select(99)
+            .exec()
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                Reply::Bulk("world".into()),
+                Reply::Simple("OK".into()),
+                Reply::Integer(1),
+                Reply::Error("ERR DB index is out of range".into()),
+            ],
+            replies
+        );
+
+        // The connection is usable after the transaction
+        assert_eq!(None, client.get("hello").await.unwrap());
+    }
+
+    /// test that the database selected within a transaction remains selected
+    #[tokio::test]
+    async fn transaction_select() {
+        let (addr, _) = start_server().await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+
+        client
+            .transaction()
+            .select(1)
+            .set("hello", "world".into())
+            .exec()
+            .await
+            .unwrap();
+
+        let value = client.get("hello").await.unwrap().unwrap();
+        assert_eq!(b"world", &value[..]);
+
+        client.select(0).await.unwrap();
+        assert_eq!(None, client.get("hello").await.unwrap());
+    }
+
+    /// test that a transaction isn't applied when a watched key was modified
+    #[tokio::test]
+    async fn transaction_watch() {
+        let (addr, _) = start_server().await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.set("stock", "10".into()).await.unwrap();
+        client.watch(&["stock".into()]).await.unwrap();
+
+        let mut other = Client::connect(addr).await.unwrap();
+        other.set("stock", "9".into()).await.unwrap();
+
+        let replies = client
+            .transaction()
+            .set("stock", "9".into())
+            .exec()
+            .await
+            .unwrap();
+        assert_eq!(None, replies);
+
+        // Watches are cleared by `EXEC`, the next transaction is applied
+        client.watch(&["stock".into()]).await.unwrap();
+
+        let replies = client
+            .transaction()
+            .set("stock", "8".into())
+            .exec()
+            .await
+            .unwrap();
+        assert_eq!(Some(vec![Reply::Simple("OK".into())]), replies);
+
+        // A key deleted after being watched aborts the transaction too
+        client.watch(&["stock".into()]).await.unwrap();
+        other.del(&["stock".into()]).await.unwrap();
+
+        let replies = client.transaction().get("stock").exec().await.unwrap();
+        assert_eq!(None, replies);
+    }
+
+    /// test that expiring or unwatching keys is taken into account
+    #[tokio::test]
+    async fn transaction_watch_expired_and_unwatched() {
+        let (addr, _) = start_server().await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client
+            .set_expires("lock", "owner".into(), Duration::from_millis(100))
+            .await
+            .unwrap();
+        client.watch(&["lock".into()]).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let replies = client.transaction().get("lock").exec().await.unwrap();
+        assert_eq!(None, replies);
+
+        client.watch(&["lock".into()]).await.unwrap();
+        client.unwatch().await.unwrap();
+
+        let mut other = Client::connect(addr).await.unwrap();
+        other.set("lock", "other".into()).await.unwrap();
+
+        let replies = client.transaction().get("lock").exec().await.unwrap();
+        assert_eq!(Some(vec![Reply::Bulk("other".into())]), replies);
+    }
+
+    /// test that keys of different shards are written concurrently, and that commands
+    /// spanning all shards see them all
+    #[tokio::test]
+    async fn sharded_keyspace() {
+        let config = Config {
+            shards: 4,
+            ..Config::default()
+        };
+        let (addr, _) = start_server_with_config(config).await;
+
+        let keys: Vec<String> = (0..64).map(|i| format!("key:{}", i)).collect();
+
+        let writers: Vec<_> = keys
+            .chunks(8)
+            .map(|chunk| {
+                let chunk = chunk.to_vec();
+
+                tokio::spawn(async move {
+                    let mut client = Client::connect(addr).await.unwrap();
+
+                    for key in chunk {
+                        client
+                            .set_expires(&key, key.clone().into(), Duration::from_secs(60))
+                            .await
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.await.unwrap();
+        }
+
+        let mut client = Client::connect(addr).await.unwrap();
+        assert_eq!(64, client.dbsize().await.unwrap());
+
+        let replies = client
+            .transaction()
+            .get("key:0")
+            .get("key:63")
+            .del(&keys)
+            .exec()
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                Reply::Bulk("key:0".into()),
+                Reply::Bulk("key:63".into()),
+                Reply::Integer(64),
+            ],
+            replies
+        );
+
+        // The memory of the keys of every shard is released
+        assert_eq!(0, client.dbsize().await.unwrap());
+        let info = client.info(Some("memory")).await.unwrap();
+        assert!(info.contains("used_memory:0\r\n"), "{}", info);
+    }
+
+    #[tokio::test]
+    async fn eval_rate_limiter() {
+        let (addr, _) = start_server().await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+
+        let script = "
+            local current = redis.call('GET', KEYS[1])
+            if current and tonumber(current) >= tonumber(ARGV[1]) then
+                return -1
+            end
+            local count = (tonumber(current) or 0) + 1
+            redis.call('SET', KEYS[1], count)
+            return count
+        ";
+        let keys = ["requests".to_string()];
+        let args = ["2".into()];
+
+        assert_eq!(
+            Reply::Integer(1),
+            client.eval(script, &keys, &args).await.unwrap()
+        );
+        assert_eq!(
+            Reply::Integer(2),
+            client.eval(script, &keys, &args).await.unwrap()
+        );
+        assert_eq!(
+            Reply::Integer(-1),
+            client.eval(script, &keys, &args).await.unwrap()
+        );
+        assert_eq!(Some("2".into()), client.get("requests").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn evalsha_and_script_cache() {
+        let (addr, _) = start_server().await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.set("lock", "owner".into()).await.unwrap();
+
+        let release = "
+            if redis.call('get', KEYS[1]) == ARGV[1] then
+                return redis.call('del', KEYS[1])
+            end
+            return 0
+        ";
+        let sha1 = client.script_load(release).await.unwrap();
+        let keys = ["lock".to_string()];
+
+        let reply = client
+            .evalsha(&sha1, &keys, &["other".into()])
+            .await
+            .unwrap();
+        assert_eq!(Reply::Integer(0), reply);
+        let reply = client
+            .evalsha(&sha1, &keys, &["owner".into()])
+            .await
+            .unwrap();
+        assert_eq!(Reply::Integer(1), reply);
+        assert_eq!(None, client.get("lock").await.unwrap());
+
+        let unknown = "0000000000000000000000000000000000000000".to_string();
+        let exists = client
+            .script_exists(&[sha1.clone(), unknown])
+            .await
+            .unwrap();
+        assert_eq!(vec![true, false], exists);
+
+        client.script_flush().await.unwrap();
+        assert_eq!(
+            vec![false],
+            client
+                .script_exists(std::slice::from_ref(&sha1))
+                .await
+                .unwrap()
+        );
+
+        let err = client.evalsha(&sha1, &keys, &[]).await.unwrap_err();
+        assert!(err.to_string().starts_with("NOSCRIPT"));
+
+        // `eval` falls back to sending the script, caching it again
+        let reply = client
+            .eval(release, &keys, &["owner".into()])
+            .await
+            .unwrap();
+        assert_eq!(Reply::Integer(0), reply);
+        assert_eq!(vec![true], client.script_exists(&[sha1]).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn eval_replies_and_errors() {
+        let (addr, _) = start_server().await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+
+        let reply = client
+            .eval("return {1, 'two', {ok = 'OK'}, false, 3.7}", &[], &[])
+            .await
+            .unwrap();
+        assert_eq!(
+            Reply::Array(vec![
+                Reply::Integer(1),
+                Reply::Bulk("two".into()),
+                Reply::Simple("OK".into()),
+                Reply::Null,
+                Reply::Integer(3),
+            ]),
+            reply
+        );
+
+        // `redis.pcall` returns errors as a table, while `redis.call` raises them
+        let reply = client
+            .eval("return redis.pcall('move', 'key', 1000)['err']", &[], &[])
+            .await
+            .unwrap();
+        assert_eq!(Reply::Bulk("ERR DB index is out of range".into()), reply);
+
+        let err = client
+            .eval("return redis.call('move', 'key', 1000)", &[], &[])
+            .await
+            .unwrap_err();
+        assert_eq!("ERR DB index is out of range", err.to_string());
+
+        let err = client
+            .eval("return redis.call('subscribe', 'channel')", &[], &[])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "ERR This Redis command is not allowed from script",
+            err.to_string()
+        );
+
+        let err = client.eval("return +", &[], &[]).await.unwrap_err();
+        assert!(err.to_string().starts_with("ERR Error compiling script"));
+    }
+
+    #[tokio::test]
+    async fn script_kill() {
+        let (addr, _) = start_server().await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        let mut other = Client::connect(addr).await.unwrap();
+
+        let err = other.script_kill().await.unwrap_err();
+        assert!(err.to_string().starts_with("NOTBUSY"));
+
+        let script = tokio::spawn(async move { client.eval("while true do end", &[], &[]).await });
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        other.script_kill().await.unwrap();
+
+        let err = script.await.unwrap().unwrap_err();
+        assert_eq!(
+            "ERR Script killed by user with SCRIPT KILL...",
+            err.to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn script_time_limit() {
+        let config = Config {
+            lua_time_limit: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let (addr, _) = start_server_with_config(config).await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        let mut other = Client::connect(addr).await.unwrap();
+
+        let script = tokio::spawn(async move { client.eval("while true do end", &[], &[]).await });
+
+        // Other connections wait for the script to end, without blocking the server
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(None, other.get("key").await.unwrap());
+
+        // A script which didn't write is aborted once past its time limit
+        let err = script.await.unwrap().unwrap_err();
+        assert_eq!("ERR Script timed out after 200 ms", err.to_string());
+    }
+
+    #[tokio::test]
+    async fn busy_script() {
+        let config = Config {
+            lua_time_limit: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let (addr, handle) = start_server_with_config(config).await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        let mut other = Client::connect(addr).await.unwrap();
+
+        let script = tokio::spawn(async move {
+            client
+                .eval(
+                    "redis.call('set', KEYS[1], 'v') while true do end",
+                    &["key".into()],
+                    &[],
+                )
+                .await
+        });
+
+        // A script that wrote can't be killed, nor is aborted past its time limit
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let err = other.script_kill().await.unwrap_err();
+        assert!(err.to_string().starts_with("UNKILLABLE"));
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let err = other.get("key").await.unwrap_err();
+        assert_eq!(
+            "BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSAVE.",
+            err.to_string()
+        );
+
+        let err = Client::connect(addr)
+            .await
+            .unwrap()
+            .shutdown(None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("BUSY"));
+
+        // Only stopping the server without saving ends it
+        Client::connect(addr)
+            .await
+            .unwrap()
+            .shutdown(Some(false))
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(script.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn shutdown_saves_the_databases() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            dir: dir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let (addr, handle) = start_server_with_config(config.clone()).await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.set("hello", "world".into()).await.unwrap();
+        client.shutdown(Some(true)).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let (addr, _) = start_server_with_config(config).await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        assert_eq!(Some("world".into()), client.get("hello").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn save_and_load_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            dir: dir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let (addr, _) = start_server_with_config(config.clone()).await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.set("hello", "world".into()).await.unwrap();
+        client
+            .set_expires("session", "token".into(), Duration::from_secs(60))
+            .await
+            .unwrap();
+        client
+            .set_expires("short", "lived".into(), Duration::from_millis(100))
+            .await
+            .unwrap();
+        client.select(2).await.unwrap();
+        client.set("other", "database".into()).await.unwrap();
+
+        let before = client.lastsave().await.unwrap();
+        client.save().await.unwrap();
+        assert!(client.lastsave().await.unwrap() >= before);
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // A new server loads the snapshot when starting
+        let (addr, _) = start_server_with_config(config).await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        assert_eq!(Some("world".into()), client.get("hello").await.unwrap());
+        assert_eq!(Some("token".into()), client.get("session").await.unwrap());
+        assert_eq!(None, client.get("short").await.unwrap());
+        assert_eq!(2, client.dbsize().await.unwrap());
+
+        client.select(2).await.unwrap();
+        assert_eq!(Some("database".into()), client.get("other").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn bgsave_and_save_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dump.rdb");
+
+        let config = Config {
+            dir: dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let (addr, _) = start_server_with_config(config).await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.set("hello", "world".into()).await.unwrap();
+        client.bgsave().await.unwrap();
+
+        wait_for_file(&path).await;
+        std::fs::remove_file(&path).unwrap();
+
+        // A save is triggered once enough writes were applied
+        let config = Config {
+            dir: dir.path().to_path_buf(),
+            rdb: RdbConfig {
+                save: vec![SaveRule {
+                    seconds: 0,
+                    changes: 2,
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (addr, _) = start_server_with_config(config).await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.set("first", "write".into()).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!path.exists());
+
+        client.set("second", "write".into()).await.unwrap();
+        wait_for_file(&path).await;
+    }
+
+    #[tokio::test]
+    async fn append_only_file_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = aof_config(dir.path());
+
+        let (addr, _) = start_server_with_config(config.clone()).await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.set("hello", "world".into()).await.unwrap();
+        client
+            .set_expires("session", "token".into(), Duration::from_secs(60))
+            .await
+            .unwrap();
+        client
+            .set_expires("short", "lived".into(), Duration::from_millis(100))
+            .await
+            .unwrap();
+        client.set("removed", "soon".into()).await.unwrap();
+        client.del(&["removed".into()]).await.unwrap();
+        client.set("moved", "away".into()).await.unwrap();
+        assert!(client.move_key("moved", 1).await.unwrap());
+        client
+            .transaction()
+            .set("first", "1".into())
+            .set("second", "2".into())
+            .exec()
+            .await
+            .unwrap();
+        client.select(2).await.unwrap();
+        client.set("other", "database".into()).await.unwrap();
+        client.swapdb(2, 3).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // A new server replays the append-only file when starting
+        let (addr, _) = start_server_with_config(config).await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        assert_eq!(Some("world".into()), client.get("hello").await.unwrap());
+        assert_eq!(Some("token".into()), client.get("session").await.unwrap());
+        assert_eq!(None, client.get("short").await.unwrap());
+        assert_eq!(None, client.get("removed").await.unwrap());
+        assert_eq!(Some("2".into()), client.get("second").await.unwrap());
+        assert_eq!(4, client.dbsize().await.unwrap());
+
+        client.select(1).await.unwrap();
+        assert_eq!(Some("away".into()), client.get("moved").await.unwrap());
+        client.select(2).await.unwrap();
+        assert_eq!(0, client.dbsize().await.unwrap());
+        client.select(3).await.unwrap();
+        assert_eq!(Some("database".into()), client.get("other").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn append_only_file_truncated_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("appendonly.aof");
+
+        // A crash left a command and a transaction half written
+        let complete = "*3\r\n$3\r\nset\r\n$5\r\nhello\r\n$5\r\nworld\r\n";
+        let incomplete = "*1\r\n$5\r\nmulti\r\n*3\r\n$3\r\nset\r\n$3\r\none\r\n$1\r\n1\r\n*3\r\n$3\r\nset\r\n$3\r\ntw";
+        std::fs::write(&path, format!("{}{}", complete, incomplete)).unwrap();
+
+        let (addr, _) = start_server_with_config(aof_config(dir.path())).await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        assert_eq!(Some("world".into()), client.get("hello").await.unwrap());
+        assert_eq!(None, client.get("one").await.unwrap());
+
+        // New writes are appended after the last complete command
+        client.set("after", "crash".into()).await.unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with(complete));
+        assert!(!content.contains("multi"));
+        assert!(content.ends_with("$5\r\nafter\r\n$5\r\ncrash\r\n"));
+    }
+
+    #[tokio::test]
+    async fn bgrewriteaof_compacts_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("appendonly.aof");
+        let config = aof_config(dir.path());
+
+        let (addr, _) = start_server_with_config(config.clone()).await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        for i in 0..100 {
+            client.set("counter", i.to_string().into()).await.unwrap();
+        }
+        let size = std::fs::metadata(&path).unwrap().len();
+
+        client.bgrewriteaof().await.unwrap();
+
+        // Writes keep being applied while the file is rewritten
+        client.set("during", "rewrite".into()).await.unwrap();
+
+        for _ in 0..300 {
+            if std::fs::metadata(&path).unwrap().len() < size / 10 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(std::fs::metadata(&path).unwrap().len() < size / 10);
+
+        client.set("after", "rewrite".into()).await.unwrap();
+
+        let (addr, _) = start_server_with_config(config).await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        assert_eq!(Some("99".into()), client.get("counter").await.unwrap());
+        assert_eq!(Some("rewrite".into()), client.get("during").await.unwrap());
+        assert_eq!(Some("rewrite".into()), client.get("after").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn point_in_time_recovery() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("appendonly.aof");
+        let mut config = aof_config(dir.path());
+        config.aof.timestamps = true;
+
+        let (addr, _) = start_server_with_config(config.clone()).await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.set("before", "incident".into()).await.unwrap();
+        let offset = std::fs::metadata(&path).unwrap().len();
+        client.del(&["before".into()]).await.unwrap();
+        client.set("after", "incident".into()).await.unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("#TS:"));
+
+        // The writes logged after the recovery point are moved aside
+        config.aof.recover_to = Some(RecoveryPoint::Offset(offset));
+        let (addr, _) = start_server_with_config(config.clone()).await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        assert_eq!(Some("incident".into()), client.get("before").await.unwrap());
+        assert_eq!(None, client.get("after").await.unwrap());
+        assert_eq!(offset, std::fs::metadata(&path).unwrap().len());
+
+        let skipped: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("appendonly.aof.skipped-"))
+            .collect();
+        assert_eq!(1, skipped.len());
+
+        // Recovering to a time before any write leaves the databases empty
+        config.aof.recover_to = Some(RecoveryPoint::Timestamp(0));
+        let (addr, _) = start_server_with_config(config).await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        assert_eq!(0, client.dbsize().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn bgrewriteaof_requires_append_only_file() {
+        let (addr, _) = start_server().await;
+        let mut client = Client::connect(addr).await.unwrap();
+
+        let err = client.bgrewriteaof().await.unwrap_err();
+        assert_eq!("ERR Append only file is disabled", err.to_string());
+    }
+
+    #[tokio::test]
+    async fn dump_and_restore() {
+        let (addr, _) = start_server().await;
+        let mut source = Client::connect(addr).await.unwrap();
+
+        assert_eq!(None, source.dump("missing").await.unwrap());
+
+        let value = "x".repeat(20_000);
+        source.set("hello", value.clone().into()).await.unwrap();
+        let payload = source.dump("hello").await.unwrap().unwrap();
+
+        // The payload recreates the key on another server
+        let (addr, _) = start_server().await;
+        let mut target = Client::connect(addr).await.unwrap();
+
+        target
+            .restore("hello", None, payload.clone(), false)
+            .await
+            .unwrap();
+        assert_eq!(Some(value.into()), target.get("hello").await.unwrap());
+
+        let err = target
+            .restore("hello", None, payload.clone(), false)
+            .await
+            .unwrap_err();
+        assert_eq!("BUSYKEY Target key name already exists.", err.to_string());
+
+        target.set("hello", "old".into()).await.unwrap();
+        target
+            .restore(
+                "hello",
+                Some(Duration::from_millis(100)),
+                payload.clone(),
+                true,
+            )
+            .await
+            .unwrap();
+        assert_eq!(Some(payload.clone()), target.dump("hello").await.unwrap());
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(None, target.get("hello").await.unwrap());
+
+        let mut corrupted = payload.to_vec();
+        corrupted[3] ^= 1;
+        let err = target
+            .restore("hello", None, corrupted.into(), false)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "ERR DUMP payload version or checksum are wrong",
+            err.to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn restore_rejects_overflowing_ttls() {
+        let (addr, _) = start_server().await;
+        let mut client = Client::connect(addr).await.unwrap();
+
+        client.set("hello", "world".into()).await.unwrap();
+        let payload = client.dump("hello").await.unwrap().unwrap();
+
+        let err = client
+            .restore(
+                "hello",
+                Some(Duration::from_millis(i64::MAX as u64)),
+                payload,
+                true,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "ERR invalid expire time in 'restore' command",
+            err.to_string()
+        );
+
+        // The shard of the key is still available
+        assert_eq!(Some("world".into()), client.get("hello").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn restore_rejects_lzf_strings_longer_than_their_input() {
+        let (addr, _) = start_server().await;
+        let mut client = Client::connect(addr).await.unwrap();
+
+        // An LZF string announcing a length of 2^64 - 1 bytes, with a zero checksum which
+        // isn't verified
+        let mut payload = b"\x00\xC3\x01\x81".to_vec();
+        payload.extend_from_slice(&[0xFF; 8]);
+        payload.push(0);
+        payload.extend_from_slice(&9u16.to_le_bytes());
+        payload.extend_from_slice(&[0; 8]);
+
+        let err = client
+            .restore("k", None, payload.into(), false)
+            .await
+            .unwrap_err();
+        assert_eq!("ERR Bad data format", err.to_string());
+
+        // The server and the shard of the key are still available
+        client.set("k", "v".into()).await.unwrap();
+        assert_eq!(Some("v".into()), client.get("k").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn migrate_moves_keys_to_another_server() {
+        let (addr, _) = start_server().await;
+        let mut source = Client::connect(addr).await.unwrap();
+        let (target_addr, _) = start_server().await;
+        let mut target = Client::connect(target_addr).await.unwrap();
+        let port = target_addr.port();
+        let timeout = Duration::from_secs(1);
+
+        source
+            .set_expires("a", "1".into(), Duration::from_secs(100))
+            .await
+            .unwrap();
+        source.set("b", "2".into()).await.unwrap();
+        source.set("c", "3".into()).await.unwrap();
+
+        // The key is moved, along with its TTL
+        let moved = source
+            .migrate(
+                "127.0.0.1",
+                port,
+                &["a".to_string()],
+                0,
+                timeout,
+                Default::default(),
+            )
+            .await;
+        assert!(moved.unwrap());
+        assert_eq!(None, source.get("a").await.unwrap());
+        assert_eq!(Some("1".into()), target.get("a").await.unwrap());
+        assert!(target.dump("a").await.unwrap().is_some());
+
+        // Several keys, kept with `COPY`, to another database
+        let keys = vec!["b".to_string(), "c".to_string(), "missing".to_string()];
+        let copy = MigrateOptions {
+            copy: true,
+            ..Default::default()
+        };
+        assert!(source
+            .migrate("127.0.0.1", port, &keys, 1, timeout, copy)
+            .await
+            .unwrap());
+        assert_eq!(Some("2".into()), source.get("b").await.unwrap());
+
+        target.select(1).await.unwrap();
+        assert_eq!(Some("2".into()), target.get("b").await.unwrap());
+        assert_eq!(Some("3".into()), target.get("c").await.unwrap());
+
+        // Existing keys are only overwritten with `REPLACE`
+        source.set("b", "4".into()).await.unwrap();
+        let err = source
+            .migrate("127.0.0.1", port, &keys, 1, timeout, Default::default())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "ERR Target instance replied with error: BUSYKEY Target key name already exists.",
+            err.to_string()
+        );
+        assert_eq!(Some("4".into()), source.get("b").await.unwrap());
+
+        let replace = MigrateOptions {
+            replace: true,
+            ..Default::default()
+        };
+        assert!(source
+            .migrate("127.0.0.1", port, &keys, 1, timeout, replace)
+            .await
+            .unwrap());
+        assert_eq!(None, source.get("b").await.unwrap());
+        assert_eq!(Some("4".into()), target.get("b").await.unwrap());
+
+        // Nothing to move
+        assert!(!source
+            .migrate("127.0.0.1", port, &keys, 1, timeout, Default::default())
+            .await
+            .unwrap());
+
+        source.set("d", "5".into()).await.unwrap();
+        drop(target);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let unreachable = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let err = source
+            .migrate(
+                "127.0.0.1",
+                unreachable,
+                &["d".to_string()],
+                0,
+                timeout,
+                Default::default(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "IOERR error or timeout connecting to the client",
+            err.to_string()
+        );
+        assert_eq!(Some("5".into()), source.get("d").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn replica_syncs_and_applies_the_write_stream() {
+        let (leader_addr, _) = start_server().await;
+        let mut leader = Client::connect(leader_addr).await.unwrap();
+
+        leader.set("hello", "world".into()).await.unwrap();
+        leader.select(1).await.unwrap();
+        leader.set("foo", "bar".into()).await.unwrap();
+
+        let (replica_addr, _) = start_server().await;
+        let mut replica = Client::connect(replica_addr).await.unwrap();
+
+        replica.set("stale", "value".into()).await.unwrap();
+        replica
+            .replicaof("127.0.0.1", leader_addr.port())
+            .await
+            .unwrap();
+        wait_for_link(&mut replica).await;
+
+        // The replica's databases are replaced by the leader's
+        assert_eq!(None, replica.get("stale").await.unwrap());
+        assert_eq!(Some("world".into()), replica.get("hello").await.unwrap());
+        replica.select(1).await.unwrap();
+        assert_eq!(Some("bar".into()), replica.get("foo").await.unwrap());
+
+        // Then the writes are streamed, on the database they were applied to
+        leader
+            .set_expires("temp", "value".into(), Duration::from_millis(300))
+            .await
+            .unwrap();
+        leader.del(&["foo".to_string()]).await.unwrap();
+        leader.select(0).await.unwrap();
+        leader.set("hello", "again".into()).await.unwrap();
+        wait_for_offset(&mut leader, &mut replica).await;
+
+        assert_eq!(Some("value".into()), replica.get("temp").await.unwrap());
+        assert_eq!(None, replica.get("foo").await.unwrap());
+        replica.select(0).await.unwrap();
+        assert_eq!(Some("again".into()), replica.get("hello").await.unwrap());
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        replica.select(1).await.unwrap();
+        assert_eq!(None, replica.get("temp").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn replica_rejects_writes() {
+        let (leader_addr, _) = start_server().await;
+        let (replica_addr, _) = start_server().await;
+        let mut replica = Client::connect(replica_addr).await.unwrap();
+
+        replica
+            .replicaof("127.0.0.1", leader_addr.port())
+            .await
+            .unwrap();
+        wait_for_link(&mut replica).await;
+
+        let err = replica.set("hello", "world".into()).await.unwrap_err();
+        assert_eq!(
+            "READONLY You can't write against a read only replica.",
+            err.to_string()
+        );
+
+        // Scripts may still read
+        let reply = replica
+            .eval("return redis.call('get', 'hello')", &[], &[])
+            .await
+            .unwrap();
+        assert_eq!(Reply::Null, reply);
+
+        let err = replica
+            .eval("return redis.call('set', 'hello', 'world')", &[], &[])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("READONLY"), "{}", err);
+
+        // Once promoted, writes are accepted again and the data is kept
+        let mut leader = Client::connect(leader_addr).await.unwrap();
+        leader.set("hello", "world".into()).await.unwrap();
+        wait_for_offset(&mut leader, &mut replica).await;
+
+        replica.replicaof_no_one().await.unwrap();
+        replica.set("foo", "bar".into()).await.unwrap();
+        assert_eq!(Some("world".into()), replica.get("hello").await.unwrap());
+        assert!(matches!(
+            replica.role().await.unwrap(),
+            ServerRole::Leader { .. }
+        ));
+
+        // Writes on the former leader aren't streamed anymore
+        leader.set("hello", "again".into()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(Some("world".into()), replica.get("hello").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn replica_authenticates_with_the_leader() {
+        let (leader_addr, _) = start_server_with_config(Config {
+            requirepass: Some("secret".to_string()),
+            ..Default::default()
+        })
+        .await;
+        let mut leader = Client::connect(leader_addr).await.unwrap();
+        leader.auth(None, "secret").await.unwrap();
+
+        leader
+            .acl_setuser(
+                "replicator",
+                &["on", ">pass", "+ping", "+replconf", "+psync"],
+            )
+            .await
+            .unwrap();
+        leader.set("hello", "world".into()).await.unwrap();
+
+        let (replica_addr, _) = start_server_with_config(Config {
+            masteruser: Some("replicator".to_string()),
+            masterauth: Some("pass".to_string()),
+            ..Default::default()
+        })
+        .await;
+        let mut replica = Client::connect(replica_addr).await.unwrap();
+
+        replica
+            .replicaof("127.0.0.1", leader_addr.port())
+            .await
+            .unwrap();
+        wait_for_link(&mut replica).await;
+        assert_eq!(Some("world".into()), replica.get("hello").await.unwrap());
+
+        leader.set("hello", "again".into()).await.unwrap();
+        wait_for_offset(&mut leader, &mut replica).await;
+        assert_eq!(Some("again".into()), replica.get("hello").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn failover_swaps_roles_with_the_replica() {
+        let (leader_addr, _) = start_server().await;
+        let (replica_addr, _) = start_server().await;
+        let mut leader = Client::connect(leader_addr).await.unwrap();
+        let mut replica = Client::connect(replica_addr).await.unwrap();
+
+        let err = leader.failover(None, None).await.unwrap_err();
+        assert_eq!("ERR FAILOVER requires connected replicas.", err.to_string());
+
+        replica
+            .replicaof("127.0.0.1", leader_addr.port())
+            .await
+            .unwrap();
+        wait_for_link(&mut replica).await;
+
+        let err = replica.failover(None, None).await.unwrap_err();
+        assert_eq!(
+            "ERR FAILOVER is not valid when server is a replica.",
+            err.to_string()
+        );
+
+        let err = leader
+            .failover(Some(("127.0.0.1", 1)), None)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "ERR FAILOVER target HOST and PORT is not a replica.",
+            err.to_string()
+        );
+
+        leader.set("hello", "world".into()).await.unwrap();
+        leader
+            .failover(
+                Some(("127.0.0.1", replica_addr.port())),
+                Some(Duration::from_secs(5)),
+            )
+            .await
+            .unwrap();
+
+        // The former leader follows the promoted replica, which got all the writes
+        wait_for_link(&mut leader).await;
+
+        match leader.role().await.unwrap() {
+            ServerRole::Replica { host, port, .. } => {
+                assert_eq!("127.0.0.1", host);
+                assert_eq!(replica_addr.port(), port);
+            }
+            role => panic!("unexpected role {:?}", role),
+        }
+
+        let mut new_leader = replica;
+        assert!(matches!(
+            new_leader.role().await.unwrap(),
+            ServerRole::Leader { .. }
+        ));
+        assert_eq!(Some("world".into()), new_leader.get("hello").await.unwrap());
+        assert!(new_leader
+            .info(Some("replication"))
+            .await
+            .unwrap()
+            .contains("master_failover_state:no-failover"));
+
+        // The stream continues the other way around
+        new_leader.set("foo", "bar".into()).await.unwrap();
+        wait_for_offset(&mut new_leader, &mut leader).await;
+        assert_eq!(Some("bar".into()), leader.get("foo").await.unwrap());
+
+        let err = leader.set("hello", "again".into()).await.unwrap_err();
+        assert_eq!(
+            "READONLY You can't write against a read only replica.",
+            err.to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn cluster_redirects_keys_served_by_other_nodes() {
+        let addrs = start_cluster(&["0-8191", "8192-16383"]).await;
+        let mut client = Client::connect(addrs[0]).await.unwrap();
+
+        assert_eq!(12182, client.cluster_keyslot("foo").await.unwrap());
+        assert_eq!(5061, client.cluster_keyslot("bar").await.unwrap());
+        assert_eq!(
+            client.cluster_keyslot("user").await.unwrap(),
+            client.cluster_keyslot("{user}:name").await.unwrap()
+        );
+
+        // `bar` is served by the first node, `foo` by the second one
+        client.set("bar", "1".into()).await.unwrap();
+        let err = client.set("foo", "1".into()).await.unwrap_err();
+        assert_eq!(
+            format!("MOVED 12182 127.0.0.1:{}", addrs[1].port()),
+            err.to_string()
+        );
+
+        let err = client.get("foo").await.unwrap_err();
+        assert!(err.to_string().starts_with("MOVED 12182 "), "{}", err);
+
+        let mut other = Client::connect(addrs[1]).await.unwrap();
+        other.set("foo", "2".into()).await.unwrap();
+        assert_eq!(Some("2".into()), other.get("foo").await.unwrap());
+
+        // Multi-key commands must hash to a single slot
+        let err = client
+            .del(&["bar".to_string(), "baz".to_string()])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "CROSSSLOT Keys in request don't hash to the same slot",
+            err.to_string()
+        );
+
+        client.set("{bar}:1", "2".into()).await.unwrap();
+        assert_eq!(
+            2,
+            client
+                .del(&["bar".to_string(), "{bar}:1".to_string()])
+                .await
+                .unwrap()
+        );
+
+        let err = client
+            .transaction()
+            .set("bar", "1".into())
+            .set("baz", "2".into())
+            .exec()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("EXECABORT"), "{}", err);
+
+        // The slots are listed with the node serving them
+        let id = client.cluster_myid().await.unwrap();
+        let slots = client.cluster_slots().await.unwrap();
+        assert_eq!(2, slots.len());
+        assert_eq!(
+            (0, 8191, addrs[0].port(), id),
+            (
+                slots[0].start,
+                slots[0].end,
+                slots[0].port,
+                slots[0].id.clone()
+            )
+        );
+        assert_eq!(
+            (8192, 16383, addrs[1].port()),
+            (slots[1].start, slots[1].end, slots[1].port)
+        );
+
+        client.set("{bar}:2", "3".into()).await.unwrap();
+        client.set("{bar}:3", "4".into()).await.unwrap();
+        assert_eq!(2, client.cluster_countkeysinslot(5061).await.unwrap());
+        assert_eq!(
+            1,
+            client.cluster_getkeysinslot(5061, 1).await.unwrap().len()
+        );
+
+        let mut keys = client.cluster_getkeysinslot(5061, 10).await.unwrap();
+        keys.sort();
+        assert_eq!(vec!["{bar}:2".to_string(), "{bar}:3".to_string()], keys);
+
+        let err = client.select(1).await.unwrap_err();
+        assert_eq!("ERR SELECT is not allowed in cluster mode", err.to_string());
+    }
+
+    #[tokio::test]
+    async fn role_and_info_report_replication_offsets() {
+        let (leader_addr, _) = start_server().await;
+        let mut leader = Client::connect(leader_addr).await.unwrap();
+
+        assert_eq!(
+            ServerRole::Leader {
+                offset: 0,
+                replicas: vec![]
+            },
+            leader.role().await.unwrap()
+        );
+
+        let (replica_addr, _) = start_server().await;
+        let mut replica = Client::connect(replica_addr).await.unwrap();
+
+        replica
+            .replicaof("127.0.0.1", leader_addr.port())
+            .await
+            .unwrap();
+        wait_for_link(&mut replica).await;
+
+        leader.set("hello", "world".into()).await.unwrap();
+        let offset = wait_for_offset(&mut leader, &mut replica).await;
+        assert!(offset > 0);
+
+        // The replica acknowledges its offset every second
+        let mut acked = false;
+
+        for _ in 0..30 {
+            if let ServerRole::Leader { replicas, .. } = leader.role().await.unwrap() {
+                assert_eq!(1, replicas.len());
+                assert_eq!("127.0.0.1", replicas[0].ip);
+                assert_eq!(replica_addr.port(), replicas[0].port);
+
+                if replicas[0].offset == offset {
+                    acked = true;
+                    break;
+                }
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        assert!(acked, "replica didn't acknowledge offset {}", offset);
+
+        let info = leader.info(Some("replication")).await.unwrap();
+        assert!(info.contains("role:master\r\n"), "{}", info);
+        assert!(info.contains("connected_slaves:1\r\n"), "{}", info);
+        assert!(
+            info.contains(&format!("master_repl_offset:{}\r\n", offset)),
+            "{}",
+            info
+        );
+        assert!(
+            info.contains(&format!(
+                "slave0:ip=127.0.0.1,port={},state=online,offset={},lag=",
+                replica_addr.port(),
+                offset
+            )),
+            "{}",
+            info
+        );
+
+        let info = replica.info(None).await.unwrap();
+        assert!(info.contains("role:slave\r\n"), "{}", info);
+        assert!(
+            info.contains(&format!("master_port:{}\r\n", leader_addr.port())),
+            "{}",
+            info
+        );
+        assert!(info.contains("master_link_status:up\r\n"), "{}", info);
+        assert!(
+            info.contains(&format!("slave_repl_offset:{}\r\n", offset)),
+            "{}",
+            info
+        );
+    }
+
+    #[tokio::test]
+    async fn wait_for_replicas_acknowledgements() {
+        let (leader_addr, _) = start_server().await;
+        let mut leader = Client::connect(leader_addr).await.unwrap();
+
+        // No replica to wait for
+        assert_eq!(0, leader.wait(0, None).await.unwrap());
+
+        let (replica_addr, _) = start_server().await;
+        let mut replica = Client::connect(replica_addr).await.unwrap();
+
+        replica
+            .replicaof("127.0.0.1", leader_addr.port())
+            .await
+            .unwrap();
+        wait_for_link(&mut replica).await;
+
+        leader.set("hello", "world".into()).await.unwrap();
+
+        // Replicas are asked to acknowledge right away, rather than within a second
+        let start = tokio::time::Instant::now();
+        assert_eq!(
+            1,
+            leader.wait(1, Some(Duration::from_secs(5))).await.unwrap()
+        );
+        assert!(start.elapsed() < Duration::from_millis(900));
+        assert_eq!(Some("world".into()), replica.get("hello").await.unwrap());
+
+        // Not enough replicas: the acknowledgements received before the timeout are counted
+        let start = tokio::time::Instant::now();
+        assert_eq!(
+            1,
+            leader
+                .wait(2, Some(Duration::from_millis(200)))
+                .await
+                .unwrap()
+        );
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        let err = replica
+            .wait(1, Some(Duration::from_millis(10)))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "ERR WAIT cannot be used with replica instances.",
+            err.to_string()
+        );
+    }
+
+    /// Wait until the replica is streaming from its leader.
+    async fn wait_for_link(replica: &mut Client) {
+        for _ in 0..300 {
+            if let ServerRole::Replica { state, .. } = replica.role().await.unwrap() {
+                if state == "connected" {
+                    return;
+                }
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        panic!("replica didn't sync with its leader");
+    }
+
+    /// Wait until the replica applied all the writes streamed by the leader, returning
+    /// the offset reached.
+    async fn wait_for_offset(leader: &mut Client, replica: &mut Client) -> u64 {
+        let offset = match leader.role().await.unwrap() {
+            ServerRole::Leader { offset, .. } => offset,
+            role => panic!("unexpected role {:?}", role),
+        };
+
+        for _ in 0..300 {
+            if let ServerRole::Replica {
+                offset: reached, ..
+            } = replica.role().await.unwrap()
+            {
+                if reached == offset {
+                    return offset;
+                }
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        panic!("replica didn't reach offset {}", offset);
+    }
+
+    fn aof_config(dir: &std::path::Path) -> Config {
+        Config {
+            dir: dir.to_path_buf(),
+            aof: AofConfig {
+                enabled: true,
+                fsync: AppendFsync::Always,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    async fn wait_for_file(path: &std::path::Path) {
+        for _ in 0..300 {
+            if path.exists() {
+                return;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        panic!("{} wasn't written", path.display());
+    }
+
+    #[tokio::test]
+    async fn cluster_slots_migrate_live_with_ask_redirects() {
+        let addrs = start_cluster(&["0-8191", "8192-16383"]).await;
+        let mut source = Client::connect(addrs[0]).await.unwrap();
+        let mut target = Client::connect(addrs[1]).await.unwrap();
+
+        // The nodes learn their ids from the handshake
+        for client in [&mut source, &mut target] {
+            wait_for_handshakes(client).await;
+        }
+
+        let source_id = source.cluster_myid().await.unwrap();
+        let target_id = target.cluster_myid().await.unwrap();
+
+        // `bar` hashes to slot 5061, served by the source node
+        source.set("bar", "1".into()).await.unwrap();
+        source.set("{bar}:1", "2".into()).await.unwrap();
+
+        let err = source
+            .cluster_setslot(5061, SetSlot::Importing(target_id.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "ERR I'm already the owner of hash slot 5061",
+            err.to_string()
+        );
+
+        target
+            .cluster_setslot(5061, SetSlot::Importing(source_id.clone()))
+            .await
+            .unwrap();
+        source
+            .cluster_setslot(5061, SetSlot::Migrating(target_id.clone()))
+            .await
+            .unwrap();
+        assert!(source
+            .cluster_nodes()
+            .await
+            .unwrap()
+            .contains(&format!("[5061->-{}]", target_id)));
+
+        let keys = vec!["bar".to_string()];
+        assert!(source
+            .migrate(
+                "127.0.0.1",
+                addrs[1].port(),
+                &keys,
+                0,
+                Duration::from_secs(1),
+                Default::default()
+            )
+            .await
+            .unwrap());
+
+        // The migrated keys are redirected to the target node, which only serves them
+        // after `ASKING`
+        let err = source.get("bar").await.unwrap_err();
+        assert_eq!(
+            format!("ASK 5061 127.0.0.1:{}", addrs[1].port()),
+            err.to_string()
+        );
+        assert_eq!(Some("2".into()), source.get("{bar}:1").await.unwrap());
+
+        let err = source
+            .del(&["bar".to_string(), "{bar}:1".to_string()])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "TRYAGAIN Multiple keys request during rehashing of slot",
+            err.to_string()
+        );
+
+        let err = target.get("bar").await.unwrap_err();
+        assert_eq!(
+            format!("MOVED 5061 127.0.0.1:{}", addrs[0].port()),
+            err.to_string()
+        );
+
+        target.asking().await.unwrap();
+        assert_eq!(Some("1".into()), target.get("bar").await.unwrap());
+
+        // `ASKING` only applies to the next command
+        assert!(target
+            .get("bar")
+            .await
+            .unwrap_err()
+            .to_string()
+            .starts_with("MOVED"));
+
+        // The slot can only be given away once all its keys are migrated
+        let err = source
+            .cluster_setslot(5061, SetSlot::Node(target_id.clone()))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("still hold keys"), "{}", err);
+
+        let keys = vec!["{bar}:1".to_string()];
+        assert!(source
+            .migrate(
+                "127.0.0.1",
+                addrs[1].port(),
+                &keys,
+                0,
+                Duration::from_secs(1),
+                Default::default()
+            )
+            .await
+            .unwrap());
+
+        target
+            .cluster_setslot(5061, SetSlot::Node(target_id.clone()))
+            .await
+            .unwrap();
+        source
+            .cluster_setslot(5061, SetSlot::Node(target_id.clone()))
+            .await
+            .unwrap();
+
+        assert_eq!(Some("2".into()), target.get("{bar}:1").await.unwrap());
+        let err = source.get("bar").await.unwrap_err();
+        assert_eq!(
+            format!("MOVED 5061 127.0.0.1:{}", addrs[1].port()),
+            err.to_string()
+        );
+
+        // The target node claimed the slot with a new epoch
+        assert!(target
+            .cluster_info()
+            .await
+            .unwrap()
+            .contains("cluster_my_epoch:1"));
+
+        let slots = source.cluster_slots().await.unwrap();
+        let ranges: Vec<_> = slots
+            .iter()
+            .map(|range| (range.start, range.end, range.id.clone()))
+            .collect();
+        assert_eq!(
+            vec![
+                (0, 5060, source_id.clone()),
+                (5061, 5061, target_id.clone()),
+                (5062, 8191, source_id),
+                (8192, 16383, target_id),
+            ],
+            ranges
+        );
+    }
+
+    #[tokio::test]
+    async fn cluster_client_routes_commands_to_the_serving_nodes() {
+        let addrs = start_cluster(&["0-8191", "8192-16383"]).await;
+        let mut source = Client::connect(addrs[0]).await.unwrap();
+        let mut target = Client::connect(addrs[1]).await.unwrap();
+
+        for client in [&mut source, &mut target] {
+            wait_for_handshakes(client).await;
+        }
+
+        let source_id = source.cluster_myid().await.unwrap();
+        let target_id = target.cluster_myid().await.unwrap();
+
+        // Bootstrapped from a single node, the client learns about the other one
+        let mut client = ClusterClient::connect(&[addrs[0]]).await.unwrap();
+
+        // `foo` hashes to slot 12182, `bar` to slot 5061
+        client.set("foo", "1".into()).await.unwrap();
+        client.set("bar", "2".into()).await.unwrap();
+        assert_eq!(Some("1".into()), target.get("foo").await.unwrap());
+        assert_eq!(Some("2".into()), source.get("bar").await.unwrap());
+        assert_eq!(Some("1".into()), client.get("foo").await.unwrap());
+
+        // Multi-key commands are split by slot when it's safe, else rejected by the nodes
+        let keys = vec!["foo".to_string(), "bar".to_string(), "baz".to_string()];
+        assert_eq!(2, client.del(&keys).await.unwrap());
+        assert_eq!(None, client.get("bar").await.unwrap());
+
+        let err = client.eval("return 1", &keys, &[]).await.unwrap_err();
+        assert_eq!(
+            "CROSSSLOT Keys in request don't hash to the same slot",
+            err.to_string()
+        );
+
+        let keys = vec!["{bar}:1".to_string(), "{bar}:2".to_string()];
+        let script = "redis.call('SET', KEYS[1], 'a'); return redis.call('SET', KEYS[2], 'b')";
+        assert_eq!(
+            Reply::Simple("OK".to_string()),
+            client.eval(script, &keys, &[]).await.unwrap()
+        );
+
+        // While the slot migrates, the client follows the `ASK` redirections of the
+        // migrated keys
+        client.set("bar", "3".into()).await.unwrap();
+        target
+            .cluster_setslot(5061, SetSlot::Importing(source_id.clone()))
+            .await
+            .unwrap();
+        source
+            .cluster_setslot(5061, SetSlot::Migrating(target_id.clone()))
+            .await
+            .unwrap();
+
+        let keys = vec!["bar".to_string()];
+        assert!(source
+            .migrate(
+                "127.0.0.1",
+                addrs[1].port(),
+                &keys,
+                0,
+                Duration::from_secs(1),
+                Default::default()
+            )
+            .await
+            .unwrap());
+
+        assert_eq!(Some("3".into()), client.get("bar").await.unwrap());
+        assert_eq!(Some("a".into()), client.get("{bar}:1").await.unwrap());
+
+        // A new key of the slot is created by the importing node
+        client.set("{bar}:3", "c".into()).await.unwrap();
+        target.asking().await.unwrap();
+        assert_eq!(Some("c".into()), target.get("{bar}:3").await.unwrap());
+
+        let keys = vec!["{bar}:1".to_string(), "{bar}:2".to_string()];
+        assert!(source
+            .migrate(
+                "127.0.0.1",
+                addrs[1].port(),
+                &keys,
+                0,
+                Duration::from_secs(1),
+                Default::default()
+            )
+            .await
+            .unwrap());
+
+        target
+            .cluster_setslot(5061, SetSlot::Node(target_id.clone()))
+            .await
+            .unwrap();
+        source
+            .cluster_setslot(5061, SetSlot::Node(target_id))
+            .await
+            .unwrap();
+
+        // Once the slot is given away, the client is redirected for good
+        assert_eq!(Some("3".into()), client.get("bar").await.unwrap());
+        assert_eq!(Some("b".into()), client.get("{bar}:2").await.unwrap());
+        assert_eq!(Some("3".into()), target.get("bar").await.unwrap());
+    }
+
+    /// Under `noeviction`, writes growing the databases fail with `OOM` once over
+    /// `maxmemory`, while reads and deletions go on.
+    #[tokio::test]
+    async fn maxmemory_rejects_writes_without_eviction() {
+        // Each key below uses about 200 bytes
+        let (addr, _) =
+            start_server_with_config(maxmemory_config(1000, MaxMemoryPolicy::NoEviction)).await;
+        let mut client = Client::connect(addr).await.unwrap();
+
+        for i in 0..6 {
+            client
+                .set(&format!("k{}", i), Bytes::from(vec![b'x'; 100]))
+                .await
+                .unwrap();
+        }
+
+        let err = client.set("k6", "1".into()).await.unwrap_err();
+        assert_eq!(
+            "OOM command not allowed when used memory > 'maxmemory'.",
+            err.to_string()
+        );
+
+        assert!(client.get("k0").await.unwrap().is_some());
+        assert_eq!(
+            2,
+            client
+                .del(&["k0".to_string(), "k1".to_string()])
+                .await
+                .unwrap()
+        );
+        client.set("k6", "1".into()).await.unwrap();
+
+        let info = client.info(Some("memory")).await.unwrap();
+        assert!(info.contains("maxmemory:1000\r\n"), "{}", info);
+        assert!(info.contains("maxmemory_policy:noeviction\r\n"), "{}", info);
+    }
+
+    /// Under `allkeys-lru`, the least recently used key is evicted.
+    #[tokio::test]
+    async fn maxmemory_evicts_least_recently_used_keys() {
+        let (addr, _) =
+            start_server_with_config(maxmemory_config(1000, MaxMemoryPolicy::AllKeysLru)).await;
+        let mut client = Client::connect(addr).await.unwrap();
+
+        for i in 0..5 {
+            client
+                .set(&format!("k{}", i), Bytes::from(vec![b'x'; 100]))
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        // `k0` is now more recently used than the others
+        client.get("k0").await.unwrap();
+
+        client
+            .set("k5", Bytes::from(vec![b'x'; 100]))
+            .await
+            .unwrap();
+        client
+            .set("k6", Bytes::from(vec![b'x'; 100]))
+            .await
+            .unwrap();
+
+        assert_eq!(None, client.get("k1").await.unwrap());
+        assert!(client.get("k0").await.unwrap().is_some());
+        assert_eq!(6, client.dbsize().await.unwrap());
+        assert!(client
+            .info(Some("stats"))
+            .await
+            .unwrap()
+            .contains("evicted_keys:1\r\n"));
+    }
+
+    /// Under `allkeys-lfu`, the least frequently used key is evicted.
+    #[tokio::test]
+    async fn maxmemory_evicts_least_frequently_used_keys() {
+        let (addr, _) =
+            start_server_with_config(maxmemory_config(1000, MaxMemoryPolicy::AllKeysLfu)).await;
+        let mut client = Client::connect(addr).await.unwrap();
+
+        for i in 0..5 {
+            client
+                .set(&format!("k{}", i), Bytes::from(vec![b'x'; 100]))
+                .await
+                .unwrap();
+        }
+
+        for _ in 0..10 {
+            client.get("k0").await.unwrap();
+        }
+
+        for i in 5..9 {
+            client
+                .set(&format!("k{}", i), Bytes::from(vec![b'x'; 100]))
+                .await
+                .unwrap();
+        }
+
+        assert!(client.get("k0").await.unwrap().is_some());
+        assert_eq!(6, client.dbsize().await.unwrap());
+        assert!(client
+            .info(Some("stats"))
+            .await
+            .unwrap()
+            .contains("evicted_keys:3\r\n"));
+    }
+
+    /// Under `volatile-ttl`, the keys expiring first are evicted, the keys without a TTL
+    /// never are.
+    #[tokio::test]
+    async fn maxmemory_evicts_keys_expiring_first() {
+        // Keys without a TTL use 198 bytes, keys with a TTL 230
+        let (addr, _) =
+            start_server_with_config(maxmemory_config(856, MaxMemoryPolicy::VolatileTtl)).await;
+        let mut client = Client::connect(addr).await.unwrap();
+
+        let value = Bytes::from(vec![b'x'; 100]);
+        client
+            .set_expires("v1", value.clone(), Duration::from_secs(300))
+            .await
+            .unwrap();
+        client
+            .set_expires("v2", value.clone(), Duration::from_secs(100))
+            .await
+            .unwrap();
+        client.set("p1", value.clone()).await.unwrap();
+        client.set("p2", value.clone()).await.unwrap();
+        client.set("p3", value.clone()).await.unwrap();
+
+        client.set("p4", value.clone()).await.unwrap();
+        assert_eq!(None, client.get("v2").await.unwrap());
+        assert!(client.get("v1").await.unwrap().is_some());
+
+        client.set("p5", value.clone()).await.unwrap();
+        assert_eq!(None, client.get("v1").await.unwrap());
+
+        let err = client.set("p6", value).await.unwrap_err();
+        assert!(err.to_string().starts_with("OOM"), "{}", err);
+        assert_eq!(5, client.dbsize().await.unwrap());
+    }
+
+    /// Wait until the cluster node learned the ids of the others from the handshake.
+    async fn wait_for_handshakes(client: &mut Client) {
+        for _ in 0..200 {
+            if !client.cluster_nodes().await.unwrap().contains("handshake") {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// `CONFIG GET` matches parameters with glob-style patterns, `CONFIG SET` changes the
+    /// ones read at runtime.
+    #[tokio::test]
+    async fn config_get_and_set() {
+        let (addr, _) =
+            start_server_with_config(maxmemory_config(1000, MaxMemoryPolicy::NoEviction)).await;
+        let mut client = Client::connect(addr).await.unwrap();
+
+        assert_eq!(
+            client.config_get("maxmemory*").await.unwrap(),
+            vec![
+                ("maxmemory".to_string(), "1000".to_string()),
+                ("maxmemory-policy".to_string(), "noeviction".to_string()),
+                ("maxmemory-samples".to_string(), "100".to_string()),
+            ]
+        );
+
+        for i in 0..6 {
+            client
+                .set(&format!("k{}", i), Bytes::from(vec![b'x'; 100]))
+                .await
+                .unwrap();
+        }
+        assert!(client.set("k6", "1".into()).await.is_err());
+
+        // Raising the limit lets writes through again
+        client.config_set("maxmemory", "1mb").await.unwrap();
+        client.set("k6", "1".into()).await.unwrap();
+        assert_eq!(
+            client.config_get("MAXMEMORY").await.unwrap(),
+            vec![("maxmemory".to_string(), "1048576".to_string())]
+        );
+
+        let err = client.config_set("port", "7000").await.unwrap_err();
+        assert_eq!(
+            "ERR CONFIG SET failed (possibly related to argument 'port') - can't set immutable config",
+            err.to_string()
+        );
+
+        let err = client
+            .config_set("maxmemory-policy", "sometimes")
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("argument 'maxmemory-policy'"),
+            "{}",
+            err
+        );
+
+        let err = client.config_set("unknown", "1").await.unwrap_err();
+        assert_eq!(
+            "ERR Unknown option or number of arguments for CONFIG SET - 'unknown'",
+            err.to_string()
+        );
+
+        assert!(client.config_get("nothing*").await.unwrap().is_empty());
+    }
+
+    /// `CONFIG REWRITE` persists the parameters changed at runtime to the configuration file.
+    #[tokio::test]
+    async fn config_rewrite() {
+        let (addr, _) = start_server().await;
+        let mut client = Client::connect(addr).await.unwrap();
+
+        let err = client.config_rewrite().await.unwrap_err();
+        assert_eq!(
+            "ERR The server is running without a config file",
+            err.to_string()
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mini-redis.conf");
+        std::fs::write(&path, "# Evict keys once over 100mb\nmaxmemory 100mb\n").unwrap();
+
+        let mut config = Config::default();
+        config.load_file(&path).unwrap();
+
+        let (addr, _) = start_server_with_config(config).await;
+        let mut client = Client::connect(addr).await.unwrap();
+
+        client.config_set("maxmemory", "200mb").await.unwrap();
+        client
+            .config_set("notify-keyspace-events", "Ex")
+            .await
+            .unwrap();
+        client.config_rewrite().await.unwrap();
+
+        let mut config = Config::default();
+        config.load_file(&path).unwrap();
+        assert_eq!(200 * 1024 * 1024, config.maxmemory.limit);
+        assert_eq!("xE", config.notify_keyspace_events.to_string());
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("# Evict keys once over 100mb\n"));
+    }
+
+    /// Connections over `maxclients` are refused with an error.
+    #[tokio::test]
+    async fn maxclients_refuses_connections() {
+        let (addr, _) = start_server_with_config(Config {
+            maxclients: 1,
+            ..Default::default()
+        })
+        .await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.ping(None).await.unwrap();
+
+        let mut refused = Client::connect(addr).await.unwrap();
+        let err = refused.ping(None).await.unwrap_err();
+        assert_eq!("ERR max number of clients reached", err.to_string());
+
+        // The slot is freed once the first client disconnects
+        client.quit().await.unwrap();
+
+        for _ in 0..100 {
+            let mut client = Client::connect(addr).await.unwrap();
+            if client.ping(None).await.is_ok() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        panic!("connection still refused");
+    }
+
+    /// Idle connections are closed once the timeout elapses.
+    #[tokio::test]
+    async fn timeout_closes_idle_connections() {
+        let (addr, _) = start_server_with_config(Config {
+            timeout: Duration::from_secs(1),
+            ..Default::default()
+        })
+        .await;
+
+        let mut idle = Client::connect(addr).await.unwrap();
+        let mut active = Client::connect(addr).await.unwrap();
+
+        for _ in 0..3 {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            active.ping(None).await.unwrap();
+        }
+
+        assert!(idle.ping(None).await.is_err());
+    }
+
+    /// The server accepts connections on each of its listeners, TCP or Unix sockets.
+    #[tokio::test]
+    async fn accepts_connections_on_every_listener() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tcp_listeners = [
+            TcpListener::bind("127.0.0.1:0").await.unwrap(),
+            TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        ];
+        let addrs: Vec<_> = tcp_listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap())
+            .collect();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mini-redis.sock");
+
+        // A socket file left by a previous run is replaced
+        drop(server::bind_unix_socket(&path, 0).unwrap());
+        let unix_listener = server::bind_unix_socket(&path, 0o770).unwrap();
+        assert_eq!(
+            0o770,
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777
+        );
+
+        let mut listeners: Vec<ListenSocket> =
+            tcp_listeners.into_iter().map(ListenSocket::from).collect();
+        listeners.push(unix_listener.into());
+
+        tokio::spawn(async move {
+            server::run_with_listeners(listeners, Config::default(), tokio::signal::ctrl_c()).await;
+        });
+
+        let mut first = Client::connect(addrs[0]).await.unwrap();
+        let mut second = Client::connect(addrs[1]).await.unwrap();
+        let mut unix = Client::connect_unix(&path).await.unwrap();
+
+        first.set("key", "value".into()).await.unwrap();
+        assert_eq!(Some(Bytes::from("value")), second.get("key").await.unwrap());
+
+        unix.set("key", "unix".into()).await.unwrap();
+        assert_eq!(Some(Bytes::from("unix")), first.get("key").await.unwrap());
+    }
+
+    /// Start a cluster node for each of the given slot ranges.
+    async fn start_cluster(slots: &[&str]) -> Vec<SocketAddr> {
+        let mut listeners = vec![];
+
+        for _ in slots {
+            listeners.push(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        }
+
+        let addrs: Vec<_> = listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap())
+            .collect();
+        let bus_ports = free_ports(slots.len()).await;
+
+        for (i, listener) in listeners.into_iter().enumerate() {
+            let nodes = addrs
+                .iter()
+                .zip(&bus_ports)
+                .zip(slots)
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, ((addr, bus_port), slots))| {
+                    format!("{}@{}={}", addr, bus_port, slots).parse().unwrap()
+                })
+                .collect();
+
+            let config = Config {
+                cluster: Some(ClusterConfig {
+                    slots: SlotRange::parse_ranges(slots[i]).unwrap(),
+                    nodes,
+                    bus_port: Some(bus_ports[i]),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+
+            tokio::spawn(async move {
+                server::run_with_config(listener, config, tokio::signal::ctrl_c()).await;
+            });
+        }
+
+        addrs
+    }
+
+    /// Returns `count` ports free to listen on.
+    async fn free_ports(count: usize) -> Vec<u16> {
+        let mut listeners = vec![];
+
+        for _ in 0..count {
+            listeners.push(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        }
+
+        listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap().port())
+            .collect()
+    }
+
+    fn maxmemory_config(limit: usize, policy: MaxMemoryPolicy) -> Config {
+        Config {
+            maxmemory: MaxMemoryConfig {
+                limit,
+                policy,
+                // Sampling all the keys makes the evictions predictable
+                samples: 100,
+            },
+            ..Default::default()
+        }
+    }
+
     async fn start_server() -> (SocketAddr, JoinHandle<()>) {
+        start_server_with_config(Config::default()).await
+    }
+
+    async fn start_server_with_config(config: Config) -> (SocketAddr, JoinHandle<()>) {
         let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
         let addr = listener.local_addr().unwrap();
 
         let handle = tokio::spawn(async move {
-            server::run(listener, tokio::signal::ctrl_c()).await;
+            server::run_with_config(listener, config, tokio::signal::ctrl_c()).await;
         });
 
         (addr, handle)
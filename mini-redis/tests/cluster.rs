@@ -0,0 +1,197 @@
+use std::{
+    net::{SocketAddr, TcpListener},
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+use tempfile::TempDir;
+use tokio::time;
+
+use mini_redis::clients::client::Client;
+
+mod integration_tests {
+    use super::*;
+
+    /// Three `mini-redis-server` processes met with `CLUSTER MEET` converge on a single view
+    /// of the cluster through the gossip, and agree on a node failing once it's killed.
+    #[tokio::test]
+    async fn cluster_nodes_converge_and_detect_failures() {
+        let mut nodes = vec![Node::start(), Node::start(), Node::start()];
+
+        let mut clients = vec![];
+        for node in &nodes {
+            clients.push(node.connect().await);
+        }
+
+        let mut ids = vec![];
+        for (client, slots) in clients.iter_mut().zip([0..5461, 5461..10923, 10923..16384]) {
+            client
+                .cluster_addslots(&slots.collect::<Vec<_>>())
+                .await
+                .unwrap();
+            ids.push(client.cluster_myid().await.unwrap());
+        }
+
+        // The first node meets the others, which learn about each other from its gossip
+        for node in &nodes[1..] {
+            clients[0]
+                .cluster_meet("127.0.0.1", node.addr.port(), Some(node.bus_port))
+                .await
+                .unwrap();
+        }
+
+        for client in &mut clients {
+            wait_for(client, |info, nodes| {
+                info.contains("cluster_state:ok")
+                    && info.contains("cluster_known_nodes:3")
+                    && !nodes.contains("handshake")
+            })
+            .await;
+        }
+
+        let slots = clients[2].cluster_slots().await.unwrap();
+        let served: Vec<_> = slots
+            .iter()
+            .map(|range| (range.start, range.end, range.port, range.id.clone()))
+            .collect();
+        assert_eq!(
+            vec![
+                (0, 5460, nodes[0].addr.port(), ids[0].clone()),
+                (5461, 10922, nodes[1].addr.port(), ids[1].clone()),
+                (10923, 16383, nodes[2].addr.port(), ids[2].clone()),
+            ],
+            served
+        );
+
+        // Every node has claimed its slots with an epoch of its own
+        let description = clients[1].cluster_nodes().await.unwrap();
+        assert_eq!(3, description.lines().count());
+        assert!(description
+            .lines()
+            .any(|line| line.starts_with(&ids[1]) && line.contains("myself,master")));
+
+        // The nodes redirect the keys to the node serving them
+        let err = clients[0].set("foo", "1".into()).await.unwrap_err();
+        assert_eq!(
+            format!("MOVED 12182 127.0.0.1:{}", nodes[2].addr.port()),
+            err.to_string()
+        );
+
+        // Once killed, the last node is flagged as failing by the majority of the others
+        nodes.pop();
+        clients.pop();
+
+        for client in &mut clients {
+            wait_for(client, |info, nodes| {
+                info.contains("cluster_state:fail")
+                    && nodes
+                        .lines()
+                        .any(|line| line.starts_with(&ids[2]) && line.contains("master,fail "))
+            })
+            .await;
+        }
+
+        let err = clients[0].get("foo").await.unwrap_err();
+        assert_eq!("CLUSTERDOWN The cluster is down", err.to_string());
+
+        // Forgotten by every node, it's gone for good
+        for client in &mut clients {
+            client.cluster_forget(&ids[2]).await.unwrap();
+        }
+
+        for client in &mut clients {
+            let description = client.cluster_nodes().await.unwrap();
+            assert_eq!(2, description.lines().count());
+            assert!(!description.contains(&ids[2]));
+        }
+
+        let err = clients[0].cluster_forget(&ids[0]).await.unwrap_err();
+        assert_eq!(
+            "ERR I tried hard but I can't forget myself...",
+            err.to_string()
+        );
+
+        let err = clients[0].cluster_addslots(&[0]).await.unwrap_err();
+        assert_eq!("ERR Slot 0 is already busy", err.to_string());
+    }
+
+    /// A `mini-redis-server` process, running in cluster mode.
+    struct Node {
+        process: Child,
+        addr: SocketAddr,
+        bus_port: u16,
+        _dir: TempDir,
+    }
+
+    impl Node {
+        fn start() -> Node {
+            let addr = free_addr();
+            let bus_port = free_addr().port();
+            let dir = tempfile::tempdir().unwrap();
+
+            let process = Command::new(env!("CARGO_BIN_EXE_mini-redis-server"))
+                .args(["--port", &addr.port().to_string()])
+                .args(["--dir", dir.path().to_str().unwrap()])
+                .args(["--save", ""])
+                .arg("--cluster-enabled")
+                .args(["--cluster-bus-port", &bus_port.to_string()])
+                .args(["--cluster-node-timeout", "500"])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap();
+
+            Node {
+                process,
+                addr,
+                bus_port,
+                _dir: dir,
+            }
+        }
+
+        /// Connect to the node, once it listens.
+        async fn connect(&self) -> Client {
+            for _ in 0..500 {
+                if let Ok(client) = Client::connect(self.addr).await {
+                    return client;
+                }
+
+                time::sleep(Duration::from_millis(10)).await;
+            }
+
+            panic!("cluster node not listening on {}", self.addr);
+        }
+    }
+
+    impl Drop for Node {
+        fn drop(&mut self) {
+            let _ = self.process.kill();
+            let _ = self.process.wait();
+        }
+    }
+
+    /// Wait until `CLUSTER INFO` and `CLUSTER NODES` of the node satisfy `condition`.
+    async fn wait_for(client: &mut Client, condition: impl Fn(&str, &str) -> bool) {
+        let (mut info, mut nodes) = (String::new(), String::new());
+
+        for _ in 0..200 {
+            info = client.cluster_info().await.unwrap();
+            nodes = client.cluster_nodes().await.unwrap();
+
+            if condition(&info, &nodes) {
+                return;
+            }
+
+            time::sleep(Duration::from_millis(50)).await;
+        }
+
+        panic!("cluster didn't converge:\n{}\n{}", info, nodes);
+    }
+
+    /// Returns a local address free to listen on.
+    fn free_addr() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+}
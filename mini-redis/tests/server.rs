@@ -6,7 +6,11 @@ use tokio::{
     time,
 };
 
-use mini_redis::server;
+use mini_redis::{
+    clients::client::{Client, ServerRole},
+    config::{ClusterConfig, Config, LagPolicy, SlotRange},
+    server,
+};
 
 mod integration_tests {
     use super::*;
@@ -211,6 +215,397 @@ mod integration_tests {
         );
     }
 
+    #[tokio::test]
+    async fn pub_sub_pattern() {
+        let (addr, _) = start_server().await;
+
+        let mut sub = TcpStream::connect(addr).await.unwrap();
+        sub.write_all(b"*2\r\n$10\r\nPSUBSCRIBE\r\n$3\r\nh*o\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 34];
+        sub.read_exact(&mut response).await.unwrap();
+        assert_eq!(
+            &b"*3\r\n$10\r\npsubscribe\r\n$3\r\nh*o\r\n:1\r\n"[..],
+            &response[..]
+        );
+
+        // Publish on a channel matching the pattern
+        let mut publisher = TcpStream::connect(addr).await.unwrap();
+        publisher
+            .write_all(b"*3\r\n$7\r\nPUBLISH\r\n$5\r\nhello\r\n$5\r\nworld\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 4];
+        publisher.read_exact(&mut response).await.unwrap();
+        assert_eq!(b":1\r\n", &response);
+
+        let mut response = [0; 49];
+        sub.read_exact(&mut response).await.unwrap();
+        assert_eq!(
+            &b"*4\r\n$8\r\npmessage\r\n$3\r\nh*o\r\n$5\r\nhello\r\n$5\r\nworld\r\n"[..],
+            &response[..]
+        );
+
+        // Publish on a channel not matching the pattern
+        publisher
+            .write_all(b"*3\r\n$7\r\nPUBLISH\r\n$3\r\nfoo\r\n$3\r\nbar\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 4];
+        publisher.read_exact(&mut response).await.unwrap();
+        assert_eq!(b":0\r\n", &response);
+    }
+
+    #[tokio::test]
+    async fn ping_while_subscribed() {
+        let (addr, _) = start_server().await;
+
+        let mut sub = TcpStream::connect(addr).await.unwrap();
+        sub.write_all(b"*2\r\n$9\r\nSUBSCRIBE\r\n$5\r\nhello\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 34];
+        sub.read_exact(&mut response).await.unwrap();
+
+        // Ping without message
+        sub.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+
+        let mut response = [0; 20];
+        sub.read_exact(&mut response).await.unwrap();
+        assert_eq!(&b"*2\r\n$4\r\npong\r\n$0\r\n\r\n"[..], &response[..]);
+
+        // Ping with message
+        sub.write_all(b"*2\r\n$4\r\nPING\r\n$2\r\nhi\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 22];
+        sub.read_exact(&mut response).await.unwrap();
+        assert_eq!(&b"*2\r\n$4\r\npong\r\n$2\r\nhi\r\n"[..], &response[..]);
+
+        // Other commands are rejected without closing the connection
+        sub.write_all(b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n")
+            .await
+            .unwrap();
+
+        let expected = b"-ERR Can't execute 'get': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context\r\n";
+        let mut response = vec![0; expected.len()];
+        sub.read_exact(&mut response).await.unwrap();
+        assert_eq!(&expected[..], &response[..]);
+    }
+
+    #[tokio::test]
+    async fn leave_subscribed_mode_when_unsubscribed() {
+        let (addr, _) = start_server().await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"*2\r\n$9\r\nSUBSCRIBE\r\n$5\r\nhello\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 34];
+        stream.read_exact(&mut response).await.unwrap();
+
+        // Unsubscribe from all channels
+        stream
+            .write_all(b"*1\r\n$11\r\nUNSUBSCRIBE\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 37];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(
+            &b"*3\r\n$11\r\nunsubscribe\r\n$5\r\nhello\r\n:0\r\n"[..],
+            &response[..]
+        );
+
+        // Regular commands are processed again
+        stream
+            .write_all(b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 5];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"$-1\r\n", &response);
+
+        // Unsubscribing while not subscribed gets a null channel reply
+        stream
+            .write_all(b"*1\r\n$11\r\nUNSUBSCRIBE\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 31];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(
+            &b"*3\r\n$11\r\nunsubscribe\r\n$-1\r\n:0\r\n"[..],
+            &response[..]
+        );
+    }
+
+    #[tokio::test]
+    async fn reset_and_quit_while_subscribed() {
+        let (addr, _) = start_server().await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"*3\r\n$9\r\nSUBSCRIBE\r\n$5\r\nhello\r\n$3\r\nfoo\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 66];
+        stream.read_exact(&mut response).await.unwrap();
+
+        // Reset drops all subscriptions at once
+        stream.write_all(b"*1\r\n$5\r\nRESET\r\n").await.unwrap();
+
+        let mut response = [0; 8];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"+RESET\r\n", &response);
+
+        stream
+            .write_all(b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 5];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"$-1\r\n", &response);
+
+        // Subscribe again, then quit
+        stream
+            .write_all(b"*2\r\n$9\r\nSUBSCRIBE\r\n$5\r\nhello\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 34];
+        stream.read_exact(&mut response).await.unwrap();
+
+        stream.write_all(b"*1\r\n$4\r\nQUIT\r\n").await.unwrap();
+
+        let mut response = [0; 5];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"+OK\r\n", &response);
+
+        // The server closed the connection
+        assert_eq!(0, stream.read(&mut response).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn reset_selects_default_database() {
+        let (addr, _) = start_server().await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"*2\r\n$6\r\nSELECT\r\n$1\r\n1\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 5];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"+OK\r\n", &response);
+
+        stream
+            .write_all(b"*3\r\n$3\r\nSET\r\n$5\r\nhello\r\n$5\r\nworld\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 5];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"+OK\r\n", &response);
+
+        stream.write_all(b"*1\r\n$5\r\nRESET\r\n").await.unwrap();
+
+        let mut response = [0; 8];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"+RESET\r\n", &response);
+
+        // The key was set in database 1
+        stream
+            .write_all(b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 5];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"$-1\r\n", &response);
+    }
+
+    #[tokio::test]
+    async fn multi_exec() {
+        let (addr, _) = start_server().await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"*1\r\n$5\r\nMULTI\r\n").await.unwrap();
+
+        let mut response = [0; 5];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"+OK\r\n", &response);
+
+        stream
+            .write_all(b"*3\r\n$3\r\nSET\r\n$5\r\nhello\r\n$5\r\nworld\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 9];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"+QUEUED\r\n", &response);
+
+        stream
+            .write_all(b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 9];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"+QUEUED\r\n", &response);
+
+        stream.write_all(b"*1\r\n$4\r\nEXEC\r\n").await.unwrap();
+
+        let mut response = [0; 20];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"*2\r\n+OK\r\n$5\r\nworld\r\n", &response);
+    }
+
+    #[tokio::test]
+    async fn multi_exec_abort_on_queuing_error() {
+        let (addr, _) = start_server().await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"*1\r\n$5\r\nMULTI\r\n").await.unwrap();
+
+        let mut response = [0; 5];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"+OK\r\n", &response);
+
+        stream
+            .write_all(b"*3\r\n$3\r\nSET\r\n$5\r\nhello\r\n$5\r\nworld\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 9];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"+QUEUED\r\n", &response);
+
+        stream.write_all(b"*1\r\n$3\r\nFOO\r\n").await.unwrap();
+
+        let mut response = [0; 28];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"-ERR unknown command 'foo'\r\n", &response);
+
+        stream.write_all(b"*1\r\n$4\r\nEXEC\r\n").await.unwrap();
+
+        let mut response = [0; 62];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(
+            &b"-EXECABORT Transaction discarded because of previous errors.\r\n"[..],
+            &response[..]
+        );
+
+        // None of the queued commands were applied
+        stream
+            .write_all(b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 5];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"$-1\r\n", &response);
+    }
+
+    #[tokio::test]
+    async fn multi_discard() {
+        let (addr, _) = start_server().await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"*1\r\n$4\r\nEXEC\r\n").await.unwrap();
+
+        let mut response = [0; 25];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"-ERR EXEC without MULTI\r\n", &response);
+
+        stream.write_all(b"*1\r\n$7\r\nDISCARD\r\n").await.unwrap();
+
+        let mut response = [0; 28];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"-ERR DISCARD without MULTI\r\n", &response);
+
+        stream.write_all(b"*1\r\n$5\r\nMULTI\r\n").await.unwrap();
+
+        let mut response = [0; 5];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"+OK\r\n", &response);
+
+        stream.write_all(b"*1\r\n$5\r\nMULTI\r\n").await.unwrap();
+
+        let mut response = [0; 36];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"-ERR MULTI calls can not be nested\r\n", &response);
+
+        stream
+            .write_all(b"*3\r\n$3\r\nSET\r\n$5\r\nhello\r\n$5\r\nworld\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 9];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"+QUEUED\r\n", &response);
+
+        stream.write_all(b"*1\r\n$7\r\nDISCARD\r\n").await.unwrap();
+
+        let mut response = [0; 5];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"+OK\r\n", &response);
+
+        stream
+            .write_all(b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 5];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"$-1\r\n", &response);
+    }
+
+    #[tokio::test]
+    async fn watch_inside_multi() {
+        let (addr, _) = start_server().await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"*1\r\n$5\r\nMULTI\r\n").await.unwrap();
+
+        let mut response = [0; 5];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"+OK\r\n", &response);
+
+        stream
+            .write_all(b"*2\r\n$5\r\nWATCH\r\n$5\r\nhello\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 47];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(
+            &b"-ERR Command not allowed inside a transaction\r\n"[..],
+            &response[..]
+        );
+
+        stream.write_all(b"*1\r\n$4\r\nEXEC\r\n").await.unwrap();
+
+        let mut response = [0; 62];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(
+            &b"-EXECABORT Transaction discarded because of previous errors.\r\n"[..],
+            &response[..]
+        );
+    }
+
     #[tokio::test]
     async fn send_error_unknown_command() {
         let (addr, _) = start_server().await;
@@ -228,12 +623,508 @@ mod integration_tests {
         assert_eq!(b"-ERR unknown command \'unknown\'\r\n", &response);
     }
 
+    /// A subscriber that falls behind its channel capacity is told how many
+    /// messages it missed, then receives the most recent ones
+    #[tokio::test]
+    async fn pub_sub_lagged_subscriber_is_notified() {
+        let (addr, _) = start_server_with_config(pub_sub_config(2, LagPolicy::Skip)).await;
+
+        let mut sub = TcpStream::connect(addr).await.unwrap();
+        sub.write_all(b"*2\r\n$9\r\nSUBSCRIBE\r\n$5\r\nhello\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 34];
+        sub.read_exact(&mut response).await.unwrap();
+
+        // Publish 4 messages in a row, overflowing the channel capacity
+        let mut publisher = TcpStream::connect(addr).await.unwrap();
+        publisher
+            .write_all(
+                b"*3\r\n$7\r\nPUBLISH\r\n$5\r\nhello\r\n$1\r\n1\r\n\
+                  *3\r\n$7\r\nPUBLISH\r\n$5\r\nhello\r\n$1\r\n2\r\n\
+                  *3\r\n$7\r\nPUBLISH\r\n$5\r\nhello\r\n$1\r\n3\r\n\
+                  *3\r\n$7\r\nPUBLISH\r\n$5\r\nhello\r\n$1\r\n4\r\n",
+            )
+            .await
+            .unwrap();
+
+        let mut response = [0; 16];
+        publisher.read_exact(&mut response).await.unwrap();
+        assert_eq!(b":1\r\n:1\r\n:1\r\n:1\r\n", &response);
+
+        // The subscriber is notified it missed 2 messages
+        let mut response = [0; 31];
+        sub.read_exact(&mut response).await.unwrap();
+        assert_eq!(
+            &b"*3\r\n$6\r\nlagged\r\n$5\r\nhello\r\n:2\r\n"[..],
+            &response[..]
+        );
+
+        // Then receives the messages still retained by the channel
+        let mut response = [0; 35];
+        sub.read_exact(&mut response).await.unwrap();
+        assert_eq!(
+            &b"*3\r\n$7\r\nmessage\r\n$5\r\nhello\r\n$1\r\n3\r\n"[..],
+            &response[..]
+        );
+
+        let mut response = [0; 35];
+        sub.read_exact(&mut response).await.unwrap();
+        assert_eq!(
+            &b"*3\r\n$7\r\nmessage\r\n$5\r\nhello\r\n$1\r\n4\r\n"[..],
+            &response[..]
+        );
+
+        // The lag is reported by introspection
+        publisher
+            .write_all(b"*2\r\n$6\r\nPUBSUB\r\n$3\r\nLAG\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 48];
+        publisher.read_exact(&mut response).await.unwrap();
+        assert_eq!(
+            &b"$41\r\nid=1 sub=1 psub=0 lag-events=1 dropped=2\n\r\n"[..],
+            &response[..]
+        );
+    }
+
+    /// A subscriber that falls behind its channel capacity is disconnected
+    #[tokio::test]
+    async fn pub_sub_lagged_subscriber_is_disconnected() {
+        let (addr, _) = start_server_with_config(pub_sub_config(2, LagPolicy::Disconnect)).await;
+
+        let mut sub = TcpStream::connect(addr).await.unwrap();
+        sub.write_all(b"*2\r\n$9\r\nSUBSCRIBE\r\n$5\r\nhello\r\n")
+            .await
+            .unwrap();
+
+        let mut response = [0; 34];
+        sub.read_exact(&mut response).await.unwrap();
+
+        let mut publisher = TcpStream::connect(addr).await.unwrap();
+        publisher
+            .write_all(
+                b"*3\r\n$7\r\nPUBLISH\r\n$5\r\nhello\r\n$1\r\n1\r\n\
+                  *3\r\n$7\r\nPUBLISH\r\n$5\r\nhello\r\n$1\r\n2\r\n\
+                  *3\r\n$7\r\nPUBLISH\r\n$5\r\nhello\r\n$1\r\n3\r\n",
+            )
+            .await
+            .unwrap();
+
+        let mut response = [0; 12];
+        publisher.read_exact(&mut response).await.unwrap();
+        assert_eq!(b":1\r\n:1\r\n:1\r\n", &response);
+
+        // The connection is closed without any message being delivered
+        let mut response = [0; 1];
+        assert_eq!(0, sub.read(&mut response).await.unwrap());
+    }
+
+    /// A publisher waits for a slow subscriber to catch up instead of dropping messages
+    #[tokio::test]
+    async fn pub_sub_backpressure_blocks_publisher() {
+        let (addr, _) = start_server_with_config(pub_sub_config(1, LagPolicy::Backpressure)).await;
+
+        let client = Client::connect(addr).await.unwrap();
+        let mut subscriber = client.subscribe(vec!["hello".into()]).await.unwrap();
+
+        let publisher = tokio::spawn(async move {
+            let mut client = Client::connect(addr).await.unwrap();
+            for i in 0..10 {
+                client.publish("hello", i.to_string().into()).await.unwrap();
+            }
+        });
+
+        for i in 0..10 {
+            let message = subscriber.next_message().await.unwrap().unwrap();
+            assert_eq!(i.to_string().as_bytes(), &message.content[..]);
+        }
+
+        publisher.await.unwrap();
+        assert_eq!(0, subscriber.get_dropped());
+    }
+
+    fn pub_sub_config(capacity: usize, lag_policy: LagPolicy) -> Config {
+        let mut config = Config::default();
+        config.pub_sub.capacity = capacity;
+        config.pub_sub.lag_policy = lag_policy;
+        config
+    }
+
+    #[tokio::test]
+    async fn set_rejects_expirations_out_of_range() {
+        let (addr, _) = start_server().await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        for option in ["EX", "PX", "EXAT", "PXAT"] {
+            stream
+                .write_all(&command(&[
+                    "SET",
+                    "key",
+                    "value",
+                    option,
+                    &u64::MAX.to_string(),
+                ]))
+                .await
+                .unwrap();
+            assert_eq!(
+                "-ERR invalid expire time in 'set' command",
+                read_line(&mut stream).await
+            );
+        }
+
+        // Neither the connection nor the shard of the key are affected
+        stream
+            .write_all(&command(&["SET", "key", "value", "EX", "60"]))
+            .await
+            .unwrap();
+        assert_eq!("+OK", read_line(&mut stream).await);
+    }
+
+    #[tokio::test]
+    async fn restore_with_absolute_ttl() {
+        let (addr, _) = start_server().await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.set("hello", "world".into()).await.unwrap();
+        let payload = client.dump("hello").await.unwrap().unwrap();
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        let restore = |key: &str, ttl: u64, options: &[&str]| {
+            let mut args = vec![
+                "RESTORE".as_bytes().to_vec(),
+                key.as_bytes().to_vec(),
+                ttl.to_string().into_bytes(),
+                payload.to_vec(),
+            ];
+            args.extend(options.iter().map(|option| option.as_bytes().to_vec()));
+
+            let mut request = format!("*{}\r\n", args.len()).into_bytes();
+            for arg in args {
+                request.extend(format!("${}\r\n", arg.len()).into_bytes());
+                request.extend(arg);
+                request.extend(b"\r\n");
+            }
+            request
+        };
+
+        // A TTL in the past doesn't create the key
+        stream
+            .write_all(&restore("past", 1000, &["ABSTTL"]))
+            .await
+            .unwrap();
+
+        let mut response = [0; 5];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"+OK\r\n", &response);
+        assert_eq!(None, client.get("past").await.unwrap());
+
+        let in_an_hour = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+            + 3_600_000;
+        stream
+            .write_all(&restore(
+                "future",
+                in_an_hour,
+                &["absttl", "IDLETIME", "10"],
+            ))
+            .await
+            .unwrap();
+
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(b"+OK\r\n", &response);
+
+        // The key was last accessed `IDLETIME` seconds ago, until read
+        assert_eq!(Some(10), client.object_idletime("future").await.unwrap());
+        assert_eq!(Some("world".into()), client.get("future").await.unwrap());
+        assert_eq!(Some(0), client.object_idletime("future").await.unwrap());
+        assert_eq!(None, client.object_idletime("missing").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn psync_continues_the_stream_from_the_backlog() {
+        let config = Config {
+            repl_backlog_size: 256,
+            ..Default::default()
+        };
+        let (addr, _) = start_server_with_config(config).await;
+
+        let mut client = Client::connect(addr).await.unwrap();
+        client.set("a", "1".into()).await.unwrap();
+
+        // A replica performs a full sync, then receives the write stream
+        let mut replica = TcpStream::connect(addr).await.unwrap();
+        replica
+            .write_all(&command(&["PSYNC", "?", "-1"]))
+            .await
+            .unwrap();
+
+        let response = read_line(&mut replica).await;
+        let (replid, offset) = match response.split(' ').collect::<Vec<_>>()[..] {
+            ["+FULLRESYNC", replid, offset] => (replid.to_string(), offset.parse::<u64>().unwrap()),
+            _ => panic!("unexpected reply {:?}", response),
+        };
+
+        let len: usize = read_line(&mut replica).await[1..].parse().unwrap();
+        let mut payload = vec![0; len];
+        replica.read_exact(&mut payload).await.unwrap();
+
+        client.set("b", "2".into()).await.unwrap();
+
+        let expected =
+            b"*2\r\n$6\r\nselect\r\n$1\r\n0\r\n*3\r\n$3\r\nset\r\n$1\r\nb\r\n$1\r\n2\r\n";
+        let mut stream = vec![0; expected.len()];
+        replica.read_exact(&mut stream).await.unwrap();
+        assert_eq!(&expected[..], &stream[..]);
+
+        // Once reconnected, it only receives the writes it missed
+        drop(replica);
+        client.set("c", "3".into()).await.unwrap();
+
+        let offset = offset + stream.len() as u64;
+        let mut replica = TcpStream::connect(addr).await.unwrap();
+        replica
+            .write_all(&command(&["PSYNC", &replid, &offset.to_string()]))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            format!("+CONTINUE {}", replid),
+            read_line(&mut replica).await
+        );
+
+        let expected = b"*3\r\n$3\r\nset\r\n$1\r\nc\r\n$1\r\n3\r\n";
+        let mut stream = vec![0; expected.len()];
+        replica.read_exact(&mut stream).await.unwrap();
+        assert_eq!(&expected[..], &stream[..]);
+        drop(replica);
+
+        // Another replication id requires a full sync
+        let mut replica = TcpStream::connect(addr).await.unwrap();
+        let other_replid = "0".repeat(40);
+        replica
+            .write_all(&command(&["PSYNC", &other_replid, &offset.to_string()]))
+            .await
+            .unwrap();
+        assert!(read_line(&mut replica).await.starts_with("+FULLRESYNC "));
+        drop(replica);
+
+        // As does an offset the backlog no longer holds
+        client.set("d", "x".repeat(512).into()).await.unwrap();
+
+        let mut replica = TcpStream::connect(addr).await.unwrap();
+        replica
+            .write_all(&command(&["PSYNC", &replid, &offset.to_string()]))
+            .await
+            .unwrap();
+        assert!(read_line(&mut replica).await.starts_with("+FULLRESYNC "));
+    }
+
+    #[tokio::test]
+    async fn failover_discards_open_transactions() {
+        let (leader_addr, _) = start_server().await;
+        let (replica_addr, _) = start_server().await;
+
+        let mut leader = Client::connect(leader_addr).await.unwrap();
+        let mut replica = Client::connect(replica_addr).await.unwrap();
+        replica
+            .replicaof("127.0.0.1", leader_addr.port())
+            .await
+            .unwrap();
+        wait_for_replica(&mut leader).await;
+
+        // A transaction with writes is queued on the leader
+        let mut stream = TcpStream::connect(leader_addr).await.unwrap();
+        stream.write_all(&command(&["MULTI"])).await.unwrap();
+        assert_eq!("+OK", read_line(&mut stream).await);
+        stream
+            .write_all(&command(&["SET", "hello", "world"]))
+            .await
+            .unwrap();
+        assert_eq!("+QUEUED", read_line(&mut stream).await);
+
+        leader
+            .failover(Some(("127.0.0.1", replica_addr.port())), None)
+            .await
+            .unwrap();
+
+        for _ in 0..300 {
+            if matches!(leader.role().await.unwrap(), ServerRole::Replica { .. }) {
+                break;
+            }
+
+            time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // The server became a replica before `EXEC`
+        stream.write_all(&command(&["EXEC"])).await.unwrap();
+        assert_eq!(
+            "-READONLY Transaction discarded, the server became a read only replica.",
+            read_line(&mut stream).await
+        );
+        assert_eq!(None, replica.get("hello").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn failover_pauses_writes_until_aborted() {
+        let (addr, _) = start_server().await;
+        let mut client = Client::connect(addr).await.unwrap();
+
+        // A replica which never acknowledges the writes
+        let mut replica = TcpStream::connect(addr).await.unwrap();
+        replica
+            .write_all(&command(&["REPLCONF", "listening-port", "7777"]))
+            .await
+            .unwrap();
+        assert_eq!("+OK", read_line(&mut replica).await);
+        replica
+            .write_all(&command(&["PSYNC", "?", "-1"]))
+            .await
+            .unwrap();
+        assert!(read_line(&mut replica).await.starts_with("+FULLRESYNC"));
+        let len: usize = read_line(&mut replica).await[1..].parse().unwrap();
+        let mut payload = vec![0; len];
+        replica.read_exact(&mut payload).await.unwrap();
+
+        client
+            .failover(None, Some(Duration::from_millis(300)))
+            .await
+            .unwrap();
+
+        let err = client.failover(None, None).await.unwrap_err();
+        assert_eq!("ERR FAILOVER already in progress.", err.to_string());
+
+        let info = client.info(Some("replication")).await.unwrap();
+        assert!(
+            info.contains("master_failover_state:waiting-for-sync"),
+            "{}",
+            info
+        );
+
+        // Writes are held until the failover is aborted, reads aren't
+        let start = time::Instant::now();
+        assert_eq!(None, client.get("hello").await.unwrap());
+        assert!(start.elapsed() < Duration::from_millis(200));
+
+        client.set("hello", "world".into()).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        assert!(matches!(
+            client.role().await.unwrap(),
+            ServerRole::Leader { .. }
+        ));
+        let info = client.info(Some("replication")).await.unwrap();
+        assert!(
+            info.contains("master_failover_state:no-failover"),
+            "{}",
+            info
+        );
+    }
+
+    #[tokio::test]
+    async fn cluster_shards_lists_slots_and_nodes() {
+        let config = Config {
+            cluster: Some(ClusterConfig {
+                slots: vec![SlotRange {
+                    start: 0,
+                    end: 16383,
+                }],
+                bus_port: Some(0),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let (addr, _) = start_server_with_config(config).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        stream
+            .write_all(&command(&["CLUSTER", "SHARDS"]))
+            .await
+            .unwrap();
+
+        let mut lines = vec![];
+        for _ in 0..18 {
+            lines.push(read_line(&mut stream).await);
+        }
+
+        assert_eq!(
+            vec![
+                "*1", "*4", "$5", "slots", "*2", ":0", ":16383", "$5", "nodes", "*1", "*14", "$2",
+                "id", "$40"
+            ],
+            lines[..14]
+        );
+        assert_eq!(
+            vec!["$4", "port", &format!(":{}", addr.port())],
+            lines[15..]
+        );
+
+        // Cluster commands require the cluster mode
+        let (addr, _) = start_server().await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(&command(&["CLUSTER", "SLOTS"]))
+            .await
+            .unwrap();
+        assert_eq!(
+            "-ERR This instance has cluster support disabled",
+            read_line(&mut stream).await
+        );
+    }
+
+    /// Wait until the leader has a replica online.
+    async fn wait_for_replica(leader: &mut Client) {
+        for _ in 0..300 {
+            if let ServerRole::Leader { replicas, .. } = leader.role().await.unwrap() {
+                if !replicas.is_empty() {
+                    return;
+                }
+            }
+
+            time::sleep(Duration::from_millis(10)).await;
+        }
+
+        panic!("no replica synced with the leader");
+    }
+
+    /// Encode a command as an array of bulk strings.
+    fn command(args: &[&str]) -> Vec<u8> {
+        let mut request = format!("*{}\r\n", args.len()).into_bytes();
+
+        for arg in args {
+            request.extend(format!("${}\r\n{}\r\n", arg.len(), arg).into_bytes());
+        }
+
+        request
+    }
+
+    /// Read a line of the response, without its CRLF.
+    async fn read_line(stream: &mut TcpStream) -> String {
+        let mut line = vec![];
+
+        while !line.ends_with(b"\r\n") {
+            line.push(stream.read_u8().await.unwrap());
+        }
+
+        line.truncate(line.len() - 2);
+        String::from_utf8(line).unwrap()
+    }
+
     async fn start_server() -> (SocketAddr, JoinHandle<()>) {
+        start_server_with_config(Config::default()).await
+    }
+
+    async fn start_server_with_config(config: Config) -> (SocketAddr, JoinHandle<()>) {
         let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
         let addr = listener.local_addr().unwrap();
 
         let handle = tokio::spawn(async move {
-            server::run(listener, tokio::signal::ctrl_c()).await;
+            server::run_with_config(listener, config, tokio::signal::ctrl_c()).await;
         });
 
         (addr, handle)
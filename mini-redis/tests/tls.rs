@@ -0,0 +1,243 @@
+use bytes::Bytes;
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+};
+use std::{net::SocketAddr, path::Path};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use mini_redis::{
+    clients::client::Client,
+    config::{Config, TlsAuthClients, TlsClientConfig, TlsConfig},
+    server::{self, ListenSocket},
+};
+
+mod integration_tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn set_and_get_over_tls() {
+        let certs = Certs::generate();
+        let addr = start_server(certs.server_config(TlsAuthClients::No)).await;
+
+        let mut client = Client::connect_tls("localhost", addr.port(), &certs.client_config(false))
+            .await
+            .unwrap();
+        client.set("hello", "world".into()).await.unwrap();
+
+        assert_eq!(
+            Some(Bytes::from("world")),
+            client.get("hello").await.unwrap()
+        );
+
+        // The certificate is also valid for the IP address
+        let mut client = Client::connect_tls("127.0.0.1", addr.port(), &certs.client_config(false))
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(Bytes::from("world")),
+            client.get("hello").await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn verifies_the_server_certificate() {
+        let certs = Certs::generate();
+        let addr = start_server(certs.server_config(TlsAuthClients::No)).await;
+
+        // Signed by another CA
+        let other = Certs::generate();
+        assert!(
+            Client::connect_tls("localhost", addr.port(), &other.client_config(false))
+                .await
+                .is_err()
+        );
+
+        // Issued for another name
+        let config = TlsClientConfig {
+            sni: Some("example.com".to_string()),
+            ..certs.client_config(false)
+        };
+        assert!(Client::connect_tls("localhost", addr.port(), &config)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn requires_client_certificates() {
+        let certs = Certs::generate();
+        let addr = start_server(certs.server_config(TlsAuthClients::Yes)).await;
+
+        let mut client = Client::connect_tls("localhost", addr.port(), &certs.client_config(true))
+            .await
+            .unwrap();
+        assert_eq!(&b"PONG"[..], &client.ping(None).await.unwrap()[..]);
+
+        // With TLS 1.3 the server rejects the missing certificate after the client's handshake
+        // completed, the first request failing
+        if let Ok(mut client) =
+            Client::connect_tls("localhost", addr.port(), &certs.client_config(false)).await
+        {
+            assert!(client.ping(None).await.is_err());
+        }
+
+        // Signed by another CA
+        let other = Certs::generate();
+        let config = TlsClientConfig {
+            ca_cert_file: certs.path("ca.pem"),
+            ..other.client_config(true)
+        };
+
+        if let Ok(mut client) = Client::connect_tls("localhost", addr.port(), &config).await {
+            assert!(client.ping(None).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn accepts_optional_client_certificates() {
+        let certs = Certs::generate();
+        let addr = start_server(certs.server_config(TlsAuthClients::Optional)).await;
+
+        for with_cert in [true, false] {
+            let mut client =
+                Client::connect_tls("localhost", addr.port(), &certs.client_config(with_cert))
+                    .await
+                    .unwrap();
+            assert_eq!(&b"PONG"[..], &client.ping(None).await.unwrap()[..]);
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_plain_connections_on_the_tls_port() {
+        let certs = Certs::generate();
+        let addr = start_server(certs.server_config(TlsAuthClients::No)).await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+
+        // The handshake fails, the server closing the connection without a reply
+        let mut buf = vec![];
+        let _ = stream.read_to_end(&mut buf).await;
+        assert!(!buf.starts_with(b"+PONG"));
+    }
+
+    #[tokio::test]
+    async fn serves_plain_and_tls_listeners_together() {
+        let certs = Certs::generate();
+
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tls_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tcp_addr = tcp_listener.local_addr().unwrap();
+        let tls_addr = tls_listener.local_addr().unwrap();
+
+        let config = Config {
+            tls: certs.server_config(TlsAuthClients::No),
+            ..Default::default()
+        };
+
+        tokio::spawn(async move {
+            let listeners = vec![
+                ListenSocket::from(tcp_listener),
+                ListenSocket::Tls(tls_listener),
+            ];
+            server::run_with_listeners(listeners, config, tokio::signal::ctrl_c()).await;
+        });
+
+        let mut plain = Client::connect(tcp_addr).await.unwrap();
+        let mut tls =
+            Client::connect_tls("localhost", tls_addr.port(), &certs.client_config(false))
+                .await
+                .unwrap();
+
+        plain.set("key", "value".into()).await.unwrap();
+        assert_eq!(Some(Bytes::from("value")), tls.get("key").await.unwrap());
+    }
+
+    /// Start a server only listening for TLS connections.
+    async fn start_server(tls: TlsConfig) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let config = Config {
+            tls,
+            ..Default::default()
+        };
+
+        tokio::spawn(async move {
+            server::run_with_listeners(
+                vec![ListenSocket::Tls(listener)],
+                config,
+                tokio::signal::ctrl_c(),
+            )
+            .await;
+        });
+
+        addr
+    }
+
+    /// A CA, and the server and client certificates it signed, written to a temporary directory.
+    struct Certs {
+        dir: TempDir,
+    }
+
+    impl Certs {
+        fn generate() -> Certs {
+            let dir = tempfile::tempdir().unwrap();
+
+            let mut params = CertificateParams::new(vec![]).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca_key = KeyPair::generate().unwrap();
+            let ca = params.self_signed(&ca_key).unwrap();
+            write(dir.path(), "ca", &ca, None);
+
+            for (name, purpose) in [
+                ("server", ExtendedKeyUsagePurpose::ServerAuth),
+                ("client", ExtendedKeyUsagePurpose::ClientAuth),
+            ] {
+                let mut params =
+                    CertificateParams::new(vec!["localhost".to_string(), "127.0.0.1".to_string()])
+                        .unwrap();
+                params.extended_key_usages = vec![purpose];
+                let key = KeyPair::generate().unwrap();
+                let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+                write(dir.path(), name, &cert, Some(&key));
+            }
+
+            Certs { dir }
+        }
+
+        fn path(&self, name: &str) -> std::path::PathBuf {
+            self.dir.path().join(name)
+        }
+
+        fn server_config(&self, auth_clients: TlsAuthClients) -> TlsConfig {
+            TlsConfig {
+                cert_file: Some(self.path("server.pem")),
+                key_file: Some(self.path("server.key")),
+                ca_cert_file: Some(self.path("ca.pem")),
+                auth_clients,
+                ..Default::default()
+            }
+        }
+
+        fn client_config(&self, with_cert: bool) -> TlsClientConfig {
+            TlsClientConfig {
+                ca_cert_file: self.path("ca.pem"),
+                cert_file: with_cert.then(|| self.path("client.pem")),
+                key_file: with_cert.then(|| self.path("client.key")),
+                sni: None,
+            }
+        }
+    }
+
+    fn write(dir: &Path, name: &str, cert: &Certificate, key: Option<&KeyPair>) {
+        std::fs::write(dir.join(format!("{}.pem", name)), cert.pem()).unwrap();
+
+        if let Some(key) = key {
+            std::fs::write(dir.join(format!("{}.key", name)), key.serialize_pem()).unwrap();
+        }
+    }
+}